@@ -0,0 +1,114 @@
+#!/usr/bin/env python3
+"""
+Turns an extracted Simple Icons npm package into the brand icons bundled with authenticator-rs:
+
+    brand_icons.py <package dir> <icons dir> <gresource xml>
+
+Icons are copied to <icons dir> filled with their brand colour, along with an index.json of their slugs,
+titles, aliases and domains. <gresource xml> lists them all under the brands prefix.
+"""
+
+import json
+import re
+import sys
+import unicodedata
+from pathlib import Path
+from urllib.parse import urlparse
+from xml.sax.saxutils import escape
+
+PREFIX = "/uk/co/grumlimited/authenticator-rs/brands"
+
+TITLE_TO_SLUG = {"+": "plus", ".": "dot", "&": "and", "đ": "d", "ħ": "h", "ı": "i", "ĸ": "k", "ŀ": "l", "ł": "l", "ß": "ss", "ŧ": "t"}
+
+
+def slug(title):
+    """As titleToSlug of the Simple Icons SDK."""
+    title = "".join(TITLE_TO_SLUG.get(c, c) for c in title.lower())
+    title = unicodedata.normalize("NFD", title)
+    return re.sub(r"[^a-z0-9]", "", title)
+
+
+def aliases(icon):
+    aliases = icon.get("aliases", {})
+    names = list(aliases.get("aka", []))
+    names += [dup["title"] for dup in aliases.get("dup", [])]
+    names += list(aliases.get("loc", {}).values())
+    return names
+
+
+def domains(icon, names):
+    """
+    Domains of the source and guidelines URLs, when they are the brand's own rather than e.g. GitHub or Wikipedia's,
+    along with whether the domain is exactly the brand's name: proton.me goes to Proton Mail, but github.com only to GitHub.
+    """
+    keys = {slug(name) for name in names}
+    found = {}
+
+    for url in (icon.get("source", ""), icon.get("guidelines", "")):
+        host = (urlparse(url).hostname or "").removeprefix("www.")
+        labels = host.split(".")
+
+        if len(labels) < 2:
+            continue
+
+        # e.g. example.co.uk rather than co.uk
+        registrable = labels[-3:] if len(labels) > 2 and len(labels[-2]) <= 3 else labels[-2:]
+        label = slug(registrable[0])
+
+        if label in keys:
+            found[".".join(registrable)] = True
+        elif len(label) >= 4 and any(key.startswith(label) for key in keys):
+            found.setdefault(".".join(registrable), False)
+
+    return found
+
+
+def main(package, icons_dir, xml):
+    data = package / "_data" / "simple-icons.json"
+    if not data.exists():
+        data = package / "data" / "simple-icons.json"
+
+    icons = json.loads(data.read_text())
+    if isinstance(icons, dict):
+        icons = icons["icons"]
+
+    icons_dir.mkdir(parents=True, exist_ok=True)
+
+    index = []
+    files = []
+
+    for icon in icons:
+        icon_slug = icon.get("slug") or slug(icon["title"])
+        svg = package / "icons" / f"{icon_slug}.svg"
+
+        if not svg.exists():
+            print(f"skipping {icon['title']}: no {svg.name}", file=sys.stderr)
+            continue
+
+        content = svg.read_text().replace("<svg ", f'<svg fill="#{icon["hex"]}" ', 1)
+        (icons_dir / svg.name).write_text(content)
+
+        names = [icon["title"]] + aliases(icon)
+        index.append({"slug": icon_slug, "title": icon["title"], "aliases": aliases(icon), "domains": domains(icon, names)})
+        files.append(svg.name)
+
+    # a domain only goes to the brands named after it, if there are any
+    exact = {domain for entry in index for domain, is_exact in entry["domains"].items() if is_exact}
+    for entry in index:
+        entry["domains"] = [domain for domain, is_exact in entry["domains"].items() if is_exact or domain not in exact]
+
+    (icons_dir / "index.json").write_text(json.dumps(index, ensure_ascii=False))
+
+    with open(xml, "w") as out:
+        out.write('<?xml version="1.0" encoding="UTF-8"?>\n<gresources>\n')
+        out.write(f'\t<gresource prefix="{PREFIX}">\n')
+        out.write(f'\t\t<file compressed="true" alias="index.json">{escape(str(icons_dir / "index.json"))}</file>\n')
+        for name in files:
+            out.write(f'\t\t<file compressed="true" preprocess="xml-stripblanks" alias="{name}">{escape(str(icons_dir / name))}</file>\n')
+        out.write("\t</gresource>\n</gresources>\n")
+
+    print(f"{len(files)} brand icons")
+
+
+if __name__ == "__main__":
+    main(Path(sys.argv[1]), Path(sys.argv[2]), Path(sys.argv[3]))
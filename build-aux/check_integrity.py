@@ -0,0 +1,34 @@
+#!/usr/bin/env python3
+"""
+Checks a downloaded file against a Subresource Integrity string, as npm publishes in `dist.integrity`:
+
+    check_integrity.py <file> <sha512-...>
+
+Exits with an error, deleting the file, unless they match.
+"""
+
+import base64
+import hashlib
+import sys
+from pathlib import Path
+
+
+def integrity(path, algorithm):
+    digest = hashlib.new(algorithm, path.read_bytes()).digest()
+    return f"{algorithm}-{base64.b64encode(digest).decode()}"
+
+
+def main(path, expected):
+    if not expected:
+        sys.exit(f"No integrity given for {path.name}: pin it in the Makefile first")
+
+    algorithm = expected.split("-", 1)[0]
+    actual = integrity(path, algorithm)
+
+    if actual != expected:
+        path.unlink()
+        sys.exit(f"{path.name} does not match its pinned integrity:\n  expected {expected}\n  got      {actual}")
+
+
+if __name__ == "__main__":
+    main(Path(sys.argv[1]), sys.argv[2] if len(sys.argv) > 2 else "")
@@ -0,0 +1,4 @@
+-- TOTP parameters of accounts, for services not using the usual SHA1, 6 digits and 30 seconds
+ALTER TABLE accounts ADD COLUMN algorithm TEXT NOT NULL DEFAULT 'SHA1';
+ALTER TABLE accounts ADD COLUMN digits INTEGER NOT NULL DEFAULT 6;
+ALTER TABLE accounts ADD COLUMN period INTEGER NOT NULL DEFAULT 30;
@@ -0,0 +1,2 @@
+-- counter of HOTP accounts, which codes are generated from instead of the time
+ALTER TABLE accounts ADD COLUMN counter INTEGER NOT NULL DEFAULT 0;
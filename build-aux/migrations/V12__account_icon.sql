@@ -0,0 +1,3 @@
+-- icon and website of accounts, for groups mixing several services
+ALTER TABLE accounts ADD COLUMN icon TEXT;
+ALTER TABLE accounts ADD COLUMN url TEXT;
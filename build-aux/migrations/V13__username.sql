@@ -0,0 +1,2 @@
+-- username accounts sign in with, copied from their menu
+ALTER TABLE accounts ADD COLUMN username TEXT NOT NULL DEFAULT '';
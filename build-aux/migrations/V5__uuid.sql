@@ -0,0 +1,9 @@
+ALTER TABLE groups ADD COLUMN uuid TEXT;
+ALTER TABLE accounts ADD COLUMN uuid TEXT;
+
+-- random version 4 UUIDs, formatted as uuid::Uuid does
+UPDATE groups SET uuid = lower(hex(randomblob(4))) || '-' || lower(hex(randomblob(2))) || '-4' || substr(lower(hex(randomblob(2))), 2) || '-' || substr('89ab', 1 + (abs(random()) % 4), 1) || substr(lower(hex(randomblob(2))), 2) || '-' || lower(hex(randomblob(6)));
+UPDATE accounts SET uuid = lower(hex(randomblob(4))) || '-' || lower(hex(randomblob(2))) || '-4' || substr(lower(hex(randomblob(2))), 2) || '-' || substr('89ab', 1 + (abs(random()) % 4), 1) || substr(lower(hex(randomblob(2))), 2) || '-' || lower(hex(randomblob(6)));
+
+CREATE UNIQUE INDEX groups_uuid ON groups (uuid);
+CREATE UNIQUE INDEX accounts_uuid ON accounts (uuid);
@@ -0,0 +1,62 @@
+-- milliseconds since epoch of the last change to each group and account, kept up to date by the triggers below
+ALTER TABLE groups ADD COLUMN modified INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE accounts ADD COLUMN modified INTEGER NOT NULL DEFAULT 0;
+
+-- deleted groups and accounts, so that deletions reach other devices
+CREATE TABLE sync_tombstones (
+    uuid        TEXT PRIMARY KEY,
+    modified    INTEGER NOT NULL
+);
+
+-- version of each group and account last written to or read from the sync folder
+CREATE TABLE sync_versions (
+    uuid        TEXT PRIMARY KEY,
+    modified    INTEGER NOT NULL,
+    device      TEXT NOT NULL,
+    digest      TEXT NOT NULL
+);
+
+-- number of changes already merged from the log of each device
+CREATE TABLE sync_devices (
+    device      TEXT PRIMARY KEY,
+    changes     INTEGER NOT NULL
+);
+
+CREATE TABLE sync_settings (
+    key         TEXT PRIMARY KEY,
+    value       TEXT NOT NULL
+);
+
+-- statements setting `modified` themselves, i.e. merging changes from other devices, keep their timestamp
+CREATE TRIGGER groups_inserted AFTER INSERT ON groups
+BEGIN
+    UPDATE groups SET modified = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER) WHERE id = NEW.id AND NEW.modified = 0;
+    DELETE FROM sync_tombstones WHERE uuid = NEW.uuid;
+END;
+
+-- collapsing or expanding a group is a preference of each device, not a change to sync
+CREATE TRIGGER groups_updated AFTER UPDATE OF name, url ON groups WHEN NEW.modified = OLD.modified
+BEGIN
+    UPDATE groups SET modified = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER) WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER groups_deleted AFTER DELETE ON groups
+BEGIN
+    INSERT OR REPLACE INTO sync_tombstones (uuid, modified) VALUES (OLD.uuid, CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER));
+END;
+
+CREATE TRIGGER accounts_inserted AFTER INSERT ON accounts
+BEGIN
+    UPDATE accounts SET modified = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER) WHERE id = NEW.id AND NEW.modified = 0;
+    DELETE FROM sync_tombstones WHERE uuid = NEW.uuid;
+END;
+
+CREATE TRIGGER accounts_updated AFTER UPDATE ON accounts WHEN NEW.modified = OLD.modified
+BEGIN
+    UPDATE accounts SET modified = CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER) WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER accounts_deleted AFTER DELETE ON accounts
+BEGIN
+    INSERT OR REPLACE INTO sync_tombstones (uuid, modified) VALUES (OLD.uuid, CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER));
+END;
@@ -0,0 +1,5 @@
+-- settings of the remote (WebDAV) and local backups
+CREATE TABLE backup_settings (
+    key         TEXT PRIMARY KEY,
+    value       TEXT NOT NULL
+);
@@ -0,0 +1,2 @@
+-- how codes are generated from the secret: plain TOTP, or Steam Guard's 5 character codes
+ALTER TABLE accounts ADD COLUMN account_type TEXT NOT NULL DEFAULT 'totp';
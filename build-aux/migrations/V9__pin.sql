@@ -0,0 +1,2 @@
+-- PIN of Yandex Key and Mobile-OTP accounts, which codes are derived from along with the secret
+ALTER TABLE accounts ADD COLUMN pin TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,262 @@
+use std::str::FromStr;
+
+use rusqlite::Connection;
+
+use crate::helpers::{Integrity, IntegrityReport, Profiles, SecretStores, SecretType, SyncFolder, VAULT_PASSPHRASE_ENV};
+
+const USAGE: &str = "Usage: authenticator-rs [OPTION…]
+
+Options handled without starting the graphical interface:
+  --check-integrity     report accounts and keyring items which do not match
+  --repair-integrity    remove orphaned and duplicated keyring items
+  --migrate-secrets=<KEYRING|VAULT|KEYUTILS>
+                        move every secret to the Secret Service, the encrypted vault
+                        or the kernel keyring, and store new secrets there
+  --sync                send changes to the sync folder and merge those of other devices
+  --profile=<NAME>      use the accounts of another profile, e.g. work or personal;
+                        created if it does not exist yet
+  --help                show this message
+
+Without --profile, the options above work on the default profile
+and the graphical interface opens the profile used last.
+
+The vault passphrase is read from AUTHENTICATOR_RS_VAULT_PASSPHRASE.
+Any other option is handed over to GTK.";
+
+const MIGRATE_SECRETS: &str = "--migrate-secrets=";
+const PROFILE: &str = "--profile";
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Command {
+    CheckIntegrity,
+    RepairIntegrity,
+    MigrateSecrets(SecretType),
+    Sync,
+    Help,
+    /// Option recognised, but with a value which is not.
+    Invalid(String),
+}
+
+/**
+ * Command line options, split between those handled here and those left for GTK.
+ */
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct Cli {
+    pub command: Option<Command>,
+    pub profile: Option<String>,
+    pub gtk_args: Vec<String>,
+}
+
+impl Cli {
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Cli {
+        let mut cli = Cli::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--check-integrity" => cli.command = Some(Command::CheckIntegrity),
+                "--repair-integrity" => cli.command = Some(Command::RepairIntegrity),
+                "--sync" => cli.command = Some(Command::Sync),
+                "--help" | "-h" => cli.command = Some(Command::Help),
+                _ if arg.starts_with(MIGRATE_SECRETS) => {
+                    cli.command = match SecretType::from_str(&arg[MIGRATE_SECRETS.len()..]) {
+                        Ok(SecretType::LOCAL) | Err(_) => Some(Command::Invalid(arg)),
+                        Ok(secret_type) => Some(Command::MigrateSecrets(secret_type)),
+                    }
+                }
+                PROFILE => match args.next() {
+                    Some(name) => cli.set_profile(arg, name),
+                    None => cli.command = Some(Command::Invalid(arg)),
+                },
+                _ if arg.starts_with(PROFILE) && arg[PROFILE.len()..].starts_with('=') => {
+                    let name = arg[PROFILE.len() + 1..].to_owned();
+                    cli.set_profile(arg, name)
+                }
+                _ => cli.gtk_args.push(arg),
+            }
+        }
+
+        cli
+    }
+
+    fn set_profile(&mut self, arg: String, name: String) {
+        match Profiles::validate(name.as_str()) {
+            Ok(()) => self.profile = Some(name),
+            Err(_) => self.command = Some(Command::Invalid(arg)),
+        }
+    }
+
+    /**
+     * Runs the command and returns the process exit code:
+     * 0 when nothing is left to fix, 1 on error, 2 when issues or sync conflicts remain.
+     */
+    pub fn run(command: Command, connection: &Connection) -> i32 {
+        match command {
+            Command::Help | Command::Invalid(_) => Self::usage(&command),
+            Command::CheckIntegrity => match Integrity::scan(connection) {
+                Ok(report) => {
+                    Self::print_report(&report);
+                    if report.issues.is_empty() {
+                        0
+                    } else {
+                        2
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Integrity check failed: {:?}", e);
+                    1
+                }
+            },
+            Command::RepairIntegrity => match Integrity::scan(connection).and_then(|report| {
+                Self::print_report(&report);
+                Integrity::repair_all(connection, &report)
+            }) {
+                Ok(remaining) if remaining.is_empty() => {
+                    println!("Repaired");
+                    0
+                }
+                Ok(remaining) => {
+                    println!("Left for manual repair:");
+                    remaining.iter().for_each(|issue| println!("  - {}", issue));
+                    2
+                }
+                Err(e) => {
+                    eprintln!("Integrity repair failed: {:?}", e);
+                    1
+                }
+            },
+            Command::MigrateSecrets(to) => match SecretStores::migrate(connection, &to) {
+                Ok(migrated) => {
+                    println!("Moved {} secret(s) to {}", migrated, to);
+
+                    match SecretStores::select(&to) {
+                        Ok(()) => 0,
+                        Err(e) => {
+                            eprintln!("New secrets still go to {}: {}", SecretStores::selected(), e.message());
+                            1
+                        }
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Secret migration failed: {:?}", e);
+                    if to == SecretType::VAULT {
+                        eprintln!("Is {} set?", VAULT_PASSPHRASE_ENV);
+                    }
+                    1
+                }
+            },
+            Command::Sync => match SyncFolder::sync(connection) {
+                Ok(None) => {
+                    eprintln!("No sync folder chosen yet");
+                    1
+                }
+                Ok(Some(report)) => {
+                    println!("Sent {} and received {} change(s)", report.sent, report.received);
+                    report.conflicts.iter().for_each(|conflict| println!("  - {}", conflict));
+                    report.unreadable.iter().for_each(|device| println!("  - could not read the log of {}", device));
+                    if report.conflicts.is_empty() && report.unreadable.is_empty() {
+                        0
+                    } else {
+                        2
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Sync failed: {:?}", e);
+                    1
+                }
+            },
+        }
+    }
+
+    /**
+     * Handles the commands which need neither the database nor the keyring.
+     */
+    pub fn usage(command: &Command) -> i32 {
+        match command {
+            Command::Invalid(arg) => {
+                eprintln!("Invalid option: {}", arg);
+                eprintln!("{}", USAGE);
+                1
+            }
+            _ => {
+                println!("{}", USAGE);
+                0
+            }
+        }
+    }
+
+    fn print_report(report: &IntegrityReport) {
+        println!(
+            "Checked {} accounts against {} keyring items: {} issue(s)",
+            report.accounts,
+            report.items,
+            report.issues.len()
+        );
+        report.issues.iter().for_each(|issue| println!("  - {}", issue));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn should_leave_unknown_options_to_gtk() {
+        let cli = Cli::parse(args(&["authenticator-rs", "--gapplication-service"]));
+
+        assert_eq!(None, cli.command);
+        assert_eq!(args(&["authenticator-rs", "--gapplication-service"]), cli.gtk_args);
+    }
+
+    #[test]
+    fn should_parse_integrity_commands() {
+        let cli = Cli::parse(args(&["authenticator-rs", "--check-integrity"]));
+        assert_eq!(Some(Command::CheckIntegrity), cli.command);
+        assert_eq!(args(&["authenticator-rs"]), cli.gtk_args);
+
+        let cli = Cli::parse(args(&["authenticator-rs", "--repair-integrity"]));
+        assert_eq!(Some(Command::RepairIntegrity), cli.command);
+    }
+
+    #[test]
+    fn should_parse_sync_command() {
+        let cli = Cli::parse(args(&["authenticator-rs", "--profile=work", "--sync"]));
+        assert_eq!(Some(Command::Sync), cli.command);
+        assert_eq!(Some("work".to_owned()), cli.profile);
+    }
+
+    #[test]
+    fn should_parse_profile_in_both_forms() {
+        let cli = Cli::parse(args(&["authenticator-rs", "--profile", "work", "--check-integrity"]));
+        assert_eq!(Some("work".to_owned()), cli.profile);
+        assert_eq!(Some(Command::CheckIntegrity), cli.command);
+        assert_eq!(args(&["authenticator-rs"]), cli.gtk_args);
+
+        let cli = Cli::parse(args(&["authenticator-rs", "--profile=personal"]));
+        assert_eq!(Some("personal".to_owned()), cli.profile);
+        assert_eq!(None, cli.command);
+
+        let cli = Cli::parse(args(&["authenticator-rs", "--profile=../work"]));
+        assert_eq!(None, cli.profile);
+        assert_eq!(Some(Command::Invalid("--profile=../work".to_owned())), cli.command);
+
+        let cli = Cli::parse(args(&["authenticator-rs", "--profile"]));
+        assert_eq!(Some(Command::Invalid("--profile".to_owned())), cli.command);
+    }
+
+    #[test]
+    fn should_parse_secret_backend_to_migrate_to() {
+        let cli = Cli::parse(args(&["authenticator-rs", "--migrate-secrets=VAULT"]));
+        assert_eq!(Some(Command::MigrateSecrets(SecretType::VAULT)), cli.command);
+
+        let cli = Cli::parse(args(&["authenticator-rs", "--migrate-secrets=LOCAL"]));
+        assert_eq!(Some(Command::Invalid("--migrate-secrets=LOCAL".to_owned())), cli.command);
+
+        let cli = Cli::parse(args(&["authenticator-rs", "--migrate-secrets="]));
+        assert_eq!(Some(Command::Invalid("--migrate-secrets=".to_owned())), cli.command);
+    }
+}
@@ -10,18 +10,28 @@ use log::error;
 use rusqlite::Connection;
 
 use crate::helpers::Backup;
-use crate::helpers::{Keyring, RepositoryError};
+use crate::helpers::{Database, RepositoryError, SecretStores};
 use crate::main_window::Display;
 use crate::main_window::MainWindow;
 use crate::NAMESPACE_PREFIX;
 
-pub type AccountsImportExportResult = Result<(), RepositoryError>;
+pub type AccountsImportExportResult = Result<ImportExportStatus, RepositoryError>;
 type PopupButtonClosure = Box<dyn Fn(&[gtk::glib::Value]) -> Option<gtk::glib::Value>>;
 
+/**
+ * Sent back by imports and exports: progress along the way for those reading several images, then completion.
+ */
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ImportExportStatus {
+    Progress(String),
+    Done,
+}
+
 #[derive(Debug, Clone)]
 pub enum ImportType {
     Internal,
     GoogleAuthenticator,
+    Steam,
 }
 
 pub trait Exporting {
@@ -63,15 +73,41 @@ impl Exporting for MainWindow {
                 gtk::ResponseType::Accept => {
                     dialog.close();
 
-                    let path = dialog.filename().unwrap();
+                    let Some(path) = dialog.filename() else {
+                        error!("Export cancelled: no filename chosen");
+                        error_popup.show();
+                        return;
+                    };
+
+                    let all_secrets = match SecretStores::all_secrets(&Database::lock(&connection)) {
+                        Ok(all_secrets) => all_secrets,
+                        Err(e) => {
+                            error!("Could not read secrets: {:?}", e);
+                            error_popup.show();
+                            return;
+                        }
+                    };
 
                     let (tx, rx) = async_channel::bounded::<AccountsImportExportResult>(1);
 
-                    glib::spawn_future(async move {
-                        rx.recv().await.unwrap() // discard
-                    });
+                    glib::spawn_future_local(clone!(
+                        #[strong]
+                        error_popup,
+                        async move {
+                            match rx.recv().await {
+                                Ok(Ok(_)) => {}
+                                Ok(Err(e)) => {
+                                    error!("Export failed: {:?}", e);
+                                    error_popup.show();
+                                }
+                                Err(_) => {
+                                    error!("Export task channel closed unexpectedly");
+                                    error_popup.show();
+                                }
+                            }
+                        }
+                    ));
 
-                    let all_secrets = Keyring::all_secrets().unwrap();
                     glib::spawn_future(clone!(
                         #[strong]
                         path,
@@ -99,10 +135,40 @@ impl Exporting for MainWindow {
                 get_widget!(builder, gtk::Label, error_popup_body);
                 get_widget!(builder, gtk::FileFilter, yaml_filter);
                 get_widget!(builder, gtk::FileFilter, yaml_filter_ga);
+                get_widget!(builder, gtk::FileFilter, steam_filter);
+
+                // a large Google Authenticator export comes as several QR codes, maybe screenshots of them all in a folder
+                let whole_folder = gtk::CheckButton::with_label(&gettext("Import every image of this folder"));
+
+                // accounts go to a group per issuer, unless a group is picked or typed in
+                let target_group = gtk::ComboBoxText::with_entry();
 
                 match import_type {
                     ImportType::Internal => dialog.set_filter(&yaml_filter),
-                    ImportType::GoogleAuthenticator => dialog.set_filter(&yaml_filter_ga),
+                    ImportType::GoogleAuthenticator => {
+                        dialog.set_filter(&yaml_filter_ga);
+                        dialog.set_action(gtk::FileChooserAction::Open);
+                        dialog.set_select_multiple(true);
+
+                        target_group.append(Some(""), &gettext("By issuer"));
+                        target_group.set_active_id(Some(""));
+
+                        match Database::load_account_groups(&Database::lock(&connection), None) {
+                            Ok(groups) => groups
+                                .iter()
+                                .for_each(|group| target_group.append(Some(group.name.as_str()), group.name.as_str())),
+                            Err(e) => error!("Could not load groups: {:?}", e),
+                        }
+
+                        let extra = gtk::Box::new(gtk::Orientation::Horizontal, 12);
+                        extra.pack_start(&whole_folder, false, false, 0);
+                        extra.pack_end(&target_group, false, false, 0);
+                        extra.pack_end(&gtk::Label::new(Some(&gettext("Group"))), false, false, 0);
+
+                        dialog.set_extra_widget(&extra);
+                        extra.show_all();
+                    }
+                    ImportType::Steam => dialog.set_filter(&steam_filter),
                 }
 
                 error_popup.set_title(&gettext("Error"));
@@ -123,9 +189,19 @@ impl Exporting for MainWindow {
                     gtk::ResponseType::Accept => {
                         dialog.close();
 
-                        let path: Option<PathBuf> = dialog.filename();
-                        let path = match path {
-                            Some(p) => p,
+                        let paths: Vec<PathBuf> = match import_type {
+                            ImportType::GoogleAuthenticator if whole_folder.is_active() => dialog.current_folder().into_iter().collect(),
+                            ImportType::GoogleAuthenticator => dialog.filenames(),
+                            _ => dialog.filename().into_iter().collect(),
+                        };
+
+                        let group = match target_group.active_id() {
+                            Some(id) if id.is_empty() => None,
+                            _ => target_group.active_text().map(|name| name.trim().to_owned()).filter(|name| !name.is_empty()),
+                        };
+
+                        let path = match paths.first() {
+                            Some(p) => p.clone(),
                             None => {
                                 error!("Import cancelled: no filename chosen");
                                 error_popup_body.set_label(&gettext("No filename chosen for import"));
@@ -140,14 +216,22 @@ impl Exporting for MainWindow {
                             #[strong(rename_to = gui)]
                             gui,
                             async move {
-                                match rx.recv().await {
-                                    Ok(Ok(_)) => {
-                                        gui.accounts_window.refresh_accounts(&gui);
-                                        gui.accounts_window.accounts_container.set_sensitive(true);
-                                    }
+                                let mut result = rx.recv().await;
+
+                                while let Ok(Ok(ImportExportStatus::Progress(progress))) = &result {
+                                    gui.accounts_window.import_banner_label.set_label(progress.as_str());
+                                    gui.accounts_window.import_banner.show();
+                                    result = rx.recv().await;
+                                }
+
+                                gui.accounts_window.import_banner.hide();
+                                gui.accounts_window.accounts_container.set_sensitive(true);
+
+                                match result {
+                                    Ok(Ok(_)) => gui.accounts_window.refresh_accounts(&gui),
                                     Ok(Err(e)) => {
                                         error!("Import failed: {:?}", e);
-                                        gui.errors.error_display_message.set_text(format!("{:?}", e).as_str());
+                                        gui.errors.error_display_message.set_text(e.message().as_str());
                                         gui.switch_to(Display::Errors);
                                     }
                                     Err(_) => {
@@ -159,16 +243,21 @@ impl Exporting for MainWindow {
                             }
                         ));
 
+                        gui.accounts_window.accounts_container.set_sensitive(false);
+
                         glib::spawn_future(clone!(
                             #[strong]
                             connection,
                             #[strong]
-                            path,
-                            #[strong]
                             import_type,
                             #[strong]
                             tx,
-                            async move { Backup::restore_account_and_signal_back(import_type, path, connection, tx).await }
+                            async move {
+                                match import_type {
+                                    ImportType::GoogleAuthenticator => Backup::restore_gauth_accounts_and_signal_back(paths, group, connection, tx).await,
+                                    _ => Backup::restore_account_and_signal_back(import_type, path, connection, tx).await,
+                                }
+                            }
                         ));
                     }
                     _ => dialog.close(),
@@ -1,49 +1,90 @@
+use std::collections::BTreeMap;
+use std::fs;
 use std::fs::File;
 use std::io;
-use std::io::Write;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
+use base32::Alphabet;
+use base64::Engine;
+use gettextrs::gettext;
 use log::warn;
 use rusqlite::Connection;
+use serde::Deserialize;
 
-use crate::exporting::{AccountsImportExportResult, ImportType};
-use crate::helpers::RepositoryError::GAuthQrCodeError;
-use crate::helpers::{Database, Keyring, Paths, QrCode, QrCodeResult, RepositoryError, SecretType};
-use crate::model::{Account, AccountGroup};
+use crate::exporting::{AccountsImportExportResult, ImportExportStatus, ImportType};
+use crate::helpers::RepositoryError::{GAuthQrCodeError, SteamGuardError};
+use crate::helpers::{
+    AllSecrets, BackupReason, Database, GAuthAccount, GAuthExport, IconStore, LocalBackups, Paths, QrCode, RepositoryError, SecretStores, SecretType,
+};
+use crate::model::{Account, AccountGroup, AccountType};
 
 pub struct Backup;
 
+/**
+ * The fields of a Steam Desktop Authenticator `.maFile` needed to generate codes. `shared_secret` is base64 encoded.
+ */
+#[derive(Deserialize)]
+struct SteamGuardFile {
+    shared_secret: String,
+    account_name: String,
+}
+
 impl Backup {
     pub async fn save_accounts(
         path: PathBuf,
         connection: Arc<Mutex<Connection>>,
-        all_secrets: Vec<(String, String)>,
+        all_secrets: AllSecrets,
         tx: async_channel::Sender<AccountsImportExportResult>,
     ) {
         let group_accounts = {
-            let connection = connection.lock().unwrap_or_else(|poisoned| {
-                warn!("Database connection mutex was poisoned. Recovering.");
-                poisoned.into_inner()
-            });
+            let connection = Database::lock(&connection);
 
-            let mut group_accounts = Database::load_account_groups(&connection, None).unwrap();
-            Keyring::associate_secrets(&mut group_accounts, &all_secrets, &connection).unwrap();
-
-            group_accounts
+            Database::load_account_groups(&connection, None).map(|mut group_accounts| {
+                SecretStores::associate_secrets(&mut group_accounts, &all_secrets);
+                Self::embed_icons(&mut group_accounts);
+                group_accounts
+            })
         };
 
-        let path = path.as_path();
-        match Self::serialise_accounts(group_accounts, path) {
-            Ok(()) => tx.send(Ok(())).await.expect("Could not send message"),
-            Err(e) => tx.send(Err(e)).await.expect("Could not send message"),
+        let result = group_accounts.and_then(|group_accounts| Self::serialise_accounts(group_accounts, path.as_path()));
+
+        if tx.send(result.map(|_| ImportExportStatus::Done)).await.is_err() {
+            warn!("Could not send export result: channel closed");
         }
     }
 
     pub fn serialise_accounts(account_groups: Vec<AccountGroup>, out: &Path) -> Result<(), RepositoryError> {
-        let mut file = File::create(out).map_err(RepositoryError::IoError)?;
-        let yaml = serde_yaml::to_string(&account_groups).map_err(RepositoryError::SerialisationError)?;
-        file.write_all(yaml.as_bytes()).map_err(RepositoryError::IoError)
+        let file = File::create(out).map_err(RepositoryError::IoError)?;
+        Self::write_accounts(&account_groups, file)
+    }
+
+    pub fn write_accounts<W: Write>(account_groups: &[AccountGroup], mut out: W) -> Result<(), RepositoryError> {
+        let yaml = serde_yaml::to_string(account_groups).map_err(RepositoryError::SerialisationError)?;
+        out.write_all(yaml.as_bytes()).map_err(RepositoryError::IoError)
+    }
+
+    /**
+     * Every account along with its secret, whichever backend it is kept in.
+     */
+    pub fn load_accounts(connection: &Connection) -> Result<Vec<AccountGroup>, RepositoryError> {
+        let all_secrets = SecretStores::all_secrets(connection)?;
+        let mut account_groups = Database::load_account_groups(connection, None)?;
+        SecretStores::associate_secrets(&mut account_groups, &all_secrets);
+        Self::embed_icons(&mut account_groups);
+        Ok(account_groups)
+    }
+
+    /**
+     * Backups carry the content of account icons: the icon store of the device they are restored on may not have them.
+     */
+    fn embed_icons(account_groups: &mut [AccountGroup]) {
+        let icons = IconStore::open();
+
+        account_groups
+            .iter_mut()
+            .for_each(|group| group.entries.iter_mut().for_each(|account| icons.embed(account)));
     }
 
     pub async fn restore_account_and_signal_back(
@@ -52,73 +93,247 @@ impl Backup {
         connection: Arc<Mutex<Connection>>,
         tx: async_channel::Sender<AccountsImportExportResult>,
     ) {
-        let db = match import_type {
-            ImportType::Internal => Self::restore_accounts(path, connection.clone()).await,
-            ImportType::GoogleAuthenticator => Self::restore_gauth_accounts(path, connection.clone()).await,
+        let backup = LocalBackups::open().take(&Database::lock(&connection), BackupReason::Import);
+
+        let db = match (backup, import_type) {
+            (Err(e), _) => Err(e),
+            (Ok(_), ImportType::Internal) => Self::restore_accounts(path, connection.clone()).await,
+            (Ok(_), ImportType::GoogleAuthenticator) => Self::restore_gauth_accounts(vec![path], None, connection.clone(), &tx).await,
+            (Ok(_), ImportType::Steam) => Self::restore_steam_accounts(path, connection.clone()).await,
         };
 
-        match db.and_then(|_| Paths::update_keyring_secrets(connection)) {
-            Ok(_) => tx.send(Ok(())).await.expect("Could not send message"),
-            Err(e) => tx.send(Err(e)).await.expect("Could not send message"),
+        let result = db.and_then(|_| Paths::update_keyring_secrets(connection));
+
+        if tx.send(result.map(|_| ImportExportStatus::Done)).await.is_err() {
+            warn!("Could not send import result: channel closed");
         }
     }
 
     async fn restore_accounts(path: PathBuf, connection: Arc<Mutex<Connection>>) -> Result<(), RepositoryError> {
         let mut account_groups = Self::deserialise_accounts(path.as_path())?;
 
-        let connection = connection.lock().unwrap_or_else(|poisoned| {
-            warn!("Database connection mutex was poisoned. Recovering.");
-            poisoned.into_inner()
-        });
+        let connection = Database::lock(&connection);
 
-        // Mark incoming secrets as LOCAL so they will be migrated to keyring later.
+        // Mark incoming secrets as LOCAL so they will be migrated to the secret backend later.
         account_groups
             .iter_mut()
             .for_each(|group| group.entries.iter_mut().for_each(|account| account.secret_type = SecretType::LOCAL));
 
+        let icons = IconStore::open();
+
         for group in account_groups.iter_mut() {
+            for account in group.entries.iter_mut() {
+                icons.extract(account)?;
+            }
+
             Database::save_group_and_accounts(&connection, group)?;
         }
 
         Ok(())
     }
 
-    async fn restore_gauth_accounts(path: PathBuf, connection: Arc<Mutex<Connection>>) -> Result<(), RepositoryError> {
-        use google_authenticator_converter::process_data;
+    /**
+     * Imports the Google Authenticator exports of several images, or of every image of a directory, in one go.
+     * Accounts go to the `group` named, or to a group per issuer when none is.
+     */
+    pub async fn restore_gauth_accounts_and_signal_back(
+        paths: Vec<PathBuf>,
+        group: Option<String>,
+        connection: Arc<Mutex<Connection>>,
+        tx: async_channel::Sender<AccountsImportExportResult>,
+    ) {
+        let backup = LocalBackups::open().take(&Database::lock(&connection), BackupReason::Import);
+
+        let result = match backup {
+            Ok(_) => Self::restore_gauth_accounts(paths, group, connection.clone(), &tx).await,
+            Err(e) => Err(e),
+        };
+
+        let result = result.and_then(|_| Paths::update_keyring_secrets(connection));
 
-        let path_str = path
-            .to_str()
-            .ok_or_else(|| RepositoryError::IoError(io::Error::new(io::ErrorKind::InvalidInput, "Failed to convert path to string: invalid unicode")))?;
+        if tx.send(result.map(|_| ImportExportStatus::Done)).await.is_err() {
+            warn!("Could not send import result: channel closed");
+        }
+    }
 
-        let qr_result = QrCode::process_qr_code(path_str.to_owned()).await;
+    /**
+     * Large exports are split across several QR codes: nothing is imported unless they are all there.
+     * Images without any Google Authenticator QR code are skipped. Progress is sent on `tx` after each image.
+     */
+    async fn restore_gauth_accounts(
+        paths: Vec<PathBuf>,
+        group: Option<String>,
+        connection: Arc<Mutex<Connection>>,
+        tx: &async_channel::Sender<AccountsImportExportResult>,
+    ) -> Result<(), RepositoryError> {
+        let mut exports = vec![];
+        let image_paths = Self::image_paths(paths)?;
 
-        match qr_result {
-            QrCodeResult::Valid(qr_code) => {
-                let accounts = process_data(qr_code.qr_code_payload.as_str()).map_err(|e| {
-                    warn!("Failed to parse GAuth payload: {:?}", e);
-                    GAuthQrCodeError(format!("Invalid GAuth data: {}", e))
-                })?;
+        for (index, path) in image_paths.iter().enumerate() {
+            let path_str = path
+                .to_str()
+                .ok_or_else(|| RepositoryError::IoError(io::Error::new(io::ErrorKind::InvalidInput, "Failed to convert path to string: invalid unicode")))?;
 
-                let entries = accounts
-                    .into_iter()
-                    .map(|account| {
-                        let secret = account.secret.clone();
-                        let secret_type = SecretType::LOCAL;
-                        Account::new(0, 0, &account.name, &secret, secret_type)
-                    })
-                    .collect::<Vec<Account>>();
+            match QrCode::process_qr_codes(path_str.to_owned()).await {
+                Ok(qr_codes) => {
+                    for qr_code in qr_codes.into_iter().filter(QrCode::is_gauth_export) {
+                        exports.push(GAuthExport::parse(qr_code.qr_code_payload.as_str())?);
+                    }
+                }
+                Err(e) => warn!("No GAuth QR code in {}: {}", path.display(), e),
+            }
 
-                let mut account_groups = AccountGroup::new(0, "GAuth", None, None, false, entries);
+            let progress = format!(
+                "{} {}/{}, {} {}",
+                gettext("Image"),
+                index + 1,
+                image_paths.len(),
+                GAuthExport::missing(&exports).len(),
+                gettext("QR code(s) missing")
+            );
 
-                let connection = connection.lock().unwrap();
-                Database::save_group_and_accounts(&connection, &mut account_groups)?;
-                Ok(())
+            if tx.send(Ok(ImportExportStatus::Progress(progress))).await.is_err() {
+                warn!("Could not send import progress: channel closed");
             }
-            QrCodeResult::Invalid(e) => {
-                warn!("Invalid GAuth QR code: {}", e);
-                Err(GAuthQrCodeError(format!("Invalid GAuth code: {}", e)))
+        }
+
+        if exports.is_empty() {
+            return Err(GAuthQrCodeError("Invalid GAuth code: no GAuth QR codes found".to_owned()));
+        }
+
+        let accounts = GAuthExport::stitch(exports)?;
+
+        Self::save_gauth_accounts(accounts, group, &connection)
+    }
+
+    /**
+     * Files of `paths`, along with the files of those which are directories, in the order of their names.
+     */
+    fn image_paths(paths: Vec<PathBuf>) -> Result<Vec<PathBuf>, RepositoryError> {
+        let mut image_paths = vec![];
+
+        for path in paths {
+            if path.is_dir() {
+                let mut files = fs::read_dir(&path)?
+                    .map(|entry| entry.map(|entry| entry.path()))
+                    .collect::<io::Result<Vec<PathBuf>>>()?;
+
+                files.retain(|file| file.is_file());
+                files.sort();
+
+                image_paths.extend(files);
+            } else {
+                image_paths.push(path);
             }
         }
+
+        Ok(image_paths)
+    }
+
+    /**
+     * Imports a Google Authenticator export read with the camera, as importing an image of it would.
+     */
+    pub async fn restore_gauth_payload_and_signal_back(
+        payload: String,
+        connection: Arc<Mutex<Connection>>,
+        tx: async_channel::Sender<AccountsImportExportResult>,
+    ) {
+        let backup = LocalBackups::open().take(&Database::lock(&connection), BackupReason::Import);
+
+        let result = backup
+            .and_then(|_| Self::restore_gauth_payload(payload.as_str(), &connection))
+            .and_then(|_| Paths::update_keyring_secrets(connection));
+
+        if tx.send(result.map(|_| ImportExportStatus::Done)).await.is_err() {
+            warn!("Could not send import result: channel closed");
+        }
+    }
+
+    /**
+     * `payload` is an `otpauth-migration://` URI.
+     */
+    fn restore_gauth_payload(payload: &str, connection: &Arc<Mutex<Connection>>) -> Result<(), RepositoryError> {
+        let accounts = GAuthExport::stitch(vec![GAuthExport::parse(payload)?])?;
+
+        Self::save_gauth_accounts(accounts, None, connection)
+    }
+
+    /**
+     * All accounts are saved in a single transaction, so that a failing one leaves no others behind.
+     * Groups are reused by name, importing the same export again updates its accounts.
+     */
+    fn save_gauth_accounts(accounts: Vec<GAuthAccount>, group: Option<String>, connection: &Arc<Mutex<Connection>>) -> Result<(), RepositoryError> {
+        let connection = Database::lock(connection);
+        let tx = connection.unchecked_transaction()?;
+
+        for mut account_group in Self::gauth_groups(accounts, group) {
+            Database::save_group_and_accounts(&tx, &mut account_group)?;
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /**
+     * Accounts in the `group` named, or else in a group per issuer. Accounts without an issuer are grouped by the
+     * `Issuer:` prefix of their name, if any, and go to `GAuth` otherwise.
+     */
+    fn gauth_groups(accounts: Vec<GAuthAccount>, group: Option<String>) -> Vec<AccountGroup> {
+        let mut by_group: BTreeMap<String, Vec<Account>> = BTreeMap::new();
+
+        for gauth_account in accounts {
+            let group_name = group.clone().filter(|group| !group.trim().is_empty()).unwrap_or_else(|| {
+                let issuer = match gauth_account.issuer.trim() {
+                    "" => gauth_account.name.split_once(':').map(|(issuer, _)| issuer.trim()).unwrap_or_default(),
+                    issuer => issuer,
+                };
+
+                if issuer.is_empty() { "GAuth" } else { issuer }.to_owned()
+            });
+
+            let mut account = Account::new(0, 0, &gauth_account.name, &gauth_account.secret, SecretType::LOCAL);
+            account.account_type = gauth_account.account_type;
+            account.algorithm = gauth_account.algorithm;
+            account.digits = gauth_account.digits;
+            account.counter = gauth_account.counter;
+
+            by_group.entry(group_name).or_default().push(account);
+        }
+
+        by_group
+            .into_iter()
+            .map(|(name, entries)| AccountGroup::new(0, name.as_str(), None, None, false, entries))
+            .collect()
+    }
+
+    /**
+     * Steam accounts all go to the same `Steam` group, so that importing a file again updates its account.
+     */
+    async fn restore_steam_accounts(path: PathBuf, connection: Arc<Mutex<Connection>>) -> Result<(), RepositoryError> {
+        let file = File::open(path).map_err(RepositoryError::IoError)?;
+        let account = Self::steam_guard_account(file)?;
+
+        let mut account_group = AccountGroup::new(0, "Steam", None, None, false, vec![account]);
+
+        let connection = Database::lock(&connection);
+        Database::save_group_and_accounts(&connection, &mut account_group)
+    }
+
+    fn steam_guard_account<R: Read>(reader: R) -> Result<Account, RepositoryError> {
+        let steam_guard: SteamGuardFile = serde_json::from_reader(reader).map_err(|e| {
+            warn!("Failed to parse maFile: {:?}", e);
+            SteamGuardError(format!("Invalid maFile, encrypted ones must be decrypted first: {}", e))
+        })?;
+
+        let secret = base64::engine::general_purpose::STANDARD
+            .decode(steam_guard.shared_secret.trim())
+            .map_err(|e| SteamGuardError(format!("Invalid shared secret: {}", e)))?;
+
+        let secret = base32::encode(Alphabet::Rfc4648 { padding: false }, &secret);
+
+        let mut account = Account::new(0, 0, steam_guard.account_name.as_str(), secret.as_str(), SecretType::LOCAL);
+        account.account_type = AccountType::Steam;
+        Ok(account)
     }
 
     fn deserialise_accounts(out: &Path) -> Result<Vec<AccountGroup>, RepositoryError> {
@@ -126,3 +341,70 @@ impl Backup {
         serde_yaml::from_reader(file).map_err(RepositoryError::SerialisationError)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Backup;
+    use crate::helpers::GAuthAccount;
+    use crate::model::{AccountType, TotpAlgorithm};
+
+    #[test]
+    fn steam_guard_account() {
+        let ma_file =
+            r#"{"shared_secret":"SGVsbG8h3q2+7w==","serial_number":"1234","revocation_code":"R12345","account_name":"alice","identity_secret":"c2VjcmV0"}"#;
+
+        let account = Backup::steam_guard_account(ma_file.as_bytes()).unwrap();
+
+        assert_eq!("alice", account.label);
+        assert_eq!("JBSWY3DPEHPK3PXP", account.secret);
+        assert_eq!(AccountType::Steam, account.account_type);
+    }
+
+    #[test]
+    fn gauth_groups() {
+        let account = |name: &str, issuer: &str| GAuthAccount {
+            name: name.to_owned(),
+            secret: "JBSWY3DPEHPK3PXP".to_owned(),
+            issuer: issuer.to_owned(),
+            algorithm: TotpAlgorithm::Sha256,
+            digits: 8,
+            account_type: AccountType::Hotp,
+            counter: 7,
+        };
+
+        let accounts = vec![
+            account("alice", "Example"),
+            account("Other:bob", ""),
+            account("carol", ""),
+            account("Example:dave", "Example"),
+        ];
+
+        let groups = Backup::gauth_groups(accounts.clone(), None);
+
+        let names = |group: &crate::model::AccountGroup| group.entries.iter().map(|account| account.label.clone()).collect::<Vec<String>>();
+
+        assert_eq!(
+            vec!["Example", "GAuth", "Other"],
+            groups.iter().map(|group| group.name.as_str()).collect::<Vec<&str>>()
+        );
+        assert_eq!(vec!["alice", "Example:dave"], names(&groups[0]));
+        assert_eq!(vec!["carol"], names(&groups[1]));
+        assert_eq!(vec!["Other:bob"], names(&groups[2]));
+
+        let imported = &groups[0].entries[0];
+        assert_eq!(
+            (AccountType::Hotp, TotpAlgorithm::Sha256, 8, 7),
+            (imported.account_type, imported.algorithm, imported.digits, imported.counter)
+        );
+
+        let groups = Backup::gauth_groups(accounts, Some("Work".to_owned()));
+        assert_eq!(1, groups.len());
+        assert_eq!(("Work", 4), (groups[0].name.as_str(), groups[0].entries.len()));
+    }
+
+    #[test]
+    fn encrypted_steam_guard_file() {
+        assert!(Backup::steam_guard_account("bm90IGpzb24=".as_bytes()).is_err());
+        assert!(Backup::steam_guard_account(r#"{"account_name":"alice","shared_secret":"not base64!"}"#.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,222 @@
+use std::sync::OnceLock;
+
+use anyhow::Result;
+use gtk::gdk_pixbuf::Pixbuf;
+use log::{debug, warn};
+use serde::Deserialize;
+use url::Url;
+
+use crate::helpers::AccountGroupIcon;
+
+/// Where `make brand-icons` puts the icon pack and its `index.json` in the resources.
+pub const BRANDS_PREFIX: &str = "/uk/co/grumlimited/authenticator-rs/brands";
+
+static BRAND_ICONS: OnceLock<BrandIcons> = OnceLock::new();
+
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+pub struct BrandIcon {
+    pub slug: String,
+    pub title: String,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// Registrable domains of the brand, e.g. `github.com`.
+    #[serde(default)]
+    pub domains: Vec<String>,
+}
+
+/**
+ * Brand icons bundled with the application, from Simple Icons, so that groups get an icon without going online.
+ */
+#[derive(Debug, Default)]
+pub struct BrandIcons {
+    icons: Vec<BrandIcon>,
+}
+
+impl BrandIcons {
+    /**
+     * The bundled icons, read once. Empty when the application was built without them.
+     */
+    pub fn get() -> &'static BrandIcons {
+        BRAND_ICONS.get_or_init(|| {
+            let index = format!("{}/index.json", BRANDS_PREFIX);
+
+            match gio::resources_lookup_data(index.as_str(), gio::ResourceLookupFlags::NONE) {
+                Ok(bytes) => Self::from_index(&bytes).unwrap_or_else(|e| {
+                    warn!("Could not read brand icons index: {:?}", e);
+                    BrandIcons::default()
+                }),
+                Err(e) => {
+                    debug!("No bundled brand icons: {}", e);
+                    BrandIcons::default()
+                }
+            }
+        })
+    }
+
+    pub fn from_index(index: &[u8]) -> Result<BrandIcons> {
+        Ok(BrandIcons {
+            icons: serde_json::from_slice(index)?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.icons.is_empty()
+    }
+
+    /**
+     * Icon of the first of `hints` to match one: group names, issuers, URLs or hostnames.
+     */
+    pub fn find(&self, hints: &[&str]) -> Option<&BrandIcon> {
+        hints.iter().find_map(|hint| self.find_one(hint))
+    }
+
+    fn find_one(&self, hint: &str) -> Option<&BrandIcon> {
+        let Some(host) = Self::host(hint) else {
+            return self.by_name(hint);
+        };
+
+        let by_domain = self.icons.iter().find(|icon| {
+            icon.domains
+                .iter()
+                .any(|domain| host == *domain || host.ends_with(format!(".{}", domain).as_str()))
+        });
+
+        // then by the labels of the host, e.g. `gitlab` for `gitlab.example.org`
+        by_domain.or_else(|| host.rsplit('.').skip(1).find_map(|label| self.by_name(label)))
+    }
+
+    fn by_name(&self, name: &str) -> Option<&BrandIcon> {
+        let key = Self::normalize(name);
+
+        if key.is_empty() {
+            return None;
+        }
+
+        self.icons
+            .iter()
+            .find(|icon| icon.slug == key || Self::normalize(icon.title.as_str()) == key || icon.aliases.iter().any(|alias| Self::normalize(alias) == key))
+    }
+
+    /**
+     * Icons with `query` in their title or aliases, at most `limit` of them: exact matches first,
+     * then those starting with it.
+     */
+    pub fn search(&self, query: &str, limit: usize) -> Vec<&BrandIcon> {
+        let key = Self::normalize(query);
+
+        let rank = |icon: &BrandIcon| {
+            let mut names = vec![Self::normalize(icon.title.as_str())];
+            names.extend(icon.aliases.iter().map(|alias| Self::normalize(alias)));
+
+            names
+                .iter()
+                .filter_map(|name| match name {
+                    name if *name == key => Some(0),
+                    name if name.starts_with(key.as_str()) => Some(1),
+                    name if name.contains(key.as_str()) => Some(2),
+                    _ => None,
+                })
+                .min()
+        };
+
+        let mut found: Vec<(u8, &BrandIcon)> = self.icons.iter().filter_map(|icon| rank(icon).map(|rank| (rank, icon))).collect();
+        found.sort_by_key(|(rank, _)| *rank);
+
+        found.into_iter().take(limit).map(|(_, icon)| icon).collect()
+    }
+
+    /**
+     * The SVG of `icon`, as written to the icons directory when picked for a group.
+     */
+    pub fn content(icon: &BrandIcon) -> Result<AccountGroupIcon> {
+        let bytes = gio::resources_lookup_data(Self::path(icon).as_str(), gio::ResourceLookupFlags::NONE)?;
+
+        Ok(AccountGroupIcon {
+            content: bytes.to_vec(),
+            extension: Some("svg+xml".to_owned()),
+        })
+    }
+
+    pub fn load_icon(icon: &BrandIcon, size: i32) -> Result<Pixbuf> {
+        Ok(Pixbuf::from_resource_at_scale(Self::path(icon).as_str(), size, size, true)?)
+    }
+
+    fn path(icon: &BrandIcon) -> String {
+        format!("{}/{}.svg", BRANDS_PREFIX, icon.slug)
+    }
+
+    /**
+     * Host of `hint` without `www.`, if it reads as a URL or a hostname rather than a name.
+     */
+    fn host(hint: &str) -> Option<String> {
+        let hint = hint.trim();
+
+        if !hint.contains('.') || hint.contains(char::is_whitespace) {
+            return None;
+        }
+
+        let url = if hint.contains("://") {
+            Url::parse(hint)
+        } else {
+            Url::parse(format!("https://{}", hint).as_str())
+        };
+
+        url.ok()
+            .and_then(|url| url.host_str().map(|host| host.trim_start_matches("www.").to_lowercase()))
+            .filter(|host| host.contains('.'))
+    }
+
+    /**
+     * Lower-case letters and digits only, as in Simple Icons slugs: `Proton Mail` and `proton-mail` give `protonmail`.
+     */
+    fn normalize(name: &str) -> String {
+        name.to_lowercase()
+            .replace('+', "plus")
+            .replace('&', "and")
+            .chars()
+            .filter(|c| c.is_alphanumeric())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BrandIcons;
+
+    const INDEX: &str = r#"[
+        {"slug": "github", "title": "GitHub", "domains": ["github.com"]},
+        {"slug": "gitlab", "title": "GitLab"},
+        {"slug": "protonmail", "title": "Proton Mail", "aliases": ["ProtonMail"], "domains": ["proton.me"]},
+        {"slug": "googlecloud", "title": "Google Cloud"},
+        {"slug": "google", "title": "Google", "domains": ["google.com"]}
+    ]"#;
+
+    #[test]
+    fn find() {
+        let icons = BrandIcons::from_index(INDEX.as_bytes()).unwrap();
+        let slug = |hints: &[&str]| icons.find(hints).map(|icon| icon.slug.clone());
+
+        assert_eq!(Some("github".to_owned()), slug(&["GitHub"]));
+        assert_eq!(Some("protonmail".to_owned()), slug(&["proton mail"]));
+        assert_eq!(Some("protonmail".to_owned()), slug(&["https://account.proton.me/login"]));
+        assert_eq!(Some("github".to_owned()), slug(&["www.github.com"]));
+        assert_eq!(Some("gitlab".to_owned()), slug(&["gitlab.example.org"]));
+        assert_eq!(Some("google".to_owned()), slug(&["Work", "accounts.google.com"]));
+
+        assert_eq!(None, slug(&["Work", ""]));
+        assert!(BrandIcons::default().find(&["GitHub"]).is_none());
+    }
+
+    #[test]
+    fn search() {
+        let icons = BrandIcons::from_index(INDEX.as_bytes()).unwrap();
+        let slugs = |query: &str, limit: usize| icons.search(query, limit).into_iter().map(|icon| icon.slug.as_str()).collect::<Vec<&str>>();
+
+        assert_eq!(vec!["github", "gitlab"], slugs("git", 10));
+        assert_eq!(vec!["googlecloud", "google"], slugs("goo", 10));
+        assert_eq!(vec!["google", "googlecloud"], slugs("google", 10));
+        assert_eq!(vec!["protonmail"], slugs("mail", 10));
+        assert_eq!(vec!["github"], slugs("git", 1));
+        assert_eq!(5, slugs("", 10).len());
+    }
+}
@@ -0,0 +1,499 @@
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::mem;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use gio::prelude::SettingsExt;
+use image::{GrayImage, ImageFormat};
+use libc::{c_int, c_ulong, c_void};
+use log::{debug, info, warn};
+
+use crate::helpers::{Paths, QrCode, RepositoryError};
+
+type Result<T> = ::std::result::Result<T, RepositoryError>;
+
+/**
+ * Frames QR codes are looked for in, be it from a camera or recorded.
+ */
+pub trait FrameSource {
+    /**
+     * The next frame in grey levels, `None` once there are no more.
+     */
+    fn next_frame(&mut self) -> Result<Option<GrayImage>>;
+}
+
+pub struct Camera;
+
+impl Camera {
+    /**
+     * The `camera-device` setting, if any.
+     */
+    pub fn configured_device() -> Option<PathBuf> {
+        Paths::settings()
+            .map(|settings| settings.string("camera-device").to_string())
+            .filter(|device| !device.is_empty())
+            .map(PathBuf::from)
+    }
+
+    /**
+     * The camera at `device`, the first one found without. A directory of images, or an image file, stands in for a
+     * camera so that scanning can be tried with recorded frames.
+     */
+    pub fn open(device: Option<&Path>) -> Result<Box<dyn FrameSource>> {
+        match device {
+            Some(device) if device.is_dir() || device.is_file() => Ok(Box::new(RecordedFrames::open(device)?)),
+            Some(device) => Ok(Box::new(V4l2Camera::open(device)?)),
+            None => Ok(Box::new(V4l2Camera::first()?)),
+        }
+    }
+
+    /**
+     * Reads frames until one shows an `otpauth://` or `otpauth-migration://` QR code, which is returned.
+     * Returns `None` when `cancelled` is set, or once the source has no more frames.
+     */
+    pub fn scan(source: &mut dyn FrameSource, cancelled: &AtomicBool) -> Result<Option<QrCode>> {
+        let mut frames = 0;
+
+        while !cancelled.load(Ordering::Relaxed) {
+            let Some(frame) = source.next_frame()? else {
+                debug!("No more frames after {}", frames);
+                return Ok(None);
+            };
+
+            frames += 1;
+
+            let (qr_codes, _) = QrCode::decode_frame(frame);
+
+            if let Some(qr_code) = qr_codes.into_iter().find(QrCode::is_otpauth) {
+                info!("QR code found in frame {}", frames);
+                return Ok(Some(qr_code));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/**
+ * Frames recorded as image files: a directory of them, read in the order of their names, or a single one.
+ */
+pub struct RecordedFrames {
+    paths: Vec<PathBuf>,
+}
+
+impl RecordedFrames {
+    pub fn open(path: &Path) -> Result<RecordedFrames> {
+        let mut paths = if path.is_dir() {
+            fs::read_dir(path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<io::Result<Vec<PathBuf>>>()?
+        } else {
+            vec![path.to_path_buf()]
+        };
+
+        // frames are taken from the end
+        paths.sort();
+        paths.reverse();
+
+        Ok(RecordedFrames { paths })
+    }
+}
+
+impl FrameSource for RecordedFrames {
+    fn next_frame(&mut self) -> Result<Option<GrayImage>> {
+        match self.paths.pop() {
+            Some(path) => image::open(&path)
+                .map(|image| Some(image.to_luma8()))
+                .map_err(|e| RepositoryError::CameraError(format!("Invalid frame {}: {}", path.display(), e))),
+            None => Ok(None),
+        }
+    }
+}
+
+const VIDEO_CAPTURE: u32 = 1;
+const MEMORY_MMAP: u32 = 1;
+const FIELD_NONE: u32 = 1;
+
+const CAP_VIDEO_CAPTURE: u32 = 0x0000_0001;
+const CAP_STREAMING: u32 = 0x0400_0000;
+const CAP_DEVICE_CAPS: u32 = 0x8000_0000;
+
+const PIX_FMT_GREY: u32 = u32::from_le_bytes(*b"GREY");
+const PIX_FMT_YUYV: u32 = u32::from_le_bytes(*b"YUYV");
+const PIX_FMT_MJPEG: u32 = u32::from_le_bytes(*b"MJPG");
+
+const BUFFERS: u32 = 4;
+const FRAME_TIMEOUT_MS: c_int = 5000;
+
+#[repr(C)]
+struct Capability {
+    driver: [u8; 16],
+    card: [u8; 32],
+    bus_info: [u8; 32],
+    version: u32,
+    capabilities: u32,
+    device_caps: u32,
+    reserved: [u32; 3],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PixFormat {
+    width: u32,
+    height: u32,
+    pixel_format: u32,
+    field: u32,
+    bytes_per_line: u32,
+    size_image: u32,
+    colorspace: u32,
+    private: u32,
+    flags: u32,
+    ycbcr_enc: u32,
+    quantization: u32,
+    xfer_func: u32,
+}
+
+/**
+ * `struct v4l2_format` restricted to the single-planar format. The union it holds is 8-byte aligned.
+ */
+#[repr(C)]
+struct Format {
+    buffer_type: u32,
+    padding: u32,
+    pix: PixFormat,
+    raw: [u8; 200 - mem::size_of::<PixFormat>()],
+}
+
+#[repr(C)]
+struct RequestBuffers {
+    count: u32,
+    buffer_type: u32,
+    memory: u32,
+    capabilities: u32,
+    flags: u8,
+    reserved: [u8; 3],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+union BufferLocation {
+    offset: u32,
+    user_pointer: c_ulong,
+    fd: i32,
+}
+
+#[repr(C)]
+struct Buffer {
+    index: u32,
+    buffer_type: u32,
+    bytes_used: u32,
+    flags: u32,
+    field: u32,
+    timestamp: libc::timeval,
+    timecode: [u32; 4],
+    sequence: u32,
+    memory: u32,
+    location: BufferLocation,
+    length: u32,
+    reserved: u32,
+    request_fd: i32,
+}
+
+/**
+ * `_IOC` of asm-generic/ioctl.h.
+ */
+const fn ioctl_code(direction: c_ulong, number: c_ulong, size: usize) -> c_ulong {
+    (direction << 30) | ((size as c_ulong) << 16) | ((b'V' as c_ulong) << 8) | number
+}
+
+const WRITE: c_ulong = 1;
+const READ: c_ulong = 2;
+
+const VIDIOC_QUERYCAP: c_ulong = ioctl_code(READ, 0, mem::size_of::<Capability>());
+const VIDIOC_S_FMT: c_ulong = ioctl_code(READ | WRITE, 5, mem::size_of::<Format>());
+const VIDIOC_REQBUFS: c_ulong = ioctl_code(READ | WRITE, 8, mem::size_of::<RequestBuffers>());
+const VIDIOC_QUERYBUF: c_ulong = ioctl_code(READ | WRITE, 9, mem::size_of::<Buffer>());
+const VIDIOC_QBUF: c_ulong = ioctl_code(READ | WRITE, 15, mem::size_of::<Buffer>());
+const VIDIOC_DQBUF: c_ulong = ioctl_code(READ | WRITE, 17, mem::size_of::<Buffer>());
+const VIDIOC_STREAMON: c_ulong = ioctl_code(WRITE, 18, mem::size_of::<c_int>());
+const VIDIOC_STREAMOFF: c_ulong = ioctl_code(WRITE, 19, mem::size_of::<c_int>());
+
+struct MappedBuffer {
+    start: *mut c_void,
+    length: usize,
+}
+
+/**
+ * A Video4Linux capture device, streaming through buffers mapped in memory.
+ */
+pub struct V4l2Camera {
+    file: File,
+    buffers: Vec<MappedBuffer>,
+    width: u32,
+    height: u32,
+    bytes_per_line: u32,
+    pixel_format: u32,
+}
+
+impl V4l2Camera {
+    /**
+     * The first of `/dev/video*` able to capture video.
+     */
+    pub fn first() -> Result<V4l2Camera> {
+        let mut devices = fs::read_dir("/dev")?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with("video")))
+            .collect::<Vec<PathBuf>>();
+
+        devices.sort();
+
+        for device in devices {
+            match V4l2Camera::open(&device) {
+                Ok(camera) => return Ok(camera),
+                Err(e) => debug!("{} is not a usable camera: {:?}", device.display(), e),
+            }
+        }
+
+        Err(RepositoryError::CameraError("No camera found".to_owned()))
+    }
+
+    pub fn open(device: &Path) -> Result<V4l2Camera> {
+        let file = OpenOptions::new().read(true).write(true).custom_flags(libc::O_NONBLOCK).open(device)?;
+
+        let mut capability: Capability = unsafe { mem::zeroed() };
+        Self::ioctl(&file, VIDIOC_QUERYCAP, &mut capability)?;
+
+        let capabilities = if capability.capabilities & CAP_DEVICE_CAPS != 0 {
+            capability.device_caps
+        } else {
+            capability.capabilities
+        };
+
+        if capabilities & CAP_VIDEO_CAPTURE == 0 || capabilities & CAP_STREAMING == 0 {
+            return Err(RepositoryError::CameraError("No camera found".to_owned()));
+        }
+
+        let mut format: Format = unsafe { mem::zeroed() };
+        format.buffer_type = VIDEO_CAPTURE;
+        format.pix.width = 640;
+        format.pix.height = 480;
+        format.pix.pixel_format = PIX_FMT_YUYV;
+        format.pix.field = FIELD_NONE;
+
+        // the driver changes the format to the closest one it supports
+        Self::ioctl(&file, VIDIOC_S_FMT, &mut format)?;
+
+        if ![PIX_FMT_GREY, PIX_FMT_YUYV, PIX_FMT_MJPEG].contains(&format.pix.pixel_format) {
+            warn!("Unsupported pixel format {:?}", format.pix.pixel_format.to_le_bytes());
+            return Err(RepositoryError::CameraError("The camera could not be opened".to_owned()));
+        }
+
+        info!(
+            "Capturing {}x{} {} frames from {}",
+            format.pix.width,
+            format.pix.height,
+            String::from_utf8_lossy(&format.pix.pixel_format.to_le_bytes()),
+            device.display()
+        );
+
+        let mut camera = V4l2Camera {
+            file,
+            buffers: vec![],
+            width: format.pix.width,
+            height: format.pix.height,
+            bytes_per_line: format.pix.bytes_per_line,
+            pixel_format: format.pix.pixel_format,
+        };
+
+        camera.start()?;
+        Ok(camera)
+    }
+
+    fn start(&mut self) -> Result<()> {
+        let mut request = RequestBuffers {
+            count: BUFFERS,
+            buffer_type: VIDEO_CAPTURE,
+            memory: MEMORY_MMAP,
+            capabilities: 0,
+            flags: 0,
+            reserved: [0; 3],
+        };
+
+        Self::ioctl(&self.file, VIDIOC_REQBUFS, &mut request)?;
+
+        for index in 0..request.count {
+            let mut buffer = Self::buffer(index);
+            Self::ioctl(&self.file, VIDIOC_QUERYBUF, &mut buffer)?;
+
+            let length = buffer.length as usize;
+            let offset = unsafe { buffer.location.offset } as libc::off_t;
+
+            let start = unsafe {
+                libc::mmap(
+                    ptr::null_mut(),
+                    length,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_SHARED,
+                    self.file.as_raw_fd(),
+                    offset,
+                )
+            };
+
+            if start == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error().into());
+            }
+
+            self.buffers.push(MappedBuffer { start, length });
+            Self::ioctl(&self.file, VIDIOC_QBUF, &mut buffer)?;
+        }
+
+        let mut buffer_type = VIDEO_CAPTURE as c_int;
+        Self::ioctl(&self.file, VIDIOC_STREAMON, &mut buffer_type)
+    }
+
+    fn buffer(index: u32) -> Buffer {
+        let mut buffer: Buffer = unsafe { mem::zeroed() };
+        buffer.index = index;
+        buffer.buffer_type = VIDEO_CAPTURE;
+        buffer.memory = MEMORY_MMAP;
+        buffer
+    }
+
+    fn ioctl<T>(file: &File, request: c_ulong, argument: &mut T) -> Result<()> {
+        loop {
+            let result = unsafe { libc::ioctl(file.as_raw_fd(), request as _, argument as *mut T) };
+
+            if result >= 0 {
+                return Ok(());
+            }
+
+            match io::Error::last_os_error() {
+                e if e.kind() == io::ErrorKind::Interrupted => continue,
+                e => return Err(e.into()),
+            }
+        }
+    }
+
+    fn wait_for_frame(&self) -> Result<()> {
+        let mut poll_fd = libc::pollfd {
+            fd: self.file.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        match unsafe { libc::poll(&mut poll_fd, 1, FRAME_TIMEOUT_MS) } {
+            0 => Err(RepositoryError::CameraError("The camera stopped sending frames".to_owned())),
+            result if result < 0 => Err(io::Error::last_os_error().into()),
+            _ => Ok(()),
+        }
+    }
+
+    /**
+     * Grey levels of a captured frame: its luma bytes for YUYV, decoded for MJPEG.
+     */
+    fn grey(&self, data: &[u8]) -> Option<GrayImage> {
+        let (width, height) = (self.width as usize, self.height as usize);
+
+        match self.pixel_format {
+            PIX_FMT_MJPEG => image::load_from_memory_with_format(data, ImageFormat::Jpeg).ok().map(|image| image.to_luma8()),
+            pixel_format => {
+                let bytes_per_pixel = if pixel_format == PIX_FMT_YUYV { 2 } else { 1 };
+                let stride = (self.bytes_per_line as usize).max(width * bytes_per_pixel);
+
+                let pixels = (0..height)
+                    .flat_map(|y| (0..width).map(move |x| y * stride + x * bytes_per_pixel))
+                    .map(|i| data.get(i).copied())
+                    .collect::<Option<Vec<u8>>>()?;
+
+                GrayImage::from_raw(self.width, self.height, pixels)
+            }
+        }
+    }
+}
+
+impl FrameSource for V4l2Camera {
+    fn next_frame(&mut self) -> Result<Option<GrayImage>> {
+        loop {
+            self.wait_for_frame()?;
+
+            let mut buffer = Self::buffer(0);
+
+            match Self::ioctl(&self.file, VIDIOC_DQBUF, &mut buffer) {
+                Err(RepositoryError::IoError(e)) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                result => result?,
+            }
+
+            let frame = self.buffers.get(buffer.index as usize).and_then(|mapped| {
+                let length = (buffer.bytes_used as usize).min(mapped.length);
+                let data = unsafe { std::slice::from_raw_parts(mapped.start as *const u8, length) };
+                self.grey(data)
+            });
+
+            Self::ioctl(&self.file, VIDIOC_QBUF, &mut buffer)?;
+
+            // frames cut short, e.g. corrupted JPEG ones, are skipped
+            if frame.is_some() {
+                return Ok(frame);
+            }
+        }
+    }
+}
+
+impl Drop for V4l2Camera {
+    fn drop(&mut self) {
+        let mut buffer_type = VIDEO_CAPTURE as c_int;
+
+        if let Err(e) = Self::ioctl(&self.file, VIDIOC_STREAMOFF, &mut buffer_type) {
+            warn!("Could not stop camera: {:?}", e);
+        }
+
+        for buffer in &self.buffers {
+            unsafe { libc::munmap(buffer.start, buffer.length) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::atomic::AtomicBool;
+
+    use super::*;
+
+    #[test]
+    fn scan_recorded_frames() {
+        let path = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/frames"));
+
+        // a blank frame, then a QR code which is not a secret, before two accounts
+        let mut frames = RecordedFrames::open(path).unwrap();
+        let qr_code = Camera::scan(&mut frames, &AtomicBool::new(false)).unwrap().unwrap();
+
+        assert_eq!("otpauth://totp/Example:carol?secret=MFRGGZDFMZTWQ2LK&issuer=Example", qr_code.qr_code_payload);
+        assert_eq!(1, frames.paths.len());
+
+        let mut frames = RecordedFrames::open(&path.join("02.png")).unwrap();
+        assert_eq!(None, Camera::scan(&mut frames, &AtomicBool::new(false)).unwrap());
+
+        let mut frames = RecordedFrames::open(path).unwrap();
+        assert_eq!(None, Camera::scan(&mut frames, &AtomicBool::new(true)).unwrap());
+        assert_eq!(4, frames.paths.len());
+    }
+
+    #[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+    #[test]
+    fn ioctl_codes() {
+        assert_eq!(0x8068_5600, VIDIOC_QUERYCAP);
+        assert_eq!(0xc0d0_5605, VIDIOC_S_FMT);
+        assert_eq!(0xc014_5608, VIDIOC_REQBUFS);
+        assert_eq!(0xc058_5609, VIDIOC_QUERYBUF);
+        assert_eq!(0xc058_560f, VIDIOC_QBUF);
+        assert_eq!(0xc058_5611, VIDIOC_DQBUF);
+        assert_eq!(0x4004_5612, VIDIOC_STREAMON);
+        assert_eq!(0x4004_5613, VIDIOC_STREAMOFF);
+    }
+}
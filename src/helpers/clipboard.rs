@@ -0,0 +1,155 @@
+use gtk::gdk_pixbuf::Pixbuf;
+use image::{GrayImage, Luma};
+use log::warn;
+
+use crate::helpers::QrCode;
+
+const URI_SCHEMES: [&str; 3] = ["otpauth://", "otpauth-migration://", "motp://"];
+
+/**
+ * What the clipboard holds, as far as adding accounts goes.
+ */
+#[derive(Debug, PartialEq)]
+pub enum Pasted {
+    /// QR codes decoded from a copied image, or why none could be.
+    QrCodes(Result<Vec<QrCode>, String>),
+    /// An `otpauth://`, `otpauth-migration://` or `motp://` URI.
+    Uri(QrCode),
+    /// A bare base32 secret, upper-cased and without spaces or dashes.
+    Secret(String),
+    Nothing,
+}
+
+impl Pasted {
+    /**
+     * Reads the clipboard, images first. Images are decoded in memory, never written to disk.
+     */
+    pub async fn read() -> Pasted {
+        let clipboard = gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD);
+
+        let (tx, rx) = async_channel::bounded::<Option<GrayImage>>(1);
+        clipboard.request_image(move |_, pixbuf| {
+            if let Err(e) = tx.try_send(pixbuf.and_then(Self::to_luma)) {
+                warn!("Could not send clipboard image: {:?}", e);
+            }
+        });
+
+        if let Ok(Some(image)) = rx.recv().await {
+            return Pasted::QrCodes(QrCode::process_qr_image(image).await);
+        }
+
+        let (tx, rx) = async_channel::bounded::<Option<String>>(1);
+        clipboard.request_text(move |_, text| {
+            if let Err(e) = tx.try_send(text.map(str::to_owned)) {
+                warn!("Could not send clipboard text: {:?}", e);
+            }
+        });
+
+        match rx.recv().await {
+            Ok(Some(text)) => Pasted::from_text(text.as_str()),
+            _ => Pasted::Nothing,
+        }
+    }
+
+    pub fn from_text(text: &str) -> Pasted {
+        let text = text.trim();
+
+        if URI_SCHEMES
+            .iter()
+            .any(|scheme| text.get(..scheme.len()).is_some_and(|prefix| prefix.eq_ignore_ascii_case(scheme)))
+        {
+            return Pasted::Uri(QrCode::new(text.to_owned()));
+        }
+
+        let secret: String = text.chars().filter(|c| !c.is_whitespace() && *c != '-').collect::<String>().to_uppercase();
+        let unpadded = secret.trim_end_matches('=');
+
+        if !unpadded.is_empty() && unpadded.chars().all(|c| c.is_ascii_uppercase() || ('2'..='7').contains(&c)) {
+            Pasted::Secret(secret)
+        } else {
+            Pasted::Nothing
+        }
+    }
+
+    fn to_luma(pixbuf: &Pixbuf) -> Option<GrayImage> {
+        if pixbuf.bits_per_sample() != 8 || pixbuf.n_channels() < 3 {
+            warn!(
+                "Unsupported clipboard image: {} bits, {} channels",
+                pixbuf.bits_per_sample(),
+                pixbuf.n_channels()
+            );
+            return None;
+        }
+
+        let bytes = pixbuf.read_pixel_bytes();
+
+        Some(Self::luma(
+            &bytes,
+            pixbuf.width() as u32,
+            pixbuf.height() as u32,
+            pixbuf.rowstride() as usize,
+            pixbuf.n_channels() as usize,
+        ))
+    }
+
+    /**
+     * Converts RGB or RGBA rows to grey levels. Transparent pixels are laid over white,
+     * copied QR codes often being black modules on a transparent background.
+     */
+    fn luma(pixels: &[u8], width: u32, height: u32, rowstride: usize, n_channels: usize) -> GrayImage {
+        GrayImage::from_fn(width, height, |x, y| {
+            let i = y as usize * rowstride + x as usize * n_channels;
+            let pixel = &pixels[i..i + n_channels];
+
+            let grey = (299 * pixel[0] as u32 + 587 * pixel[1] as u32 + 114 * pixel[2] as u32) / 1000;
+            let alpha = if n_channels > 3 { pixel[3] as u32 } else { 255 };
+
+            Luma([((grey * alpha + 255 * (255 - alpha)) / 255) as u8])
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pasted;
+    use crate::helpers::QrCode;
+
+    #[test]
+    fn from_text() {
+        let uri = "otpauth://totp/Example:alice?secret=JBSWY3DPEHPK3PXP&issuer=Example";
+        assert_eq!(Pasted::Uri(QrCode::new(uri.to_owned())), Pasted::from_text(format!("  {}\n", uri).as_str()));
+
+        let export = "OTPAUTH-MIGRATION://offline?data=CiIKCkhlbGxvId6tvu8SBWFsaWNlGgdFeGFtcGxlIAEoATACEAE%3D";
+        assert_eq!(Pasted::Uri(QrCode::new(export.to_owned())), Pasted::from_text(export));
+
+        assert_eq!(Pasted::Secret("JBSWY3DPEHPK3PXP".to_owned()), Pasted::from_text("jbsw y3dp-ehpk 3pxp"));
+        assert_eq!(Pasted::Secret("GEZDGNBV====".to_owned()), Pasted::from_text("GEZDGNBV===="));
+
+        assert_eq!(Pasted::Nothing, Pasted::from_text(""));
+        assert_eq!(Pasted::Nothing, Pasted::from_text("===="));
+        assert_eq!(Pasted::Nothing, Pasted::from_text("https://example.com/?secret=JBSWY3DPEHPK3PXP"));
+        assert_eq!(Pasted::Nothing, Pasted::from_text("JBSWY3DPEHPK3PX1"));
+    }
+
+    #[test]
+    fn luma() {
+        // two RGBA pixels per row, rows padded to 12 bytes
+        let pixels = [
+            0, 0, 0, 255, 255, 255, 255, 255, 9, 9, 9, 9, //
+            0, 0, 0, 0, 255, 0, 0, 255, 9, 9, 9, 9,
+        ];
+
+        let image = Pasted::luma(&pixels, 2, 2, 12, 4);
+
+        assert_eq!(0, image.get_pixel(0, 0)[0]);
+        assert_eq!(255, image.get_pixel(1, 0)[0]);
+        assert_eq!(255, image.get_pixel(0, 1)[0]);
+        assert_eq!(76, image.get_pixel(1, 1)[0]);
+
+        let pixels = [10, 10, 10, 200, 200, 200];
+        let image = Pasted::luma(&pixels, 2, 1, 6, 3);
+
+        assert_eq!(10, image.get_pixel(0, 0)[0]);
+        assert_eq!(200, image.get_pixel(1, 0)[0]);
+    }
+}
@@ -0,0 +1,319 @@
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{Duration, SystemTime, SystemTimeError, UNIX_EPOCH};
+
+use chrono::DateTime;
+use curl::easy::Easy;
+use gio::prelude::SettingsExt;
+use log::debug;
+
+use crate::helpers::{Paths, RepositoryError};
+use crate::NAMESPACE;
+
+type Result<T> = ::std::result::Result<T, RepositoryError>;
+
+/**
+ * Skew, in seconds, from which the accounts list warns about the clock.
+ */
+pub const DEFAULT_DRIFT_THRESHOLD: i64 = 10;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// seconds between the NTP epoch (1900) and the unix one
+const NTP_EPOCH: f64 = 2_208_988_800.0;
+
+static OFFSET: AtomicI64 = AtomicI64::new(0);
+
+/**
+ * Something telling the actual time, to compare the local clock against.
+ */
+pub trait TimeSource {
+    /**
+     * Seconds since the epoch.
+     */
+    fn time(&self) -> Result<f64>;
+}
+
+/**
+ * The `Date` header of the response to a HEAD request. Any web server does.
+ */
+pub struct HttpDate {
+    pub url: String,
+}
+
+/**
+ * A (S)NTP server, e.g. `ntp://pool.ntp.org`.
+ */
+pub struct Ntp {
+    pub address: String,
+}
+
+/**
+ * Time codes are generated from: the system clock, corrected by the offset set in the settings.
+ */
+pub struct Clock;
+
+impl Clock {
+    /**
+     * Reads the offset from the settings. Until then, the system clock is used as is.
+     */
+    pub fn load() {
+        let offset = Paths::settings().map(|settings| settings.int("time-offset")).unwrap_or(0);
+        OFFSET.store(offset as i64, Ordering::Relaxed);
+    }
+
+    pub fn offset() -> i64 {
+        OFFSET.load(Ordering::Relaxed)
+    }
+
+    pub fn set_offset(offset: i64) -> Result<()> {
+        gio::Settings::new(NAMESPACE)
+            .set_int("time-offset", offset as i32)
+            .map_err(|e| RepositoryError::ClockError(format!("{:?}", e)))?;
+
+        OFFSET.store(offset, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /**
+     * Seconds since the epoch, offset included.
+     */
+    pub fn now() -> ::std::result::Result<u64, SystemTimeError> {
+        let system = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        Ok((system + Self::offset()).max(0) as u64)
+    }
+
+    /**
+     * Time source configured in the settings, if any.
+     */
+    pub fn configured_source() -> Option<String> {
+        Paths::settings()
+            .map(|settings| settings.string("time-source").to_string())
+            .filter(|source| !source.is_empty())
+    }
+
+    pub fn set_configured_source(source: &str) -> Result<()> {
+        gio::Settings::new(NAMESPACE)
+            .set_string("time-source", source)
+            .map_err(|e| RepositoryError::ClockError(format!("{:?}", e)))
+    }
+
+    pub fn drift_threshold() -> i64 {
+        Paths::settings()
+            .map(|settings| settings.int("drift-threshold") as i64)
+            .unwrap_or(DEFAULT_DRIFT_THRESHOLD)
+    }
+
+    /**
+     * `ntp://host[:port]` for an NTP server, or the URL of any web server.
+     */
+    pub fn source(source: &str) -> Result<Box<dyn TimeSource + Send>> {
+        let source = source.trim();
+
+        if let Some(address) = source.strip_prefix("ntp://") {
+            let address = address.trim_end_matches('/');
+
+            if address.is_empty() {
+                return Err(RepositoryError::ClockError("time_source_invalid".to_owned()));
+            }
+
+            let address = if address.contains(':') {
+                address.to_owned()
+            } else {
+                format!("{}:123", address)
+            };
+            Ok(Box::new(Ntp { address }))
+        } else if source.starts_with("http://") || source.starts_with("https://") {
+            Ok(Box::new(HttpDate { url: source.to_owned() }))
+        } else {
+            Err(RepositoryError::ClockError("time_source_invalid".to_owned()))
+        }
+    }
+
+    /**
+     * Seconds the system clock is behind the source: the offset which would set it right.
+     */
+    pub fn drift(source: &dyn TimeSource) -> Result<i64> {
+        let before = Self::system()?;
+        let time = source.time()?;
+        let after = Self::system()?;
+
+        Ok((time - (before + after) / 2.0).round() as i64)
+    }
+
+    /**
+     * Skew left once the offset is applied, if above the threshold.
+     */
+    pub fn skew(drift: i64, offset: i64, threshold: i64) -> Option<i64> {
+        let skew = drift - offset;
+        (skew.abs() > threshold).then_some(skew)
+    }
+
+    fn system() -> Result<f64> {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs_f64())
+            .map_err(|e| RepositoryError::ClockError(format!("{:?}", e)))
+    }
+}
+
+impl TimeSource for HttpDate {
+    fn time(&self) -> Result<f64> {
+        debug!("HEAD {}", self.url);
+
+        let mut date = None;
+        let mut handle = Easy::new();
+
+        handle.url(self.url.as_str())?;
+        handle.nobody(true)?;
+        handle.timeout(TIMEOUT)?;
+
+        {
+            let mut transfer = handle.transfer();
+            transfer.header_function(|header| {
+                let header = String::from_utf8_lossy(header);
+
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.trim().eq_ignore_ascii_case("date") {
+                        date = Some(value.trim().to_owned());
+                    }
+                }
+
+                true
+            })?;
+            transfer.perform()?;
+        }
+
+        let date = date.ok_or_else(|| RepositoryError::ClockError("time_source_no_date".to_owned()))?;
+
+        DateTime::parse_from_rfc2822(date.as_str())
+            .map(|date| date.timestamp() as f64)
+            .map_err(|_| RepositoryError::ClockError("time_source_no_date".to_owned()))
+    }
+}
+
+impl TimeSource for Ntp {
+    fn time(&self) -> Result<f64> {
+        debug!("NTP {}", self.address);
+
+        let address = self
+            .address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| RepositoryError::ClockError("time_source_invalid".to_owned()))?;
+
+        let socket = UdpSocket::bind(if address.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })?;
+        socket.set_read_timeout(Some(TIMEOUT))?;
+        socket.connect(address)?;
+
+        // version 3, client mode
+        let mut packet = [0u8; 48];
+        packet[0] = 0x1b;
+        socket.send(&packet)?;
+
+        let received = socket.recv(&mut packet)?;
+
+        if received < 48 {
+            return Err(RepositoryError::ClockError("time_source_invalid_response".to_owned()));
+        }
+
+        // transmit timestamp: 32 bits of seconds then 32 bits of fraction
+        let seconds = u32::from_be_bytes([packet[40], packet[41], packet[42], packet[43]]) as f64;
+        let fraction = u32::from_be_bytes([packet[44], packet[45], packet[46], packet[47]]) as f64 / 4_294_967_296.0;
+
+        if seconds == 0.0 {
+            return Err(RepositoryError::ClockError("time_source_invalid_response".to_owned()));
+        }
+
+        Ok(seconds + fraction - NTP_EPOCH)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, UdpSocket};
+    use std::thread;
+
+    use super::*;
+
+    struct Fixed(f64);
+
+    impl TimeSource for Fixed {
+        fn time(&self) -> Result<f64> {
+            Ok(self.0)
+        }
+    }
+
+    #[test]
+    fn source() {
+        assert!(Clock::source("ntp://pool.ntp.org").is_ok());
+        assert!(Clock::source("https://example.com").is_ok());
+        assert!(Clock::source(" http://127.0.0.1:8080/ ").is_ok());
+
+        assert!(matches!(Clock::source("ntp://"), Err(RepositoryError::ClockError(_))));
+        assert!(matches!(Clock::source("pool.ntp.org"), Err(RepositoryError::ClockError(_))));
+        assert!(matches!(Clock::source(""), Err(RepositoryError::ClockError(_))));
+    }
+
+    #[test]
+    fn drift() {
+        let now = Clock::system().unwrap();
+
+        assert_eq!(0, Clock::drift(&Fixed(now)).unwrap());
+        assert_eq!(120, Clock::drift(&Fixed(now + 120.0)).unwrap());
+        assert_eq!(-45, Clock::drift(&Fixed(now - 45.0)).unwrap());
+    }
+
+    #[test]
+    fn skew() {
+        assert_eq!(None, Clock::skew(5, 0, 10));
+        assert_eq!(Some(120), Clock::skew(120, 0, 10));
+        assert_eq!(Some(-30), Clock::skew(-30, 0, 10));
+        // already corrected
+        assert_eq!(None, Clock::skew(120, 118, 10));
+        assert_eq!(Some(-120), Clock::skew(0, 120, 10));
+    }
+
+    #[test]
+    fn http_date() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+
+            let mut request = String::new();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            while reader.read_line(&mut request).unwrap() > 2 {
+                request.clear();
+            }
+
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nDate: Sun, 06 Nov 1994 08:49:37 GMT\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+        });
+
+        assert_eq!(784_111_777.0, HttpDate { url }.time().unwrap());
+    }
+
+    #[test]
+    fn ntp() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap().to_string();
+
+        thread::spawn(move || {
+            let mut packet = [0u8; 48];
+            let (_, client) = server.recv_from(&mut packet).unwrap();
+
+            // 1994-11-06T08:49:37.5Z
+            packet[0] = 0x1c;
+            packet[40..44].copy_from_slice(&((784_111_777.0 + NTP_EPOCH) as u32).to_be_bytes());
+            packet[44..48].copy_from_slice(&0x8000_0000u32.to_be_bytes());
+
+            server.send_to(&packet, client).unwrap();
+        });
+
+        assert_eq!(784_111_777.5, Ntp { address }.time().unwrap());
+    }
+}
@@ -2,31 +2,50 @@ use log::{debug, warn};
 use rusqlite::types::ToSqlOutput;
 use rusqlite::{named_params, params, Connection, OpenFlags, OptionalExtension, Params, Row, Statement, ToSql};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::fmt::Debug;
 use std::str::FromStr;
 use std::string::ToString;
+use std::sync::{Arc, Mutex, MutexGuard};
 use strum_macros::Display;
 use strum_macros::EnumString;
+use uuid::Uuid;
 
 use crate::helpers::repository_error::RepositoryError;
 use crate::helpers::Paths;
-use crate::helpers::SecretType::{KEYRING, LOCAL};
-use crate::model::{Account, AccountGroup};
+use crate::helpers::SecretType::LOCAL;
+use crate::model::{Account, AccountGroup, AccountType, TotpAlgorithm};
 
 #[derive(Debug, Clone)]
 pub struct Database;
 
-#[derive(Debug, Eq, PartialEq, EnumString, Serialize, Deserialize, Clone, Display, Default)]
+/**
+ * Where the secret of an account lives: LOCAL is the legacy plaintext column of the accounts table,
+ * KEYRING the Secret Service, VAULT the encrypted vault file and KEYUTILS the kernel keyring.
+ */
+#[derive(Debug, Eq, PartialEq, Hash, EnumString, Serialize, Deserialize, Clone, Display, Default)]
 #[allow(clippy::upper_case_acronyms)]
 pub enum SecretType {
     LOCAL,
     #[default]
     KEYRING,
+    VAULT,
+    KEYUTILS,
 }
 
 type Result<T> = core::result::Result<T, RepositoryError>;
 
 impl Database {
+    /**
+     * Locks the shared connection, recovering it if a previous holder panicked.
+     */
+    pub fn lock(connection: &Arc<Mutex<Connection>>) -> MutexGuard<'_, Connection> {
+        connection.lock().unwrap_or_else(|poisoned| {
+            warn!("Database connection mutex was poisoned. Recovering.");
+            poisoned.into_inner()
+        })
+    }
+
     pub fn has_groups(connection: &Connection) -> Result<bool> {
         let mut stmt = connection.prepare("SELECT COUNT(*) FROM groups")?;
 
@@ -39,18 +58,13 @@ impl Database {
     }
 
     pub fn load_account_groups(connection: &Connection, filter: Option<&str>) -> Result<Vec<AccountGroup>> {
-        let mut stmt = connection.prepare("SELECT id, name, icon, url, collapsed FROM groups ORDER BY LOWER(name)")?;
+        let mut stmt = connection.prepare("SELECT id, name, icon, url, collapsed, uuid FROM groups ORDER BY LOWER(name)")?;
 
         let row_iter = stmt.query_map(params![], |row| {
             let id = row.get_unwrap(0);
-            let name: String = row.get_unwrap(1);
-            let icon: Option<String> = row.get(2).optional().unwrap_or(None);
-            let url: Option<String> = row.get(3).optional().unwrap_or(None);
-            let collapsed: bool = row.get_unwrap(4);
-
             let entries = Self::get_accounts(connection, id, filter).map_err(|_| rusqlite::Error::InvalidQuery)?;
 
-            Ok(AccountGroup::new(id, name.as_str(), icon.as_deref(), url.as_deref(), collapsed, entries))
+            Self::extract_group(row, entries)
         })?;
 
         let account_groups = row_iter
@@ -79,9 +93,11 @@ impl Database {
     pub fn save_group(connection: &Connection, group: &mut AccountGroup) -> Result<()> {
         debug!("Adding group {}", group.name);
 
+        Self::ensure_uuid(&mut group.uuid);
+
         connection.execute(
-            "INSERT INTO groups (name, icon, url, collapsed) VALUES (?1, ?2, ?3, ?4)",
-            params![group.name, group.icon, group.url, group.collapsed],
+            "INSERT INTO groups (name, icon, url, collapsed, uuid) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![group.name, group.icon, group.url, group.collapsed, group.uuid],
         )?;
 
         let mut stmt = connection.prepare("SELECT last_insert_rowid()")?;
@@ -93,34 +109,42 @@ impl Database {
             .map_err(RepositoryError::SqlError)
     }
 
-    fn group_by_name(connection: &Connection, name: &str) -> Result<Option<AccountGroup>> {
-        let mut stmt = connection.prepare("SELECT id, name, icon, url, collapsed FROM groups WHERE name = :name")?;
-
-        stmt.query_row(named_params! {":name": name}, |row| {
-            let group_id = row.get_unwrap(0);
-            let group_name: String = row.get_unwrap(1);
-            let group_icon: Option<String> = row.get(2).optional().unwrap_or(None);
-            let group_url: Option<String> = row.get(3).optional().unwrap_or(None);
-            let collapsed: bool = row.get_unwrap(4);
-
-            Ok(AccountGroup::new(
-                group_id,
-                group_name.as_str(),
-                group_icon.as_deref(),
-                group_url.as_deref(),
-                collapsed,
-                vec![],
-            ))
-        })
-        .optional()
-        .map_err(RepositoryError::SqlError)
+    /**
+     * Group with the given uuid or, failing that, the given name.
+     */
+    fn group_by_uuid_or_name(connection: &Connection, uuid: &str, name: &str) -> Result<Option<AccountGroup>> {
+        let mut stmt =
+            connection.prepare("SELECT id, name, icon, url, collapsed, uuid FROM groups WHERE uuid = :uuid OR name = :name ORDER BY uuid = :uuid DESC")?;
+
+        stmt.query_row(named_params! {":uuid": uuid, ":name": name}, |row| Self::extract_group(row, vec![]))
+            .optional()
+            .map_err(RepositoryError::SqlError)
     }
 
+    fn extract_group(row: &Row, entries: Vec<Account>) -> rusqlite::Result<AccountGroup> {
+        let id = row.get_unwrap(0);
+        let name: String = row.get_unwrap(1);
+        let icon: Option<String> = row.get(2).optional().unwrap_or(None);
+        let url: Option<String> = row.get(3).optional().unwrap_or(None);
+        let collapsed: bool = row.get_unwrap(4);
+
+        let mut group = AccountGroup::new(id, name.as_str(), icon.as_deref(), url.as_deref(), collapsed, entries);
+        group.uuid = row.get(5)?;
+        Ok(group)
+    }
+
+    /**
+     * Groups are matched by uuid first, then by name, so that restoring a backup lands in the
+     * groups it was taken from even if they were renamed since.
+     */
     pub fn save_group_and_accounts(connection: &Connection, group: &mut AccountGroup) -> Result<()> {
-        let existing_group = Self::group_by_name(connection, group.name.as_str())?;
+        let existing_group = Self::group_by_uuid_or_name(connection, group.uuid.as_str(), group.name.as_str())?;
 
         let group_saved_result = match existing_group {
-            Some(group) => Ok(group.id),
+            Some(existing_group) => {
+                group.uuid = existing_group.uuid;
+                Ok(existing_group.id)
+            }
             None => Self::save_group(connection, group).map(|_| group.id),
         };
 
@@ -172,19 +196,13 @@ impl Database {
     }
 
     pub fn get_group(connection: &Connection, group_id: u32) -> Result<AccountGroup> {
-        let mut stmt = connection.prepare("SELECT id, name, icon, url, collapsed FROM groups WHERE id = :group_id")?;
+        let mut stmt = connection.prepare("SELECT id, name, icon, url, collapsed, uuid FROM groups WHERE id = :group_id")?;
 
         stmt.query_row(
             named_params! {
             ":group_id": group_id
             },
             |row| {
-                let group_id: u32 = row.get_unwrap(0);
-                let group_name: String = row.get_unwrap(1);
-                let group_icon: Option<String> = row.get(2).optional().unwrap_or(None);
-                let group_url: Option<String> = row.get(3).optional().unwrap_or(None);
-                let collapsed: bool = row.get_unwrap(4);
-
                 let accounts = match Self::get_accounts(connection, group_id, None) {
                     Ok(v) => v,
                     Err(e) => {
@@ -193,17 +211,41 @@ impl Database {
                     }
                 };
 
-                row.get(0)
-                    .map(|id| AccountGroup::new(id, group_name.as_str(), group_icon.as_deref(), group_url.as_deref(), collapsed, accounts))
+                Self::extract_group(row, accounts)
             },
         )
         .map_err(RepositoryError::SqlError)
     }
 
+    /**
+     * Group with the given uuid, without its accounts.
+     */
+    pub fn get_group_by_uuid(connection: &Connection, uuid: &str) -> Result<Option<AccountGroup>> {
+        let mut stmt = connection.prepare("SELECT id, name, icon, url, collapsed, uuid FROM groups WHERE uuid = ?1")?;
+
+        stmt.query_row(params![uuid], |row| Self::extract_group(row, vec![]))
+            .optional()
+            .map_err(RepositoryError::SqlError)
+    }
+
+    /**
+     * Accounts are matched by uuid first, then by label within the group. A matched account keeps its uuid.
+     */
     pub fn upsert_account(connection: &Connection, account: &mut Account) -> Result<u32> {
-        match Self::get_account_by_label_and_group(connection, account.label.as_str(), account.group_id)? {
+        let existing = match Self::get_account_by_uuid(connection, account.uuid.as_str())? {
+            Some(a) => Some(a),
+            None => Self::get_account_by_label_and_group(connection, account.label.as_str(), account.group_id)?,
+        };
+
+        // icons are files of this device: one from elsewhere is only kept if this device has it too
+        if account.icon.as_ref().is_some_and(|icon| !Paths::icons_path(icon).is_file()) {
+            account.icon = existing.as_ref().and_then(|a| a.icon.clone());
+        }
+
+        match existing {
             Some(a) => {
                 account.id = a.id;
+                account.uuid = a.uuid;
                 account.secret_type = LOCAL; // so that keyring get updated too
                 Self::update_account(connection, account)
             }
@@ -212,18 +254,52 @@ impl Database {
     }
 
     pub fn get_account_by_label_and_group(connection: &Connection, name: &str, group_id: u32) -> Result<Option<Account>> {
-        let stmt = connection.prepare("SELECT id, group_id, label, secret, secret_type FROM accounts WHERE label = ?1 AND group_id = ?2")?;
+        let stmt =
+            connection.prepare("SELECT id, group_id, label, secret, secret_type, uuid, account_type, pin, algorithm, digits, period, counter, icon, url, username FROM accounts WHERE label = ?1 AND group_id = ?2")?;
         Self::_get_account(stmt, params![name, group_id])
     }
 
+    pub fn get_account_by_uuid(connection: &Connection, uuid: &str) -> Result<Option<Account>> {
+        let stmt = connection.prepare(
+            "SELECT id, group_id, label, secret, secret_type, uuid, account_type, pin, algorithm, digits, period, counter, icon, url, username FROM accounts WHERE uuid = ?1",
+        )?;
+        Self::_get_account(stmt, params![uuid])
+    }
+
+    /**
+     * Gives a uuid to accounts and groups which come without one, e.g. from backups predating uuids.
+     */
+    fn ensure_uuid(uuid: &mut String) {
+        if uuid.is_empty() {
+            *uuid = Uuid::new_v4().to_string();
+        }
+    }
+
     pub fn save_account(connection: &Connection, account: &mut Account) -> Result<u32> {
         debug!("Adding account {}", account.label);
-        let secret = if account.secret_type == KEYRING { "" } else { account.secret.as_str() };
+        let secret = if account.secret_type == LOCAL { account.secret.as_str() } else { "" };
+
+        Self::ensure_uuid(&mut account.uuid);
 
         connection
             .execute(
-                "INSERT INTO accounts (label, group_id, secret, secret_type) VALUES (?1, ?2, ?3, ?4)",
-                params![account.label, account.group_id, secret, account.secret_type],
+                "INSERT INTO accounts (label, group_id, secret, secret_type, uuid, account_type, pin, algorithm, digits, period, counter, icon, url, username) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+                params![
+                    account.label,
+                    account.group_id,
+                    secret,
+                    account.secret_type,
+                    account.uuid,
+                    account.account_type,
+                    account.pin,
+                    account.algorithm,
+                    account.digits,
+                    account.period,
+                    account.counter,
+                    account.icon,
+                    account.url,
+                    account.username
+                ],
             )
             .map_err(RepositoryError::SqlError)?;
 
@@ -237,19 +313,45 @@ impl Database {
 
     pub fn update_account(connection: &Connection, account: &mut Account) -> Result<u32> {
         debug!("Updating account [{}:{}]", account.label, account.id);
-        let secret = if account.secret_type == KEYRING { "" } else { account.secret.as_str() };
+        let secret = if account.secret_type == LOCAL { account.secret.as_str() } else { "" };
 
         connection
             .execute(
-                "UPDATE accounts SET label = ?2, secret = ?3, group_id = ?4, secret_type = ?5 WHERE id = ?1",
-                params![account.id, account.label, secret, account.group_id, account.secret_type],
+                "UPDATE accounts SET label = ?2, secret = ?3, group_id = ?4, secret_type = ?5, account_type = ?6, pin = ?7, algorithm = ?8, digits = ?9, period = ?10, counter = ?11, icon = ?12, url = ?13, username = ?14 WHERE id = ?1",
+                params![
+                    account.id,
+                    account.label,
+                    secret,
+                    account.group_id,
+                    account.secret_type,
+                    account.account_type,
+                    account.pin,
+                    account.algorithm,
+                    account.digits,
+                    account.period,
+                    account.counter,
+                    account.icon,
+                    account.url,
+                    account.username
+                ],
             )
             .map(|_| account.id)
             .map_err(RepositoryError::SqlError)
     }
 
+    /**
+     * Moves HOTP accounts on to their next code, once the current one is used.
+     */
+    pub fn increment_counter(connection: &Connection, account_id: u32) -> Result<usize> {
+        let mut stmt = connection.prepare("UPDATE accounts SET counter = counter + 1 WHERE id = ?1")?;
+
+        stmt.execute(params![account_id]).map_err(RepositoryError::SqlError)
+    }
+
     pub fn get_account(connection: &Connection, account_id: u32) -> Result<Option<Account>> {
-        let stmt = connection.prepare("SELECT id, group_id, label, secret, secret_type FROM accounts WHERE id = ?1")?;
+        let stmt = connection.prepare(
+            "SELECT id, group_id, label, secret, secret_type, uuid, account_type, pin, algorithm, digits, period, counter, icon, url, username FROM accounts WHERE id = ?1",
+        )?;
         Self::_get_account(stmt, params![account_id])
     }
 
@@ -263,7 +365,17 @@ impl Database {
 
                 let secret_type = Self::extract_secret_type(row, 4);
 
-                let account = Account::new(id, group_id, label.as_str(), secret.as_str(), secret_type?);
+                let mut account = Account::new(id, group_id, label.as_str(), secret.as_str(), secret_type?);
+                account.uuid = row.get(5)?;
+                account.account_type = Self::extract_account_type(row, 6)?;
+                account.pin = row.get(7)?;
+                account.algorithm = Self::extract_algorithm(row, 8)?;
+                account.digits = row.get(9)?;
+                account.period = row.get(10)?;
+                account.counter = row.get(11)?;
+                account.icon = row.get(12)?;
+                account.url = row.get(13)?;
+                account.username = row.get(14)?;
 
                 Ok(account)
             })
@@ -284,6 +396,52 @@ impl Database {
         }
     }
 
+    fn extract_account_type(row: &Row, idx: usize) -> rusqlite::Result<AccountType> {
+        let v: String = row.get(idx)?;
+
+        Ok(AccountType::from_str(v.as_str()).unwrap_or_else(|_| {
+            warn!("Invalid account type [{}]", v);
+            AccountType::Totp
+        }))
+    }
+
+    fn extract_algorithm(row: &Row, idx: usize) -> rusqlite::Result<TotpAlgorithm> {
+        let v: String = row.get(idx)?;
+
+        Ok(TotpAlgorithm::from_str(v.as_str()).unwrap_or_else(|_| {
+            warn!("Invalid algorithm [{}]", v);
+            TotpAlgorithm::Sha1
+        }))
+    }
+
+    pub fn secret_types(connection: &Connection) -> Result<Vec<SecretType>> {
+        let mut stmt = connection.prepare("SELECT DISTINCT secret_type FROM accounts")?;
+
+        let results = stmt
+            .query_map([], |row| Self::extract_secret_type(row, 0))?
+            .collect::<rusqlite::Result<Vec<SecretType>>>();
+
+        results.map_err(RepositoryError::SqlError)
+    }
+
+    /**
+     * Icon filenames groups and accounts refer to.
+     */
+    pub fn icons(connection: &Connection) -> Result<HashSet<String>> {
+        let mut stmt = connection.prepare("SELECT icon FROM groups WHERE icon IS NOT NULL UNION SELECT icon FROM accounts WHERE icon IS NOT NULL")?;
+
+        let results = stmt.query_map([], |row| row.get(0))?.collect::<rusqlite::Result<HashSet<String>>>();
+
+        results.map_err(RepositoryError::SqlError)
+    }
+
+    pub fn clear_icons(connection: &Connection) -> Result<usize> {
+        let groups = connection.execute("UPDATE groups SET icon = NULL WHERE icon IS NOT NULL", [])?;
+        let accounts = connection.execute("UPDATE accounts SET icon = NULL WHERE icon IS NOT NULL", [])?;
+
+        Ok(groups + accounts)
+    }
+
     pub fn delete_group(connection: &Connection, group_id: u32) -> Result<usize> {
         let mut stmt = connection.prepare("DELETE FROM groups WHERE id = ?1")?;
 
@@ -297,7 +455,9 @@ impl Database {
     }
 
     fn get_accounts(connection: &Connection, group_id: u32, filter: Option<&str>) -> Result<Vec<Account>> {
-        let mut stmt = connection.prepare("SELECT id, label, secret, secret_type FROM accounts WHERE group_id = ?1 AND label LIKE ?2 ORDER BY LOWER(label)")?;
+        let mut stmt = connection.prepare(
+            "SELECT id, label, secret, secret_type, uuid, account_type, pin, algorithm, digits, period, counter, icon, url, username FROM accounts WHERE group_id = ?1 AND label LIKE ?2 ORDER BY LOWER(label)",
+        )?;
 
         let label_filter = filter.map(|f| format!("%{}%", f)).unwrap_or_else(|| "%".to_owned());
 
@@ -310,7 +470,17 @@ impl Database {
 
                 let secret: String = row.get_unwrap(2);
 
-                let account = Account::new(id, group_id, label.as_str(), secret.as_str(), secret_type?);
+                let mut account = Account::new(id, group_id, label.as_str(), secret.as_str(), secret_type?);
+                account.uuid = row.get(4)?;
+                account.account_type = Self::extract_account_type(row, 5)?;
+                account.pin = row.get(6)?;
+                account.algorithm = Self::extract_algorithm(row, 7)?;
+                account.digits = row.get(8)?;
+                account.period = row.get(9)?;
+                account.counter = row.get(10)?;
+                account.icon = row.get(11)?;
+                account.url = row.get(12)?;
+                account.username = row.get(13)?;
                 Ok(account)
             })?
             .collect::<rusqlite::Result<Vec<Account>>>();
@@ -326,14 +496,28 @@ impl ToSql for SecretType {
     }
 }
 
+impl ToSql for AccountType {
+    #[inline]
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.to_string()))
+    }
+}
+
+impl ToSql for TotpAlgorithm {
+    #[inline]
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.to_string()))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use rusqlite::Connection;
     use std::sync::{Arc, Mutex};
 
     use crate::helpers::runner;
-    use crate::helpers::SecretType::LOCAL;
-    use crate::model::{Account, AccountGroup};
+    use crate::helpers::SecretType::{KEYUTILS, LOCAL, VAULT};
+    use crate::model::{Account, AccountGroup, AccountType, TotpAlgorithm};
 
     use super::Database;
 
@@ -405,6 +589,113 @@ mod tests {
         assert_eq!("new secret", account_reloaded.secret);
     }
 
+    #[test]
+    fn secrets_of_other_backends_stay_out_of_the_database() {
+        let connection = Connection::open_in_memory().unwrap();
+        let connection: Arc<Mutex<Connection>> = Arc::new(Mutex::new(connection));
+
+        runner::run(connection.clone()).unwrap();
+
+        let connection = connection.lock().expect("Failed to acquire database connection lock");
+
+        let mut group = AccountGroup::new(0, "group", None, None, false, vec![]);
+        Database::save_group(&connection, &mut group).unwrap();
+
+        let mut account = Account::new(0, group.id, "label", "secret", VAULT);
+        Database::save_account(&connection, &mut account).unwrap();
+        assert_eq!("", Database::get_account(&connection, account.id).unwrap().unwrap().secret);
+
+        account.secret_type = KEYUTILS;
+        Database::update_account(&connection, &mut account).unwrap();
+        assert_eq!("", Database::get_account(&connection, account.id).unwrap().unwrap().secret);
+    }
+
+    #[test]
+    fn account_type_and_pin() {
+        let connection = Connection::open_in_memory().unwrap();
+        let connection: Arc<Mutex<Connection>> = Arc::new(Mutex::new(connection));
+
+        runner::run(connection.clone()).unwrap();
+
+        let connection = connection.lock().expect("Failed to acquire database connection lock");
+
+        let mut group = AccountGroup::new(0, "group", None, None, false, vec![]);
+        Database::save_group(&connection, &mut group).unwrap();
+
+        let mut account = Account::new(0, group.id, "label", "secret", LOCAL);
+        Database::save_account(&connection, &mut account).unwrap();
+        assert_eq!(AccountType::Totp, Database::get_account(&connection, account.id).unwrap().unwrap().account_type);
+
+        account.account_type = AccountType::Motp;
+        account.pin = "1234".to_owned();
+        Database::update_account(&connection, &mut account).unwrap();
+
+        let saved = Database::get_account(&connection, account.id).unwrap().unwrap();
+        assert_eq!(AccountType::Motp, saved.account_type);
+        assert_eq!("1234", saved.pin);
+
+        let groups = Database::load_account_groups(&connection, None).unwrap();
+        assert_eq!(AccountType::Motp, groups[0].entries[0].account_type);
+        assert_eq!("1234", groups[0].entries[0].pin);
+    }
+
+    #[test]
+    fn totp_parameters() {
+        let connection = Connection::open_in_memory().unwrap();
+        let connection: Arc<Mutex<Connection>> = Arc::new(Mutex::new(connection));
+
+        runner::run(connection.clone()).unwrap();
+
+        let connection = connection.lock().expect("Failed to acquire database connection lock");
+
+        let mut group = AccountGroup::new(0, "group", None, None, false, vec![]);
+        Database::save_group(&connection, &mut group).unwrap();
+
+        let mut account = Account::new(0, group.id, "label", "secret", LOCAL);
+        Database::save_account(&connection, &mut account).unwrap();
+
+        let saved = Database::get_account(&connection, account.id).unwrap().unwrap();
+        assert_eq!((TotpAlgorithm::Sha1, 6, 30), (saved.algorithm, saved.digits, saved.period));
+
+        account.algorithm = TotpAlgorithm::Sha512;
+        account.digits = 8;
+        account.period = 60;
+        Database::update_account(&connection, &mut account).unwrap();
+
+        let saved = Database::get_account(&connection, account.id).unwrap().unwrap();
+        assert_eq!((TotpAlgorithm::Sha512, 8, 60), (saved.algorithm, saved.digits, saved.period));
+
+        let groups = Database::load_account_groups(&connection, None).unwrap();
+        let loaded = &groups[0].entries[0];
+        assert_eq!((TotpAlgorithm::Sha512, 8, 60), (loaded.algorithm, loaded.digits, loaded.period));
+    }
+
+    #[test]
+    fn increment_counter() {
+        let connection = Connection::open_in_memory().unwrap();
+        let connection: Arc<Mutex<Connection>> = Arc::new(Mutex::new(connection));
+
+        runner::run(connection.clone()).unwrap();
+
+        let connection = connection.lock().expect("Failed to acquire database connection lock");
+
+        let mut group = AccountGroup::new(0, "group", None, None, false, vec![]);
+        Database::save_group(&connection, &mut group).unwrap();
+
+        let mut account = Account::new(0, group.id, "label", "secret", LOCAL);
+        account.account_type = AccountType::Hotp;
+        account.counter = 41;
+        Database::save_account(&connection, &mut account).unwrap();
+
+        Database::increment_counter(&connection, account.id).unwrap();
+
+        let saved = Database::get_account(&connection, account.id).unwrap().unwrap();
+        assert_eq!((AccountType::Hotp, 42), (saved.account_type, saved.counter));
+
+        let groups = Database::load_account_groups(&connection, None).unwrap();
+        assert_eq!(42, groups[0].entries[0].counter);
+    }
+
     #[test]
     fn test_update_group() {
         let connection = Connection::open_in_memory().unwrap();
@@ -474,7 +765,7 @@ mod tests {
         let mut account1 = Account::new(0, group.id, "hhh", "secret3", LOCAL);
         Database::save_account(&connection, &mut account1).expect("boom!");
 
-        let expected = AccountGroup::new(
+        let mut expected = AccountGroup::new(
             1,
             "bbb",
             Some("icon"),
@@ -482,18 +773,32 @@ mod tests {
             false,
             vec![Account {
                 id: 1,
+                uuid: account1.uuid.clone(),
                 group_id: 1,
                 label: "hhh".to_owned(),
                 secret: "secret3".to_owned(),
                 secret_type: LOCAL,
+                account_type: AccountType::Totp,
+                pin: String::new(),
+                algorithm: TotpAlgorithm::Sha1,
+                digits: 6,
+                period: 30,
+                counter: 0,
+                icon: None,
+                icon_content: None,
+                url: None,
+                username: String::new(),
             }],
         );
+        expected.uuid = group.uuid.clone();
+
         let groups = Database::load_account_groups(&connection, None).unwrap();
 
         assert_eq!(vec![expected], groups);
     }
 
     #[test]
+    #[allow(clippy::get_first)]
     fn save_group_ordering() {
         let connection = Connection::open_in_memory().unwrap();
         let connection: Arc<Mutex<Connection>> = Arc::new(Mutex::new(connection));
@@ -629,4 +934,69 @@ mod tests {
         assert_eq!(2, account_group.entries.len());
         assert_eq!(2, accounts.len());
     }
+
+    #[test]
+    fn upsert_account_matches_uuid_before_label() {
+        let connection = Connection::open_in_memory().unwrap();
+        let connection: Arc<Mutex<Connection>> = Arc::new(Mutex::new(connection));
+
+        runner::run(connection.clone()).unwrap();
+
+        let connection = connection.lock().expect("Failed to acquire database connection lock");
+
+        let mut group = AccountGroup::new(0, "group", None, None, false, vec![Account::new(0, 0, "label", "secret", LOCAL)]);
+        Database::save_group_and_accounts(&connection, &mut group).unwrap();
+        let saved = group.entries.remove(0);
+
+        // as restored from a backup, after the account got renamed
+        let mut restored = Account::new(0, group.id, "renamed", "secret", LOCAL);
+        restored.uuid = saved.uuid.clone();
+        Database::upsert_account(&connection, &mut restored).unwrap();
+
+        let accounts = Database::get_accounts(&connection, group.id, None).unwrap();
+        assert_eq!(1, accounts.len());
+        assert_eq!(saved.id, accounts[0].id);
+        assert_eq!(saved.uuid, accounts[0].uuid);
+        assert_eq!("renamed", accounts[0].label);
+
+        // backups predating uuids
+        let mut legacy = Account::new(0, group.id, "legacy", "secret", LOCAL);
+        legacy.uuid = String::new();
+        Database::upsert_account(&connection, &mut legacy).unwrap();
+        assert!(uuid::Uuid::parse_str(legacy.uuid.as_str()).is_ok());
+    }
+
+    #[test]
+    fn upsert_account_keeps_icon_of_this_device() {
+        let connection = Connection::open_in_memory().unwrap();
+        let connection: Arc<Mutex<Connection>> = Arc::new(Mutex::new(connection));
+
+        runner::run(connection.clone()).unwrap();
+
+        let connection = connection.lock().expect("Failed to acquire database connection lock");
+
+        let mut group = AccountGroup::new(0, "group", None, None, false, vec![]);
+        Database::save_group(&connection, &mut group).unwrap();
+
+        let mut account = Account::new(0, group.id, "label", "secret", LOCAL);
+        account.icon = Some("local".to_owned());
+        account.url = Some("github.com".to_owned());
+        Database::save_account(&connection, &mut account).unwrap();
+
+        let saved = Database::get_account(&connection, account.id).unwrap().unwrap();
+        assert_eq!(Some("local".to_owned()), saved.icon);
+        assert_eq!(Some("github.com".to_owned()), saved.url);
+
+        // as restored from a backup made on a device with another icon
+        let mut restored = Account::new(0, group.id, "label", "secret", LOCAL);
+        restored.uuid = account.uuid.clone();
+        restored.icon = Some("elsewhere".to_owned());
+        restored.url = Some("gitlab.com".to_owned());
+        Database::upsert_account(&connection, &mut restored).unwrap();
+
+        let accounts = Database::get_accounts(&connection, group.id, None).unwrap();
+        assert_eq!(1, accounts.len());
+        assert_eq!(Some("local".to_owned()), accounts[0].icon);
+        assert_eq!(Some("gitlab.com".to_owned()), accounts[0].url);
+    }
 }
@@ -0,0 +1,33 @@
+use std::fs;
+use std::path::PathBuf;
+
+use rusqlite::Connection;
+use uuid::Uuid;
+
+use crate::helpers::runner;
+
+/**
+ * Temporary directory of a test, holding a migrated database, deleted along with everything in it once dropped.
+ */
+pub(crate) struct Fixture {
+    pub directory: PathBuf,
+    pub connection: Connection,
+}
+
+impl Fixture {
+    pub fn new(name: &str) -> Fixture {
+        let directory = std::env::temp_dir().join(format!("authenticator-rs-{}-{}", name, Uuid::new_v4()));
+        fs::create_dir_all(&directory).unwrap();
+
+        let mut connection = Connection::open(directory.join("authenticator.db")).unwrap();
+        runner::migrate(&mut connection).unwrap();
+
+        Fixture { directory, connection }
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.directory);
+    }
+}
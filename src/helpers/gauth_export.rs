@@ -0,0 +1,326 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use base64::Engine;
+use log::warn;
+use percent_encoding::percent_decode_str;
+use url::Url;
+
+use crate::helpers::RepositoryError;
+use crate::helpers::RepositoryError::GAuthQrCodeError;
+use crate::model::{AccountType, TotpAlgorithm, DEFAULT_DIGITS};
+
+/**
+ * One QR code of a Google Authenticator export, read from an `otpauth-migration://offline?data=` URI.
+ * Large exports are split in several such batches sharing the same `batch_id`.
+ */
+#[derive(Debug, PartialEq)]
+pub struct GAuthExport {
+    pub accounts: Vec<GAuthAccount>,
+    pub batch_size: u64,
+    pub batch_index: u64,
+    pub batch_id: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GAuthAccount {
+    pub name: String,
+    /// base32, without padding
+    pub secret: String,
+    /// Empty when the account has none.
+    pub issuer: String,
+    pub algorithm: TotpAlgorithm,
+    pub digits: u32,
+    /// Either TOTP or HOTP.
+    pub account_type: AccountType,
+    /// Only used by HOTP accounts.
+    pub counter: u64,
+}
+
+/**
+ * A value of a protobuf message, as per https://protobuf.dev/programming-guides/encoding/.
+ */
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+impl GAuthExport {
+    pub fn parse(uri: &str) -> Result<GAuthExport, RepositoryError> {
+        let url = Url::parse(uri).map_err(|e| GAuthQrCodeError(format!("Invalid GAuth data: {}", e)))?;
+
+        if !url.scheme().eq_ignore_ascii_case("otpauth-migration") {
+            return Err(GAuthQrCodeError(format!("Invalid GAuth data: unexpected scheme {}", url.scheme())));
+        }
+
+        // '+' is part of base64, not an encoded space
+        let data = url
+            .query()
+            .and_then(|query| query.split('&').find_map(|pair| pair.strip_prefix("data=")))
+            .map(|data| percent_decode_str(data).decode_utf8_lossy().into_owned())
+            .ok_or_else(|| GAuthQrCodeError("Invalid GAuth data: no data found".to_owned()))?;
+
+        let payload = base64::engine::general_purpose::STANDARD
+            .decode(data.trim())
+            .map_err(|e| GAuthQrCodeError(format!("Invalid GAuth data: {}", e)))?;
+
+        Self::decode(&payload)
+    }
+
+    /**
+     * Accounts of every export, once each, making sure all the batches of each export are there.
+     */
+    pub fn stitch(exports: Vec<GAuthExport>) -> Result<Vec<GAuthAccount>, RepositoryError> {
+        let missing = Self::missing(&exports);
+
+        if !missing.is_empty() {
+            let codes = missing.iter().map(|(index, size)| format!("{} of {}", index, size)).collect::<Vec<String>>();
+            return Err(GAuthQrCodeError(format!("Incomplete GAuth export: QR code {} missing", codes.join(", "))));
+        }
+
+        let mut by_batch_id: BTreeMap<u64, BTreeMap<u64, GAuthExport>> = BTreeMap::new();
+
+        for export in exports {
+            // the same batch may well have been scanned twice
+            by_batch_id.entry(export.batch_id).or_default().insert(export.batch_index, export);
+        }
+
+        let mut accounts: Vec<GAuthAccount> = vec![];
+
+        for batches in by_batch_id.into_values() {
+            for account in batches.into_values().flat_map(|export| export.accounts) {
+                if !accounts.contains(&account) {
+                    accounts.push(account);
+                }
+            }
+        }
+
+        Ok(accounts)
+    }
+
+    /**
+     * QR codes of these exports not read yet, numbered from 1, along with how many their export has.
+     */
+    pub fn missing(exports: &[GAuthExport]) -> Vec<(u64, u64)> {
+        let mut by_batch_id: BTreeMap<u64, (u64, BTreeSet<u64>)> = BTreeMap::new();
+
+        for export in exports {
+            let (batch_size, read) = by_batch_id.entry(export.batch_id).or_default();
+            *batch_size = export.batch_size.max(*batch_size);
+            read.insert(export.batch_index);
+        }
+
+        by_batch_id
+            .into_values()
+            .flat_map(|(batch_size, read)| {
+                let batch_size = batch_size.max(1);
+                (0..batch_size)
+                    .filter(move |index| !read.contains(index))
+                    .map(move |index| (index + 1, batch_size))
+            })
+            .collect()
+    }
+
+    fn decode(payload: &[u8]) -> Result<GAuthExport, RepositoryError> {
+        let mut export = GAuthExport {
+            accounts: vec![],
+            batch_size: 1,
+            batch_index: 0,
+            batch_id: 0,
+        };
+
+        for field in Self::fields(payload)? {
+            match field {
+                (1, Value::Bytes(bytes)) => export.accounts.push(Self::decode_account(bytes)?),
+                (3, Value::Varint(batch_size)) => export.batch_size = batch_size,
+                (4, Value::Varint(batch_index)) => export.batch_index = batch_index,
+                (5, Value::Varint(batch_id)) => export.batch_id = batch_id,
+                _ => {}
+            }
+        }
+
+        Ok(export)
+    }
+
+    /**
+     * An `OtpParameters` message. Unspecified and unknown enum values fall back to the usual SHA1, 6 digits TOTP.
+     */
+    fn decode_account(bytes: &[u8]) -> Result<GAuthAccount, RepositoryError> {
+        let mut secret: &[u8] = &[];
+
+        let mut account = GAuthAccount {
+            name: String::new(),
+            secret: String::new(),
+            issuer: String::new(),
+            algorithm: TotpAlgorithm::default(),
+            digits: DEFAULT_DIGITS,
+            account_type: AccountType::Totp,
+            counter: 0,
+        };
+
+        for field in Self::fields(bytes)? {
+            match field {
+                (1, Value::Bytes(bytes)) => secret = bytes,
+                (2, Value::Bytes(bytes)) => account.name = String::from_utf8_lossy(bytes).into_owned(),
+                (3, Value::Bytes(bytes)) => account.issuer = String::from_utf8_lossy(bytes).into_owned(),
+                (4, Value::Varint(algorithm)) => {
+                    account.algorithm = match algorithm {
+                        0 | 1 => TotpAlgorithm::Sha1,
+                        2 => TotpAlgorithm::Sha256,
+                        3 => TotpAlgorithm::Sha512,
+                        algorithm => {
+                            warn!("Unsupported GAuth algorithm {}, using SHA1", algorithm);
+                            TotpAlgorithm::Sha1
+                        }
+                    }
+                }
+                (5, Value::Varint(digits)) => account.digits = if digits == 2 { 8 } else { DEFAULT_DIGITS },
+                (6, Value::Varint(1)) => account.account_type = AccountType::Hotp,
+                (7, Value::Varint(counter)) => account.counter = counter,
+                _ => {}
+            }
+        }
+
+        account.secret = base32::encode(base32::Alphabet::Rfc4648 { padding: false }, secret);
+
+        Ok(account)
+    }
+
+    fn fields(mut bytes: &[u8]) -> Result<Vec<(u64, Value<'_>)>, RepositoryError> {
+        let truncated = || GAuthQrCodeError("Invalid GAuth data: truncated payload".to_owned());
+
+        let mut fields = vec![];
+
+        while !bytes.is_empty() {
+            let key = Self::varint(&mut bytes).ok_or_else(truncated)?;
+
+            let value = match key & 7 {
+                0 => Value::Varint(Self::varint(&mut bytes).ok_or_else(truncated)?),
+                1 | 5 => {
+                    let length = if key & 7 == 1 { 8 } else { 4 };
+                    bytes = bytes.get(length..).ok_or_else(truncated)?;
+                    Value::Fixed
+                }
+                2 => {
+                    let length = Self::varint(&mut bytes).ok_or_else(truncated)? as usize;
+                    let value = bytes.get(..length).ok_or_else(truncated)?;
+                    bytes = &bytes[length..];
+                    Value::Bytes(value)
+                }
+                wire_type => return Err(GAuthQrCodeError(format!("Invalid GAuth data: unexpected wire type {}", wire_type))),
+            };
+
+            fields.push((key >> 3, value));
+        }
+
+        Ok(fields)
+    }
+
+    fn varint(bytes: &mut &[u8]) -> Option<u64> {
+        let mut value = 0u64;
+
+        for shift in (0..64).step_by(7) {
+            let (byte, rest) = bytes.split_first()?;
+            *bytes = rest;
+
+            value |= ((byte & 0x7f) as u64) << shift;
+
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GAuthAccount, GAuthExport};
+    use crate::helpers::RepositoryError;
+    use crate::model::{AccountType, TotpAlgorithm};
+
+    // the example of the google_authenticator_converter crate: three accounts, batch 1 of 1, batch id 1076589581
+    const EXPORT: &str = "otpauth-migration://offline?data=CjMKCkhlbGxvId6tvu8SGFRlc3QxOnRlc3QxQGV4YW1wbGUxLmNvbRoFVGVzdDEgASgBMAIKMwoKSGVsbG8h3q2%2B8BIYVGVzdDI6dGVzdDJAZXhhbXBsZTIuY29tGgVUZXN0MiABKAEwAgozCgpIZWxsbyHerb7xEhhUZXN0Mzp0ZXN0M0BleGFtcGxlMy5jb20aBVRlc3QzIAEoATACEAEYASAAKI3orYEE";
+
+    fn account(name: &str, secret: &str) -> GAuthAccount {
+        GAuthAccount {
+            name: name.to_owned(),
+            secret: secret.to_owned(),
+            issuer: String::new(),
+            algorithm: TotpAlgorithm::Sha1,
+            digits: 6,
+            account_type: AccountType::Totp,
+            counter: 0,
+        }
+    }
+
+    fn issued(issuer: &str, name: &str, secret: &str) -> GAuthAccount {
+        GAuthAccount {
+            issuer: issuer.to_owned(),
+            ..account(name, secret)
+        }
+    }
+
+    fn batch(batch_id: u64, batch_index: u64, batch_size: u64, accounts: Vec<GAuthAccount>) -> GAuthExport {
+        GAuthExport {
+            accounts,
+            batch_size,
+            batch_index,
+            batch_id,
+        }
+    }
+
+    #[test]
+    fn parse() {
+        let export = GAuthExport::parse(EXPORT).unwrap();
+
+        assert_eq!(
+            vec![
+                issued("Test1", "Test1:test1@example1.com", "JBSWY3DPEHPK3PXP"),
+                issued("Test2", "Test2:test2@example2.com", "JBSWY3DPEHPK3PXQ"),
+                issued("Test3", "Test3:test3@example3.com", "JBSWY3DPEHPK3PXR"),
+            ],
+            export.accounts
+        );
+        assert_eq!((1, 0, 1076589581), (export.batch_size, export.batch_index, export.batch_id));
+
+        // bob of Example, SHA512, 8 digits, HOTP at counter 42
+        let export = GAuthExport::parse("otpauth-migration://offline?data=CiIKCkhlbGxvId6tvu8SA2JvYhoHRXhhbXBsZSADKAIwATgqEAEYASAAKAc%3D").unwrap();
+        assert_eq!(
+            vec![GAuthAccount {
+                algorithm: TotpAlgorithm::Sha512,
+                digits: 8,
+                account_type: AccountType::Hotp,
+                counter: 42,
+                ..issued("Example", "bob", "JBSWY3DPEHPK3PXP")
+            }],
+            export.accounts
+        );
+
+        assert!(GAuthExport::parse("otpauth-migration://offline?data=CjMKCkhl").is_err());
+        assert!(GAuthExport::parse("otpauth-migration://offline").is_err());
+        assert!(GAuthExport::parse("otpauth://totp/alice?secret=JBSWY3DPEHPK3PXP").is_err());
+    }
+
+    #[test]
+    fn stitch() {
+        let alice = account("alice", "JBSWY3DPEHPK3PXP");
+        let bob = account("bob", "KRSXG5CTMVRXEZLU");
+        let carol = account("carol", "MFRGGZDFMZTWQ2LK");
+
+        let accounts = GAuthExport::stitch(vec![
+            batch(7, 1, 2, vec![carol.clone()]),
+            batch(7, 0, 2, vec![alice.clone(), bob.clone()]),
+            batch(7, 0, 2, vec![alice.clone(), bob.clone()]),
+            batch(9, 0, 1, vec![bob.clone()]),
+        ])
+        .unwrap();
+
+        assert_eq!(vec![alice.clone(), bob.clone(), carol], accounts);
+
+        let incomplete = vec![batch(7, 0, 3, vec![alice]), batch(7, 2, 3, vec![bob])];
+        assert_eq!(vec![(2, 3)], GAuthExport::missing(&incomplete));
+        assert!(matches!(GAuthExport::stitch(incomplete), Err(RepositoryError::GAuthQrCodeError(e)) if e.contains("QR code 2 of 3 missing")));
+    }
+}
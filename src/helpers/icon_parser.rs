@@ -1,12 +1,19 @@
+use std::cmp::Reverse;
 use std::path::Path;
 use std::time::Duration;
 
 use anyhow::Result;
 use curl::easy::Easy;
-use gtk::gdk_pixbuf::Pixbuf;
-use log::debug;
+use gtk::gdk_pixbuf::{Pixbuf, PixbufLoader};
+use gtk::prelude::PixbufLoaderExt;
+use log::{debug, warn};
 use regex::Regex;
 use scraper::*;
+use serde::Deserialize;
+use url::Url;
+
+/// Rank of scalable icons, which look sharp at any size.
+const SCALABLE: u32 = u32::MAX;
 
 #[derive(Debug, Clone)]
 pub struct IconParser {}
@@ -15,6 +22,8 @@ pub struct IconParser {}
 pub enum IconError {
     #[error("Could not find icon in html")]
     ParsingError,
+    #[error("HTTP error {0}")]
+    HttpError(u32),
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -23,60 +32,221 @@ pub struct AccountGroupIcon {
     pub extension: Option<String>,
 }
 
+/**
+ * An icon a page links to, with the width it is declared at. Larger ones are tried first.
+ */
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Candidate {
+    url: Url,
+    size: u32,
+}
+
+struct Download {
+    data: Vec<u8>,
+    extension: Option<String>,
+    /// Where redirections ended, which relative links are resolved against.
+    url: Url,
+}
+
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    icons: Vec<ManifestIcon>,
+}
+
+#[derive(Deserialize)]
+struct ManifestIcon {
+    src: String,
+    #[serde(default)]
+    sizes: String,
+    #[serde(default, rename = "type")]
+    mime_type: String,
+    #[serde(default)]
+    purpose: String,
+}
+
 impl IconParser {
     pub async fn html_notify(sender: async_channel::Sender<Result<AccountGroupIcon>>, url: String) {
         let result = Self::html(&url).await;
-        sender.send(result).await.expect("Could not send result")
+        if let Err(e) = sender.send(result).await {
+            warn!("Could not send result: {:?}", e);
+        }
     }
 
+    /**
+     * Icon of the website at `url`, e.g. `github.com`: the largest of those declared in its page and web app manifest
+     * which decodes, `/favicon.ico` otherwise.
+     */
     pub async fn html(url: &str) -> Result<AccountGroupIcon> {
-        let (data, extension) = Self::download(url).await?;
-
-        match extension {
-            Some(extension) if extension.ends_with("icon") => Ok(AccountGroupIcon {
-                content: data,
-                extension: Some(extension),
-            }),
-            _ => {
-                let html = String::from_utf8_lossy(&data).into_owned();
-                Self::icon(url, html.as_str()).await
+        let url = Self::site_url(url)?;
+        let page = Self::download(&url).await?;
+
+        if page.extension.as_deref().is_some_and(|extension| extension != "html") && Self::decode(&page.data).is_ok() {
+            return Ok(AccountGroupIcon {
+                content: page.data,
+                extension: page.extension,
+            });
+        }
+
+        let html = String::from_utf8_lossy(&page.data).into_owned();
+        let (mut candidates, manifest) = Self::candidates(&page.url, html.as_str());
+
+        if let Some(manifest) = manifest {
+            match Self::download(&manifest).await {
+                Ok(manifest) => candidates.extend(Self::manifest_candidates(&manifest.url, &manifest.data)),
+                Err(e) => debug!("Could not download manifest {}: {:?}", manifest, e),
             }
         }
+
+        candidates.sort_by_key(|candidate| Reverse(candidate.size));
+
+        if let Ok(favicon) = page.url.join("/favicon.ico") {
+            candidates.push(Candidate { url: favicon, size: 0 });
+        }
+
+        Self::first_valid(candidates).await
     }
 
-    async fn icon(url: &str, html: &str) -> Result<AccountGroupIcon> {
-        let icon_url = {
-            let document = Html::parse_document(html);
+    async fn first_valid(candidates: Vec<Candidate>) -> Result<AccountGroupIcon> {
+        let mut tried: Vec<Url> = vec![];
 
-            let selector_1 = Selector::parse(r#"link[rel="apple-touch-icon"]"#).ok();
-            let selector_2 = Selector::parse(r#"link[rel="shortcut icon"]"#).ok();
-            let selector_3 = Selector::parse(r#"link[rel="icon"]"#).ok();
+        for candidate in candidates {
+            if tried.contains(&candidate.url) {
+                continue;
+            }
+
+            debug!("icon_url: {}", candidate.url);
+
+            match Self::download(&candidate.url).await {
+                Ok(icon) => match Self::decode(&icon.data) {
+                    Ok(_) => {
+                        return Ok(AccountGroupIcon {
+                            content: icon.data,
+                            extension: icon.extension,
+                        })
+                    }
+                    Err(e) => debug!("Invalid icon {}: {:?}", candidate.url, e),
+                },
+                Err(e) => debug!("Could not download icon {}: {:?}", candidate.url, e),
+            }
 
-            let option_1 = selector_1.and_then(|s| document.select(&s).next());
-            let option_2 = selector_2.and_then(|s| document.select(&s).next());
-            let option_3 = selector_3.and_then(|s| document.select(&s).next());
+            tried.push(candidate.url);
+        }
 
-            let choice = option_1.or(option_2).or(option_3);
+        Err(IconError::ParsingError.into())
+    }
 
-            match choice.and_then(|v| v.value().attr("href")) {
-                Some(href) if href.starts_with('/') => Ok(format!("{}/{}", url, href)),
-                Some(href) if href.starts_with("http") => Ok(href.to_string()),
-                Some(href) => Ok(format!("{}/{}", url, href)),
-                None => Err(IconError::ParsingError),
+    /**
+     * Icons linked to by `html`, resolved against its `<base>` if any, and its web app manifest.
+     * Safari's monochrome `mask-icon`s are left out.
+     */
+    fn candidates(page_url: &Url, html: &str) -> (Vec<Candidate>, Option<Url>) {
+        let document = Html::parse_document(html);
+
+        let select = |selector: &str| {
+            Selector::parse(selector)
+                .map(|selector| document.select(&selector).collect::<Vec<_>>())
+                .unwrap_or_default()
+        };
+
+        let base = select("base[href]")
+            .first()
+            .and_then(|base| base.value().attr("href"))
+            .and_then(|href| page_url.join(href.trim()).ok())
+            .unwrap_or_else(|| page_url.clone());
+
+        let mut candidates = vec![];
+        let mut manifest = None;
+
+        for link in select("link[rel][href]") {
+            let link = link.value();
+            let rels: Vec<String> = link.attr("rel").unwrap_or_default().split_whitespace().map(str::to_ascii_lowercase).collect();
+
+            let Some(url) = link.attr("href").and_then(|href| base.join(href.trim()).ok()) else {
+                continue;
+            };
+
+            let is_apple_touch_icon = rels.iter().any(|rel| rel.starts_with("apple-touch-icon"));
+
+            if rels.iter().any(|rel| rel == "manifest") {
+                manifest = manifest.or(Some(url));
+            } else if is_apple_touch_icon || rels.iter().any(|rel| rel == "icon") {
+                // Apple's default, a classic favicon's otherwise
+                let default_size = if is_apple_touch_icon { 180 } else { 16 };
+                let size = Self::size(link.attr("sizes").unwrap_or_default(), link.attr("type").unwrap_or_default(), &url);
+
+                candidates.push(Candidate {
+                    url,
+                    size: size.unwrap_or(default_size),
+                });
             }
-        }?;
+        }
 
-        debug!("icon_url: {}", icon_url);
+        (candidates, manifest)
+    }
 
-        Self::download_icon(icon_url.as_str()).await
+    /**
+     * Icons of a web app manifest, resolved against its own URL as per the spec. Monochrome ones are left out.
+     */
+    fn manifest_candidates(manifest_url: &Url, data: &[u8]) -> Vec<Candidate> {
+        let manifest: Manifest = match serde_json::from_slice(data) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                debug!("Invalid manifest {}: {:?}", manifest_url, e);
+                return vec![];
+            }
+        };
+
+        manifest
+            .icons
+            .into_iter()
+            .filter(|icon| icon.purpose.is_empty() || icon.purpose.split_whitespace().any(|purpose| purpose != "monochrome"))
+            .filter_map(|icon| {
+                let url = manifest_url.join(icon.src.trim()).ok()?;
+                let size = Self::size(icon.sizes.as_str(), icon.mime_type.as_str(), &url).unwrap_or(0);
+                Some(Candidate { url, size })
+            })
+            .collect()
     }
 
-    async fn download_icon(icon_url: &str) -> Result<AccountGroupIcon> {
-        let (data, extension) = Self::download(icon_url).await?;
-        Ok(AccountGroupIcon { content: data, extension })
+    /**
+     * Largest width of a `sizes` attribute, e.g. 32 for `16x16 32x32`. SVG icons come first.
+     */
+    fn size(sizes: &str, mime_type: &str, url: &Url) -> Option<u32> {
+        if sizes.eq_ignore_ascii_case("any") || mime_type.eq_ignore_ascii_case("image/svg+xml") || url.path().to_ascii_lowercase().ends_with(".svg") {
+            return Some(SCALABLE);
+        }
+
+        sizes
+            .split_whitespace()
+            .filter_map(|size| size.to_ascii_lowercase().split_once('x').and_then(|(width, _)| width.parse::<u32>().ok()))
+            .max()
     }
 
-    async fn download(icon_url: &str) -> Result<(Vec<u8>, Option<String>)> {
+    /**
+     * Decodes the icon as it would be displayed, which rules out error pages and formats without a loader.
+     */
+    fn decode(data: &[u8]) -> Result<Pixbuf> {
+        let loader = PixbufLoader::new();
+        loader.write(data)?;
+        loader.close()?;
+
+        loader.pixbuf().ok_or_else(|| IconError::ParsingError.into())
+    }
+
+    /**
+     * `url` as a URL, `https://` being assumed for bare hostnames.
+     */
+    pub fn site_url(url: &str) -> Result<Url> {
+        let url = url.trim();
+
+        match Url::parse(url) {
+            Ok(parsed) if parsed.has_host() => Ok(parsed),
+            _ => Ok(Url::parse(format!("https://{}", url).as_str())?),
+        }
+    }
+
+    async fn download(url: &Url) -> Result<Download> {
         let mut data = Vec::new();
         let mut handle = Easy::new();
 
@@ -84,7 +254,7 @@ impl IconParser {
         handle.useragent("Mozilla/5.0 (X11; Linux x86_64; rv:109.0) Gecko/20100101 Firefox/113.0")?;
         handle.autoreferer(true)?;
         handle.timeout(Duration::from_secs(5))?;
-        handle.url(icon_url)?;
+        handle.url(url.as_str())?;
 
         {
             let mut transfer = handle.transfer();
@@ -96,13 +266,24 @@ impl IconParser {
             transfer.perform()?;
         }
 
+        let status = handle.response_code()?;
+        if status >= 400 {
+            return Err(IconError::HttpError(status).into());
+        }
+
         let extension = handle.content_type().map(|e| e.and_then(Self::extension).map(str::to_owned))?;
+        let effective_url = handle.effective_url()?.and_then(|effective_url| Url::parse(effective_url).ok());
 
-        Ok((data, extension))
+        Ok(Download {
+            data,
+            extension,
+            url: effective_url.unwrap_or_else(|| url.clone()),
+        })
     }
 
     fn extension(content_type: &str) -> Option<&str> {
-        let regex = Regex::new(r"^.*/(?P<extension>.*?)$").unwrap();
+        // parameters such as `; charset=utf-8` left out
+        let regex = Regex::new(r"^[^;]*/(?P<extension>[^;]*?)\s*(;.*)?$").unwrap();
 
         regex
             .captures(content_type)
@@ -110,11 +291,15 @@ impl IconParser {
     }
 
     pub fn load_icon(filepath: &Path, dark_mode: bool) -> Result<Pixbuf> {
+        Self::load_icon_at_size(filepath, 48, dark_mode)
+    }
+
+    pub fn load_icon_at_size(filepath: &Path, size: i32, dark_mode: bool) -> Result<Pixbuf> {
         let alpha = if dark_mode { (32, 32, 32) } else { (255, 255, 255) };
 
         debug!("loading icon {} with alpha channels {:?}", filepath.display(), &alpha);
 
-        Pixbuf::from_file_at_scale(filepath, 48, 48, true)
+        Pixbuf::from_file_at_scale(filepath, size, size, true)
             .map_err(|e| e.into())
             .and_then(|pixbuf| pixbuf.add_alpha(true, alpha.0, alpha.1, alpha.2).map_err(|e| e.into()))
     }
@@ -122,30 +307,208 @@ impl IconParser {
 
 #[cfg(test)]
 mod tests {
+    use std::io::{Cursor, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
     use async_std::task;
+    use image::{ImageFormat, RgbaImage};
 
     use super::*;
 
+    /**
+     * Serves `routes`, by path, on a local port until the tests end. Other paths are not found.
+     */
+    fn serve(routes: Vec<(&'static str, &'static str, Vec<u8>)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+
+        thread::spawn(move || {
+            for mut stream in listener.incoming().map_while(|stream| stream.ok()) {
+                let mut request = vec![];
+                let mut buffer = [0; 1024];
+
+                while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                    match stream.read(&mut buffer) {
+                        Ok(0) | Err(_) => break,
+                        Ok(read) => request.extend_from_slice(&buffer[..read]),
+                    }
+                }
+
+                let request = String::from_utf8_lossy(&request);
+                let path = request.split_whitespace().nth(1).unwrap_or_default();
+
+                let (status, content_type, body) = match routes.iter().find(|(route, _, _)| *route == path) {
+                    Some((_, content_type, body)) => ("200 OK", *content_type, body.clone()),
+                    None => ("404 Not Found", "text/html", b"not found".to_vec()),
+                };
+
+                let headers = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    content_type,
+                    body.len()
+                );
+
+                let _ = stream.write_all(headers.as_bytes()).and_then(|_| stream.write_all(&body));
+            }
+        });
+
+        address
+    }
+
+    fn image(size: u32, format: ImageFormat) -> Vec<u8> {
+        let mut bytes = Cursor::new(vec![]);
+        RgbaImage::from_pixel(size, size, image::Rgba([200, 40, 40, 255]))
+            .write_to(&mut bytes, format)
+            .unwrap();
+        bytes.into_inner()
+    }
+
+    /**
+     * A bitmap `.ico`: the loader of gdk-pixbuf rejects the PNG compressed ones `image` writes.
+     */
+    fn ico(size: u8) -> Vec<u8> {
+        let side = size as u32;
+        let mask_row = (side.div_ceil(32) * 4) as usize;
+        let bitmap_size = 40 + side * side * 4 + mask_row as u32 * side;
+
+        let mut ico = vec![0, 0, 1, 0, 1, 0, size, size, 0, 0, 1, 0, 32, 0];
+        ico.extend_from_slice(&bitmap_size.to_le_bytes());
+        ico.extend_from_slice(&22u32.to_le_bytes());
+
+        // BITMAPINFOHEADER, twice as high for the AND mask
+        ico.extend_from_slice(&40u32.to_le_bytes());
+        ico.extend_from_slice(&side.to_le_bytes());
+        ico.extend_from_slice(&(side * 2).to_le_bytes());
+        ico.extend_from_slice(&[1, 0, 32, 0]);
+        ico.extend_from_slice(&[0; 24]);
+
+        ico.extend((0..side * side).flat_map(|_| [40, 40, 200, 255]));
+        ico.extend(vec![0; mask_row * side as usize]);
+        ico
+    }
+
     #[test]
     fn extension() {
         assert_eq!("png", IconParser::extension("image/png").unwrap());
+        assert_eq!("html", IconParser::extension("text/html; charset=utf-8").unwrap());
         assert_eq!(None, IconParser::extension(""));
         assert_eq!(None, IconParser::extension("no slash"));
     }
 
     #[test]
-    fn download() {
-        let fut = IconParser::download_icon("https://static.bbci.co.uk/wwhp/1.145.0/responsive/img/apple-touch/apple-touch-180.jpg");
+    fn candidates() {
+        let page_url = Url::parse("https://www.example.com/account/login").unwrap();
+        let html = r#"<html><head>
+            <base href="/static/">
+            <link rel="icon" href="favicon-32.png" sizes="32x32">
+            <link rel="apple-touch-icon" href="../touch.png">
+            <link rel="mask-icon" href="mask.svg">
+            <link rel="Shortcut Icon" href="//cdn.example.com/favicon.ico">
+            <link rel="manifest" href="/site.webmanifest">
+        </head></html>"#;
+
+        let (candidates, manifest) = IconParser::candidates(&page_url, html);
+        let candidates: Vec<(&str, u32)> = candidates.iter().map(|candidate| (candidate.url.as_str(), candidate.size)).collect();
+
+        assert_eq!(
+            vec![
+                ("https://www.example.com/static/favicon-32.png", 32),
+                ("https://www.example.com/touch.png", 180),
+                ("https://cdn.example.com/favicon.ico", 16),
+            ],
+            candidates
+        );
+        assert_eq!(Some("https://www.example.com/site.webmanifest"), manifest.as_ref().map(Url::as_str));
+
+        let manifest = br#"{"icons": [
+            {"src": "icons/192.png", "sizes": "192x192", "type": "image/png"},
+            {"src": "/mono.png", "sizes": "512x512", "purpose": "monochrome"},
+            {"src": "icon.svg"}
+        ]}"#;
+
+        let manifest_url = Url::parse("https://www.example.com/app/manifest.json").unwrap();
+        let candidates: Vec<(String, u32)> = IconParser::manifest_candidates(&manifest_url, manifest)
+            .into_iter()
+            .map(|candidate| (candidate.url.to_string(), candidate.size))
+            .collect();
+
+        assert_eq!(
+            vec![
+                ("https://www.example.com/app/icons/192.png".to_owned(), 192),
+                ("https://www.example.com/app/icon.svg".to_owned(), SCALABLE),
+            ],
+            candidates
+        );
+        assert!(IconParser::manifest_candidates(&manifest_url, b"not json").is_empty());
+    }
 
-        let icon_parser_result = task::block_on(fut).unwrap();
-        assert_eq!("jpeg", icon_parser_result.extension.unwrap());
+    #[test]
+    fn size() {
+        let url = Url::parse("https://example.com/icon.png").unwrap();
+
+        assert_eq!(Some(32), IconParser::size("16x16 32x32", "", &url));
+        assert_eq!(Some(180), IconParser::size("180X180", "", &url));
+        assert_eq!(Some(SCALABLE), IconParser::size("any", "", &url));
+        assert_eq!(Some(SCALABLE), IconParser::size("", "image/svg+xml", &url));
+        assert_eq!(Some(SCALABLE), IconParser::size("", "", &Url::parse("https://example.com/icon.SVG").unwrap()));
+        assert_eq!(None, IconParser::size("", "", &url));
+        assert_eq!(None, IconParser::size("large", "", &url));
     }
 
     #[test]
     fn html() {
-        let fut = IconParser::html("https://www.free.fr");
+        let page = r#"<html><head>
+            <link rel="icon" href="/broken.png" sizes="192x192">
+            <link rel="icon" href="/small.png" sizes="64x64">
+            <link rel="manifest" href="/app/manifest.json">
+        </head></html>"#;
+
+        let manifest = r#"{"icons": [
+            {"src": "/missing.png", "sizes": "512x512"},
+            {"src": "icon.png", "sizes": "128x128"}
+        ]}"#;
+
+        let address = serve(vec![
+            ("/", "text/html; charset=utf-8", page.as_bytes().to_vec()),
+            ("/broken.png", "image/png", b"<html>not an image</html>".to_vec()),
+            ("/small.png", "image/png", image(64, ImageFormat::Png)),
+            ("/app/manifest.json", "application/manifest+json", manifest.as_bytes().to_vec()),
+            ("/app/icon.png", "image/png", image(128, ImageFormat::Png)),
+        ]);
+
+        // the largest which decodes, the 512 one being missing and the 192 one broken
+        let icon = task::block_on(IconParser::html(format!("{}/", address).as_str())).unwrap();
+        assert_eq!(image(128, ImageFormat::Png), icon.content);
+        assert_eq!(Some("png".to_owned()), icon.extension);
+    }
+
+    #[test]
+    fn html_favicon() {
+        let address = serve(vec![
+            ("/", "text/html", b"<html><head><title>No icon</title></head></html>".to_vec()),
+            ("/favicon.ico", "image/vnd.microsoft.icon", ico(32)),
+            ("/logo.png", "image/png", image(48, ImageFormat::Png)),
+        ]);
+
+        let icon = task::block_on(IconParser::html(address.as_str())).unwrap();
+        assert_eq!(ico(32), icon.content);
+        assert_eq!(Some("vnd.microsoft.icon".to_owned()), icon.extension);
+
+        // an image rather than a page
+        let icon = task::block_on(IconParser::html(format!("{}/logo.png", address).as_str())).unwrap();
+        assert_eq!(image(48, ImageFormat::Png), icon.content);
+    }
 
-        let icon_parser_result = task::block_on(fut).unwrap();
-        assert_eq!("png", icon_parser_result.extension.unwrap());
+    #[test]
+    fn html_without_icon() {
+        let address = serve(vec![(
+            "/",
+            "text/html",
+            b"<html><head><link rel=\"icon\" href=\"/missing.png\"></head></html>".to_vec(),
+        )]);
+
+        assert!(task::block_on(IconParser::html(address.as_str())).is_err());
     }
 }
@@ -0,0 +1,344 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use log::{debug, warn};
+use rusqlite::Connection;
+use sha2::{Digest, Sha256};
+
+use crate::helpers::{Database, Paths, RepositoryError};
+use crate::model::Account;
+
+type Result<T> = ::std::result::Result<T, RepositoryError>;
+
+/// Subdirectory of the icons being edited, until their group is saved.
+const TMP: &str = "tmp";
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct IconCacheSize {
+    pub icons: usize,
+    pub bytes: u64,
+}
+
+/**
+ * Group and account icons of a profile, named after the SHA-256 of their content so that those with the same icon share one file.
+ * A file is only deleted once no group or account refers to it any more.
+ */
+pub struct IconStore {
+    directory: PathBuf,
+}
+
+impl IconStore {
+    pub fn new(directory: PathBuf) -> IconStore {
+        IconStore { directory }
+    }
+
+    /**
+     * Icons of the current profile.
+     */
+    pub fn open() -> IconStore {
+        Self::new(Paths::icons_path(""))
+    }
+
+    pub fn tmp_path(&self, filename: &str) -> PathBuf {
+        self.directory.join(TMP).join(filename)
+    }
+
+    /**
+     * Writes an icon picked or downloaded for a group being edited, until the group is saved.
+     */
+    pub fn write_tmp(&self, filename: &str, bytes: &[u8]) -> Result<PathBuf> {
+        let path = self.tmp_path(filename);
+        Self::write(&path, bytes)?;
+        Ok(path)
+    }
+
+    pub fn remove_tmp(&self, filename: &str) {
+        let path = self.tmp_path(filename);
+
+        if path.is_file() {
+            match fs::remove_file(&path) {
+                Ok(_) => debug!("removed temp file: {}", path.display()),
+                Err(e) => warn!("could not delete temp file {}: {:?}", path.display(), e),
+            }
+        }
+    }
+
+    /**
+     * Moves the icon being edited as `filename`, if any, to the store. Returns the filename its group or account refers to from then on:
+     * that of its content, or `filename` itself when the icon was left as it was.
+     */
+    pub fn commit(&self, filename: &str) -> Result<String> {
+        match fs::read(self.tmp_path(filename)) {
+            Ok(bytes) => {
+                let stored = self.store(bytes.as_slice())?;
+                self.remove_tmp(filename);
+                Ok(stored)
+            }
+            Err(_) => Ok(filename.to_owned()),
+        }
+    }
+
+    /**
+     * Filename of an icon with this content, written unless another group or account uses it already.
+     */
+    pub fn store(&self, bytes: &[u8]) -> Result<String> {
+        let filename = Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        let path = self.directory.join(filename.as_str());
+
+        if !path.is_file() {
+            debug!("storing icon {}", path.display());
+            Self::write(&path, bytes)?;
+        }
+
+        Ok(filename)
+    }
+
+    pub fn contains(&self, filename: &str) -> bool {
+        self.directory.join(filename).is_file()
+    }
+
+    /**
+     * Adds the content of its icon to an account leaving this device, in a backup or a sync log.
+     */
+    pub fn embed(&self, account: &mut Account) {
+        if let Some(icon) = &account.icon {
+            match fs::read(self.directory.join(icon)) {
+                Ok(bytes) => account.icon_content = Some(base64::engine::general_purpose::STANDARD.encode(bytes)),
+                Err(e) => warn!("Could not read icon {} of {}: {:?}", icon, account.label, e),
+            }
+        }
+    }
+
+    /**
+     * Stores the icon content an account arriving from a backup or a sync log carries, and has the account refer to it.
+     * Without content the account keeps the icon filename it came with.
+     */
+    pub fn extract(&self, account: &mut Account) -> Result<()> {
+        if let Some(content) = account.icon_content.take() {
+            match base64::engine::general_purpose::STANDARD.decode(content) {
+                Ok(bytes) => account.icon = Some(self.store(bytes.as_slice())?),
+                Err(e) => warn!("Could not decode icon of {}: {:?}", account.label, e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Deletes the icon `filename` unless a group or account still refers to it, once it has been taken off one.
+     */
+    pub fn release(&self, connection: &Connection, filename: &str) -> Result<bool> {
+        if Database::icons(connection)?.contains(filename) {
+            debug!("icon {} is still used", filename);
+            return Ok(false);
+        }
+
+        let path = self.directory.join(filename);
+
+        if path.is_file() {
+            fs::remove_file(&path)?;
+            debug!("deleted icon {}", path.display());
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /**
+     * Deletes the icons nothing refers to, along with those left behind by edits which were not saved.
+     * Returns how many files were deleted.
+     */
+    pub fn sweep(&self, connection: &Connection) -> Result<usize> {
+        let referenced: HashSet<String> = Database::icons(connection)?;
+
+        let unused = Self::files(&self.directory)?
+            .into_iter()
+            .filter(|path| !path.file_name().and_then(|name| name.to_str()).is_some_and(|name| referenced.contains(name)));
+
+        let mut deleted = 0;
+
+        for path in unused.chain(Self::files(&self.directory.join(TMP))?) {
+            match fs::remove_file(&path) {
+                Ok(_) => {
+                    debug!("deleted unused icon {}", path.display());
+                    deleted += 1;
+                }
+                Err(e) => warn!("could not delete unused icon {}: {:?}", path.display(), e),
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    /**
+     * Takes their icon off every group and account, and deletes all icons. They then show the bundled brand icon matching them, if any.
+     */
+    pub fn clear(&self, connection: &Connection) -> Result<usize> {
+        Database::clear_icons(connection)?;
+        self.sweep(connection)
+    }
+
+    pub fn size(&self) -> Result<IconCacheSize> {
+        let icons = Self::files(&self.directory)?;
+        let tmp = Self::files(&self.directory.join(TMP))?;
+
+        let bytes = icons
+            .iter()
+            .chain(tmp.iter())
+            .filter_map(|path| path.metadata().ok())
+            .map(|metadata| metadata.len())
+            .sum();
+
+        Ok(IconCacheSize { icons: icons.len(), bytes })
+    }
+
+    fn files(directory: &Path) -> Result<Vec<PathBuf>> {
+        if !directory.is_dir() {
+            return Ok(vec![]);
+        }
+
+        let files = fs::read_dir(directory)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file())
+            .collect();
+
+        Ok(files)
+    }
+
+    fn write(path: &Path, bytes: &[u8]) -> Result<()> {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+
+        let mut file = tempfile_fast::Sponge::new_for(path)?;
+        file.write_all(bytes)?;
+        file.commit()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::fixture::Fixture;
+    use crate::helpers::SecretType;
+    use crate::model::{Account, AccountGroup};
+
+    fn icons(fixture: &Fixture) -> PathBuf {
+        fixture.directory.join("icons")
+    }
+
+    fn icon_store(fixture: &Fixture) -> IconStore {
+        IconStore::new(icons(fixture))
+    }
+
+    fn group(fixture: &Fixture, name: &str, icon: Option<&str>) -> AccountGroup {
+        let mut group = AccountGroup::new(0, name, icon, None, false, vec![]);
+        Database::save_group(&fixture.connection, &mut group).unwrap();
+        group
+    }
+
+    #[test]
+    fn commit() {
+        let fixture = Fixture::new("icons");
+        let store = icon_store(&fixture);
+
+        store.write_tmp("edited", b"icon").unwrap();
+        let first = store.commit("edited").unwrap();
+
+        store.write_tmp("other", b"icon").unwrap();
+        let second = store.commit("other").unwrap();
+
+        // same content, same file
+        assert_eq!(first, second);
+        assert_eq!(64, first.len());
+        assert_eq!(b"icon".to_vec(), fs::read(icons(&fixture).join(first.as_str())).unwrap());
+        assert!(!store.tmp_path("edited").exists());
+
+        // nothing edited: the group keeps its icon
+        assert_eq!("legacy", store.commit("legacy").unwrap());
+
+        assert_eq!(IconCacheSize { icons: 1, bytes: 4 }, store.size().unwrap());
+    }
+
+    #[test]
+    fn embed_and_extract() {
+        let fixture = Fixture::new("icons");
+        let store = icon_store(&fixture);
+
+        let mut account = Account::new(0, 0, "alice", "", SecretType::LOCAL);
+        account.icon = Some(store.store(b"icon").unwrap());
+        store.embed(&mut account);
+        assert_eq!(Some("aWNvbg==".to_owned()), account.icon_content);
+
+        // another device, without the file
+        let other = Fixture::new("icons");
+        let other_store = icon_store(&other);
+        let mut restored = account.clone();
+        other_store.extract(&mut restored).unwrap();
+
+        assert_eq!(account.icon, restored.icon);
+        assert_eq!(None, restored.icon_content);
+        assert!(other_store.contains(restored.icon.as_deref().unwrap()));
+
+        // the file is gone: nothing to embed
+        let mut missing = Account::new(0, 0, "bob", "", SecretType::LOCAL);
+        missing.icon = Some("missing".to_owned());
+        store.embed(&mut missing);
+        assert_eq!(None, missing.icon_content);
+
+        other_store.extract(&mut missing).unwrap();
+        assert_eq!(Some("missing".to_owned()), missing.icon);
+    }
+
+    #[test]
+    fn release() {
+        let fixture = Fixture::new("icons");
+        let store = icon_store(&fixture);
+
+        let icon = store.store(b"shared").unwrap();
+        let mut first = group(&fixture, "first", Some(icon.as_str()));
+        group(&fixture, "second", Some(icon.as_str()));
+
+        first.icon = None;
+        Database::update_group(&fixture.connection, &first).unwrap();
+
+        assert!(!store.release(&fixture.connection, icon.as_str()).unwrap());
+        assert!(icons(&fixture).join(icon.as_str()).is_file());
+
+        Database::clear_icons(&fixture.connection).unwrap();
+
+        assert!(store.release(&fixture.connection, icon.as_str()).unwrap());
+        assert!(!icons(&fixture).join(icon.as_str()).exists());
+    }
+
+    #[test]
+    fn sweep() {
+        let fixture = Fixture::new("icons");
+        let store = icon_store(&fixture);
+
+        let used = store.store(b"used").unwrap();
+        let account_icon = store.store(b"account").unwrap();
+        let orphan = store.store(b"orphan").unwrap();
+        let group = group(&fixture, "group", Some(used.as_str()));
+        store.write_tmp("abandoned", b"edit").unwrap();
+
+        let mut account = Account::new(0, group.id, "alice", "", SecretType::LOCAL);
+        account.icon = Some(account_icon.clone());
+        Database::save_account(&fixture.connection, &mut account).unwrap();
+
+        assert_eq!(2, store.sweep(&fixture.connection).unwrap());
+        assert!(icons(&fixture).join(used.as_str()).is_file());
+        assert!(icons(&fixture).join(account_icon.as_str()).is_file());
+        assert!(!icons(&fixture).join(orphan.as_str()).exists());
+        assert!(!store.tmp_path("abandoned").exists());
+
+        assert_eq!(2, store.clear(&fixture.connection).unwrap());
+        assert_eq!(IconCacheSize::default(), store.size().unwrap());
+        assert!(Database::icons(&fixture.connection).unwrap().is_empty());
+    }
+}
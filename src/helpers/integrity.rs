@@ -0,0 +1,336 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use gettextrs::gettext;
+use log::{info, warn};
+use rusqlite::Connection;
+use uuid::Uuid;
+
+use crate::helpers::{Database, Keyring, RepositoryError, SecretStores, SecretType};
+use crate::model::Account;
+
+type Result<T> = ::std::result::Result<T, RepositoryError>;
+
+/**
+ * A keyring item tagged with this application, as found in the Secret Service collection.
+ * `uuid` is None when the attribute is absent, `secret` when it is not valid UTF-8.
+ */
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct KeyringItem {
+    pub item_path: String,
+    pub uuid: Option<String>,
+    pub secret: Option<String>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum IntegrityIssue {
+    /// Account in the database without any usable secret in the keyring.
+    MissingSecret { account_id: u32, label: String },
+    /// Keyring item pointing to an account which no longer exists.
+    Orphan { item_path: String, uuid: String },
+    /// Several keyring items for the same account. `conflicting` is set when they hold different secrets.
+    Duplicate {
+        account_id: u32,
+        uuid: String,
+        label: String,
+        count: usize,
+        conflicting: bool,
+    },
+    /// Keyring item read without a valid account uuid, or with a secret which is not valid UTF-8.
+    Undecodable { item_path: String, uuid: Option<String> },
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct IntegrityReport {
+    pub accounts: usize,
+    pub items: usize,
+    pub issues: Vec<IntegrityIssue>,
+}
+
+pub struct Integrity;
+
+impl Integrity {
+    /**
+     * Cross-references the accounts table with the keyring items of this application.
+     */
+    pub fn check(accounts: &[Account], items: &[KeyringItem]) -> IntegrityReport {
+        let mut issues = Vec::new();
+
+        // items by account uuid, in keyring order
+        let mut by_uuid: BTreeMap<&str, Vec<&KeyringItem>> = BTreeMap::new();
+
+        for item in items {
+            match (item.uuid.as_deref().filter(|uuid| Uuid::parse_str(uuid).is_ok()), &item.secret) {
+                (Some(uuid), Some(_)) => by_uuid.entry(uuid).or_default().push(item),
+                _ => issues.push(IntegrityIssue::Undecodable {
+                    item_path: item.item_path.clone(),
+                    uuid: item.uuid.clone(),
+                }),
+            }
+        }
+
+        let mut account_issues = accounts
+            .iter()
+            .filter_map(|account| match by_uuid.get(account.uuid.as_str()) {
+                None => Some(IntegrityIssue::MissingSecret {
+                    account_id: account.id,
+                    label: account.label.clone(),
+                }),
+                Some(items) if items.len() > 1 => Some(IntegrityIssue::Duplicate {
+                    account_id: account.id,
+                    uuid: account.uuid.clone(),
+                    label: account.label.clone(),
+                    count: items.len(),
+                    conflicting: items.iter().any(|item| item.secret != items[0].secret),
+                }),
+                Some(_) => None,
+            })
+            .collect::<Vec<IntegrityIssue>>();
+
+        let orphans = by_uuid
+            .iter()
+            .filter(|(uuid, _)| !accounts.iter().any(|account| account.uuid == **uuid))
+            .flat_map(|(uuid, items)| {
+                items.iter().map(|item| IntegrityIssue::Orphan {
+                    item_path: item.item_path.clone(),
+                    uuid: uuid.to_string(),
+                })
+            });
+
+        account_issues.extend(orphans);
+        account_issues.append(&mut issues);
+
+        IntegrityReport {
+            accounts: accounts.len(),
+            items: items.len(),
+            issues: account_issues,
+        }
+    }
+
+    /**
+     * Only accounts kept in the Secret Service are checked: other backends are not shared with other applications.
+     * The application's own secrets, e.g. the sync passphrase, are not account secrets and are left out.
+     */
+    pub fn scan(connection: &Connection) -> Result<IntegrityReport> {
+        let accounts = Database::load_account_groups(connection, None)?
+            .into_iter()
+            .flat_map(|group| group.entries)
+            .filter(|account| account.secret_type == SecretType::KEYRING)
+            .collect::<Vec<Account>>();
+
+        let own_secrets = SecretStores::own_secrets().into_iter().map(|(key, _)| key).collect::<Vec<&str>>();
+
+        let items = Keyring::items()?
+            .into_iter()
+            .filter(|item| !item.uuid.as_deref().is_some_and(|uuid| own_secrets.contains(&uuid)))
+            .collect::<Vec<KeyringItem>>();
+
+        Ok(Self::check(&accounts, &items))
+    }
+
+    /**
+     * Applies the repair matching the issue. Accounts with a missing secret are deleted,
+     * since there is nothing left to generate codes from: callers confirm it with the user first.
+     */
+    pub fn repair(connection: &Connection, issue: &IntegrityIssue) -> Result<()> {
+        info!("Repairing {:?}", issue);
+
+        match issue {
+            IntegrityIssue::MissingSecret { account_id, .. } => Database::delete_account(connection, *account_id).map(|_| ()),
+            IntegrityIssue::Orphan { item_path, .. } | IntegrityIssue::Undecodable { item_path, .. } => Keyring::delete_item(item_path),
+            IntegrityIssue::Duplicate { uuid, .. } => Keyring::remove_duplicates(uuid),
+        }
+    }
+
+    /**
+     * Repairs everything which can be fixed without losing a secret the user may still need:
+     * orphans and duplicates, keeping the secret currently in use.
+     * Accounts with a missing secret are left for the user to re-enter or delete, undecodable items to delete one by one.
+     */
+    pub fn repair_all(connection: &Connection, report: &IntegrityReport) -> Result<Vec<IntegrityIssue>> {
+        let mut remaining = Vec::new();
+
+        for issue in &report.issues {
+            if issue.is_safe_to_repair() {
+                Self::repair(connection, issue)?;
+            } else {
+                warn!("Not repairing {:?}", issue);
+                remaining.push(issue.clone());
+            }
+        }
+
+        Ok(remaining)
+    }
+}
+
+impl IntegrityIssue {
+    pub fn is_safe_to_repair(&self) -> bool {
+        matches!(self, IntegrityIssue::Orphan { .. } | IntegrityIssue::Duplicate { .. })
+    }
+
+    pub fn repair_label(&self) -> String {
+        match self {
+            IntegrityIssue::MissingSecret { .. } => gettext("Delete account"),
+            IntegrityIssue::Orphan { .. } | IntegrityIssue::Undecodable { .. } => gettext("Delete item"),
+            IntegrityIssue::Duplicate { .. } => gettext("Keep one"),
+        }
+    }
+}
+
+impl fmt::Display for IntegrityIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntegrityIssue::MissingSecret { account_id, label } => write!(
+                f,
+                "{} ({}): {}",
+                label,
+                account_id,
+                gettext("no secret in keyring. Edit the account to enter it again, or delete it.")
+            ),
+            IntegrityIssue::Orphan { uuid, .. } => write!(f, "{} {}", gettext("Keyring item for unknown account"), uuid),
+            IntegrityIssue::Duplicate {
+                account_id,
+                label,
+                count,
+                conflicting,
+                ..
+            } => {
+                write!(f, "{} ({}): {} {}", label, account_id, count, gettext("keyring items"))?;
+                if *conflicting {
+                    write!(f, ", {}", gettext("with different secrets"))?;
+                }
+                Ok(())
+            }
+            IntegrityIssue::Undecodable { uuid, .. } => {
+                write!(f, "{} {}", gettext("Unreadable keyring item for account"), uuid.as_deref().unwrap_or("?"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uuid(id: u32) -> String {
+        format!("00000000-0000-4000-8000-{:012}", id)
+    }
+
+    fn account(id: u32, label: &str) -> Account {
+        let mut account = Account::new(id, 1, label, "", SecretType::KEYRING);
+        account.uuid = uuid(id);
+        account
+    }
+
+    fn item(path: &str, uuid: Option<&str>, secret: Option<&str>) -> KeyringItem {
+        KeyringItem {
+            item_path: path.to_owned(),
+            uuid: uuid.map(str::to_owned),
+            secret: secret.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn consistent_keyring_has_no_issues() {
+        let accounts = vec![account(1, "a"), account(2, "b")];
+        let items = vec![item("/1", Some(&uuid(1)), Some("AAAA")), item("/2", Some(&uuid(2)), Some("BBBB"))];
+
+        let report = Integrity::check(&accounts, &items);
+
+        assert!(report.issues.is_empty());
+        assert_eq!(2, report.accounts);
+        assert_eq!(2, report.items);
+    }
+
+    #[test]
+    fn reports_missing_orphan_duplicate_and_undecodable() {
+        let accounts = vec![account(1, "a"), account(2, "b"), account(3, "c")];
+        let items = vec![
+            item("/1", Some(&uuid(1)), Some("AAAA")),
+            item("/1bis", Some(&uuid(1)), Some("AAAA")),
+            item("/3", Some(&uuid(3)), None),
+            item("/9", Some(&uuid(9)), Some("ZZZZ")),
+            item("/x", None, Some("XXXX")),
+            item("/y", Some("not a uuid"), Some("YYYY")),
+        ];
+
+        let report = Integrity::check(&accounts, &items);
+
+        assert_eq!(
+            vec![
+                IntegrityIssue::Duplicate {
+                    account_id: 1,
+                    uuid: uuid(1),
+                    label: "a".to_owned(),
+                    count: 2,
+                    conflicting: false
+                },
+                IntegrityIssue::MissingSecret {
+                    account_id: 2,
+                    label: "b".to_owned()
+                },
+                IntegrityIssue::MissingSecret {
+                    account_id: 3,
+                    label: "c".to_owned()
+                },
+                IntegrityIssue::Orphan {
+                    item_path: "/9".to_owned(),
+                    uuid: uuid(9)
+                },
+                IntegrityIssue::Undecodable {
+                    item_path: "/3".to_owned(),
+                    uuid: Some(uuid(3))
+                },
+                IntegrityIssue::Undecodable {
+                    item_path: "/x".to_owned(),
+                    uuid: None
+                },
+                IntegrityIssue::Undecodable {
+                    item_path: "/y".to_owned(),
+                    uuid: Some("not a uuid".to_owned())
+                },
+            ],
+            report.issues
+        );
+    }
+
+    #[test]
+    fn flags_conflicting_duplicates() {
+        let accounts = vec![account(1, "a")];
+        let items = vec![item("/1", Some(&uuid(1)), Some("AAAA")), item("/1bis", Some(&uuid(1)), Some("BBBB"))];
+
+        let report = Integrity::check(&accounts, &items);
+
+        assert_eq!(
+            vec![IntegrityIssue::Duplicate {
+                account_id: 1,
+                uuid: uuid(1),
+                label: "a".to_owned(),
+                count: 2,
+                conflicting: true
+            }],
+            report.issues
+        );
+    }
+
+    #[test]
+    fn missing_secrets_and_undecodable_items_are_not_repaired_automatically() {
+        assert!(!IntegrityIssue::MissingSecret {
+            account_id: 1,
+            label: "a".to_owned()
+        }
+        .is_safe_to_repair());
+
+        assert!(!IntegrityIssue::Undecodable {
+            item_path: "/x".to_owned(),
+            uuid: None
+        }
+        .is_safe_to_repair());
+
+        assert!(IntegrityIssue::Orphan {
+            item_path: "/9".to_owned(),
+            uuid: uuid(9)
+        }
+        .is_safe_to_repair());
+    }
+}
@@ -1,20 +1,20 @@
 use std::collections::HashMap;
 
 use log::{debug, warn};
-use rusqlite::Connection;
 use secret_service::blocking::SecretService;
 use secret_service::{EncryptionType, Error as SsError};
 
 use crate::helpers::repository_error::RepositoryError;
-use crate::helpers::{Database, SecretType};
-use crate::model::{Account, AccountGroup};
+use crate::helpers::{KeyringItem, Profiles, SecretStore};
 
 type Result<T> = ::std::result::Result<T, RepositoryError>;
 
 const APPLICATION: &str = "Authenticator-rs";
 const APPLICATION_KEY: &str = "application";
-const APPLICATION_VALUE: &str = "authenticator-rs";
+const ACCOUNT_UUID_KEY: &str = "uuid";
+/// Items stored before accounts had a uuid are keyed by account database id, see rekey().
 const ACCOUNT_ID_KEY: &str = "account_id";
+const PROFILE_KEY: &str = "profile";
 
 pub struct Keyring;
 
@@ -31,40 +31,45 @@ impl Keyring {
         collection.ensure_unlocked().map_err(RepositoryError::KeyringError)
     }
 
-    fn store(ss: &SecretService, label: &str, account_id: u32, secret: &str) -> Result<()> {
+    fn store(ss: &SecretService, label: &str, uuid: &str, secret: &str) -> Result<()> {
         let collection = ss.get_default_collection().map_err(RepositoryError::KeyringError)?;
 
-        let str_account_id = format!("{}", account_id);
-
-        let mut attributes: HashMap<&str, &str> = HashMap::new();
-        attributes.insert(ACCOUNT_ID_KEY, str_account_id.as_str());
-        attributes.insert(APPLICATION_KEY, APPLICATION_VALUE);
+        let namespace = Profiles::keyring_namespace();
+        let profile = Profiles::current();
 
         collection.create_item(
             format!("{} TOTP ({})", APPLICATION, label).as_str(),
-            attributes,
+            Self::attributes(namespace.as_str(), profile.as_str(), uuid),
             secret.as_bytes(),
             true,
             "text/plain",
         )?;
 
-        debug!("Saved {} ({}) to keyring", label, account_id);
+        debug!("Saved {} ({}) to keyring", label, uuid);
         Ok(())
     }
 
-    pub fn upsert(label: &str, account_id: u32, secret: &str) -> Result<()> {
+    fn attributes<'a>(namespace: &'a str, profile: &'a str, uuid: &'a str) -> HashMap<&'a str, &'a str> {
+        let mut attributes = HashMap::from([(APPLICATION_KEY, namespace), (ACCOUNT_UUID_KEY, uuid)]);
+
+        if !Profiles::is_default() {
+            attributes.insert(PROFILE_KEY, profile);
+        }
+
+        attributes
+    }
+
+    pub fn upsert(label: &str, uuid: &str, secret: &str) -> Result<()> {
         let ss = Self::connect()?;
-        Self::store(&ss, label, account_id, secret)
+        Self::store(&ss, label, uuid, secret)
     }
 
-    pub fn secret(account_id: u32) -> Result<Option<String>> {
+    pub fn secret(uuid: &str) -> Result<Option<String>> {
         let ss = Self::connect()?;
         let collection = ss.get_default_collection()?;
 
-        let str_account_id = format!("{}", account_id);
-        let mut attributes: HashMap<&str, &str> = HashMap::new();
-        attributes.insert(ACCOUNT_ID_KEY, str_account_id.as_str());
-        attributes.insert(APPLICATION_KEY, APPLICATION_VALUE);
+        let namespace = Profiles::keyring_namespace();
+        let attributes = HashMap::from([(APPLICATION_KEY, namespace.as_str()), (ACCOUNT_UUID_KEY, uuid)]);
 
         let search_items = collection.search_items(attributes)?;
 
@@ -77,13 +82,12 @@ impl Keyring {
         }
     }
 
-    pub fn remove(account_id: u32) -> Result<()> {
+    pub fn remove(uuid: &str) -> Result<()> {
         let ss = Self::connect()?;
         let collection = ss.get_default_collection()?;
 
-        let mut attributes = HashMap::from([(APPLICATION_KEY, APPLICATION_VALUE)]);
-        let str_account_id = format!("{}", account_id);
-        attributes.insert(ACCOUNT_ID_KEY, str_account_id.as_str());
+        let namespace = Profiles::keyring_namespace();
+        let attributes = HashMap::from([(APPLICATION_KEY, namespace.as_str()), (ACCOUNT_UUID_KEY, uuid)]);
 
         let search_items = collection.search_items(attributes)?;
 
@@ -97,7 +101,8 @@ impl Keyring {
         let ss = Self::connect()?;
         let collection = ss.get_default_collection()?;
 
-        let attributes = HashMap::from([(APPLICATION_KEY, APPLICATION_VALUE)]);
+        let namespace = Profiles::keyring_namespace();
+        let attributes = HashMap::from([(APPLICATION_KEY, namespace.as_str())]);
         let results = collection.search_items(attributes)?;
 
         let secrets = results
@@ -109,75 +114,132 @@ impl Keyring {
                     .and_then(|v| String::from_utf8(v).map_err(RepositoryError::KeyringDecodingError))
                     .ok();
 
-                let account_id = match item.get_attributes() {
+                let uuid = match item.get_attributes() {
                     Ok(attributes) => attributes
                         .into_iter()
-                        .filter(|(key, _)| key == ACCOUNT_ID_KEY)
-                        .map(|(_, account_id)| account_id)
+                        .filter(|(key, _)| key == ACCOUNT_UUID_KEY)
+                        .map(|(_, uuid)| uuid)
                         .collect::<Vec<String>>()
                         .first()
                         .cloned(),
                     Err(_) => None,
                 };
 
-                (account_id, secret)
+                (uuid, secret)
             })
-            .filter(|(account_id, secret)| account_id.is_some() && secret.is_some())
-            .map(|(account_id, secret)| (account_id.unwrap(), secret.unwrap()))
+            .filter(|(uuid, secret)| uuid.is_some() && secret.is_some())
+            .map(|(uuid, secret)| (uuid.unwrap(), secret.unwrap()))
             .collect::<Vec<(String, String)>>();
 
         Ok(secrets)
     }
 
-    pub fn set_secrets(group_accounts: &mut [AccountGroup], connection: &Connection) -> std::result::Result<(), RepositoryError> {
-        let all_secrets = Self::all_secrets()?;
-        Self::associate_secrets(group_accounts, &all_secrets, connection)
+    /**
+     * Lists every item tagged with this application, including those whose secret is not valid UTF-8.
+     * Fails when a secret cannot be read at all, e.g. the collection is locked: the item may well be in use.
+     */
+    pub fn items() -> Result<Vec<KeyringItem>> {
+        let ss = Self::connect()?;
+        let collection = ss.get_default_collection()?;
+
+        let namespace = Profiles::keyring_namespace();
+        let attributes = HashMap::from([(APPLICATION_KEY, namespace.as_str())]);
+        let results = collection.search_items(attributes)?;
+
+        results
+            .iter()
+            .map(|item| {
+                let secret = String::from_utf8(item.get_secret()?).ok();
+                let uuid = item.get_attributes()?.remove(ACCOUNT_UUID_KEY);
+
+                Ok(KeyringItem {
+                    item_path: item.item_path.to_string(),
+                    uuid,
+                    secret,
+                })
+            })
+            .collect::<Result<Vec<KeyringItem>>>()
     }
 
-    pub fn associate_secrets(
-        group_accounts: &mut [AccountGroup],
-        all_secrets: &[(String, String)],
-        connection: &Connection,
-    ) -> std::result::Result<(), RepositoryError> {
+    pub fn delete_item(item_path: &str) -> Result<()> {
         let ss = Self::connect()?;
-        group_accounts
-            .iter_mut()
-            .try_for_each(|group| Self::group_account_secret(&ss, group, all_secrets, connection))
-    }
-
-    fn group_account_secret(
-        ss: &SecretService,
-        group_account: &mut AccountGroup,
-        all_secrets: &[(String, String)],
-        connection: &Connection,
-    ) -> std::result::Result<(), RepositoryError> {
-        group_account
-            .entries
-            .iter_mut()
-            .try_for_each(|a| Self::account_secret(ss, a, all_secrets, connection))
-    }
-
-    fn account_secret(
-        ss: &SecretService,
-        account: &mut Account,
-        all_secrets: &[(String, String)],
-        connection: &Connection,
-    ) -> std::result::Result<(), RepositoryError> {
-        debug!("Loading keyring secret for {} ({})", account.label, account.id);
-
-        if let Some((_, secret)) = all_secrets.iter().find(|(account_id, _)| *account_id == format!("{}", account.id)) {
-            account.secret = secret.clone();
-            account.secret_type = SecretType::KEYRING;
-            return Ok(());
+        let collection = ss.get_default_collection()?;
+
+        let namespace = Profiles::keyring_namespace();
+        let attributes = HashMap::from([(APPLICATION_KEY, namespace.as_str())]);
+        let search_items = collection.search_items(attributes)?;
+
+        match search_items.iter().find(|item| item.item_path.as_str() == item_path) {
+            Some(item) => item.delete().map_err(RepositoryError::KeyringError),
+            None => Err(RepositoryError::KeyringError(SsError::NoResult)),
         }
+    }
+
+    /**
+     * Keeps the first item found for the account - the one secret() returns - and deletes the others.
+     */
+    pub fn remove_duplicates(uuid: &str) -> Result<()> {
+        let ss = Self::connect()?;
+        let collection = ss.get_default_collection()?;
 
-        warn!("No secret found in keyring for {} ({}). Creating one.", account.label, account.id);
+        let namespace = Profiles::keyring_namespace();
+        let attributes = HashMap::from([(APPLICATION_KEY, namespace.as_str()), (ACCOUNT_UUID_KEY, uuid)]);
 
-        Self::store(ss, account.label.as_str(), account.id, account.secret.as_str())?;
-        account.secret_type = SecretType::KEYRING;
-        account.secret.clear();
+        let search_items = collection.search_items(attributes)?;
+
+        search_items
+            .iter()
+            .skip(1)
+            .try_for_each(|item| item.delete().map_err(RepositoryError::KeyringError))
+    }
+
+    /**
+     * Swaps the account id attribute of legacy items for the account uuid, leaving their secret untouched.
+     */
+    pub fn rekey(account_id: u32, uuid: &str) -> Result<bool> {
+        let ss = Self::connect()?;
+        let collection = ss.get_default_collection()?;
+
+        let namespace = Profiles::keyring_namespace();
+        let profile = Profiles::current();
+        let str_account_id = format!("{}", account_id);
+        let legacy_attributes = HashMap::from([(APPLICATION_KEY, namespace.as_str()), (ACCOUNT_ID_KEY, str_account_id.as_str())]);
+
+        let search_items = collection.search_items(legacy_attributes)?;
+
+        search_items
+            .iter()
+            .try_for_each(|item| item.set_attributes(Self::attributes(namespace.as_str(), profile.as_str(), uuid)))?;
+
+        Ok(!search_items.is_empty())
+    }
+}
+
+impl SecretStore for Keyring {
+    fn upsert(&self, label: &str, uuid: &str, secret: &str) -> Result<()> {
+        Keyring::upsert(label, uuid, secret)
+    }
+
+    fn secret(&self, uuid: &str) -> Result<Option<String>> {
+        Keyring::secret(uuid)
+    }
+
+    fn remove(&self, uuid: &str) -> Result<()> {
+        match Keyring::remove(uuid) {
+            Err(RepositoryError::KeyringError(SsError::NoResult)) => {
+                warn!("No keyring secret found for account {}", uuid);
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    fn all_secrets(&self) -> Result<Vec<(String, String)>> {
+        Keyring::all_secrets()
+    }
 
-        Database::update_account(connection, account).map(|_| ())
+    fn rekey(&self, _: &str, account_id: u32, uuid: &str) -> Result<bool> {
+        Keyring::rekey(account_id, uuid)
     }
 }
 
@@ -189,8 +251,8 @@ mod test {
     #[ignore]
     fn should_create_collection_struct() {
         if let Ok(ss) = SecretService::connect(EncryptionType::Dh) {
-            let _ = Keyring::store(&ss, "x22", 1, "secret");
-            if let Ok(Some(result)) = Keyring::secret(1) {
+            let _ = Keyring::store(&ss, "x22", "4cd1a5f8-7b55-4ae4-9d5e-0c1e3b1f2a77", "secret");
+            if let Ok(Some(result)) = Keyring::secret("4cd1a5f8-7b55-4ae4-9d5e-0c1e3b1f2a77") {
                 assert_eq!("secret", result);
             }
         }
@@ -0,0 +1,212 @@
+use std::ffi::CString;
+use std::io;
+
+use libc::{c_long, KEYCTL_DESCRIBE, KEYCTL_GET_PERSISTENT, KEYCTL_READ, KEYCTL_SEARCH, KEYCTL_UNLINK, KEY_SPEC_USER_KEYRING};
+use log::{debug, warn};
+
+use crate::helpers::{Profiles, RepositoryError, SecretStore};
+
+type Result<T> = ::std::result::Result<T, RepositoryError>;
+
+const KEY_TYPE: &str = "user";
+
+/**
+ * Secrets kept in the kernel key retention service, as "user" keys named `authenticator-rs:<account uuid>`,
+ * or `authenticator-rs:<profile>:<account uuid>` outside of the default profile.
+ *
+ * Keys go to the persistent keyring of the user when the kernel supports it, to the user keyring
+ * otherwise. Neither survives a reboot: this backend suits headless sessions and containers which
+ * provision their secrets on start, e.g. through `--migrate-secrets`.
+ */
+pub struct Keyutils;
+
+impl Keyutils {
+    fn description(uuid: &str) -> CString {
+        CString::new(format!("{}{}", Profiles::keyutils_prefix(), uuid)).expect("no nul byte in key description")
+    }
+
+    /**
+     * Extracts the account uuid from a KEYCTL_DESCRIBE answer, i.e. `type;uid;gid;perm;description`.
+     * Keys of other profiles have another ':' after `prefix` and are left out.
+     */
+    fn uuid(description: &str, prefix: &str) -> Option<String> {
+        let mut fields = description.splitn(5, ';');
+
+        match (fields.next(), fields.nth(3)) {
+            (Some(KEY_TYPE), Some(name)) => name.strip_prefix(prefix).filter(|uuid| !uuid.contains(':')).map(str::to_owned),
+            _ => None,
+        }
+    }
+
+    fn keyring() -> c_long {
+        let persistent = unsafe { libc::syscall(libc::SYS_keyctl, KEYCTL_GET_PERSISTENT, -1 as c_long, KEY_SPEC_USER_KEYRING as c_long) };
+
+        if persistent < 0 {
+            debug!("No persistent keyring ({}), using the user keyring", io::Error::last_os_error());
+            KEY_SPEC_USER_KEYRING as c_long
+        } else {
+            persistent
+        }
+    }
+
+    fn search(keyring: c_long, uuid: &str) -> Result<Option<c_long>> {
+        let key_type = CString::new(KEY_TYPE).expect("no nul byte in key type");
+        let description = Self::description(uuid);
+
+        let key = unsafe { libc::syscall(libc::SYS_keyctl, KEYCTL_SEARCH, keyring, key_type.as_ptr(), description.as_ptr(), 0 as c_long) };
+
+        if key >= 0 {
+            Ok(Some(key))
+        } else {
+            match io::Error::last_os_error() {
+                e if e.raw_os_error() == Some(libc::ENOKEY) => Ok(None),
+                e => Err(RepositoryError::IoError(e)),
+            }
+        }
+    }
+
+    fn read(key: c_long) -> Result<Vec<u8>> {
+        Self::read_with(KEYCTL_READ, key)
+    }
+
+    fn describe(key: c_long) -> Result<String> {
+        let mut description = Self::read_with(KEYCTL_DESCRIBE, key)?;
+        // the answer is nul terminated
+        description.pop();
+        String::from_utf8(description).map_err(RepositoryError::KeyringDecodingError)
+    }
+
+    /**
+     * Runs a keyctl operation filling a buffer, growing the buffer until the answer fits.
+     */
+    fn read_with(operation: u32, key: c_long) -> Result<Vec<u8>> {
+        let mut buffer: Vec<u8> = vec![0; 256];
+
+        loop {
+            let size = unsafe { libc::syscall(libc::SYS_keyctl, operation, key, buffer.as_mut_ptr(), buffer.len()) };
+
+            if size < 0 {
+                return Err(RepositoryError::IoError(io::Error::last_os_error()));
+            }
+
+            let size = size as usize;
+            if size <= buffer.len() {
+                buffer.truncate(size);
+                return Ok(buffer);
+            }
+
+            buffer.resize(size, 0);
+        }
+    }
+}
+
+impl SecretStore for Keyutils {
+    fn upsert(&self, label: &str, uuid: &str, secret: &str) -> Result<()> {
+        let key_type = CString::new(KEY_TYPE).expect("no nul byte in key type");
+        let description = Self::description(uuid);
+
+        // add_key updates the payload of an existing key with the same description
+        let key = unsafe {
+            libc::syscall(
+                libc::SYS_add_key,
+                key_type.as_ptr(),
+                description.as_ptr(),
+                secret.as_ptr(),
+                secret.len(),
+                Self::keyring(),
+            )
+        };
+
+        if key < 0 {
+            Err(RepositoryError::IoError(io::Error::last_os_error()))
+        } else {
+            debug!("Saved {} ({}) to kernel keyring", label, uuid);
+            Ok(())
+        }
+    }
+
+    fn secret(&self, uuid: &str) -> Result<Option<String>> {
+        match Self::search(Self::keyring(), uuid)? {
+            Some(key) => {
+                let bytes = Self::read(key)?;
+                String::from_utf8(bytes).map(Some).map_err(RepositoryError::KeyringDecodingError)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn remove(&self, uuid: &str) -> Result<()> {
+        let keyring = Self::keyring();
+
+        match Self::search(keyring, uuid)? {
+            Some(key) => {
+                let result = unsafe { libc::syscall(libc::SYS_keyctl, KEYCTL_UNLINK, key, keyring) };
+
+                if result < 0 {
+                    Err(RepositoryError::IoError(io::Error::last_os_error()))
+                } else {
+                    Ok(())
+                }
+            }
+            None => {
+                warn!("No kernel key found for account {}", uuid);
+                Ok(())
+            }
+        }
+    }
+
+    fn all_secrets(&self) -> Result<Vec<(String, String)>> {
+        // reading a keyring returns the serial numbers of the keys it holds
+        let serials = Self::read(Self::keyring())?
+            .chunks_exact(4)
+            .map(|chunk| i32::from_ne_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as c_long)
+            .collect::<Vec<c_long>>();
+
+        let prefix = Profiles::keyutils_prefix();
+
+        let secrets = serials
+            .into_iter()
+            .filter_map(|key| {
+                let uuid = Self::describe(key).ok().and_then(|description| Self::uuid(&description, &prefix))?;
+                let secret = Self::read(key).ok().and_then(|bytes| String::from_utf8(bytes).ok())?;
+                Some((uuid, secret))
+            })
+            .collect();
+
+        Ok(secrets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_account_uuid_from_key_description() {
+        let prefix = "authenticator-rs:";
+
+        assert_eq!(Some("7f3a".to_owned()), Keyutils::uuid("user;1000;1000;3f010000;authenticator-rs:7f3a", prefix));
+        assert_eq!(None, Keyutils::uuid("user;1000;1000;3f010000;other:7f3a", prefix));
+        assert_eq!(None, Keyutils::uuid("keyring;1000;1000;3f010000;authenticator-rs:7f3a", prefix));
+        assert_eq!(None, Keyutils::uuid("user;1000", prefix));
+
+        // keys of the "work" profile
+        assert_eq!(None, Keyutils::uuid("user;1000;1000;3f010000;authenticator-rs:work:7f3a", prefix));
+        assert_eq!(
+            Some("7f3a".to_owned()),
+            Keyutils::uuid("user;1000;1000;3f010000;authenticator-rs:work:7f3a", "authenticator-rs:work:")
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn should_store_and_read_back_kernel_key() {
+        let store = Keyutils;
+
+        store.upsert("x22", "uuid-4242", "secret").unwrap();
+        assert_eq!(Some("secret".to_owned()), store.secret("uuid-4242").unwrap());
+
+        store.remove("uuid-4242").unwrap();
+        assert_eq!(None, store.secret("uuid-4242").unwrap());
+    }
+}
@@ -0,0 +1,471 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, Local, NaiveDateTime, Utc};
+use gettextrs::gettext;
+use log::{info, warn};
+use rand::RngCore;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use strum_macros::{Display, EnumString};
+
+use crate::helpers::{runner, Database, Paths, RepositoryError, SecretStore, SecretStores, SecretType, Vault};
+
+type Result<T> = ::std::result::Result<T, RepositoryError>;
+
+/**
+ * Key of the random key local backups encrypt secrets with, in the selected secret backend.
+ */
+pub const LOCAL_BACKUP_KEY: &str = "local-backup-key";
+
+const PREFIX: &str = "authenticator-";
+const EXTENSION: &str = ".db";
+const TIMESTAMP: &str = "%Y%m%dT%H%M%S%3fZ";
+
+const KEEP_DAILY: usize = 7;
+const KEEP_OTHERS: usize = 10;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, EnumString, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum BackupReason {
+    Daily,
+    Import,
+    Migration,
+    Restore,
+}
+
+/**
+ * A snapshot of the database, with the number of groups and accounts in it.
+ * `secrets` tells whether the secrets of its accounts were saved along, sealed with the local backup key.
+ */
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LocalBackup {
+    pub path: PathBuf,
+    pub taken: DateTime<Utc>,
+    pub reason: BackupReason,
+    pub groups: u32,
+    pub accounts: u32,
+    pub secrets: bool,
+}
+
+/**
+ * Timestamped copies of the database of a profile, taken before imports, migrations and restores, and daily.
+ * The secrets of accounts are not in the database: they are added to each copy when their backends are open,
+ * so that accounts deleted since can be restored along with their secrets.
+ */
+pub struct LocalBackups {
+    directory: PathBuf,
+}
+
+impl LocalBackups {
+    pub fn new(directory: PathBuf) -> LocalBackups {
+        LocalBackups { directory }
+    }
+
+    /**
+     * Backups of the current profile.
+     */
+    pub fn open() -> LocalBackups {
+        Self::new(Paths::backups_path())
+    }
+
+    /**
+     * None for in-memory databases, which have no file to restore.
+     */
+    pub fn take(&self, connection: &Connection, reason: BackupReason) -> Result<Option<LocalBackup>> {
+        self.take_with(connection, reason, Utc::now(), SecretStores::store)
+    }
+
+    /**
+     * Takes the daily backup, unless there is one from the last 24 hours.
+     */
+    pub fn daily_if_due(&self, connection: &Connection) -> Result<Option<LocalBackup>> {
+        let last_daily = self.list()?.into_iter().find(|backup| backup.reason == BackupReason::Daily);
+
+        match last_daily {
+            Some(backup) if Utc::now() - backup.taken < Duration::hours(24) => Ok(None),
+            _ => self.take(connection, BackupReason::Daily),
+        }
+    }
+
+    /**
+     * Latest first. Files that cannot be read as backups are skipped.
+     */
+    pub fn list(&self) -> Result<Vec<LocalBackup>> {
+        if !self.directory.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut backups = fs::read_dir(&self.directory)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter_map(|path| {
+                let (taken, reason) = Self::parse(path.file_name()?.to_str()?)?;
+
+                match Self::describe(path.as_path(), taken, reason) {
+                    Ok(backup) => Some(backup),
+                    Err(e) => {
+                        warn!("Skipping unreadable backup {}: {:?}", path.display(), e);
+                        None
+                    }
+                }
+            })
+            .collect::<Vec<LocalBackup>>();
+
+        backups.sort_by_key(|backup| std::cmp::Reverse(backup.taken));
+        Ok(backups)
+    }
+
+    /**
+     * Replaces the database with the backup, once the current one is backed up in turn, then puts back
+     * the secrets missing from their backends. Returns how many secrets were put back.
+     */
+    pub fn restore(&self, connection: &mut Connection, backup: &LocalBackup) -> Result<usize> {
+        self.restore_with(connection, backup, SecretStores::store)
+    }
+
+    fn take_with<F>(&self, connection: &Connection, reason: BackupReason, now: DateTime<Utc>, store: F) -> Result<Option<LocalBackup>>
+    where
+        F: Fn(&SecretType) -> Result<Box<dyn SecretStore>>,
+    {
+        if connection.path().is_none_or(str::is_empty) {
+            return Ok(None);
+        }
+
+        fs::create_dir_all(&self.directory)?;
+
+        let path = self.directory.join(format!("{}{}-{}{}", PREFIX, now.format(TIMESTAMP), reason, EXTENSION));
+        let path_str = path
+            .to_str()
+            .ok_or_else(|| RepositoryError::BackupError(format!("Invalid backup path {}", path.display())))?;
+
+        connection.execute("VACUUM INTO ?1", params![path_str])?;
+
+        // migrations run before any secret backend is unlocked, and leave secrets alone anyway
+        if reason != BackupReason::Migration {
+            match Self::sealed_secrets(connection, &store) {
+                Ok(sealed) => {
+                    let backup = Connection::open(&path)?;
+                    backup.execute("CREATE TABLE backup_secrets (content BLOB NOT NULL)", [])?;
+                    backup.execute("INSERT INTO backup_secrets (content) VALUES (?1)", params![sealed])?;
+                }
+                Err(e) => warn!("Backing up {} without secrets: {:?}", path.display(), e),
+            }
+        }
+
+        info!("Backed up database to {}", path.display());
+
+        self.rotate()?;
+
+        Self::describe(path.as_path(), now, reason).map(Some)
+    }
+
+    fn restore_with<F>(&self, connection: &mut Connection, backup: &LocalBackup, store: F) -> Result<usize>
+    where
+        F: Fn(&SecretType) -> Result<Box<dyn SecretStore>>,
+    {
+        let db_path = connection
+            .path()
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+            .ok_or_else(|| RepositoryError::BackupError("Cannot restore an in-memory database".to_owned()))?;
+
+        let sealed = Self::open_read_only(backup.path.as_path()).and_then(|backup| Self::sealed_content(&backup))?;
+
+        self.take_with(connection, BackupReason::Restore, Utc::now(), &store)?;
+
+        // the file is only replaced once nothing has it open
+        *connection = Connection::open_in_memory()?;
+        let copied = fs::copy(&backup.path, &db_path);
+        *connection = Connection::open_with_flags(&db_path, OpenFlags::default())?;
+        copied?;
+
+        connection.execute("DROP TABLE IF EXISTS backup_secrets", [])?;
+        runner::migrate(connection).map_err(|e| RepositoryError::BackupError(format!("{:?}", e)))?;
+
+        info!("Restored database from {}", backup.path.display());
+
+        match sealed {
+            Some(sealed) => Self::restore_secrets(connection, sealed.as_slice(), &store),
+            None => Ok(0),
+        }
+    }
+
+    fn restore_secrets<F>(connection: &Connection, sealed: &[u8], store: &F) -> Result<usize>
+    where
+        F: Fn(&SecretType) -> Result<Box<dyn SecretStore>>,
+    {
+        let Some(key) = store(&SecretStores::selected())?.secret(LOCAL_BACKUP_KEY)? else {
+            warn!("Local backup key is missing, secrets cannot be restored");
+            return Ok(0);
+        };
+
+        let secrets: BTreeMap<String, String> = serde_yaml::from_slice(&Self::vault(key.as_str()).decrypt(sealed)?)?;
+
+        let mut restored = 0;
+
+        let accounts = Database::load_account_groups(connection, None)?
+            .into_iter()
+            .flat_map(|group| group.entries)
+            .filter(|account| account.secret_type != SecretType::LOCAL);
+
+        for account in accounts {
+            let Some(secret) = secrets.get(&account.uuid) else {
+                continue;
+            };
+
+            let backend = store(&account.secret_type)?;
+
+            if backend.secret(account.uuid.as_str())?.is_none() {
+                backend.upsert(account.label.as_str(), account.uuid.as_str(), secret.as_str())?;
+                restored += 1;
+            }
+        }
+
+        info!("Restored {} secrets", restored);
+        Ok(restored)
+    }
+
+    /**
+     * Secrets of every account, keyed by account uuid.
+     */
+    fn sealed_secrets<F>(connection: &Connection, store: &F) -> Result<Vec<u8>>
+    where
+        F: Fn(&SecretType) -> Result<Box<dyn SecretStore>>,
+    {
+        let accounts = Database::load_account_groups(connection, None)?
+            .into_iter()
+            .flat_map(|group| group.entries)
+            .filter(|account| account.secret_type != SecretType::LOCAL)
+            .collect::<Vec<_>>();
+
+        let mut secret_types = accounts.iter().map(|account| account.secret_type.clone()).collect::<Vec<SecretType>>();
+        secret_types.sort_by_key(|secret_type| secret_type.to_string());
+        secret_types.dedup();
+
+        let mut secrets = BTreeMap::new();
+
+        for secret_type in secret_types {
+            for (uuid, secret) in store(&secret_type)?.all_secrets()? {
+                if accounts.iter().any(|account| account.secret_type == secret_type && account.uuid == uuid) {
+                    secrets.insert(uuid, secret);
+                }
+            }
+        }
+
+        let yaml = serde_yaml::to_string(&secrets)?;
+        Ok(Self::vault(Self::key(store)?.as_str()).seal(yaml.as_bytes(), 1))
+    }
+
+    /**
+     * Generated on first use. Being random, it needs no key stretching.
+     */
+    fn key<F>(store: &F) -> Result<String>
+    where
+        F: Fn(&SecretType) -> Result<Box<dyn SecretStore>>,
+    {
+        let selected = store(&SecretStores::selected())?;
+
+        match selected.secret(LOCAL_BACKUP_KEY)? {
+            Some(key) => Ok(key),
+            None => {
+                let mut bytes = [0u8; 32];
+                rand::thread_rng().fill_bytes(&mut bytes);
+
+                let key = base32::encode(base32::Alphabet::Rfc4648 { padding: false }, &bytes);
+                selected.upsert(gettext("Local backup key").as_str(), LOCAL_BACKUP_KEY, key.as_str())?;
+                Ok(key)
+            }
+        }
+    }
+
+    fn vault(key: &str) -> Vault {
+        Vault::new(PathBuf::new(), key)
+    }
+
+    fn rotate(&self) -> Result<()> {
+        let (daily, others): (Vec<LocalBackup>, Vec<LocalBackup>) = self.list()?.into_iter().partition(|backup| backup.reason == BackupReason::Daily);
+
+        for backup in daily.iter().skip(KEEP_DAILY).chain(others.iter().skip(KEEP_OTHERS)) {
+            info!("Removing old backup {}", backup.path.display());
+            fs::remove_file(&backup.path)?;
+        }
+
+        Ok(())
+    }
+
+    fn parse(name: &str) -> Option<(DateTime<Utc>, BackupReason)> {
+        let (timestamp, reason) = name.strip_prefix(PREFIX)?.strip_suffix(EXTENSION)?.split_once('-')?;
+
+        let taken = NaiveDateTime::parse_from_str(timestamp, TIMESTAMP).ok()?.and_utc();
+        let reason = BackupReason::from_str(reason).ok()?;
+
+        Some((taken, reason))
+    }
+
+    fn describe(path: &Path, taken: DateTime<Utc>, reason: BackupReason) -> Result<LocalBackup> {
+        let backup = Self::open_read_only(path)?;
+
+        let count = |table: &str| -> Result<u32> { Ok(backup.query_row(format!("SELECT COUNT(*) FROM {}", table).as_str(), [], |row| row.get(0))?) };
+
+        Ok(LocalBackup {
+            path: path.to_owned(),
+            taken,
+            reason,
+            groups: count("groups")?,
+            accounts: count("accounts")?,
+            secrets: Self::sealed_content(&backup)?.is_some(),
+        })
+    }
+
+    fn open_read_only(path: &Path) -> Result<Connection> {
+        Ok(Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?)
+    }
+
+    fn sealed_content(backup: &Connection) -> Result<Option<Vec<u8>>> {
+        let has_secrets = backup
+            .query_row("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'backup_secrets'", [], |_| Ok(()))
+            .optional()?
+            .is_some();
+
+        if !has_secrets {
+            return Ok(None);
+        }
+
+        Ok(backup.query_row("SELECT content FROM backup_secrets", [], |row| row.get(0)).optional()?)
+    }
+}
+
+impl BackupReason {
+    pub fn label(&self) -> String {
+        match self {
+            BackupReason::Daily => gettext("Daily"),
+            BackupReason::Import => gettext("Before import"),
+            BackupReason::Migration => gettext("Before upgrade"),
+            BackupReason::Restore => gettext("Before restore"),
+        }
+    }
+}
+
+impl LocalBackup {
+    pub fn taken_label(&self) -> String {
+        self.taken.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::helpers::fixture::Fixture;
+    use crate::helpers::secret_store::tests::MemoryStore;
+    use crate::model::{Account, AccountGroup};
+
+    /**
+     * A group with two accounts, whose secrets are in the keyring returned.
+     */
+    fn fixture() -> (Fixture, MemoryStore) {
+        let fixture = Fixture::new("backups");
+
+        let mut group = AccountGroup::new(
+            0,
+            "group",
+            None,
+            None,
+            false,
+            vec![
+                Account::new(0, 0, "alice", "", SecretType::KEYRING),
+                Account::new(0, 0, "bob", "", SecretType::KEYRING),
+            ],
+        );
+        Database::save_group_and_accounts(&fixture.connection, &mut group).unwrap();
+
+        let keyring = MemoryStore::default();
+        keyring.upsert("alice", group.entries[0].uuid.as_str(), "ALICESECRET").unwrap();
+        keyring.upsert("bob", group.entries[1].uuid.as_str(), "BOBSECRET").unwrap();
+
+        (fixture, keyring)
+    }
+
+    fn backups(fixture: &Fixture) -> LocalBackups {
+        LocalBackups::new(fixture.directory.join("backups"))
+    }
+
+    fn store(keyring: &MemoryStore) -> impl Fn(&SecretType) -> Result<Box<dyn SecretStore>> + '_ {
+        |_: &SecretType| -> Result<Box<dyn SecretStore>> { Ok(Box::new(keyring.clone())) }
+    }
+
+    fn day(day: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 10, day, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn lists_backups_with_their_counts_and_rotates_them() {
+        let (fixture, keyring) = fixture();
+        let backups = backups(&fixture);
+
+        for day in 1..=9 {
+            backups
+                .take_with(&fixture.connection, BackupReason::Daily, self::day(day), store(&keyring))
+                .unwrap();
+        }
+
+        for minute in 0..12 {
+            let taken = day(10) + Duration::minutes(minute);
+            backups.take_with(&fixture.connection, BackupReason::Import, taken, store(&keyring)).unwrap();
+        }
+
+        let migration = backups
+            .take_with(&fixture.connection, BackupReason::Migration, day(11), store(&keyring))
+            .unwrap()
+            .unwrap();
+        assert!(!migration.secrets);
+
+        let listed = backups.list().unwrap();
+        assert_eq!(migration, listed[0]);
+
+        let daily = listed.iter().filter(|backup| backup.reason == BackupReason::Daily).collect::<Vec<_>>();
+        assert_eq!(KEEP_DAILY, daily.len());
+        assert_eq!(day(9), daily[0].taken);
+        assert_eq!(day(3), daily[KEEP_DAILY - 1].taken);
+
+        assert_eq!(KEEP_OTHERS, listed.len() - daily.len());
+
+        assert!(daily.iter().all(|backup| backup.groups == 1 && backup.accounts == 2 && backup.secrets));
+    }
+
+    #[test]
+    fn restores_accounts_along_with_their_secrets() {
+        let (mut fixture, keyring) = fixture();
+        let backups = backups(&fixture);
+
+        let backup = backups
+            .take_with(&fixture.connection, BackupReason::Import, day(1), store(&keyring))
+            .unwrap()
+            .unwrap();
+
+        let group = Database::load_account_groups(&fixture.connection, None).unwrap().remove(0);
+        for account in &group.entries {
+            keyring.remove(account.uuid.as_str()).unwrap();
+            Database::delete_account(&fixture.connection, account.id).unwrap();
+        }
+        Database::delete_group(&fixture.connection, group.id).unwrap();
+
+        let restored = backups.restore_with(&mut fixture.connection, &backup, store(&keyring)).unwrap();
+        assert_eq!(2, restored);
+
+        let accounts = Database::load_account_groups(&fixture.connection, None).unwrap().remove(0).entries;
+        let mut secrets = accounts
+            .iter()
+            .map(|account| keyring.secret(account.uuid.as_str()).unwrap().unwrap())
+            .collect::<Vec<String>>();
+        secrets.sort();
+        assert_eq!(vec!["ALICESECRET", "BOBSECRET"], secrets);
+
+        let before_restore = backups.list().unwrap().remove(0);
+        assert_eq!(BackupReason::Restore, before_restore.reason);
+        assert_eq!(0, before_restore.accounts);
+    }
+}
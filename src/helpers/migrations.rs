@@ -5,15 +5,43 @@ pub mod refinery {
 }
 
 pub mod runner {
+    use log::warn;
     use refinery::{Error, Report};
     use rusqlite::Connection;
     use std::ops::DerefMut;
     use std::sync::{Arc, Mutex};
 
+    use crate::helpers::{BackupReason, LocalBackups};
+
     #[allow(clippy::result_large_err)]
     pub fn run(connection: Arc<Mutex<Connection>>) -> Result<Report, Error> {
         let mut connection = connection.lock().expect("Could not get a connection lock to run migrations");
         let connection = connection.deref_mut();
-        crate::helpers::refinery::migrations::runner().run(connection)
+        migrate(connection)
+    }
+
+    /**
+     * Backs the database up first when it has migrations to apply. A failed backup is logged rather than
+     * keeping the application from starting.
+     */
+    #[allow(clippy::result_large_err)]
+    pub fn migrate(connection: &mut Connection) -> Result<Report, Error> {
+        let runner = crate::helpers::refinery::migrations::runner();
+
+        let latest = runner.get_migrations().iter().map(|migration| migration.version()).max();
+        // fails on a new database, which has nothing to back up anyway
+        let applied = runner
+            .get_last_applied_migration(connection)
+            .ok()
+            .flatten()
+            .map(|migration| migration.version());
+
+        if applied.is_some() && applied < latest {
+            if let Err(e) = LocalBackups::open().take(connection, BackupReason::Migration) {
+                warn!("Could not back up the database before migrating it: {:?}", e);
+            }
+        }
+
+        runner.run(connection)
     }
 }
@@ -1,17 +1,53 @@
 mod backup;
+mod brand_icons;
+mod camera;
+mod clipboard;
+mod clock;
 mod database;
+#[cfg(test)]
+mod fixture;
+mod gauth_export;
 mod icon_parser;
+mod icon_store;
+mod integrity;
 mod keyring;
+mod keyutils;
+mod local_backups;
 mod migrations;
+mod open_uri;
 mod paths;
+mod profiles;
 mod qr_code;
+mod qr_image;
 mod repository_error;
+mod screenshot;
+mod secret_store;
+mod sync;
+mod vault;
+mod webdav;
 
 pub use self::backup::*;
+pub use self::brand_icons::*;
+pub use self::camera::*;
+pub use self::clipboard::*;
+pub use self::clock::*;
 pub use self::database::*;
+pub use self::gauth_export::*;
 pub use self::icon_parser::*;
+pub use self::icon_store::*;
+pub use self::integrity::*;
 pub use self::keyring::*;
+pub use self::keyutils::*;
+pub use self::local_backups::*;
 pub use self::migrations::*;
+pub use self::open_uri::*;
 pub use self::paths::*;
+pub use self::profiles::*;
 pub use self::qr_code::*;
+pub use self::qr_image::*;
 pub use self::repository_error::*;
+pub use self::screenshot::*;
+pub use self::secret_store::*;
+pub use self::sync::*;
+pub use self::vault::*;
+pub use self::webdav::*;
@@ -0,0 +1,72 @@
+use gio::{BusType, DBusCallFlags};
+use glib::{Variant, VariantDict, VariantTy};
+use log::{debug, warn};
+use url::Url;
+
+use crate::helpers::{RepositoryError, PORTAL_BUS_NAME, PORTAL_PATH};
+
+const OPEN_URI_INTERFACE: &str = "org.freedesktop.portal.OpenURI";
+
+pub struct OpenUri;
+
+impl OpenUri {
+    /**
+     * Opens `uri` with the default browser through the xdg-desktop-portal, which works in sandboxes too.
+     * Returns once the portal has taken the request: the desktop may still ask which application to use.
+     */
+    pub async fn open(uri: &Url) -> Result<(), RepositoryError> {
+        let connection = gio::bus_get_future(BusType::Session).await.map_err(|e| {
+            warn!("OpenURI portal error: {}", e);
+            Self::unavailable()
+        })?;
+
+        connection
+            .call_future(
+                Some(PORTAL_BUS_NAME),
+                PORTAL_PATH,
+                OPEN_URI_INTERFACE,
+                "OpenURI",
+                Some(&Self::parameters(uri)),
+                Some(VariantTy::new("(o)").unwrap()),
+                DBusCallFlags::NONE,
+                -1,
+            )
+            .await
+            .map_err(|e| {
+                warn!("OpenURI portal error: {}", e);
+                Self::unavailable()
+            })?;
+
+        debug!("Opened {}", uri);
+
+        Ok(())
+    }
+
+    /**
+     * `(ssa{sv})` arguments of `OpenURI`, without a parent window: the portal then centres its dialogs, if any.
+     */
+    fn parameters(uri: &Url) -> Variant {
+        Variant::tuple_from_iter(["".into(), uri.as_str().into(), VariantDict::new(None).end()] as [Variant; 3])
+    }
+
+    fn unavailable() -> RepositoryError {
+        RepositoryError::OpenUriError("Could not open the website".to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+
+    use super::OpenUri;
+
+    #[test]
+    fn parameters() {
+        let parameters = OpenUri::parameters(&Url::parse("https://github.com/login").unwrap());
+
+        assert_eq!("(ssa{sv})", parameters.type_().as_str());
+        assert_eq!(Some("".to_owned()), parameters.child_value(0).get::<String>());
+        assert_eq!(Some("https://github.com/login".to_owned()), parameters.child_value(1).get::<String>());
+        assert_eq!(0, parameters.child_value(2).n_children());
+    }
+}
@@ -3,7 +3,9 @@ use rusqlite::Connection;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
-use crate::helpers::{Database, Keyring, RepositoryError, SecretType};
+use crate::helpers::{Database, Profiles, RepositoryError, SecretStores, SecretType};
+use crate::model::Account;
+use crate::NAMESPACE;
 
 pub struct Paths;
 
@@ -14,6 +16,18 @@ impl Paths {
         path
     }
 
+    pub fn vault_path() -> PathBuf {
+        let mut path = Self::path();
+        path.push("vault.bin");
+        path
+    }
+
+    pub fn backups_path() -> PathBuf {
+        let mut path = Self::path();
+        path.push("backups");
+        path
+    }
+
     pub fn icons_path(filename: &str) -> std::path::PathBuf {
         let mut path = Self::path();
         path.push("icons");
@@ -25,7 +39,14 @@ impl Paths {
         path
     }
 
+    /**
+     * Data directory of the current profile.
+     */
     pub fn path() -> PathBuf {
+        Profiles::dir(&Self::base_path(), Profiles::current().as_str())
+    }
+
+    pub fn base_path() -> PathBuf {
         match directories::ProjectDirs::from("uk.co", "grumlimited", "authenticator-rs") {
             Some(project_dirs) => project_dirs.data_dir().into(),
             None => match std::env::current_dir() {
@@ -42,6 +63,15 @@ impl Paths {
         }
     }
 
+    /**
+     * Application settings, unless the schema is not installed - as when running tests.
+     */
+    pub fn settings() -> Option<gio::Settings> {
+        gio::SettingsSchemaSource::default()
+            .and_then(|source| source.lookup(NAMESPACE, true))
+            .map(|_| gio::Settings::new(NAMESPACE))
+    }
+
     pub fn check_configuration_dir() -> Result<(), RepositoryError> {
         let base = Self::path();
 
@@ -60,27 +90,34 @@ impl Paths {
         Ok(())
     }
 
+    /**
+     * Moves plaintext (LOCAL) secrets, e.g. freshly imported ones, to the selected secret backend.
+     */
     pub fn update_keyring_secrets(connection: Arc<Mutex<Connection>>) -> Result<(), RepositoryError> {
-        let connection = connection.lock().unwrap_or_else(|poisoned| {
-            warn!("Database connection mutex was poisoned. Recovering.");
-            poisoned.into_inner()
-        });
+        let connection = Database::lock(&connection);
 
         let accounts = Database::load_account_groups(&connection, None)?;
 
-        accounts
+        let local_accounts = accounts
             .iter()
             .flat_map(|group| group.entries.iter().cloned())
             .filter(|account| account.secret_type == SecretType::LOCAL)
-            .for_each(|ref mut account| {
-                info!("Adding {} to keyring", account.label);
-                Keyring::upsert(account.label.as_str(), account.id, account.secret.as_str()).unwrap();
-                "".clone_into(&mut account.secret);
+            .collect::<Vec<Account>>();
 
-                account.secret_type = SecretType::KEYRING;
-                Database::update_account(&connection, account).unwrap();
-            });
+        if local_accounts.is_empty() {
+            return Ok(());
+        }
 
-        Ok(())
+        let secret_type = SecretStores::selected();
+        let store = SecretStores::store(&secret_type)?;
+
+        local_accounts.into_iter().try_for_each(|ref mut account| {
+            info!("Adding {} to {}", account.label, secret_type);
+            store.upsert(account.label.as_str(), account.uuid.as_str(), account.secret.as_str())?;
+            "".clone_into(&mut account.secret);
+
+            account.secret_type = secret_type.clone();
+            Database::update_account(&connection, account).map(|_| ())
+        })
     }
 }
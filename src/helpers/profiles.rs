@@ -0,0 +1,258 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use gio::prelude::SettingsExt;
+use log::{info, warn};
+
+use crate::helpers::{Paths, RepositoryError};
+
+type Result<T> = ::std::result::Result<T, RepositoryError>;
+
+pub const DEFAULT_PROFILE: &str = "default";
+const PROFILES_DIR: &str = "profiles";
+const KEYRING_NAMESPACE: &str = "authenticator-rs";
+
+/**
+ * Profile of this session, see Profiles::set_current().
+ */
+static CURRENT: Mutex<Option<String>> = Mutex::new(None);
+
+/**
+ * Named sets of accounts, e.g. "work" and "personal", each with its own database, icons,
+ * vault file and keyring namespace.
+ *
+ * The default profile keeps the top-level data directory and the original keyring attributes,
+ * so that installs predating profiles carry on as is. Other profiles live beside it, in `<data directory>-profiles/<name>`:
+ * deleting, resetting or backing up the default profile leaves them alone.
+ */
+pub struct Profiles;
+
+impl Profiles {
+    pub fn current() -> String {
+        let current = CURRENT.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        current.clone().unwrap_or_else(|| DEFAULT_PROFILE.to_owned())
+    }
+
+    pub fn is_default() -> bool {
+        Self::current() == DEFAULT_PROFILE
+    }
+
+    /**
+     * Switches every path and keyring lookup over to the given profile. Callers are expected
+     * to reopen the database connection afterwards.
+     */
+    pub fn set_current(name: &str) -> Result<()> {
+        Self::validate(name)?;
+
+        let mut current = CURRENT.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        *current = Some(name.to_owned());
+
+        info!("Using profile {}", name);
+        Ok(())
+    }
+
+    /**
+     * Profile names end up in paths and keyring attributes: only letters, digits, '-' and '_' are allowed.
+     */
+    pub fn validate(name: &str) -> Result<()> {
+        let valid = !name.is_empty() && name.len() <= 64 && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+        if valid {
+            Ok(())
+        } else {
+            Err(RepositoryError::ProfileError(format!("Invalid profile name [{}]", name)))
+        }
+    }
+
+    pub fn dir(base: &Path, name: &str) -> PathBuf {
+        if name == DEFAULT_PROFILE {
+            base.to_path_buf()
+        } else {
+            Self::profiles_dir(base).join(name)
+        }
+    }
+
+    fn profiles_dir(base: &Path) -> PathBuf {
+        let name = base.file_name().map_or(KEYRING_NAMESPACE.into(), |name| name.to_string_lossy());
+        base.with_file_name(format!("{}-{}", name, PROFILES_DIR))
+    }
+
+    /**
+     * Moves the profiles created inside the default profile's directory, under `profiles/<name>`, beside it.
+     */
+    pub fn move_legacy() {
+        if let Err(e) = Self::move_legacy_in(&Paths::base_path()) {
+            warn!("Could not move profiles out of {}: {:?}", Paths::base_path().display(), e);
+        }
+    }
+
+    pub fn move_legacy_in(base: &Path) -> Result<()> {
+        let legacy = base.join(PROFILES_DIR);
+
+        if !legacy.is_dir() {
+            return Ok(());
+        }
+
+        for entry in std::fs::read_dir(&legacy)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let target = Self::dir(base, name.as_str());
+
+            if Self::validate(name.as_str()).is_err() || name == DEFAULT_PROFILE || target.exists() {
+                warn!("Leaving profile directory {} where it is", entry.path().display());
+                continue;
+            }
+
+            std::fs::create_dir_all(Self::profiles_dir(base))?;
+            std::fs::rename(entry.path(), &target)?;
+            info!("Moved profile {} to {}", name, target.display());
+        }
+
+        if let Err(e) = std::fs::remove_dir(&legacy) {
+            warn!("Could not remove {}: {:?}", legacy.display(), e);
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Value of the keyring `application` attribute. Other profiles get their own value rather than an
+     * extra attribute, as Secret Service searches would otherwise match their items from the default profile.
+     */
+    pub fn keyring_namespace() -> String {
+        Self::namespace(Self::current().as_str(), "/")
+    }
+
+    /**
+     * Prefix of the kernel key descriptions, e.g. `authenticator-rs:work:`.
+     */
+    pub fn keyutils_prefix() -> String {
+        format!("{}:", Self::namespace(Self::current().as_str(), ":"))
+    }
+
+    fn namespace(name: &str, separator: &str) -> String {
+        if name == DEFAULT_PROFILE {
+            KEYRING_NAMESPACE.to_owned()
+        } else {
+            format!("{}{}{}", KEYRING_NAMESPACE, separator, name)
+        }
+    }
+
+    pub fn list() -> Result<Vec<String>> {
+        Self::list_in(&Paths::base_path())
+    }
+
+    /**
+     * The default profile, followed by the others in alphabetical order.
+     */
+    pub fn list_in(base: &Path) -> Result<Vec<String>> {
+        let mut profiles = match std::fs::read_dir(Self::profiles_dir(base)) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_dir())
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .filter(|name| name != DEFAULT_PROFILE && Self::validate(name).is_ok())
+                .collect::<Vec<String>>(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(RepositoryError::IoError(e)),
+        };
+
+        profiles.sort();
+        profiles.insert(0, DEFAULT_PROFILE.to_owned());
+        Ok(profiles)
+    }
+
+    pub fn create(name: &str) -> Result<()> {
+        Self::validate(name)?;
+        std::fs::create_dir_all(Self::dir(&Paths::base_path(), name))?;
+        Ok(())
+    }
+
+    /**
+     * Profile used last time the graphical interface ran, if it still exists.
+     */
+    pub fn last_used() -> Option<String> {
+        let name = Paths::settings()?.string("profile").to_string();
+
+        match Self::list() {
+            Ok(profiles) if profiles.contains(&name) => Some(name),
+            Ok(_) => {
+                warn!("Last used profile [{}] no longer exists", name);
+                None
+            }
+            Err(e) => {
+                warn!("Could not list profiles: {:?}", e);
+                None
+            }
+        }
+    }
+
+    pub fn remember(name: &str) {
+        if let Some(Err(e)) = Paths::settings().map(|settings| settings.set_string("profile", name)) {
+            warn!("Could not save profile [{}] in settings: {:?}", name, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_profile_names() {
+        assert!(Profiles::validate("work").is_ok());
+        assert!(Profiles::validate("personal_2-fa").is_ok());
+
+        assert!(Profiles::validate("").is_err());
+        assert!(Profiles::validate("../work").is_err());
+        assert!(Profiles::validate("my work").is_err());
+        assert!(Profiles::validate(&"a".repeat(65)).is_err());
+    }
+
+    #[test]
+    fn default_profile_keeps_top_level_directory_and_namespace() {
+        let base = PathBuf::from("/data");
+
+        assert_eq!(PathBuf::from("/data"), Profiles::dir(&base, DEFAULT_PROFILE));
+        assert_eq!(PathBuf::from("/data-profiles/work"), Profiles::dir(&base, "work"));
+
+        assert_eq!("authenticator-rs", Profiles::namespace(DEFAULT_PROFILE, "/"));
+        assert_eq!("authenticator-rs/work", Profiles::namespace("work", "/"));
+    }
+
+    #[test]
+    fn lists_default_profile_first() {
+        let base = std::env::temp_dir().join(format!("authenticator-rs-profiles-{}", uuid::Uuid::new_v4()));
+
+        assert_eq!(vec![DEFAULT_PROFILE.to_owned()], Profiles::list_in(&base).unwrap());
+
+        std::fs::create_dir_all(Profiles::dir(&base, "work")).unwrap();
+        std::fs::create_dir_all(Profiles::dir(&base, "personal")).unwrap();
+        std::fs::create_dir_all(Profiles::dir(&base, "not valid")).unwrap();
+
+        assert_eq!(vec!["default", "personal", "work"], Profiles::list_in(&base).unwrap());
+        assert!(!base.exists());
+
+        std::fs::remove_dir_all(Profiles::profiles_dir(&base)).unwrap();
+    }
+
+    #[test]
+    fn moves_profiles_out_of_the_default_one() {
+        let base = std::env::temp_dir().join(format!("authenticator-rs-legacy-{}", uuid::Uuid::new_v4()));
+
+        std::fs::create_dir_all(base.join(PROFILES_DIR).join("work")).unwrap();
+        std::fs::write(base.join(PROFILES_DIR).join("work").join("authenticator.db"), b"work").unwrap();
+
+        Profiles::move_legacy_in(&base).unwrap();
+
+        assert_eq!(b"work".to_vec(), std::fs::read(Profiles::dir(&base, "work").join("authenticator.db")).unwrap());
+        assert!(!base.join(PROFILES_DIR).exists());
+        assert_eq!(vec!["default", "work"], Profiles::list_in(&base).unwrap());
+
+        // nothing left to move
+        Profiles::move_legacy_in(&base).unwrap();
+
+        std::fs::remove_dir_all(&base).unwrap();
+        std::fs::remove_dir_all(Profiles::profiles_dir(&base)).unwrap();
+    }
+}
@@ -1,7 +1,10 @@
-use crate::helpers::QrCodeResult::{Invalid, Valid};
+use crate::helpers::{GAuthExport, QrImage, TILE_SIZES};
+use crate::model::AccountType;
+use gettextrs::gettext;
+use image::GrayImage;
 use log::warn;
 use percent_encoding::percent_decode_str;
-use rqrr::PreparedImage;
+use rqrr::{DeQRError, PreparedImage};
 use url::Url;
 
 #[derive(PartialEq, Debug)]
@@ -10,7 +13,7 @@ pub enum QrCodeResult {
     Invalid(String),
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct QrCode {
     pub qr_code_payload: String,
 }
@@ -49,47 +52,160 @@ impl QrCode {
         self.qr_code_payload.clone()
     }
 
-    /// Process an image file at `path` and attempt to decode a QR code.
-    /// Returns `Valid(QrCode)` on success or `Invalid(String)` with a
-    /// descriptive message on failure.
-    pub async fn process_qr_code(path: String) -> QrCodeResult {
-        match image::open(&path) {
-            Ok(img) => {
-                let luma_img = img.to_luma8();
-                let mut prepared = PreparedImage::prepare(luma_img);
-                let grids = prepared.detect_grids();
-
-                match grids.len() {
-                    0 => {
-                        warn!("No QR grids found in {}", path);
-                        Invalid(format!("No QR codes found in {}", path))
-                    }
-                    n => {
-                        if n > 1 {
-                            warn!("Multiple QR grids found in {}, attempting first", path);
-                        }
-
-                        match grids[0].decode() {
-                            Ok((_, content)) => Valid(QrCode::new(content)),
-                            Err(e) => {
-                                warn!("Failed to decode QR from {}: {}", path, e);
-                                Invalid(format!("Failed to decode QR code: {}", e))
-                            }
-                        }
-                    }
+    /// Steam Guard for `otpauth://steam/...` URIs and for `otpauth://totp/...` ones issued by Steam,
+    /// be it through the `issuer` parameter or the `Steam:` label prefix. Yandex Key for `otpauth://yaotp/...`,
+    /// Mobile-OTP for `motp://...`. TOTP otherwise.
+    pub fn account_type(&self) -> AccountType {
+        // labels of motp:// URIs are not always valid URL authorities
+        if self.qr_code_payload.get(..7).is_some_and(|scheme| scheme.eq_ignore_ascii_case("motp://")) {
+            return AccountType::Motp;
+        }
+
+        let Ok(url) = Url::parse(self.qr_code_payload.as_str()) else {
+            return AccountType::Totp;
+        };
+
+        if url.host_str().is_some_and(|host| host.eq_ignore_ascii_case("yaotp")) {
+            return AccountType::Yandex;
+        }
+
+        let is_steam = |value: &str| value.eq_ignore_ascii_case("steam");
+
+        let label = percent_decode_str(url.path().trim_start_matches('/')).decode_utf8_lossy().into_owned();
+        let issued_by_steam = url
+            .query_pairs()
+            .any(|(k, v)| (k.eq_ignore_ascii_case("issuer") || k.eq_ignore_ascii_case("encoder")) && is_steam(&v));
+
+        if url.host_str().is_some_and(is_steam) || issued_by_steam || label.split_once(':').is_some_and(|(issuer, _)| is_steam(issuer)) {
+            AccountType::Steam
+        } else {
+            AccountType::Totp
+        }
+    }
+
+    /// Label of `otpauth://` payloads, e.g. `Example:alice`, telling several QR codes apart.
+    pub fn label(&self) -> Option<String> {
+        let url = Url::parse(self.qr_code_payload.as_str()).ok()?;
+        let label = percent_decode_str(url.path().trim_start_matches('/')).decode_utf8_lossy().into_owned();
+
+        Some(label).filter(|label| !label.is_empty())
+    }
+
+    /// Account part of the label, e.g. `alice` for `Example:alice`.
+    pub fn username(&self) -> Option<String> {
+        let label = self.label()?;
+        let username = label.split_once(':').map(|(_, username)| username).unwrap_or(label.as_str()).trim();
+
+        Some(username.to_owned()).filter(|username| !username.is_empty())
+    }
+
+    /// Decodes every QR code in the image file at `path`, without duplicates.
+    /// Fails with a descriptive message unless at least one is decoded.
+    pub async fn process_qr_codes(path: String) -> Result<Vec<QrCode>, String> {
+        let img = image::open(&path).map_err(|e| {
+            warn!("Failed to open image {}: {}", path, e);
+            format!("Failed to open image: {}", e)
+        })?;
+
+        Self::process_qr_image(img.to_luma8()).await.inspect_err(|e| warn!("{} in {}", e, path))
+    }
+
+    /// Decodes every QR code in an image already in memory, such as one pasted from the clipboard.
+    /// Retrying on preprocessed copies of large screenshots takes a while, hence a separate thread.
+    pub async fn process_qr_image(image: GrayImage) -> Result<Vec<QrCode>, String> {
+        let decoded = gio::spawn_blocking(move || Self::decode_image(image)).await;
+
+        match decoded {
+            Ok((qr_codes, _)) if !qr_codes.is_empty() => Ok(qr_codes),
+            Ok((_, Some(e))) => Err(format!("Failed to decode QR code: {}", e)),
+            Ok((_, None)) | Err(_) => Err(gettext("No QR codes found")),
+        }
+    }
+
+    /// Decodes every QR code in `image`, retrying on inverted and thresholded copies of it,
+    /// then, if none was found, on upscaled tiles of it for codes too small to be detected.
+    /// Tiles are only looked into when nothing else worked, being the slowest by far.
+    pub fn decode_image(image: GrayImage) -> (Vec<QrCode>, Option<DeQRError>) {
+        let mut decoded = (vec![], None);
+
+        for pass in QrImage::passes(&image) {
+            Self::merge(&mut decoded, Self::decode_frame(pass));
+        }
+
+        for size in TILE_SIZES {
+            if !decoded.0.is_empty() {
+                break;
+            }
+
+            for (x, y) in QrImage::tiles(image.width(), image.height(), size) {
+                for tile in QrImage::upscaled_tile(&image, x, y, size) {
+                    Self::merge(&mut decoded, Self::decode_frame(tile));
                 }
             }
-            Err(e) => {
-                warn!("Failed to open image {}: {}", path, e);
-                Invalid(format!("Failed to open image: {}", e))
+        }
+
+        decoded
+    }
+
+    fn merge(decoded: &mut (Vec<QrCode>, Option<DeQRError>), (qr_codes, error): (Vec<QrCode>, Option<DeQRError>)) {
+        for qr_code in qr_codes {
+            if !decoded.0.contains(&qr_code) {
+                decoded.0.push(qr_code);
+            }
+        }
+
+        if error.is_some() {
+            decoded.1 = error;
+        }
+    }
+
+    /// Decodes every QR code in `frame`, without duplicates, along with the last error of the grids which could not be decoded.
+    pub fn decode_frame(frame: GrayImage) -> (Vec<QrCode>, Option<DeQRError>) {
+        let mut prepared = PreparedImage::prepare(frame);
+
+        let mut qr_codes: Vec<QrCode> = vec![];
+        let mut error = None;
+
+        for grid in prepared.detect_grids() {
+            match grid.decode() {
+                Ok((_, content)) => {
+                    let qr_code = QrCode::new(content);
+
+                    if !qr_codes.contains(&qr_code) {
+                        qr_codes.push(qr_code);
+                    }
+                }
+                Err(e) => error = Some(e),
             }
         }
+
+        (qr_codes, error)
+    }
+
+    /// Whether the payload is an `otpauth://` URI with a secret, or a Google Authenticator export which can be read.
+    pub fn is_otpauth(&self) -> bool {
+        if self.is_gauth_export() {
+            return GAuthExport::parse(self.qr_code_payload.as_str()).is_ok_and(|export| !export.accounts.is_empty());
+        }
+
+        Url::parse(self.qr_code_payload.as_str())
+            .is_ok_and(|url| url.scheme().eq_ignore_ascii_case("otpauth") && url.query_pairs().any(|(k, v)| k.eq_ignore_ascii_case("secret") && !v.is_empty()))
+    }
+
+    /// Google Authenticator exports, `otpauth-migration://` URIs, hold several accounts to be imported rather than a secret.
+    pub fn is_gauth_export(&self) -> bool {
+        self.qr_code_payload
+            .get(..20)
+            .is_some_and(|scheme| scheme.eq_ignore_ascii_case("otpauth-migration://"))
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use async_std::task;
+
     use super::QrCode;
+    use crate::model::AccountType;
 
     #[test]
     fn extract_secret_end() {
@@ -163,4 +279,112 @@ mod tests {
         let result = qr_code.extract();
         assert_eq!("ABCD", result);
     }
+
+    #[test]
+    fn account_type() {
+        let account_type = |payload: &str| QrCode::new(payload.to_string()).account_type();
+
+        assert_eq!(AccountType::Steam, account_type("otpauth://totp/alice?secret=ABCD&issuer=Steam"));
+        assert_eq!(AccountType::Steam, account_type("otpauth://totp/Steam:alice?secret=ABCD"));
+        assert_eq!(AccountType::Steam, account_type("otpauth://totp/Steam%3Aalice?secret=ABCD"));
+        assert_eq!(AccountType::Steam, account_type("otpauth://steam/alice?secret=ABCD"));
+        assert_eq!(AccountType::Yandex, account_type("otpauth://yaotp/alice?secret=ABCD&pin_length=4"));
+        assert_eq!(AccountType::Motp, account_type("motp://Example:alice?secret=e3152afee62599c8"));
+
+        assert_eq!(AccountType::Totp, account_type("otpauth://totp/Example:alice?secret=ABCD&issuer=Example"));
+        assert_eq!(AccountType::Totp, account_type("otpauth://totp/Steamy:alice?secret=ABCD"));
+        assert_eq!(AccountType::Totp, account_type("ABCD"));
+    }
+
+    #[test]
+    fn label() {
+        let label = |payload: &str| QrCode::new(payload.to_string()).label();
+
+        assert_eq!(Some("Example:alice".to_owned()), label("otpauth://totp/Example%3Aalice?secret=ABCD"));
+        assert_eq!(None, label("otpauth-migration://offline?data=ABCD"));
+        assert_eq!(None, label("ABCD"));
+    }
+
+    #[test]
+    fn username() {
+        let username = |payload: &str| QrCode::new(payload.to_string()).username();
+
+        assert_eq!(
+            Some("alice@example.com".to_owned()),
+            username("otpauth://totp/Example:%20alice%40example.com?secret=ABCD")
+        );
+        assert_eq!(Some("alice".to_owned()), username("otpauth://totp/alice?secret=ABCD"));
+        assert_eq!(None, username("otpauth://totp/Example:?secret=ABCD"));
+        assert_eq!(None, username("ABCD"));
+    }
+
+    #[test]
+    fn is_otpauth() {
+        let is_otpauth = |payload: &str| QrCode::new(payload.to_string()).is_otpauth();
+
+        assert!(is_otpauth("otpauth://totp/Example:alice?secret=JBSWY3DPEHPK3PXP&issuer=Example"));
+        assert!(is_otpauth("OTPAUTH://totp/alice?secret=JBSWY3DPEHPK3PXP"));
+        assert!(is_otpauth(
+            "otpauth-migration://offline?data=CiIKCkhlbGxvId6tvu8SBWFsaWNlGgdFeGFtcGxlIAEoATACEAE%3D"
+        ));
+
+        assert!(!is_otpauth("otpauth://totp/alice?issuer=Example"));
+        assert!(!is_otpauth("otpauth-migration://offline?data=garbage"));
+        assert!(!is_otpauth("https://example.com/?secret=JBSWY3DPEHPK3PXP"));
+        assert!(!is_otpauth("JBSWY3DPEHPK3PXP"));
+    }
+
+    #[test]
+    fn process_every_qr_code() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/two_qr_codes.png");
+
+        let mut payloads: Vec<String> = task::block_on(QrCode::process_qr_codes(path.to_owned()))
+            .unwrap()
+            .into_iter()
+            .map(|qr_code| qr_code.qr_code_payload)
+            .collect();
+        payloads.sort();
+
+        assert_eq!(
+            vec![
+                "otpauth://totp/Example:alice?secret=JBSWY3DPEHPK3PXP&issuer=Example",
+                "otpauth://totp/Example:bob?secret=KRSXG5CTMVRXEZLU&issuer=Example",
+            ],
+            payloads
+        );
+
+        assert!(task::block_on(QrCode::process_qr_codes("missing.png".to_owned())).is_err());
+    }
+
+    #[test]
+    fn process_difficult_images() {
+        let corpus = [
+            // light modules on a dark page
+            ("inverted.png", vec!["otpauth://totp/Example:alice?secret=JBSWY3DPEHPK3PXP&issuer=Example"]),
+            // little contrast, lit from one side
+            ("low_contrast.png", vec!["otpauth://totp/Example:bob?secret=KRSXG5CTMVRXEZLU&issuer=Example"]),
+            // two pixels per module in a large screenshot
+            ("tiny.png", vec!["otpauth://totp/Example:carol?secret=MFRGGZDFMZTWQ2LK&issuer=Example"]),
+            (
+                "mixed_sizes.png",
+                vec![
+                    "otpauth://totp/Example:alice?secret=JBSWY3DPEHPK3PXP&issuer=Example",
+                    "otpauth://totp/Example:dave?secret=GEZDGNBVGY3TQOJQ&issuer=Example",
+                ],
+            ),
+        ];
+
+        for (file, expected) in corpus {
+            let path = format!("{}/tests/fixtures/corpus/{}", env!("CARGO_MANIFEST_DIR"), file);
+
+            let mut payloads: Vec<String> = task::block_on(QrCode::process_qr_codes(path))
+                .unwrap_or_else(|e| panic!("{}: {}", file, e))
+                .into_iter()
+                .map(|qr_code| qr_code.qr_code_payload)
+                .collect();
+            payloads.sort();
+
+            assert_eq!(expected, payloads, "{}", file);
+        }
+    }
 }
@@ -0,0 +1,133 @@
+use image::imageops::{self, FilterType};
+use image::{GrayImage, Luma};
+
+/// Sides of the square tiles small QR codes are looked for in, largest first.
+pub const TILE_SIZES: [u32; 2] = [512, 256];
+
+/**
+ * Copies of an image on which `rqrr` finds QR codes it misses on the original:
+ * dark mode screenshots, washed out or unevenly lit photos, small codes in large screenshots.
+ */
+pub struct QrImage;
+
+impl QrImage {
+    /// The image itself, then its inverted, thresholded and inverted thresholded copies.
+    pub fn passes(image: &GrayImage) -> Vec<GrayImage> {
+        let mut inverted = image.clone();
+        imageops::invert(&mut inverted);
+
+        let thresholded = Self::threshold(image);
+
+        let mut inverted_thresholded = thresholded.clone();
+        imageops::invert(&mut inverted_thresholded);
+
+        vec![image.clone(), inverted, thresholded, inverted_thresholded]
+    }
+
+    /**
+     * Black or white depending on whether each pixel is darker than the pixels around it,
+     * which evens out gradients and low contrast. Means are read off a summed-area table.
+     */
+    pub fn threshold(image: &GrayImage) -> GrayImage {
+        let (width, height) = image.dimensions();
+        let radius = (width.max(height) / 32).clamp(8, 40);
+
+        let stride = width as usize + 1;
+        let mut sums = vec![0u64; stride * (height as usize + 1)];
+
+        for y in 0..height as usize {
+            let mut row = 0u64;
+
+            for x in 0..width as usize {
+                row += image.get_pixel(x as u32, y as u32)[0] as u64;
+                sums[(y + 1) * stride + x + 1] = sums[y * stride + x + 1] + row;
+            }
+        }
+
+        GrayImage::from_fn(width, height, |x, y| {
+            let (x0, y0) = (x.saturating_sub(radius) as usize, y.saturating_sub(radius) as usize);
+            let (x1, y1) = ((x + radius + 1).min(width) as usize, (y + radius + 1).min(height) as usize);
+
+            let sum = sums[y1 * stride + x1] + sums[y0 * stride + x0] - sums[y0 * stride + x1] - sums[y1 * stride + x0];
+            let mean = sum / ((x1 - x0) * (y1 - y0)) as u64;
+
+            // a little darker than the mean, so that flat areas stay white
+            if (image.get_pixel(x, y)[0] as u64) * 100 < mean * 97 {
+                Luma([0])
+            } else {
+                Luma([255])
+            }
+        })
+    }
+
+    /**
+     * Top left corners of `size` tiles covering a `width` by `height` image. Tiles overlap by a quarter,
+     * so that codes up to a quarter of a tile wide lie wholly within one of them.
+     */
+    pub fn tiles(width: u32, height: u32, size: u32) -> Vec<(u32, u32)> {
+        let starts = |length: u32| -> Vec<u32> {
+            if length <= size {
+                return vec![0];
+            }
+
+            let last = length - size;
+            let mut starts: Vec<u32> = (0..last).step_by((size * 3 / 4) as usize).collect();
+            starts.push(last);
+            starts
+        };
+
+        let xs = starts(width);
+
+        starts(height).into_iter().flat_map(|y| xs.iter().map(move |x| (*x, y))).collect()
+    }
+
+    /**
+     * The `size` tile at `(x, y)` twice as large, then thresholded, so that modules of a pixel or two become detectable.
+     * Catmull-Rom keeps module edges sharper than bilinear filtering, without the steps of nearest neighbour.
+     */
+    pub fn upscaled_tile(image: &GrayImage, x: u32, y: u32, size: u32) -> [GrayImage; 2] {
+        let tile = imageops::crop_imm(image, x, y, size, size).to_image();
+        let upscaled = imageops::resize(&tile, tile.width() * 2, tile.height() * 2, FilterType::CatmullRom);
+        let thresholded = Self::threshold(&upscaled);
+
+        [upscaled, thresholded]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma};
+
+    use super::QrImage;
+
+    #[test]
+    fn threshold() {
+        // a dark square on a background going from dark to light
+        let image = GrayImage::from_fn(200, 100, |x, y| {
+            let light = 40 + x as u8;
+            if (95..105).contains(&x) && (45..55).contains(&y) {
+                Luma([light - 20])
+            } else {
+                Luma([light])
+            }
+        });
+
+        let thresholded = QrImage::threshold(&image);
+
+        assert_eq!(0, thresholded.get_pixel(100, 50)[0]);
+        assert_eq!(255, thresholded.get_pixel(100, 10)[0]);
+        assert_eq!(255, thresholded.get_pixel(10, 50)[0]);
+        assert_eq!(255, thresholded.get_pixel(190, 50)[0]);
+    }
+
+    #[test]
+    fn tiles() {
+        assert_eq!(vec![(0, 0)], QrImage::tiles(300, 200, 512));
+        assert_eq!(vec![(0, 0), (384, 0), (488, 0)], QrImage::tiles(1000, 400, 512));
+
+        let tiles = QrImage::tiles(1920, 1080, 256);
+        assert!(tiles.contains(&(1920 - 256, 1080 - 256)));
+        assert!(tiles.iter().all(|(x, y)| x + 256 <= 1920 && y + 256 <= 1080));
+        assert_eq!(10 * 6, tiles.len());
+    }
+}
@@ -1,5 +1,6 @@
 use std::io;
 
+use gettextrs::gettext;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -7,9 +8,46 @@ use thiserror::Error;
 #[allow(clippy::enum_variant_names)]
 pub enum RepositoryError {
     GAuthQrCodeError(String),
+    SteamGuardError(String),
     SqlError(#[from] rusqlite::Error),
     IoError(#[from] io::Error),
     SerialisationError(#[from] serde_yaml::Error),
     KeyringError(#[from] secret_service::Error),
     KeyringDecodingError(#[from] std::string::FromUtf8Error),
+    SecretStoreError(String),
+    ProfileError(String),
+    SyncError(String),
+    BackupError(String),
+    ClockError(String),
+    ScreenshotError(String),
+    CameraError(String),
+    OpenUriError(String),
+    HttpError(#[from] curl::Error),
+}
+
+impl RepositoryError {
+    /**
+     * What to tell the user, translated. Errors of the secret backends, sync, backups, time sources, screenshots, cameras and
+     * websites carry translation keys, the others get a message of their own: their `Debug` output is for the logs.
+     */
+    pub fn message(&self) -> String {
+        match self {
+            RepositoryError::SecretStoreError(key)
+            | RepositoryError::SyncError(key)
+            | RepositoryError::BackupError(key)
+            | RepositoryError::ClockError(key)
+            | RepositoryError::ScreenshotError(key)
+            | RepositoryError::CameraError(key)
+            | RepositoryError::OpenUriError(key) => gettext(key),
+            RepositoryError::GAuthQrCodeError(detail) => format!("{} ({})", gettext("Could not import the Google Authenticator export"), detail),
+            RepositoryError::SteamGuardError(detail) => format!("{} ({})", gettext("Could not import the Steam Guard file"), detail),
+            RepositoryError::ProfileError(detail) => format!("{} ({})", gettext("Could not use this profile"), detail),
+            RepositoryError::SqlError(_) => gettext("Could not read or write the database"),
+            RepositoryError::IoError(_) => gettext("Could not read or write a file"),
+            RepositoryError::SerialisationError(_) => gettext("Could not read or write the accounts file"),
+            RepositoryError::KeyringError(_) => gettext("Could not reach the keyring: is it unlocked?"),
+            RepositoryError::KeyringDecodingError(_) => gettext("A keyring secret is not readable"),
+            RepositoryError::HttpError(_) => gettext("Could not download from the website"),
+        }
+    }
 }
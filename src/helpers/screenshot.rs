@@ -0,0 +1,161 @@
+use std::path::PathBuf;
+
+use gio::{BusType, DBusCallFlags, DBusSignalFlags};
+use glib::{Variant, VariantDict, VariantTy};
+use log::{debug, warn};
+use url::Url;
+
+use crate::helpers::RepositoryError;
+
+pub(crate) const PORTAL_BUS_NAME: &str = "org.freedesktop.portal.Desktop";
+pub(crate) const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+const SCREENSHOT_INTERFACE: &str = "org.freedesktop.portal.Screenshot";
+const REQUEST_INTERFACE: &str = "org.freedesktop.portal.Request";
+
+pub struct Screenshot;
+
+impl Screenshot {
+    /**
+     * Asks the desktop for a screenshot through the xdg-desktop-portal, which works under Wayland and in sandboxes.
+     * Returns the path of the image the portal saved, which the caller is to remove.
+     */
+    pub async fn capture() -> Result<PathBuf, RepositoryError> {
+        let connection = gio::bus_get_future(BusType::Session).await.map_err(|e| {
+            warn!("Screenshot portal error: {}", e);
+            Self::unavailable()
+        })?;
+
+        let sender = connection.unique_name().ok_or_else(Self::unavailable)?;
+
+        let token = format!("authenticator_rs_{}", uuid::Uuid::new_v4().simple());
+        let request_path = Self::request_path(sender.as_str(), token.as_str());
+
+        // subscribing before the call, not to miss a response coming right away
+        let (tx, rx) = async_channel::bounded::<Variant>(1);
+
+        let _subscription = connection.subscribe_to_signal(
+            Some(PORTAL_BUS_NAME),
+            Some(REQUEST_INTERFACE),
+            Some("Response"),
+            Some(request_path.as_str()),
+            None,
+            DBusSignalFlags::NONE,
+            move |signal| {
+                if let Err(e) = tx.try_send(signal.parameters.clone()) {
+                    warn!("Could not send screenshot response: {:?}", e);
+                }
+            },
+        );
+
+        let options = VariantDict::new(None);
+        options.insert("handle_token", token.as_str());
+        options.insert("interactive", false);
+
+        let parameters = Variant::tuple_from_iter(["".into(), options.end()] as [Variant; 2]);
+
+        connection
+            .call_future(
+                Some(PORTAL_BUS_NAME),
+                PORTAL_PATH,
+                SCREENSHOT_INTERFACE,
+                "Screenshot",
+                Some(&parameters),
+                Some(VariantTy::new("(o)").unwrap()),
+                DBusCallFlags::NONE,
+                -1,
+            )
+            .await
+            .map_err(|e| {
+                warn!("Screenshot portal error: {}", e);
+                Self::unavailable()
+            })?;
+
+        let response = rx.recv().await.map_err(|e| {
+            warn!("Screenshot response channel is closed: {:?}", e);
+            Self::unavailable()
+        })?;
+
+        Self::screenshot_path(&response)
+    }
+
+    /**
+     * Object path of the request the portal answers on, predictable from the unique name of the connection.
+     */
+    fn request_path(sender: &str, token: &str) -> String {
+        let sender = sender.trim_start_matches(':').replace('.', "_");
+        format!("{}/request/{}/{}", PORTAL_PATH, sender, token)
+    }
+
+    /**
+     * `response` is the `(ua{sv})` of the `Response` signal: 0 when the screenshot was taken, 1 when cancelled.
+     */
+    fn screenshot_path(response: &Variant) -> Result<PathBuf, RepositoryError> {
+        match response.try_child_value(0).and_then(|code| code.get::<u32>()) {
+            Some(0) => {}
+            Some(1) => return Err(RepositoryError::ScreenshotError("Screenshot cancelled".to_owned())),
+            code => {
+                warn!("Screenshot portal responded with {:?}", code);
+                return Err(Self::unavailable());
+            }
+        }
+
+        let uri = response
+            .try_child_value(1)
+            .filter(|results| results.type_() == VariantTy::VARDICT)
+            .and_then(|results| VariantDict::new(Some(&results)).lookup::<String>("uri").ok().flatten())
+            .ok_or_else(Self::unavailable)?;
+
+        debug!("Screenshot saved to {}", uri);
+
+        Url::parse(uri.as_str())
+            .ok()
+            .and_then(|uri| uri.to_file_path().ok())
+            .ok_or_else(Self::unavailable)
+    }
+
+    fn unavailable() -> RepositoryError {
+        RepositoryError::ScreenshotError("Screenshots are not available".to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glib::{Variant, VariantDict};
+
+    use super::Screenshot;
+    use crate::helpers::RepositoryError;
+
+    #[test]
+    fn request_path() {
+        assert_eq!(
+            "/org/freedesktop/portal/desktop/request/1_42/authenticator_rs_1",
+            Screenshot::request_path(":1.42", "authenticator_rs_1")
+        );
+    }
+
+    #[test]
+    fn screenshot_path() {
+        let response = |code: u32, uri: Option<&str>| {
+            let results = VariantDict::new(None);
+            if let Some(uri) = uri {
+                results.insert("uri", uri);
+            }
+            Variant::tuple_from_iter([code.into(), results.end()] as [Variant; 2])
+        };
+
+        assert_eq!(
+            "/home/alice/Pictures/Screenshot.png",
+            Screenshot::screenshot_path(&response(0, Some("file:///home/alice/Pictures/Screenshot.png")))
+                .unwrap()
+                .to_str()
+                .unwrap()
+        );
+
+        assert!(matches!(
+            Screenshot::screenshot_path(&response(1, None)),
+            Err(RepositoryError::ScreenshotError(key)) if key == "Screenshot cancelled"
+        ));
+        assert!(Screenshot::screenshot_path(&response(0, None)).is_err());
+        assert!(Screenshot::screenshot_path(&response(2, None)).is_err());
+    }
+}
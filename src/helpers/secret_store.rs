@@ -0,0 +1,418 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use gettextrs::gettext;
+use gio::prelude::SettingsExt;
+use log::{debug, info, warn};
+use rusqlite::Connection;
+
+use crate::helpers::{
+    Database, Keyring, Keyutils, Paths, RepositoryError, SecretType, Vault, LOCAL_BACKUP_KEY, SYNC_PASSPHRASE_KEY, WEBDAV_PASSPHRASE_KEY, WEBDAV_PASSWORD_KEY,
+};
+use crate::model::{Account, AccountGroup};
+
+type Result<T> = ::std::result::Result<T, RepositoryError>;
+
+/**
+ * Secrets of every backend in use, as (account uuid, secret) pairs.
+ */
+pub type AllSecrets = HashMap<SecretType, Vec<(String, String)>>;
+
+/**
+ * Secrets are keyed by account uuid.
+ */
+pub trait SecretStore {
+    fn upsert(&self, label: &str, uuid: &str, secret: &str) -> Result<()>;
+
+    fn secret(&self, uuid: &str) -> Result<Option<String>>;
+
+    /**
+     * Removing the secret of an account which has none is not an error.
+     */
+    fn remove(&self, uuid: &str) -> Result<()>;
+
+    fn all_secrets(&self) -> Result<Vec<(String, String)>>;
+
+    /**
+     * Moves a secret still keyed by the account's database id, as stored before accounts had a uuid.
+     * Returns whether there was one.
+     */
+    fn rekey(&self, label: &str, account_id: u32, uuid: &str) -> Result<bool> {
+        let legacy_key = account_id.to_string();
+
+        match self.secret(legacy_key.as_str())? {
+            Some(secret) => {
+                if self.secret(uuid)?.is_none() {
+                    self.upsert(label, uuid, secret.as_str())?;
+                }
+                self.remove(legacy_key.as_str())?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+pub struct SecretStores;
+
+impl SecretStores {
+    pub fn store(secret_type: &SecretType) -> Result<Box<dyn SecretStore>> {
+        match secret_type {
+            SecretType::KEYRING => Ok(Box::new(Keyring)),
+            SecretType::VAULT => Ok(Box::new(Vault::open()?)),
+            SecretType::KEYUTILS => Ok(Box::new(Keyutils)),
+            SecretType::LOCAL => Err(RepositoryError::SecretStoreError("LOCAL secrets are kept in the database".to_owned())),
+        }
+    }
+
+    /**
+     * Backend new secrets go to, as chosen in the settings. Defaults to the Secret Service.
+     */
+    pub fn selected() -> SecretType {
+        let Some(settings) = Paths::settings() else {
+            return SecretType::KEYRING;
+        };
+
+        let value = settings.string("secret-backend");
+
+        match SecretType::from_str(value.as_str()) {
+            Ok(SecretType::LOCAL) | Err(_) => {
+                warn!("Invalid secret backend [{}]", value);
+                SecretType::KEYRING
+            }
+            Ok(secret_type) => secret_type,
+        }
+    }
+
+    /**
+     * Fails rather than abort when the settings schema is not installed, as in containers.
+     */
+    pub fn select(secret_type: &SecretType) -> Result<()> {
+        Paths::settings()
+            .ok_or_else(|| RepositoryError::SecretStoreError("settings_unavailable".to_owned()))?
+            .set_string("secret-backend", secret_type.to_string().as_str())
+            .map_err(|e| RepositoryError::SecretStoreError(format!("{:?}", e)))
+    }
+
+    /**
+     * Backends holding secrets: the selected one, plus any still used by an account.
+     */
+    pub fn in_use(connection: &Connection) -> Result<Vec<SecretType>> {
+        let mut in_use = Database::secret_types(connection)?;
+        in_use.push(Self::selected());
+        in_use.retain(|secret_type| *secret_type != SecretType::LOCAL);
+        in_use.sort_by_key(|secret_type| secret_type.to_string());
+        in_use.dedup();
+        Ok(in_use)
+    }
+
+    pub fn secret(account: &Account) -> Result<Option<String>> {
+        match account.secret_type {
+            SecretType::LOCAL => Ok(Some(account.secret.clone())),
+            ref secret_type => Self::store(secret_type)?.secret(account.uuid.as_str()),
+        }
+    }
+
+    pub fn remove(account: &Account) -> Result<()> {
+        match account.secret_type {
+            SecretType::LOCAL => Ok(()),
+            ref secret_type => Self::store(secret_type)?.remove(account.uuid.as_str()),
+        }
+    }
+
+    pub fn all_secrets(connection: &Connection) -> Result<AllSecrets> {
+        Self::in_use(connection)?
+            .into_iter()
+            .map(|secret_type| Self::store(&secret_type)?.all_secrets().map(|secrets| (secret_type, secrets)))
+            .collect()
+    }
+
+    pub fn set_secrets(group_accounts: &mut [AccountGroup], connection: &Connection) -> Result<()> {
+        let all_secrets = Self::all_secrets(connection)?;
+        Self::associate_secrets(group_accounts, &all_secrets);
+        Ok(())
+    }
+
+    /**
+     * Fills in the secret of each account from its backend. Accounts whose secret cannot be found
+     * are left with an empty one, for the integrity check to report.
+     */
+    pub fn associate_secrets(group_accounts: &mut [AccountGroup], all_secrets: &AllSecrets) {
+        group_accounts
+            .iter_mut()
+            .flat_map(|group| group.entries.iter_mut())
+            .filter(|account| account.secret_type != SecretType::LOCAL)
+            .for_each(|account| {
+                debug!("Loading {} secret for {} ({})", account.secret_type, account.label, account.id);
+
+                let secret = all_secrets
+                    .get(&account.secret_type)
+                    .and_then(|secrets| secrets.iter().find(|(uuid, _)| *uuid == account.uuid));
+
+                match secret {
+                    Some((_, secret)) => account.secret = secret.clone(),
+                    None => warn!("No secret found in {} for {} ({}).", account.secret_type, account.label, account.id),
+                }
+            });
+    }
+
+    /**
+     * Secrets the application keeps for itself rather than for an account, as (key, label) pairs.
+     */
+    pub fn own_secrets() -> Vec<(&'static str, String)> {
+        vec![
+            (SYNC_PASSPHRASE_KEY, gettext("Sync passphrase")),
+            (WEBDAV_PASSWORD_KEY, gettext("WebDAV password")),
+            (WEBDAV_PASSPHRASE_KEY, gettext("WebDAV backup passphrase")),
+            (LOCAL_BACKUP_KEY, gettext("Local backup key")),
+        ]
+    }
+
+    /**
+     * Moves the secrets of every account to the given backend, along with the application's own secrets
+     * kept in the backend selected so far.
+     */
+    pub fn migrate(connection: &Connection, to: &SecretType) -> Result<usize> {
+        let migrated = Self::migrate_with(connection, to, Self::store)?;
+
+        let from = Self::selected();
+        if from != *to {
+            let from = Self::store(&from)?;
+            let target = Self::store(to)?;
+
+            for (key, label) in Self::own_secrets() {
+                if let Some(secret) = from.secret(key)? {
+                    target.upsert(label.as_str(), key, secret.as_str())?;
+                    from.remove(key)?;
+                }
+            }
+        }
+
+        Ok(migrated)
+    }
+
+    /**
+     * Each account is copied to the new backend and updated in the database before its secret is
+     * removed from the old one, so that an interrupted migration never loses a secret.
+     */
+    pub fn migrate_with<F>(connection: &Connection, to: &SecretType, store: F) -> Result<usize>
+    where
+        F: Fn(&SecretType) -> Result<Box<dyn SecretStore>>,
+    {
+        let target = store(to)?;
+
+        let accounts = Database::load_account_groups(connection, None)?
+            .into_iter()
+            .flat_map(|group| group.entries)
+            .filter(|account| account.secret_type != *to);
+
+        let mut migrated = 0;
+
+        for mut account in accounts {
+            let secret = match account.secret_type {
+                SecretType::LOCAL => Some(account.secret.clone()),
+                ref secret_type => store(secret_type)?.secret(account.uuid.as_str())?,
+            };
+
+            let Some(secret) = secret.filter(|secret| !secret.is_empty()) else {
+                warn!("No secret to migrate for {} ({})", account.label, account.id);
+                continue;
+            };
+
+            target.upsert(account.label.as_str(), account.uuid.as_str(), secret.as_str())?;
+
+            let previous = account.secret_type.clone();
+            account.secret_type = to.clone();
+            account.secret.clear();
+            Database::update_account(connection, &mut account)?;
+
+            if previous != SecretType::LOCAL {
+                if let Err(e) = store(&previous).and_then(|previous| previous.remove(account.uuid.as_str())) {
+                    warn!("Could not remove secret of {} ({}) from {}: {:?}", account.label, account.id, previous, e);
+                }
+            }
+
+            info!("Moved secret of {} ({}) from {} to {}", account.label, account.id, previous, to);
+            migrated += 1;
+        }
+
+        Ok(migrated)
+    }
+
+    /**
+     * Re-keys secrets stored under account database ids to account uuids. Runs on start-up,
+     * backends which cannot be opened (e.g. a locked vault) are left for next time.
+     */
+    pub fn rekey_legacy_secrets(connection: &Connection) -> Result<usize> {
+        Self::rekey_legacy_secrets_with(connection, Self::store)
+    }
+
+    pub fn rekey_legacy_secrets_with<F>(connection: &Connection, store: F) -> Result<usize>
+    where
+        F: Fn(&SecretType) -> Result<Box<dyn SecretStore>>,
+    {
+        let accounts = Database::load_account_groups(connection, None)?
+            .into_iter()
+            .flat_map(|group| group.entries)
+            .filter(|account| account.secret_type != SecretType::LOCAL)
+            .collect::<Vec<Account>>();
+
+        let mut rekeyed = 0;
+
+        for secret_type in Self::in_use(connection)? {
+            let backend = match store(&secret_type) {
+                Ok(backend) => backend,
+                Err(e) => {
+                    warn!("Not re-keying {} secrets: {:?}", secret_type, e);
+                    continue;
+                }
+            };
+
+            for account in accounts.iter().filter(|account| account.secret_type == secret_type) {
+                if backend.rekey(account.label.as_str(), account.id, account.uuid.as_str())? {
+                    info!("Re-keyed {} secret of {} ({}) to {}", secret_type, account.label, account.id, account.uuid);
+                    rekeyed += 1;
+                }
+            }
+        }
+
+        Ok(rekeyed)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+    use std::rc::Rc;
+    use std::sync::{Arc, Mutex};
+
+    use rusqlite::Connection;
+
+    use crate::helpers::{runner, Database, RepositoryError, SecretType};
+    use crate::model::{Account, AccountGroup};
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    pub(crate) struct MemoryStore {
+        secrets: Rc<RefCell<BTreeMap<String, String>>>,
+    }
+
+    impl SecretStore for MemoryStore {
+        fn upsert(&self, _: &str, uuid: &str, secret: &str) -> Result<()> {
+            self.secrets.borrow_mut().insert(uuid.to_owned(), secret.to_owned());
+            Ok(())
+        }
+
+        fn secret(&self, uuid: &str) -> Result<Option<String>> {
+            Ok(self.secrets.borrow().get(uuid).cloned())
+        }
+
+        fn remove(&self, uuid: &str) -> Result<()> {
+            self.secrets.borrow_mut().remove(uuid);
+            Ok(())
+        }
+
+        fn all_secrets(&self) -> Result<Vec<(String, String)>> {
+            Ok(self.secrets.borrow().iter().map(|(uuid, secret)| (uuid.clone(), secret.clone())).collect())
+        }
+    }
+
+    #[test]
+    fn migrates_local_and_keyring_secrets_to_another_backend() {
+        let connection = Arc::new(Mutex::new(Connection::open_in_memory().unwrap()));
+        runner::run(connection.clone()).unwrap();
+        let connection = connection.lock().unwrap();
+
+        let mut group = AccountGroup::new(
+            0,
+            "group",
+            None,
+            None,
+            false,
+            vec![
+                Account::new(0, 0, "local", "LOCALSECRET", SecretType::LOCAL),
+                Account::new(0, 0, "keyring", "", SecretType::KEYRING),
+            ],
+        );
+        Database::save_group_and_accounts(&connection, &mut group).unwrap();
+
+        let keyring_account = Database::get_account_by_label_and_group(&connection, "keyring", group.id).unwrap().unwrap();
+
+        let keyring = MemoryStore::default();
+        keyring.upsert("keyring", keyring_account.uuid.as_str(), "KEYRINGSECRET").unwrap();
+        let vault = MemoryStore::default();
+
+        let store = |secret_type: &SecretType| -> Result<Box<dyn SecretStore>> {
+            match secret_type {
+                SecretType::KEYRING => Ok(Box::new(keyring.clone())),
+                SecretType::VAULT => Ok(Box::new(vault.clone())),
+                _ => Err(RepositoryError::SecretStoreError("unexpected backend".to_owned())),
+            }
+        };
+
+        let migrated = SecretStores::migrate_with(&connection, &SecretType::VAULT, store).unwrap();
+        assert_eq!(2, migrated);
+
+        let accounts = Database::load_account_groups(&connection, None).unwrap().remove(0).entries;
+        assert!(accounts
+            .iter()
+            .all(|account| account.secret_type == SecretType::VAULT && account.secret.is_empty()));
+
+        let mut secrets = vault.all_secrets().unwrap().into_iter().map(|(_, secret)| secret).collect::<Vec<String>>();
+        secrets.sort();
+        assert_eq!(vec!["KEYRINGSECRET".to_owned(), "LOCALSECRET".to_owned()], secrets);
+
+        assert!(keyring.all_secrets().unwrap().is_empty());
+    }
+
+    #[test]
+    fn associates_secrets_per_backend() {
+        let mut groups = vec![AccountGroup::new(
+            1,
+            "group",
+            None,
+            None,
+            false,
+            vec![
+                Account::new(1, 1, "keyring", "", SecretType::KEYRING),
+                Account::new(2, 1, "vault", "", SecretType::VAULT),
+                Account::new(3, 1, "local", "LOCALSECRET", SecretType::LOCAL),
+                Account::new(4, 1, "missing", "", SecretType::VAULT),
+            ],
+        )];
+
+        let all_secrets = AllSecrets::from([
+            (SecretType::KEYRING, vec![(groups[0].entries[0].uuid.clone(), "KEYRINGSECRET".to_owned())]),
+            (SecretType::VAULT, vec![(groups[0].entries[1].uuid.clone(), "VAULTSECRET".to_owned())]),
+        ]);
+
+        SecretStores::associate_secrets(&mut groups, &all_secrets);
+
+        let secrets = groups[0].entries.iter().map(|account| account.secret.as_str()).collect::<Vec<&str>>();
+        assert_eq!(vec!["KEYRINGSECRET", "VAULTSECRET", "LOCALSECRET", ""], secrets);
+    }
+
+    #[test]
+    fn rekeys_secrets_stored_under_account_ids() {
+        let connection = Arc::new(Mutex::new(Connection::open_in_memory().unwrap()));
+        runner::run(connection.clone()).unwrap();
+        let connection = connection.lock().unwrap();
+
+        let mut group = AccountGroup::new(0, "group", None, None, false, vec![Account::new(0, 0, "keyring", "", SecretType::KEYRING)]);
+        Database::save_group_and_accounts(&connection, &mut group).unwrap();
+        let account = group.entries.remove(0);
+
+        let keyring = MemoryStore::default();
+        keyring.upsert("keyring", account.id.to_string().as_str(), "KEYRINGSECRET").unwrap();
+
+        let store = |_: &SecretType| -> Result<Box<dyn SecretStore>> { Ok(Box::new(keyring.clone())) };
+
+        assert_eq!(1, SecretStores::rekey_legacy_secrets_with(&connection, store).unwrap());
+        assert_eq!(vec![(account.uuid.clone(), "KEYRINGSECRET".to_owned())], keyring.all_secrets().unwrap());
+
+        // nothing left to do the next time round
+        assert_eq!(0, SecretStores::rekey_legacy_secrets_with(&connection, store).unwrap());
+    }
+}
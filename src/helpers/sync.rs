@@ -0,0 +1,1102 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use aes::cipher::block_padding::Pkcs7;
+use aes::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use gettextrs::gettext;
+use hmac::{Hmac, Mac};
+use log::{debug, info, warn};
+use rand::RngCore;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::helpers::vault::{ACCEPTED_ITERATIONS, ITERATIONS};
+use crate::helpers::{Database, IconStore, RepositoryError, SecretStore, SecretStores, SecretType};
+use crate::model::{Account, AccountGroup};
+
+type Result<T> = ::std::result::Result<T, RepositoryError>;
+type HmacSha256 = Hmac<Sha256>;
+type Aes256CbcEnc = cbc::Encryptor<aes::Aes256>;
+type Aes256CbcDec = cbc::Decryptor<aes::Aes256>;
+
+/**
+ * Key of the sync passphrase in the selected secret backend.
+ */
+pub const SYNC_PASSPHRASE_KEY: &str = "sync-passphrase";
+
+const LOG_EXTENSION: &str = "log";
+const MAGIC: &str = "ARSSYNC";
+const VERSION: u32 = 1;
+const SALT_LEN: usize = 16;
+const IV_LEN: usize = 16;
+const TAG_LEN: usize = 32;
+const ENCODING: base32::Alphabet = base32::Alphabet::Rfc4648 { padding: false };
+
+const DIRECTORY_SETTING: &str = "directory";
+const DEVICE_SETTING: &str = "device";
+
+/**
+ * Passphrase fingerprint, salt and iterations.
+ */
+type KeyParameters = ([u8; 32], [u8; SALT_LEN], u32);
+
+/**
+ * Keys derived for the logs read this session, so that PBKDF2 only runs once per log.
+ */
+static DERIVED_KEYS: Mutex<BTreeMap<KeyParameters, LogKeys>> = Mutex::new(BTreeMap::new());
+
+#[derive(Clone)]
+struct LogKeys {
+    encryption: [u8; 32],
+    authentication: [u8; 32],
+}
+
+/**
+ * Content of a change, in the shape of backups: groups come without their accounts.
+ */
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum SyncRecord {
+    Group(AccountGroup),
+    Account { group: String, account: Account },
+    Deleted,
+}
+
+/**
+ * One line of a device log. `base` is the version of the group or account the change was made on,
+ * which tells concurrent changes apart from those made on top of each other.
+ */
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SyncChange {
+    pub uuid: String,
+    pub device: String,
+    pub modified: i64,
+    pub base: i64,
+    pub record: SyncRecord,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Version {
+    modified: i64,
+    device: String,
+    digest: String,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Resolution {
+    KeptLocal,
+    KeptRemote,
+    /// The account belongs to a group deleted on this device.
+    Dropped,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SyncConflict {
+    pub uuid: String,
+    pub name: String,
+    pub device: String,
+    pub resolution: Resolution,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct SyncReport {
+    pub sent: usize,
+    pub received: usize,
+    pub conflicts: Vec<SyncConflict>,
+    /// Devices whose log could not be read, e.g. written under another passphrase. They are retried next time.
+    pub unreadable: Vec<String>,
+}
+
+/**
+ * Keeps devices in sync through a folder shared by other means, e.g. Syncthing or NFS.
+ *
+ * Each device appends its changes to its own log, `<device uuid>.log`, so that no two devices ever write to
+ * the same file. The first line of a log holds the PBKDF2 parameters, every other line one change,
+ * encrypted with AES-256-CBC and authenticated with HMAC-SHA256 under keys derived from the sync passphrase.
+ *
+ * Changes from other devices are merged last writer wins. When a group or account was changed on two
+ * devices without either seeing the other change, the outcome is reported as a conflict.
+ */
+pub struct SyncFolder {
+    directory: PathBuf,
+    passphrase: String,
+    device: String,
+    iterations: u32,
+}
+
+impl SyncFolder {
+    pub fn new(directory: PathBuf, passphrase: &str, device: &str) -> SyncFolder {
+        SyncFolder {
+            directory,
+            passphrase: passphrase.to_owned(),
+            device: device.to_owned(),
+            iterations: ITERATIONS,
+        }
+    }
+
+    /**
+     * Sync folder of the current profile, None until one is chosen.
+     */
+    pub fn open(connection: &Connection) -> Result<Option<SyncFolder>> {
+        let Some(directory) = Self::directory(connection)? else {
+            return Ok(None);
+        };
+
+        let passphrase = SecretStores::store(&SecretStores::selected())?
+            .secret(SYNC_PASSPHRASE_KEY)?
+            .ok_or_else(|| RepositoryError::SyncError("sync_passphrase_missing".to_owned()))?;
+
+        let device = Self::device(connection)?;
+
+        Ok(Some(SyncFolder::new(directory, passphrase.as_str(), device.as_str())))
+    }
+
+    /**
+     * Runs a sync if a folder was chosen.
+     */
+    pub fn sync(connection: &Connection) -> Result<Option<SyncReport>> {
+        Self::open(connection)?.map(|folder| folder.run(connection)).transpose()
+    }
+
+    pub fn directory(connection: &Connection) -> Result<Option<PathBuf>> {
+        Self::setting(connection, DIRECTORY_SETTING).map(|directory| directory.map(PathBuf::from))
+    }
+
+    /**
+     * Starts syncing through `directory`. The passphrase is checked against the logs already there,
+     * and goes to the selected secret backend. Choosing another folder sends everything again.
+     */
+    pub fn configure(connection: &Connection, directory: &Path, passphrase: &str) -> Result<()> {
+        if passphrase.is_empty() {
+            return Err(RepositoryError::SyncError("sync_passphrase_missing".to_owned()));
+        }
+
+        let device = Self::device(connection)?;
+        SyncFolder::new(directory.to_path_buf(), passphrase, device.as_str()).check()?;
+
+        SecretStores::store(&SecretStores::selected())?.upsert(gettext("Sync passphrase").as_str(), SYNC_PASSPHRASE_KEY, passphrase)?;
+
+        if Self::directory(connection)?.as_deref() != Some(directory) {
+            connection.execute("DELETE FROM sync_versions", [])?;
+            connection.execute("DELETE FROM sync_devices", [])?;
+        }
+
+        Self::set_setting(connection, DIRECTORY_SETTING, directory.to_string_lossy().as_ref())?;
+
+        info!("Syncing through {}", directory.display());
+        Ok(())
+    }
+
+    pub fn disable(connection: &Connection) -> Result<()> {
+        connection.execute("DELETE FROM sync_settings WHERE key = ?1", params![DIRECTORY_SETTING])?;
+
+        if let Err(e) = SecretStores::store(&SecretStores::selected()).and_then(|store| store.remove(SYNC_PASSPHRASE_KEY)) {
+            warn!("Could not remove sync passphrase: {:?}", e);
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Sends local changes, then merges those of other devices. New accounts from other devices
+     * go to the selected secret backend. Logs which cannot be read are skipped and reported.
+     */
+    pub fn run(&self, connection: &Connection) -> Result<SyncReport> {
+        self.run_with(connection, &SecretStores::selected(), SecretStores::store)
+    }
+
+    pub fn run_with<F>(&self, connection: &Connection, target: &SecretType, store: F) -> Result<SyncReport>
+    where
+        F: Fn(&SecretType) -> Result<Box<dyn SecretStore>>,
+    {
+        if !self.directory.is_dir() {
+            return Err(RepositoryError::SyncError("sync_folder_missing".to_owned()));
+        }
+
+        let tx = connection.unchecked_transaction()?;
+        let mut versions = Self::versions(&tx)?;
+        let sent = self.send(&tx, &mut versions, &store)?;
+        // committed on its own, so that a failure receiving does not append the same changes again next time
+        tx.commit()?;
+
+        let tx = connection.unchecked_transaction()?;
+        let mut report = self.receive(&tx, &mut versions, target, &store)?;
+        report.sent = sent;
+        tx.commit()?;
+
+        info!(
+            "Sent {} and received {} changes, {} conflicts",
+            report.sent,
+            report.received,
+            report.conflicts.len()
+        );
+        Ok(report)
+    }
+
+    fn send<F>(&self, connection: &Connection, versions: &mut BTreeMap<String, Version>, store: &F) -> Result<usize>
+    where
+        F: Fn(&SecretType) -> Result<Box<dyn SecretStore>>,
+    {
+        let now = chrono::Utc::now().timestamp_millis();
+
+        let changes = Self::local_records(connection, store)?
+            .into_iter()
+            .filter_map(|(uuid, (modified, record))| {
+                let version = versions.get(&uuid);
+
+                match version {
+                    Some(version) if version.digest == record.digest() => None,
+                    None if record == SyncRecord::Deleted => None,
+                    _ => {
+                        let base = version.map_or(0, |version| version.modified);
+
+                        // secrets can change without the row changing
+                        let modified = if modified > base { modified } else { now.max(base + 1) };
+
+                        Some(SyncChange {
+                            uuid,
+                            device: self.device.clone(),
+                            modified,
+                            base,
+                            record,
+                        })
+                    }
+                }
+            })
+            .collect::<Vec<SyncChange>>();
+
+        if changes.is_empty() {
+            return Ok(0);
+        }
+
+        for change in &changes {
+            Self::save_version(connection, versions, change)?;
+        }
+
+        self.append(&changes)?;
+
+        Ok(changes.len())
+    }
+
+    fn receive<F>(&self, connection: &Connection, versions: &mut BTreeMap<String, Version>, target: &SecretType, store: &F) -> Result<SyncReport>
+    where
+        F: Fn(&SecretType) -> Result<Box<dyn SecretStore>>,
+    {
+        let mut report = SyncReport::default();
+        let mut changes = Vec::new();
+
+        for (device, path) in self.logs()? {
+            let merged = Self::merged(connection, device.as_str())?;
+            let (count, new_changes) = match self.read(&path, device.as_str(), merged) {
+                Ok(read) => read,
+                Err(e) => {
+                    warn!("Skipping log {}: {:?}", path.display(), e);
+                    report.unreadable.push(device);
+                    continue;
+                }
+            };
+
+            debug!("{} new changes from {}", new_changes.len(), device);
+            changes.extend(new_changes);
+
+            connection.execute(
+                "INSERT OR REPLACE INTO sync_devices (device, changes) VALUES (?1, ?2)",
+                params![device, count as i64],
+            )?;
+        }
+
+        // groups first, so that accounts find theirs even when the group changed since
+        changes.sort_by(|a, b| (a.record.rank(), a.modified, &a.device).cmp(&(b.record.rank(), b.modified, &b.device)));
+
+        for change in changes {
+            let (newer, concurrent) = match versions.get(&change.uuid) {
+                None => (true, false),
+                Some(version) => (
+                    (change.modified, &change.device) > (version.modified, &version.device),
+                    change.base < version.modified && change.record.digest() != version.digest,
+                ),
+            };
+
+            let applied = newer && self.apply(connection, &change, target, store)?;
+
+            let resolution = match (newer, applied) {
+                (true, true) => Resolution::KeptRemote,
+                (true, false) => Resolution::Dropped,
+                (false, _) => Resolution::KeptLocal,
+            };
+
+            if concurrent || resolution == Resolution::Dropped {
+                let conflict = SyncConflict {
+                    uuid: change.uuid.clone(),
+                    name: Self::name(connection, &change)?,
+                    device: change.device.clone(),
+                    resolution,
+                };
+
+                warn!("Sync conflict: {}", conflict);
+                report.conflicts.push(conflict);
+            }
+
+            if applied {
+                Self::save_version(connection, versions, &change)?;
+                report.received += 1;
+            }
+        }
+
+        Ok(report)
+    }
+
+    /**
+     * Applies a change from another device, with its timestamp. Returns false when it cannot be:
+     * an account whose group is gone.
+     */
+    fn apply<F>(&self, connection: &Connection, change: &SyncChange, target: &SecretType, store: &F) -> Result<bool>
+    where
+        F: Fn(&SecretType) -> Result<Box<dyn SecretStore>>,
+    {
+        debug!("Applying {:?} from {}", change.record, change.device);
+
+        match &change.record {
+            SyncRecord::Group(group) => match Database::get_group_by_uuid(connection, change.uuid.as_str())? {
+                Some(mut existing) => {
+                    existing.name.clone_from(&group.name);
+                    existing.url.clone_from(&group.url);
+                    Database::update_group(connection, &existing)?;
+                }
+                None => {
+                    let mut group = AccountGroup::new(0, group.name.as_str(), None, group.url.as_deref(), false, vec![]);
+                    group.uuid.clone_from(&change.uuid);
+                    Database::save_group(connection, &mut group)?;
+                }
+            },
+            SyncRecord::Account { group, account } => {
+                let Some(group) = Database::get_group_by_uuid(connection, group.as_str())? else {
+                    return Ok(false);
+                };
+
+                let (id, secret_type, previous_icon) = match Database::get_account_by_uuid(connection, change.uuid.as_str())? {
+                    Some(existing) => (existing.id, existing.secret_type, existing.icon),
+                    None => (0, target.clone(), None),
+                };
+
+                let mut account = Account {
+                    id,
+                    group_id: group.id,
+                    uuid: change.uuid.clone(),
+                    secret_type: secret_type.clone(),
+                    ..account.clone()
+                };
+
+                // icons travel with their content: one this device does not have leaves the account with its own
+                let icons = IconStore::open();
+                icons.extract(&mut account)?;
+
+                if account.icon.as_ref().is_some_and(|icon| !icons.contains(icon)) {
+                    account.icon.clone_from(&previous_icon);
+                }
+
+                if secret_type != SecretType::LOCAL {
+                    store(&secret_type)?.upsert(account.label.as_str(), account.uuid.as_str(), account.secret.as_str())?;
+                }
+
+                if id == 0 {
+                    Database::save_account(connection, &mut account)?;
+                } else {
+                    Database::update_account(connection, &mut account)?;
+                }
+
+                if let Some(icon) = previous_icon.filter(|icon| account.icon.as_ref() != Some(icon)) {
+                    if let Err(e) = icons.release(connection, icon.as_str()) {
+                        warn!("Could not delete icon {}: {:?}", icon, e);
+                    }
+                }
+            }
+            SyncRecord::Deleted => {
+                if let Some(account) = Database::get_account_by_uuid(connection, change.uuid.as_str())? {
+                    Database::delete_account(connection, account.id)?;
+
+                    if let Some(icon) = &account.icon {
+                        if let Err(e) = IconStore::open().release(connection, icon) {
+                            warn!("Could not delete icon {}: {:?}", icon, e);
+                        }
+                    }
+
+                    if account.secret_type != SecretType::LOCAL {
+                        store(&account.secret_type)?.remove(account.uuid.as_str())?;
+                    }
+                } else if let Some(group) = Database::get_group_by_uuid(connection, change.uuid.as_str())? {
+                    Database::delete_group(connection, group.id)?;
+
+                    // icons are not synced, only their group is
+                    if let Some(icon) = group.icon {
+                        if let Err(e) = IconStore::open().release(connection, icon.as_str()) {
+                            warn!("Could not delete icon {}: {:?}", icon, e);
+                        }
+                    }
+                }
+            }
+        }
+
+        for table in ["groups", "accounts", "sync_tombstones"] {
+            connection.execute(
+                format!("UPDATE {} SET modified = ?2 WHERE uuid = ?1", table).as_str(),
+                params![change.uuid, change.modified],
+            )?;
+        }
+
+        Ok(true)
+    }
+
+    /**
+     * Current groups, accounts and deletions, with the time of their last change.
+     * Accounts whose secret cannot be found are left out rather than sent without one.
+     */
+    fn local_records<F>(connection: &Connection, store: &F) -> Result<BTreeMap<String, (i64, SyncRecord)>>
+    where
+        F: Fn(&SecretType) -> Result<Box<dyn SecretStore>>,
+    {
+        let mut groups = Database::load_account_groups(connection, None)?;
+
+        let mut secrets = HashMap::new();
+        for secret_type in Database::secret_types(connection)?.into_iter().filter(|t| *t != SecretType::LOCAL) {
+            let all_secrets = store(&secret_type)?.all_secrets()?;
+            secrets.insert(secret_type, all_secrets);
+        }
+        SecretStores::associate_secrets(&mut groups, &secrets);
+
+        let modified = Self::query_pairs(connection, "SELECT uuid, modified FROM groups UNION ALL SELECT uuid, modified FROM accounts")?
+            .into_iter()
+            .collect::<HashMap<String, i64>>();
+
+        let mut records = BTreeMap::new();
+        let icons = IconStore::open();
+
+        for mut group in groups {
+            for mut account in group.entries.drain(..) {
+                if account.secret.is_empty() {
+                    warn!("Not syncing {} ({}): no secret", account.label, account.id);
+                    continue;
+                }
+
+                icons.embed(&mut account);
+
+                let record = SyncRecord::Account {
+                    group: group.uuid.clone(),
+                    account: account.clone(),
+                };
+                records.insert(account.uuid.clone(), (modified.get(&account.uuid).copied().unwrap_or(0), record));
+            }
+
+            records.insert(group.uuid.clone(), (modified.get(&group.uuid).copied().unwrap_or(0), SyncRecord::Group(group)));
+        }
+
+        for (uuid, modified) in Self::query_pairs(connection, "SELECT uuid, modified FROM sync_tombstones")? {
+            records.entry(uuid).or_insert((modified, SyncRecord::Deleted));
+        }
+
+        Ok(records)
+    }
+
+    fn query_pairs(connection: &Connection, query: &str) -> Result<Vec<(String, i64)>> {
+        let mut stmt = connection.prepare(query)?;
+
+        let pairs = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<(String, i64)>>>()?;
+
+        Ok(pairs)
+    }
+
+    fn versions(connection: &Connection) -> Result<BTreeMap<String, Version>> {
+        let mut stmt = connection.prepare("SELECT uuid, modified, device, digest FROM sync_versions")?;
+
+        let versions = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    Version {
+                        modified: row.get(1)?,
+                        device: row.get(2)?,
+                        digest: row.get(3)?,
+                    },
+                ))
+            })?
+            .collect::<rusqlite::Result<BTreeMap<String, Version>>>()?;
+
+        Ok(versions)
+    }
+
+    fn save_version(connection: &Connection, versions: &mut BTreeMap<String, Version>, change: &SyncChange) -> Result<()> {
+        let version = Version {
+            modified: change.modified,
+            device: change.device.clone(),
+            digest: change.record.digest(),
+        };
+
+        connection.execute(
+            "INSERT OR REPLACE INTO sync_versions (uuid, modified, device, digest) VALUES (?1, ?2, ?3, ?4)",
+            params![change.uuid, version.modified, version.device, version.digest],
+        )?;
+
+        versions.insert(change.uuid.clone(), version);
+        Ok(())
+    }
+
+    fn merged(connection: &Connection, device: &str) -> Result<usize> {
+        let merged: Option<i64> = connection
+            .query_row("SELECT changes FROM sync_devices WHERE device = ?1", params![device], |row| row.get(0))
+            .optional()?;
+
+        Ok(merged.unwrap_or(0) as usize)
+    }
+
+    /**
+     * Name of the group or account a change is about, for conflict reports.
+     */
+    fn name(connection: &Connection, change: &SyncChange) -> Result<String> {
+        let name = match &change.record {
+            SyncRecord::Group(group) => Some(group.name.clone()),
+            SyncRecord::Account { account, .. } => Some(account.label.clone()),
+            SyncRecord::Deleted => match Database::get_account_by_uuid(connection, change.uuid.as_str())? {
+                Some(account) => Some(account.label),
+                None => Database::get_group_by_uuid(connection, change.uuid.as_str())?.map(|group| group.name),
+            },
+        };
+
+        Ok(name.unwrap_or_else(|| change.uuid.clone()))
+    }
+
+    fn device(connection: &Connection) -> Result<String> {
+        match Self::setting(connection, DEVICE_SETTING)? {
+            Some(device) => Ok(device),
+            None => {
+                let device = Uuid::new_v4().to_string();
+                Self::set_setting(connection, DEVICE_SETTING, device.as_str())?;
+                Ok(device)
+            }
+        }
+    }
+
+    fn setting(connection: &Connection, key: &str) -> Result<Option<String>> {
+        connection
+            .query_row("SELECT value FROM sync_settings WHERE key = ?1", params![key], |row| row.get(0))
+            .optional()
+            .map_err(RepositoryError::SqlError)
+    }
+
+    fn set_setting(connection: &Connection, key: &str, value: &str) -> Result<()> {
+        connection.execute("INSERT OR REPLACE INTO sync_settings (key, value) VALUES (?1, ?2)", params![key, value])?;
+        Ok(())
+    }
+
+    fn log_path(&self, device: &str) -> PathBuf {
+        self.directory.join(format!("{}.{}", device, LOG_EXTENSION))
+    }
+
+    /**
+     * Logs of the other devices. Anything else in the folder, e.g. conflicting copies made by the
+     * file synchronisation tool, is ignored.
+     */
+    fn logs(&self) -> Result<Vec<(String, PathBuf)>> {
+        let mut logs = fs::read_dir(&self.directory)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().and_then(|extension| extension.to_str()) == Some(LOG_EXTENSION))
+            .filter_map(|path| {
+                let device = path.file_stem()?.to_str()?.to_owned();
+                Uuid::parse_str(device.as_str()).ok().map(|_| (device, path))
+            })
+            .filter(|(device, _)| *device != self.device)
+            .collect::<Vec<(String, PathBuf)>>();
+
+        logs.sort();
+        Ok(logs)
+    }
+
+    /**
+     * Makes sure the passphrase opens the logs already in the folder.
+     */
+    fn check(&self) -> Result<()> {
+        if !self.directory.is_dir() {
+            return Err(RepositoryError::SyncError("sync_folder_missing".to_owned()));
+        }
+
+        for (device, path) in self.logs()? {
+            let content = fs::read_to_string(&path)?;
+            let mut lines = content.lines();
+
+            if let (Some(header), Some(line)) = (lines.next(), lines.next()) {
+                debug!("Checking passphrase against log of {}", device);
+                Self::unseal(&self.header_keys(header)?, line)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Reads the changes of a device log past the `merged` first ones. Returns how many changes the log
+     * holds, along with the new ones. A log shorter than expected was started over: it is read again from the start.
+     */
+    fn read(&self, path: &Path, device: &str, merged: usize) -> Result<(usize, Vec<SyncChange>)> {
+        let content = fs::read_to_string(path)?;
+
+        // an unterminated last line is still being written
+        let complete = content.rfind('\n').map_or("", |end| &content[..end]);
+        let mut lines = complete.lines();
+
+        let Some(header) = lines.next() else {
+            return Ok((0, vec![]));
+        };
+
+        let lines = lines.collect::<Vec<&str>>();
+        let skip = if lines.len() < merged { 0 } else { merged };
+
+        if skip == lines.len() {
+            return Ok((lines.len(), vec![]));
+        }
+
+        let keys = self.header_keys(header)?;
+
+        let mut changes = Vec::new();
+
+        for line in &lines[skip..] {
+            let change: SyncChange = serde_yaml::from_slice(&Self::unseal(&keys, line)?)?;
+
+            if change.device == device {
+                changes.push(change);
+            } else {
+                warn!("Ignoring change of {} found in the log of {}", change.device, device);
+            }
+        }
+
+        Ok((lines.len(), changes))
+    }
+
+    fn append(&self, changes: &[SyncChange]) -> Result<()> {
+        let path = self.log_path(self.device.as_str());
+
+        let header = match fs::read_to_string(&path) {
+            Ok(content) => content.lines().next().map(str::to_owned),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(RepositoryError::IoError(e)),
+        };
+
+        let mut lines = String::new();
+
+        let keys = match header {
+            Some(header) => self.header_keys(header.as_str())?,
+            None => {
+                let mut salt = [0u8; SALT_LEN];
+                rand::thread_rng().fill_bytes(&mut salt);
+
+                lines.push_str(format!("{} {} {} {}\n", MAGIC, VERSION, self.iterations, base32::encode(ENCODING, &salt)).as_str());
+                self.keys(salt, self.iterations)
+            }
+        };
+
+        for change in changes {
+            let yaml = serde_yaml::to_string(change)?;
+            lines.push_str(Self::seal(&keys, yaml.as_bytes()).as_str());
+            lines.push('\n');
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        file.write_all(lines.as_bytes())?;
+        file.sync_all()?;
+
+        debug!("Wrote {} changes to {}", changes.len(), path.display());
+        Ok(())
+    }
+
+    fn header_keys(&self, header: &str) -> Result<LogKeys> {
+        let fields = header.split_whitespace().collect::<Vec<&str>>();
+
+        match fields.as_slice() {
+            [MAGIC, version, iterations, salt] if *version == VERSION.to_string() => {
+                let iterations = iterations
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|iterations| ACCEPTED_ITERATIONS.contains(iterations))
+                    .ok_or_else(|| RepositoryError::SyncError("sync_corrupted".to_owned()))?;
+
+                let salt: [u8; SALT_LEN] = base32::decode(ENCODING, salt)
+                    .and_then(|salt| salt.try_into().ok())
+                    .ok_or_else(|| RepositoryError::SyncError("sync_corrupted".to_owned()))?;
+
+                Ok(self.keys(salt, iterations))
+            }
+            [MAGIC, version, ..] => Err(RepositoryError::SyncError(format!("Unsupported sync log version {}", version))),
+            _ => Err(RepositoryError::SyncError("sync_corrupted".to_owned())),
+        }
+    }
+
+    fn keys(&self, salt: [u8; SALT_LEN], iterations: u32) -> LogKeys {
+        let fingerprint: [u8; 32] = Sha256::digest(self.passphrase.as_bytes()).into();
+
+        let mut cached = DERIVED_KEYS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        cached
+            .entry((fingerprint, salt, iterations))
+            .or_insert_with(|| {
+                let mut derived = [0u8; 64];
+                pbkdf2::pbkdf2_hmac::<Sha256>(self.passphrase.as_bytes(), &salt, iterations, &mut derived);
+
+                let mut keys = LogKeys {
+                    encryption: [0u8; 32],
+                    authentication: [0u8; 32],
+                };
+                keys.encryption.copy_from_slice(&derived[..32]);
+                keys.authentication.copy_from_slice(&derived[32..]);
+                keys
+            })
+            .clone()
+    }
+
+    /**
+     * iv | ciphertext | tag, the tag covering iv and ciphertext.
+     */
+    fn seal(keys: &LogKeys, plaintext: &[u8]) -> String {
+        let mut iv = [0u8; IV_LEN];
+        rand::thread_rng().fill_bytes(&mut iv);
+
+        let ciphertext = Aes256CbcEnc::new(&keys.encryption.into(), &iv.into()).encrypt_padded_vec_mut::<Pkcs7>(plaintext);
+
+        let mut bytes = iv.to_vec();
+        bytes.extend_from_slice(&ciphertext);
+
+        let mut mac = HmacSha256::new_from_slice(&keys.authentication).expect("HMAC takes keys of any size");
+        mac.update(&bytes);
+        bytes.extend_from_slice(&mac.finalize().into_bytes());
+
+        base32::encode(ENCODING, &bytes)
+    }
+
+    fn unseal(keys: &LogKeys, line: &str) -> Result<Vec<u8>> {
+        let bytes = base32::decode(ENCODING, line.trim())
+            .filter(|bytes| bytes.len() >= IV_LEN + TAG_LEN)
+            .ok_or_else(|| RepositoryError::SyncError("sync_corrupted".to_owned()))?;
+
+        let (content, tag) = bytes.split_at(bytes.len() - TAG_LEN);
+
+        let mut mac = HmacSha256::new_from_slice(&keys.authentication).expect("HMAC takes keys of any size");
+        mac.update(content);
+        mac.verify_slice(tag)
+            .map_err(|_| RepositoryError::SyncError("sync_wrong_passphrase".to_owned()))?;
+
+        let (iv, ciphertext) = content.split_at(IV_LEN);
+
+        Aes256CbcDec::new(&keys.encryption.into(), iv.into())
+            .decrypt_padded_vec_mut::<Pkcs7>(ciphertext)
+            .map_err(|_| RepositoryError::SyncError("sync_corrupted".to_owned()))
+    }
+}
+
+impl SyncRecord {
+    fn rank(&self) -> u8 {
+        match self {
+            SyncRecord::Group(_) => 0,
+            SyncRecord::Account { .. } => 1,
+            SyncRecord::Deleted => 2,
+        }
+    }
+
+    /**
+     * Fingerprint of the content, telling whether it changed since last sent or received.
+     */
+    fn digest(&self) -> String {
+        let yaml = serde_yaml::to_string(self).expect("sync records serialise");
+        Sha256::digest(yaml.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+impl fmt::Display for SyncConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let resolution = match self.resolution {
+            Resolution::KeptLocal => gettext("changed on this device and on another one, kept the version of this device"),
+            Resolution::KeptRemote => gettext("changed on this device and on another one, kept the version of the other device"),
+            Resolution::Dropped => gettext("changed on another device, but its group was deleted on this one"),
+        };
+
+        write!(f, "{}: {} ({})", self.name, resolution, self.device)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::helpers::runner;
+
+    fn database() -> Connection {
+        let connection = Arc::new(Mutex::new(Connection::open_in_memory().unwrap()));
+        runner::run(connection.clone()).unwrap();
+
+        Arc::try_unwrap(connection).unwrap().into_inner().unwrap()
+    }
+
+    fn folder(directory: &Path, device: &str) -> SyncFolder {
+        SyncFolder {
+            directory: directory.to_path_buf(),
+            passphrase: "correct horse".to_owned(),
+            device: device.to_owned(),
+            iterations: 10,
+        }
+    }
+
+    /**
+     * Two devices, each with its own database and its own copy of the shared folder, as Syncthing would keep them.
+     */
+    struct Devices {
+        dirs: [PathBuf; 2],
+        folders: [SyncFolder; 2],
+        databases: [Connection; 2],
+    }
+
+    impl Devices {
+        fn new(name: &str) -> Devices {
+            let dirs = ["a", "b"].map(|device| std::env::temp_dir().join(format!("{}-{}-{}", name, device, Uuid::new_v4())));
+            dirs.iter().for_each(|dir| fs::create_dir_all(dir).unwrap());
+
+            Devices {
+                folders: [
+                    folder(&dirs[0], "00000000-0000-4000-8000-00000000000a"),
+                    folder(&dirs[1], "00000000-0000-4000-8000-00000000000b"),
+                ],
+                dirs,
+                databases: [database(), database()],
+            }
+        }
+
+        fn sync(&self, device: usize) -> SyncReport {
+            let store = |_: &SecretType| -> Result<Box<dyn SecretStore>> { Err(RepositoryError::SecretStoreError("LOCAL only".to_owned())) };
+            self.folders[device].run_with(&self.databases[device], &SecretType::LOCAL, store).unwrap()
+        }
+
+        /**
+         * Copies the log of each device over to the folder of the other one.
+         */
+        fn exchange(&self) {
+            for (from, to) in [(0, 1), (1, 0)] {
+                let device = self.folders[from].device.as_str();
+                let log = self.folders[from].log_path(device);
+
+                if log.is_file() {
+                    fs::copy(&log, self.folders[to].log_path(device)).unwrap();
+                }
+            }
+        }
+
+        fn accounts(&self, device: usize) -> Vec<(String, String, String)> {
+            Database::load_account_groups(&self.databases[device], None)
+                .unwrap()
+                .into_iter()
+                .flat_map(|group| {
+                    group
+                        .entries
+                        .into_iter()
+                        .map(move |account| (group.name.clone(), account.label, account.secret))
+                })
+                .collect()
+        }
+
+        fn account(&self, device: usize, uuid: &str) -> Account {
+            Database::get_account_by_uuid(&self.databases[device], uuid).unwrap().unwrap()
+        }
+
+        fn set_modified(&self, device: usize, uuid: &str, modified: i64) {
+            self.databases[device]
+                .execute("UPDATE accounts SET modified = ?2 WHERE uuid = ?1", params![uuid, modified])
+                .unwrap();
+        }
+    }
+
+    impl Drop for Devices {
+        fn drop(&mut self) {
+            self.dirs.iter().for_each(|dir| fs::remove_dir_all(dir).unwrap());
+        }
+    }
+
+    fn add_account(connection: &Connection, group: &str, label: &str, secret: &str) -> Account {
+        let mut group = AccountGroup::new(0, group, None, None, false, vec![Account::new(0, 0, label, secret, SecretType::LOCAL)]);
+        Database::save_group_and_accounts(connection, &mut group).unwrap();
+        group.entries.remove(0)
+    }
+
+    #[test]
+    fn log_lines_are_encrypted_and_authenticated() {
+        let keys = folder(Path::new("/tmp"), "a").keys([7u8; SALT_LEN], 10);
+
+        let line = SyncFolder::seal(&keys, b"secret: AAAA");
+        assert!(!line.contains("AAAA"));
+        assert_eq!(b"secret: AAAA".to_vec(), SyncFolder::unseal(&keys, line.as_str()).unwrap());
+
+        let intruder = SyncFolder {
+            passphrase: "battery staple".to_owned(),
+            ..folder(Path::new("/tmp"), "a")
+        };
+        assert!(matches!(
+            SyncFolder::unseal(&intruder.keys([7u8; SALT_LEN], 10), line.as_str()),
+            Err(RepositoryError::SyncError(_))
+        ));
+    }
+
+    #[test]
+    fn syncs_new_updated_and_deleted_accounts() {
+        let devices = Devices::new("syncs_new_updated_and_deleted_accounts");
+
+        let account = add_account(&devices.databases[0], "work", "alice", "AAAA");
+        add_account(&devices.databases[0], "work", "bob", "BBBB");
+
+        assert_eq!(3, devices.sync(0).sent);
+        devices.exchange();
+        assert_eq!(3, devices.sync(1).received);
+
+        assert_eq!(devices.accounts(0), devices.accounts(1));
+        assert_eq!(account.uuid, devices.account(1, account.uuid.as_str()).uuid);
+
+        // nothing echoes back
+        assert_eq!(SyncReport::default(), devices.sync(1));
+        devices.exchange();
+        assert_eq!(SyncReport::default(), devices.sync(0));
+
+        let mut renamed = devices.account(1, account.uuid.as_str());
+        renamed.label = "alice@work".to_owned();
+        Database::update_account(&devices.databases[1], &mut renamed).unwrap();
+        Database::delete_account(
+            &devices.databases[1],
+            Database::get_account_by_label_and_group(&devices.databases[1], "bob", renamed.group_id)
+                .unwrap()
+                .unwrap()
+                .id,
+        )
+        .unwrap();
+
+        assert_eq!(2, devices.sync(1).sent);
+        devices.exchange();
+
+        let report = devices.sync(0);
+        assert_eq!(2, report.received);
+        assert!(report.conflicts.is_empty());
+
+        assert_eq!(vec![("work".to_owned(), "alice@work".to_owned(), "AAAA".to_owned())], devices.accounts(0));
+        assert_eq!(devices.accounts(0), devices.accounts(1));
+    }
+
+    #[test]
+    fn last_writer_wins_and_conflicts_are_reported() {
+        let devices = Devices::new("last_writer_wins_and_conflicts_are_reported");
+
+        let account = add_account(&devices.databases[0], "work", "alice", "AAAA");
+        devices.sync(0);
+        devices.exchange();
+        devices.sync(1);
+
+        // both devices change the account before syncing again, device b last
+        for (device, secret, modified) in [(0, "CCCC", 4_000_000_000_000), (1, "DDDD", 4_000_000_000_001)] {
+            let mut changed = devices.account(device, account.uuid.as_str());
+            changed.secret = secret.to_owned();
+            Database::update_account(&devices.databases[device], &mut changed).unwrap();
+            devices.set_modified(device, account.uuid.as_str(), modified);
+        }
+
+        devices.sync(0);
+        devices.sync(1);
+        devices.exchange();
+
+        let report_a = devices.sync(0);
+        let report_b = devices.sync(1);
+
+        assert_eq!("DDDD", devices.account(0, account.uuid.as_str()).secret);
+        assert_eq!("DDDD", devices.account(1, account.uuid.as_str()).secret);
+
+        assert_eq!(
+            vec![SyncConflict {
+                uuid: account.uuid.clone(),
+                name: "alice".to_owned(),
+                device: devices.folders[1].device.clone(),
+                resolution: Resolution::KeptRemote,
+            }],
+            report_a.conflicts
+        );
+        assert_eq!(Resolution::KeptLocal, report_b.conflicts[0].resolution);
+        assert_eq!(0, report_b.received);
+    }
+
+    #[test]
+    fn collapsing_groups_is_not_synced() {
+        let devices = Devices::new("collapsing_groups_is_not_synced");
+
+        add_account(&devices.databases[0], "work", "alice", "AAAA");
+        assert_eq!(2, devices.sync(0).sent);
+        devices.exchange();
+        assert_eq!(2, devices.sync(1).received);
+
+        let modified = |group: &AccountGroup| -> i64 {
+            devices.databases[1]
+                .query_row("SELECT modified FROM groups WHERE uuid = ?1", params![group.uuid], |row| row.get(0))
+                .unwrap()
+        };
+
+        let mut group = Database::load_account_groups(&devices.databases[1], None).unwrap().remove(0);
+        let before = modified(&group);
+
+        group.collapsed = true;
+        Database::update_group(&devices.databases[1], &group).unwrap();
+
+        assert_eq!(before, modified(&group));
+        assert_eq!(0, devices.sync(1).sent);
+        assert!(!Database::load_account_groups(&devices.databases[0], None).unwrap()[0].collapsed);
+    }
+
+    #[test]
+    fn unreadable_logs_are_skipped() {
+        let devices = Devices::new("unreadable_logs_are_skipped");
+
+        let stranger = "00000000-0000-4000-8000-00000000000c";
+        fs::write(devices.folders[0].log_path(stranger), "not a header\nnot a change\n").unwrap();
+
+        add_account(&devices.databases[0], "work", "alice", "AAAA");
+
+        let report = devices.sync(0);
+        assert_eq!(2, report.sent);
+        assert_eq!(vec![stranger.to_owned()], report.unreadable);
+
+        // the changes sent are not sent again
+        let report = devices.sync(0);
+        assert_eq!(0, report.sent);
+        assert_eq!(vec![stranger.to_owned()], report.unreadable);
+
+        devices.exchange();
+        assert_eq!(2, devices.sync(1).received);
+        assert_eq!(devices.accounts(0), devices.accounts(1));
+    }
+
+    #[test]
+    fn unterminated_lines_are_left_for_next_time() {
+        let devices = Devices::new("unterminated_lines_are_left_for_next_time");
+
+        add_account(&devices.databases[0], "work", "alice", "AAAA");
+        devices.sync(0);
+        devices.exchange();
+
+        let device = devices.folders[0].device.as_str();
+        let log = devices.folders[1].log_path(device);
+        let content = fs::read_to_string(&log).unwrap();
+
+        // the last change is still being copied
+        fs::write(&log, &content[..content.len() - 10]).unwrap();
+        assert_eq!(1, devices.sync(1).received);
+
+        fs::write(&log, &content).unwrap();
+        assert_eq!(1, devices.sync(1).received);
+        assert_eq!(devices.accounts(0), devices.accounts(1));
+    }
+}
@@ -0,0 +1,405 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use aes::cipher::block_padding::Pkcs7;
+use aes::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use hmac::{Hmac, Mac};
+use log::debug;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::helpers::{Paths, RepositoryError, SecretStore};
+
+type Result<T> = ::std::result::Result<T, RepositoryError>;
+type HmacSha256 = Hmac<Sha256>;
+type Aes256CbcEnc = cbc::Encryptor<aes::Aes256>;
+type Aes256CbcDec = cbc::Decryptor<aes::Aes256>;
+
+const MAGIC: &[u8; 8] = b"ARSVAULT";
+const VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const IV_LEN: usize = 16;
+const TAG_LEN: usize = 32;
+const HEADER_LEN: usize = MAGIC.len() + 1 + 4 + SALT_LEN + IV_LEN;
+pub(crate) const ITERATIONS: u32 = 200_000;
+
+/**
+ * Iteration counts accepted from a file header, which is only authenticated once the keys have been derived with it.
+ */
+pub(crate) const ACCEPTED_ITERATIONS: RangeInclusive<u32> = 1..=10 * ITERATIONS;
+
+pub const VAULT_PASSPHRASE_ENV: &str = "AUTHENTICATOR_RS_VAULT_PASSPHRASE";
+
+/**
+ * Passphrase given by the user for this session, see Vault::unlock().
+ */
+static PASSPHRASE: Mutex<Option<String>> = Mutex::new(None);
+
+/**
+ * Keys derived from the passphrase for the last salt seen, so that PBKDF2 only runs once per session.
+ */
+static DERIVED_KEYS: Mutex<Option<DerivedKeys>> = Mutex::new(None);
+
+#[derive(Clone)]
+struct DerivedKeys {
+    fingerprint: [u8; 32],
+    salt: [u8; SALT_LEN],
+    iterations: u32,
+    encryption: [u8; 32],
+    authentication: [u8; 32],
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+struct VaultEntry {
+    label: String,
+    secret: String,
+}
+
+/**
+ * Secrets kept in a local file, encrypted with AES-256-CBC and authenticated with HMAC-SHA256.
+ * Both keys are derived from a passphrase with PBKDF2-HMAC-SHA256.
+ *
+ * File layout: magic | version | iterations (u32 BE) | salt | iv | ciphertext | tag,
+ * the tag covering everything before it.
+ */
+pub struct Vault {
+    path: PathBuf,
+    passphrase: String,
+    iterations: u32,
+}
+
+impl Vault {
+    pub fn new(path: PathBuf, passphrase: &str) -> Vault {
+        Vault {
+            path,
+            passphrase: passphrase.to_owned(),
+            iterations: ITERATIONS,
+        }
+    }
+
+    /**
+     * Opens the vault of the current profile, with the passphrase given to unlock() or,
+     * failing that, the one in the AUTHENTICATOR_RS_VAULT_PASSPHRASE environment variable.
+     */
+    pub fn open() -> Result<Vault> {
+        Self::passphrase()
+            .map(|passphrase| Vault::new(Paths::vault_path(), passphrase.as_str()))
+            .ok_or_else(|| RepositoryError::SecretStoreError("vault_locked".to_owned()))
+    }
+
+    pub fn exists() -> bool {
+        Paths::vault_path().is_file()
+    }
+
+    pub fn is_unlocked() -> bool {
+        Self::passphrase().is_some()
+    }
+
+    /**
+     * Checks the passphrase against the vault file, if there is one yet, and keeps it for the session.
+     */
+    pub fn unlock(passphrase: &str) -> Result<()> {
+        let vault = Vault::new(Paths::vault_path(), passphrase);
+        vault.read()?;
+
+        let mut current = PASSPHRASE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        *current = Some(passphrase.to_owned());
+        Ok(())
+    }
+
+    /**
+     * Forgets the passphrase given to unlock(), e.g. when switching to another profile and its own vault.
+     */
+    pub fn lock() {
+        let mut current = PASSPHRASE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        *current = None;
+    }
+
+    fn passphrase() -> Option<String> {
+        let current = PASSPHRASE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        current.clone().or_else(|| std::env::var(VAULT_PASSPHRASE_ENV).ok())
+    }
+
+    /**
+     * Holds an exclusive lock, in a file beside the vault, until dropped: the app and the command line both write to it.
+     */
+    fn exclusive(&self) -> Result<File> {
+        if let Some(directory) = self.path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.path.with_extension("lock"))?;
+        file.lock()?;
+        Ok(file)
+    }
+
+    fn read(&self) -> Result<BTreeMap<String, VaultEntry>> {
+        if !self.path.is_file() {
+            return Ok(BTreeMap::new());
+        }
+
+        let bytes = fs::read(&self.path)?;
+        let plaintext = self.decrypt(&bytes)?;
+
+        serde_yaml::from_slice(&plaintext).map_err(RepositoryError::SerialisationError)
+    }
+
+    fn write(&self, entries: &BTreeMap<String, VaultEntry>) -> Result<()> {
+        let plaintext = serde_yaml::to_string(entries)?;
+
+        // keep the salt and iterations of the existing file so the derived keys stay valid
+        let (salt, iterations) = match fs::read(&self.path) {
+            Ok(bytes) if bytes.len() >= HEADER_LEN => (Self::salt(&bytes), Self::iterations(&bytes)),
+            _ => {
+                let mut salt = [0u8; SALT_LEN];
+                rand::thread_rng().fill_bytes(&mut salt);
+                (salt, self.iterations)
+            }
+        };
+
+        let bytes = self.encrypt(plaintext.as_bytes(), salt, iterations);
+
+        let mut file = tempfile_fast::Sponge::new_for(&self.path)?;
+        file.write_all(&bytes)?;
+        file.commit()?;
+
+        debug!("Wrote {} secrets to vault {}", entries.len(), self.path.display());
+        Ok(())
+    }
+
+    fn salt(bytes: &[u8]) -> [u8; SALT_LEN] {
+        let mut salt = [0u8; SALT_LEN];
+        salt.copy_from_slice(&bytes[MAGIC.len() + 5..MAGIC.len() + 5 + SALT_LEN]);
+        salt
+    }
+
+    fn iterations(bytes: &[u8]) -> u32 {
+        let mut iterations = [0u8; 4];
+        iterations.copy_from_slice(&bytes[MAGIC.len() + 1..MAGIC.len() + 5]);
+        u32::from_be_bytes(iterations)
+    }
+
+    /**
+     * Encrypts `plaintext` in the vault file format, with a salt of its own. Used for backups kept elsewhere.
+     */
+    pub(crate) fn seal(&self, plaintext: &[u8], iterations: u32) -> Vec<u8> {
+        let mut salt = [0u8; SALT_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        self.encrypt(plaintext, salt, iterations)
+    }
+
+    fn encrypt(&self, plaintext: &[u8], salt: [u8; SALT_LEN], iterations: u32) -> Vec<u8> {
+        let keys = self.keys(salt, iterations);
+
+        let mut iv = [0u8; IV_LEN];
+        rand::thread_rng().fill_bytes(&mut iv);
+
+        let ciphertext = Aes256CbcEnc::new(&keys.encryption.into(), &iv.into()).encrypt_padded_vec_mut::<Pkcs7>(plaintext);
+
+        let mut bytes = Vec::with_capacity(HEADER_LEN + ciphertext.len() + TAG_LEN);
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&iterations.to_be_bytes());
+        bytes.extend_from_slice(&salt);
+        bytes.extend_from_slice(&iv);
+        bytes.extend_from_slice(&ciphertext);
+
+        let tag = Self::tag(&keys.authentication, &bytes);
+        bytes.extend_from_slice(&tag);
+        bytes
+    }
+
+    pub(crate) fn decrypt(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        if bytes.len() < HEADER_LEN + TAG_LEN || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(RepositoryError::SecretStoreError("vault_corrupted".to_owned()));
+        }
+
+        if bytes[MAGIC.len()] != VERSION {
+            return Err(RepositoryError::SecretStoreError(format!("Unsupported vault version {}", bytes[MAGIC.len()])));
+        }
+
+        let iterations = Self::iterations(bytes);
+
+        if !ACCEPTED_ITERATIONS.contains(&iterations) {
+            return Err(RepositoryError::SecretStoreError("vault_corrupted".to_owned()));
+        }
+
+        let salt = Self::salt(bytes);
+        let iv = &bytes[HEADER_LEN - IV_LEN..HEADER_LEN];
+        let (content, tag) = bytes.split_at(bytes.len() - TAG_LEN);
+
+        let keys = self.keys(salt, iterations);
+
+        let mut mac = HmacSha256::new_from_slice(&keys.authentication).expect("HMAC takes keys of any size");
+        mac.update(content);
+        mac.verify_slice(tag)
+            .map_err(|_| RepositoryError::SecretStoreError("vault_wrong_passphrase".to_owned()))?;
+
+        Aes256CbcDec::new(&keys.encryption.into(), iv.into())
+            .decrypt_padded_vec_mut::<Pkcs7>(&content[HEADER_LEN..])
+            .map_err(|_| RepositoryError::SecretStoreError("vault_corrupted".to_owned()))
+    }
+
+    fn tag(key: &[u8], content: &[u8]) -> Vec<u8> {
+        let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any size");
+        mac.update(content);
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    fn keys(&self, salt: [u8; SALT_LEN], iterations: u32) -> DerivedKeys {
+        let fingerprint: [u8; 32] = Sha256::digest(self.passphrase.as_bytes()).into();
+
+        let mut cached = DERIVED_KEYS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        if let Some(keys) = cached.as_ref() {
+            if keys.fingerprint == fingerprint && keys.salt == salt && keys.iterations == iterations {
+                return keys.clone();
+            }
+        }
+
+        let mut derived = [0u8; 64];
+        pbkdf2::pbkdf2_hmac::<Sha256>(self.passphrase.as_bytes(), &salt, iterations, &mut derived);
+
+        let mut encryption = [0u8; 32];
+        let mut authentication = [0u8; 32];
+        encryption.copy_from_slice(&derived[..32]);
+        authentication.copy_from_slice(&derived[32..]);
+
+        let keys = DerivedKeys {
+            fingerprint,
+            salt,
+            iterations,
+            encryption,
+            authentication,
+        };
+
+        *cached = Some(keys.clone());
+        keys
+    }
+}
+
+impl SecretStore for Vault {
+    fn upsert(&self, label: &str, uuid: &str, secret: &str) -> Result<()> {
+        let _lock = self.exclusive()?;
+        let mut entries = self.read()?;
+        entries.insert(
+            uuid.to_owned(),
+            VaultEntry {
+                label: label.to_owned(),
+                secret: secret.to_owned(),
+            },
+        );
+        self.write(&entries)
+    }
+
+    fn secret(&self, uuid: &str) -> Result<Option<String>> {
+        Ok(self.read()?.remove(uuid).map(|entry| entry.secret))
+    }
+
+    fn remove(&self, uuid: &str) -> Result<()> {
+        let _lock = self.exclusive()?;
+        let mut entries = self.read()?;
+
+        if entries.remove(uuid).is_some() {
+            self.write(&entries)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn all_secrets(&self) -> Result<Vec<(String, String)>> {
+        Ok(self.read()?.into_iter().map(|(uuid, entry)| (uuid, entry.secret)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vault(name: &str, passphrase: &str) -> Vault {
+        let mut path = std::env::temp_dir();
+        path.push(format!("{}-{}.vault", name, uuid::Uuid::new_v4()));
+
+        Vault {
+            path,
+            passphrase: passphrase.to_owned(),
+            iterations: 10,
+        }
+    }
+
+    #[test]
+    fn pbkdf2_matches_rfc_7914_vectors() {
+        let mut out = [0u8; 64];
+        pbkdf2::pbkdf2_hmac::<Sha256>(b"passwd", b"salt", 1, &mut out);
+
+        assert_eq!(
+            "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc49ca9cccf179b645991664b39d77ef317c71b845b1e30bd509112041d3a19783",
+            out.iter().map(|b| format!("{:02x}", b)).collect::<String>()
+        );
+    }
+
+    #[test]
+    fn stores_and_reads_back_secrets() {
+        let vault = vault("stores_and_reads_back_secrets", "correct horse");
+
+        vault.upsert("a", "uuid-a", "AAAA").unwrap();
+        vault.upsert("b", "uuid-b", "BBBB").unwrap();
+        vault.upsert("a", "uuid-a", "CCCC").unwrap();
+
+        assert_eq!(Some("CCCC".to_owned()), vault.secret("uuid-a").unwrap());
+        assert_eq!(
+            vec![("uuid-a".to_owned(), "CCCC".to_owned()), ("uuid-b".to_owned(), "BBBB".to_owned())],
+            vault.all_secrets().unwrap()
+        );
+
+        vault.remove("uuid-a").unwrap();
+        assert_eq!(None, vault.secret("uuid-a").unwrap());
+
+        // secrets stored under account ids before uuids
+        vault.upsert("c", "3", "DDDD").unwrap();
+        assert!(vault.rekey("c", 3, "uuid-c").unwrap());
+        assert_eq!(None, vault.secret("3").unwrap());
+        assert_eq!(Some("DDDD".to_owned()), vault.secret("uuid-c").unwrap());
+
+        let content = fs::read(&vault.path).unwrap();
+        assert!(!String::from_utf8_lossy(&content).contains("BBBB"));
+
+        fs::remove_file(&vault.path).unwrap();
+        fs::remove_file(vault.path.with_extension("lock")).unwrap();
+    }
+
+    #[test]
+    fn rejects_wrong_passphrase_and_tampering() {
+        let vault = vault("rejects_wrong_passphrase_and_tampering", "correct horse");
+        vault.upsert("a", "uuid-a", "AAAA").unwrap();
+
+        let intruder = Vault {
+            path: vault.path.clone(),
+            passphrase: "battery staple".to_owned(),
+            iterations: 10,
+        };
+        assert!(matches!(intruder.secret("uuid-a"), Err(RepositoryError::SecretStoreError(_))));
+
+        let mut content = fs::read(&vault.path).unwrap();
+        content[HEADER_LEN] ^= 1;
+        fs::write(&vault.path, &content).unwrap();
+        assert!(matches!(vault.secret("uuid-a"), Err(RepositoryError::SecretStoreError(_))));
+
+        // rejected before deriving any key
+        content[MAGIC.len() + 1..MAGIC.len() + 5].copy_from_slice(&u32::MAX.to_be_bytes());
+        fs::write(&vault.path, &content).unwrap();
+        assert!(matches!(vault.secret("uuid-a"), Err(RepositoryError::SecretStoreError(e)) if e == "vault_corrupted"));
+
+        fs::remove_file(&vault.path).unwrap();
+        fs::remove_file(vault.path.with_extension("lock")).unwrap();
+    }
+}
@@ -0,0 +1,536 @@
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
+use curl::easy::{Easy, List};
+use gettextrs::gettext;
+use log::{debug, info};
+use regex::Regex;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::helpers::vault::ITERATIONS;
+use crate::helpers::{Backup, RepositoryError, SecretStores, Vault};
+use crate::model::AccountGroup;
+
+type Result<T> = ::std::result::Result<T, RepositoryError>;
+
+/**
+ * Keys of the WebDAV password and of the passphrase backups are encrypted with, in the selected secret backend.
+ */
+pub const WEBDAV_PASSWORD_KEY: &str = "webdav-password";
+pub const WEBDAV_PASSPHRASE_KEY: &str = "webdav-passphrase";
+
+pub const DEFAULT_VERSIONS: u32 = 7;
+pub const DEFAULT_INTERVAL_HOURS: u32 = 24;
+
+const PREFIX: &str = "authenticator-rs-";
+const EXTENSION: &str = ".yaml.bin";
+// UTC, so that names sort in upload order
+const TIMESTAMP: &str = "%Y%m%dT%H%M%S%3fZ";
+
+/**
+ * Where backups go and how many are kept. The password and passphrase are kept in the selected secret backend.
+ */
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct WebDavSettings {
+    pub url: String,
+    pub username: String,
+    pub versions: u32,
+    pub interval_hours: u32,
+}
+
+/**
+ * A backup on the server, named after the time it was uploaded.
+ */
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RemoteVersion {
+    pub name: String,
+    pub uploaded: DateTime<Utc>,
+}
+
+/**
+ * Uploads encrypted exports of every account to a WebDAV collection, rotating the oldest ones out.
+ * Each backup is encrypted like the vault file, with its own passphrase.
+ */
+pub struct WebDav {
+    settings: WebDavSettings,
+    password: String,
+    passphrase: String,
+    iterations: u32,
+}
+
+impl WebDav {
+    pub fn new(settings: WebDavSettings, password: &str, passphrase: &str) -> WebDav {
+        WebDav {
+            settings,
+            password: password.to_owned(),
+            passphrase: passphrase.to_owned(),
+            iterations: ITERATIONS,
+        }
+    }
+
+    /**
+     * None until remote backups are set up for the current profile.
+     */
+    pub fn settings(connection: &Connection) -> Result<Option<WebDavSettings>> {
+        let Some(url) = Self::setting(connection, "webdav.url")? else {
+            return Ok(None);
+        };
+
+        let number = |key: &str, default: u32| -> Result<u32> { Ok(Self::setting(connection, key)?.and_then(|value| value.parse().ok()).unwrap_or(default)) };
+
+        Ok(Some(WebDavSettings {
+            url,
+            username: Self::setting(connection, "webdav.username")?.unwrap_or_default(),
+            versions: number("webdav.versions", DEFAULT_VERSIONS)?,
+            interval_hours: number("webdav.interval_hours", DEFAULT_INTERVAL_HOURS)?,
+        }))
+    }
+
+    pub fn open(connection: &Connection) -> Result<Option<WebDav>> {
+        let Some(settings) = Self::settings(connection)? else {
+            return Ok(None);
+        };
+
+        let store = SecretStores::store(&SecretStores::selected())?;
+        let password = store.secret(WEBDAV_PASSWORD_KEY)?.unwrap_or_default();
+        let passphrase = store
+            .secret(WEBDAV_PASSPHRASE_KEY)?
+            .ok_or_else(|| RepositoryError::BackupError("webdav_passphrase_missing".to_owned()))?;
+
+        Ok(Some(WebDav::new(settings, password.as_str(), passphrase.as_str())))
+    }
+
+    /**
+     * Saves the settings once the server accepted them. An empty password or passphrase keeps the saved one.
+     * When backups are already there, the passphrase must open the latest one.
+     */
+    pub fn configure(connection: &Connection, settings: &WebDavSettings, password: &str, passphrase: &str) -> Result<()> {
+        let store = SecretStores::store(&SecretStores::selected())?;
+
+        let password = match password {
+            "" => store.secret(WEBDAV_PASSWORD_KEY)?.unwrap_or_default(),
+            password => password.to_owned(),
+        };
+
+        let passphrase = match passphrase {
+            "" => store
+                .secret(WEBDAV_PASSPHRASE_KEY)?
+                .ok_or_else(|| RepositoryError::BackupError("webdav_passphrase_missing".to_owned()))?,
+            passphrase => passphrase.to_owned(),
+        };
+
+        let webdav = WebDav::new(settings.clone(), password.as_str(), passphrase.as_str());
+
+        if let Some(latest) = webdav.versions()?.first() {
+            webdav.download(latest)?;
+        }
+
+        store.upsert(gettext("WebDAV password").as_str(), WEBDAV_PASSWORD_KEY, password.as_str())?;
+        store.upsert(gettext("WebDAV backup passphrase").as_str(), WEBDAV_PASSPHRASE_KEY, passphrase.as_str())?;
+
+        Self::set_setting(connection, "webdav.url", settings.url.as_str())?;
+        Self::set_setting(connection, "webdav.username", settings.username.as_str())?;
+        Self::set_setting(connection, "webdav.versions", settings.versions.to_string().as_str())?;
+        Self::set_setting(connection, "webdav.interval_hours", settings.interval_hours.to_string().as_str())?;
+
+        info!("Remote backups go to {}", settings.url);
+        Ok(())
+    }
+
+    /**
+     * Stops scheduled uploads. Backups already on the server are left alone.
+     */
+    pub fn disable(connection: &Connection) -> Result<()> {
+        connection.execute("DELETE FROM backup_settings WHERE key LIKE 'webdav.%'", [])?;
+
+        let store = SecretStores::store(&SecretStores::selected())?;
+        store.remove(WEBDAV_PASSWORD_KEY)?;
+        store.remove(WEBDAV_PASSPHRASE_KEY)?;
+
+        info!("Remote backups disabled");
+        Ok(())
+    }
+
+    /**
+     * Uploads a backup when remote backups are set up and the last upload is older than the chosen interval.
+     */
+    pub fn backup_if_due(connection: &Connection) -> Result<Option<RemoteVersion>> {
+        let Some(webdav) = Self::open(connection)? else {
+            return Ok(None);
+        };
+
+        let last_upload = Self::setting(connection, "webdav.last_upload")?.and_then(|value| value.parse::<i64>().ok());
+
+        if !Self::is_due(last_upload, webdav.settings.interval_hours, Utc::now()) {
+            return Ok(None);
+        }
+
+        webdav.backup(connection).map(Some)
+    }
+
+    pub fn backup(&self, connection: &Connection) -> Result<RemoteVersion> {
+        let account_groups = Backup::load_accounts(connection)?;
+        let version = self.upload(&account_groups)?;
+
+        Self::set_setting(connection, "webdav.last_upload", version.uploaded.timestamp().to_string().as_str())?;
+        Ok(version)
+    }
+
+    pub fn upload(&self, account_groups: &[AccountGroup]) -> Result<RemoteVersion> {
+        self.upload_at(account_groups, Utc::now())
+    }
+
+    /**
+     * Backups on the server, latest first.
+     */
+    pub fn versions(&self) -> Result<Vec<RemoteVersion>> {
+        let listing = self.request("PROPFIND", "", None)?;
+        let listing = String::from_utf8_lossy(&listing);
+
+        let href = Regex::new(r"<(?:[A-Za-z0-9]+:)?href>([^<]*)</(?:[A-Za-z0-9]+:)?href>").unwrap();
+
+        let mut versions = href
+            .captures_iter(&listing)
+            .filter_map(|captures| captures.get(1))
+            .filter_map(|href| href.as_str().trim_end_matches('/').rsplit('/').next().and_then(RemoteVersion::parse))
+            .collect::<Vec<RemoteVersion>>();
+
+        versions.sort_by_key(|version| std::cmp::Reverse(version.uploaded));
+        versions.dedup();
+
+        Ok(versions)
+    }
+
+    /**
+     * The decrypted backup, as exported by Backup::serialise_accounts().
+     */
+    pub fn download(&self, version: &RemoteVersion) -> Result<Vec<u8>> {
+        let bytes = self.request("GET", version.name.as_str(), None)?;
+
+        self.vault().decrypt(&bytes).map_err(|e| match e {
+            RepositoryError::SecretStoreError(key) if key == "vault_wrong_passphrase" => RepositoryError::BackupError("webdav_wrong_passphrase".to_owned()),
+            RepositoryError::SecretStoreError(_) => RepositoryError::BackupError("webdav_corrupted".to_owned()),
+            e => e,
+        })
+    }
+
+    /**
+     * Writes the decrypted backup to a file only readable by the user, to be imported with
+     * Backup::restore_account_and_signal_back().
+     */
+    pub fn download_to(&self, version: &RemoteVersion, path: &Path) -> Result<()> {
+        let yaml = self.download(version)?;
+
+        let mut file = OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)?;
+        file.write_all(&yaml)?;
+        Ok(())
+    }
+
+    fn upload_at(&self, account_groups: &[AccountGroup], now: DateTime<Utc>) -> Result<RemoteVersion> {
+        let mut yaml = Vec::new();
+        Backup::write_accounts(account_groups, &mut yaml)?;
+
+        let version = RemoteVersion::at(now);
+        let bytes = self.vault().seal(&yaml, self.iterations);
+
+        self.request("PUT", version.name.as_str(), Some(&bytes))?;
+        info!("Uploaded backup {}", version.name);
+
+        self.rotate()?;
+        Ok(version)
+    }
+
+    fn rotate(&self) -> Result<()> {
+        for version in self.versions()?.iter().skip(self.settings.versions.max(1) as usize) {
+            info!("Removing old backup {}", version.name);
+            self.request("DELETE", version.name.as_str(), None)?;
+        }
+
+        Ok(())
+    }
+
+    fn is_due(last_upload: Option<i64>, interval_hours: u32, now: DateTime<Utc>) -> bool {
+        last_upload.is_none_or(|last_upload| now.timestamp() - last_upload >= interval_hours as i64 * 3600)
+    }
+
+    fn vault(&self) -> Vault {
+        Vault::new(PathBuf::new(), self.passphrase.as_str())
+    }
+
+    /**
+     * `name` is relative to the collection, the collection itself when empty.
+     */
+    fn request(&self, method: &str, name: &str, body: Option<&[u8]>) -> Result<Vec<u8>> {
+        let url = format!("{}/{}", self.settings.url.trim_end_matches('/'), name);
+        debug!("{} {}", method, url);
+
+        let mut data = Vec::new();
+        let mut handle = Easy::new();
+
+        handle.url(url.as_str())?;
+        handle.custom_request(method)?;
+        handle.timeout(Duration::from_secs(30))?;
+
+        if !self.settings.username.is_empty() {
+            handle.username(self.settings.username.as_str())?;
+            handle.password(self.password.as_str())?;
+        }
+
+        let mut headers = List::new();
+        // uploads are small, waiting for "100 Continue" only slows them down
+        headers.append("Expect:")?;
+        if method == "PROPFIND" {
+            headers.append("Depth: 1")?;
+        }
+        handle.http_headers(headers)?;
+
+        let mut body = body.unwrap_or_default();
+        if !body.is_empty() {
+            handle.upload(true)?;
+            handle.in_filesize(body.len() as u64)?;
+        }
+
+        {
+            let mut transfer = handle.transfer();
+            transfer.write_function(|new_data| {
+                data.extend_from_slice(new_data);
+                Ok(new_data.len())
+            })?;
+            transfer.read_function(|into| Ok(body.read(into).unwrap_or(0)))?;
+
+            transfer.perform()?;
+        }
+
+        match handle.response_code()? {
+            200..=299 => Ok(data),
+            401 | 403 => Err(RepositoryError::BackupError("webdav_unauthorised".to_owned())),
+            404 | 409 => Err(RepositoryError::BackupError("webdav_not_found".to_owned())),
+            code => Err(RepositoryError::BackupError(format!("{} {}: HTTP {}", method, url, code))),
+        }
+    }
+
+    fn setting(connection: &Connection, key: &str) -> Result<Option<String>> {
+        connection
+            .query_row("SELECT value FROM backup_settings WHERE key = ?1", params![key], |row| row.get(0))
+            .optional()
+            .map_err(RepositoryError::SqlError)
+    }
+
+    fn set_setting(connection: &Connection, key: &str, value: &str) -> Result<()> {
+        connection.execute("INSERT OR REPLACE INTO backup_settings (key, value) VALUES (?1, ?2)", params![key, value])?;
+        Ok(())
+    }
+}
+
+impl RemoteVersion {
+    fn at(uploaded: DateTime<Utc>) -> RemoteVersion {
+        RemoteVersion {
+            name: format!("{}{}{}", PREFIX, uploaded.format(TIMESTAMP), EXTENSION),
+            uploaded,
+        }
+    }
+
+    /**
+     * None for anything in the collection that is not one of our backups.
+     */
+    pub fn parse(name: &str) -> Option<RemoteVersion> {
+        let timestamp = name.strip_prefix(PREFIX)?.strip_suffix(EXTENSION)?;
+
+        NaiveDateTime::parse_from_str(timestamp, TIMESTAMP).ok().map(|uploaded| RemoteVersion {
+            name: name.to_owned(),
+            uploaded: uploaded.and_utc(),
+        })
+    }
+}
+
+impl fmt::Display for RemoteVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.uploaded.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::io::{BufRead, BufReader};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::helpers::SecretType;
+    use crate::model::Account;
+
+    // "alice:secret"
+    const AUTHORIZATION: &str = "Basic YWxpY2U6c2VjcmV0";
+
+    type Files = Arc<Mutex<BTreeMap<String, Vec<u8>>>>;
+
+    /**
+     * Just enough of a WebDAV server for the client: PUT, GET, DELETE and a PROPFIND listing of /backups,
+     * behind basic authentication.
+     */
+    struct StandIn {
+        url: String,
+        files: Files,
+    }
+
+    impl StandIn {
+        fn start() -> StandIn {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}/backups", listener.local_addr().unwrap());
+
+            let files = Files::default();
+            let served = files.clone();
+
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    Self::serve(stream, &served);
+                }
+            });
+
+            StandIn { url, files }
+        }
+
+        fn serve(mut stream: TcpStream, files: &Files) {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+
+            let mut headers = BTreeMap::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+
+                match line.trim().split_once(':') {
+                    Some((name, value)) => headers.insert(name.to_lowercase(), value.trim().to_owned()),
+                    None => break,
+                };
+            }
+
+            let length = headers.get("content-length").and_then(|length| length.parse().ok()).unwrap_or(0);
+            let mut body = vec![0u8; length];
+            reader.read_exact(&mut body).unwrap();
+
+            let fields = request_line.split_whitespace().collect::<Vec<&str>>();
+            let (method, path) = (fields[0], fields[1]);
+            let name = path.trim_start_matches("/backups").trim_start_matches('/').to_owned();
+
+            let (status, content) = if headers.get("authorization").map(String::as_str) != Some(AUTHORIZATION) {
+                ("401 Unauthorized", Vec::new())
+            } else {
+                let mut files = files.lock().unwrap();
+
+                match method {
+                    "PUT" => {
+                        files.insert(name, body);
+                        ("201 Created", Vec::new())
+                    }
+                    "GET" => match files.get(&name) {
+                        Some(content) => ("200 OK", content.clone()),
+                        None => ("404 Not Found", Vec::new()),
+                    },
+                    "DELETE" => match files.remove(&name) {
+                        Some(_) => ("204 No Content", Vec::new()),
+                        None => ("404 Not Found", Vec::new()),
+                    },
+                    "PROPFIND" => {
+                        let responses = std::iter::once("")
+                            .chain(files.keys().map(String::as_str))
+                            .map(|name| format!("<d:response><d:href>/backups/{}</d:href></d:response>", name))
+                            .collect::<String>();
+
+                        let listing = format!(r#"<?xml version="1.0"?><d:multistatus xmlns:d="DAV:">{}</d:multistatus>"#, responses);
+                        ("207 Multi-Status", listing.into_bytes())
+                    }
+                    _ => ("405 Method Not Allowed", Vec::new()),
+                }
+            };
+
+            write!(stream, "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, content.len()).unwrap();
+            stream.write_all(&content).unwrap();
+        }
+
+        fn webdav(&self, password: &str, passphrase: &str, versions: u32) -> WebDav {
+            let settings = WebDavSettings {
+                url: self.url.clone(),
+                username: "alice".to_owned(),
+                versions,
+                interval_hours: DEFAULT_INTERVAL_HOURS,
+            };
+
+            WebDav {
+                iterations: 10,
+                ..WebDav::new(settings, password, passphrase)
+            }
+        }
+    }
+
+    fn account_groups() -> Vec<AccountGroup> {
+        let account = Account::new(1, 1, "alice@example.com", "JBSWY3DPEHPK3PXP", SecretType::LOCAL);
+        vec![AccountGroup::new(1, "Example", None, Some("https://example.com"), false, vec![account])]
+    }
+
+    fn at(hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 10, 18, hour, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn uploads_encrypted_backups_and_keeps_the_latest_versions() {
+        let server = StandIn::start();
+        let webdav = server.webdav("secret", "backup passphrase", 2);
+
+        server.files.lock().unwrap().insert("notes.txt".to_owned(), b"not a backup".to_vec());
+
+        for hour in 1..=3 {
+            webdav.upload_at(&account_groups(), at(hour)).unwrap();
+        }
+
+        let versions = webdav.versions().unwrap();
+        assert_eq!(vec![at(3), at(2)], versions.iter().map(|version| version.uploaded).collect::<Vec<_>>());
+        assert_eq!("authenticator-rs-20261018T030000000Z.yaml.bin", versions[0].name);
+
+        let files = server.files.lock().unwrap().clone();
+        assert_eq!(3, files.len(), "the oldest backup is rotated out, other files are left alone");
+        assert!(files.contains_key("notes.txt"));
+        assert!(files.values().all(|content| !String::from_utf8_lossy(content).contains("JBSWY3DPEHPK3PXP")));
+
+        let restored: Vec<AccountGroup> = serde_yaml::from_slice(&webdav.download(&versions[0]).unwrap()).unwrap();
+        assert_eq!("Example", restored[0].name);
+        assert_eq!("alice@example.com", restored[0].entries[0].label);
+        assert_eq!("JBSWY3DPEHPK3PXP", restored[0].entries[0].secret);
+    }
+
+    #[test]
+    fn rejects_wrong_password_and_passphrase() {
+        let server = StandIn::start();
+        let webdav = server.webdav("secret", "backup passphrase", DEFAULT_VERSIONS);
+
+        let version = webdav.upload_at(&account_groups(), at(1)).unwrap();
+
+        match server.webdav("wrong", "backup passphrase", DEFAULT_VERSIONS).versions() {
+            Err(RepositoryError::BackupError(key)) => assert_eq!("webdav_unauthorised", key),
+            other => panic!("expected webdav_unauthorised, got {:?}", other),
+        }
+
+        match server.webdav("secret", "wrong passphrase", DEFAULT_VERSIONS).download(&version) {
+            Err(RepositoryError::BackupError(key)) => assert_eq!("webdav_wrong_passphrase", key),
+            other => panic!("expected webdav_wrong_passphrase, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn backups_are_due_once_the_interval_has_passed() {
+        assert!(WebDav::is_due(None, 24, at(1)));
+        assert!(!WebDav::is_due(Some(at(1).timestamp()), 2, at(2)));
+        assert!(WebDav::is_due(Some(at(1).timestamp()), 2, at(3)));
+    }
+}
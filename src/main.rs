@@ -10,9 +10,11 @@ use rusqlite::Connection;
 
 use main_window::MainWindow;
 
-use crate::helpers::{runner, Database, Paths};
+use crate::cli::{Cli, Command};
+use crate::helpers::{runner, Clock, Database, IconStore, Paths, Profiles, SecretStores, DEFAULT_PROFILE};
 use crate::main_window::Action;
 
+mod cli;
 mod exporting;
 mod helpers;
 mod main_window;
@@ -25,6 +27,29 @@ const NAMESPACE_PREFIX: &str = "/uk/co/grumlimited/authenticator-rs";
 const GETTEXT_PACKAGE: &str = "authenticator-rs";
 
 fn main() {
+    let cli = Cli::parse(std::env::args());
+
+    if let Some(command @ (Command::Help | Command::Invalid(_))) = &cli.command {
+        exit(Cli::usage(command));
+    }
+
+    Profiles::move_legacy();
+
+    let profile = match (&cli.profile, &cli.command) {
+        (Some(profile), _) => profile.clone(),
+        (None, Some(_)) => DEFAULT_PROFILE.to_owned(),
+        (None, None) => Profiles::last_used().unwrap_or_else(|| DEFAULT_PROFILE.to_owned()),
+    };
+
+    if let Err(e) = Profiles::set_current(profile.as_str()) {
+        eprintln!("{:?}", e);
+        exit(1);
+    }
+
+    if cli.command.is_none() {
+        Profiles::remember(profile.as_str());
+    }
+
     if let Err(e) = Paths::check_configuration_dir() {
         eprintln!("Failed to check configuration dir: {:?}", e);
         exit(1);
@@ -32,6 +57,8 @@ fn main() {
         info!("Reading configuration from {}", Paths::path().display());
     }
 
+    Clock::load();
+
     let resource = gio::Resource::load(format!("data/{}.gresource", NAMESPACE)).unwrap_or_else(|_| {
         match gio::Resource::load(format!("/usr/share/{}/{}.gresource", NAMESPACE, NAMESPACE)) {
             Ok(r) => r,
@@ -44,6 +71,14 @@ fn main() {
 
     gio::functions::resources_register(&resource);
 
+    // brand icons are optional: groups just go without them
+    match gio::Resource::load(format!("data/{}.brands.gresource", NAMESPACE))
+        .or_else(|_| gio::Resource::load(format!("/usr/share/{}/{}.brands.gresource", NAMESPACE, NAMESPACE)))
+    {
+        Ok(brands) => gio::functions::resources_register(&brands),
+        Err(e) => info!("No brand icons: {}", e),
+    }
+
     let application = gtk::Application::new(Some(NAMESPACE), Default::default());
 
     let connection = match Database::create_connection() {
@@ -56,6 +91,19 @@ fn main() {
 
     let connection: Arc<Mutex<Connection>> = Arc::new(Mutex::new(connection));
 
+    if let Some(command) = cli.command {
+        if let Err(e) = runner::run(connection.clone()) {
+            eprintln!("Migrations failed: {:?}", e);
+            exit(1);
+        }
+
+        if let Err(e) = SecretStores::rekey_legacy_secrets(&Database::lock(&connection)) {
+            log::warn!("Could not move secrets to account uuids: {:?}", e);
+        }
+
+        exit(Cli::run(command, &Database::lock(&connection)));
+    }
+
     application.connect_startup({
         let connection = Arc::clone(&connection);
         move |_| {
@@ -90,9 +138,16 @@ fn main() {
                 info!("Migrations done running");
             }
 
+            // icons of deleted groups and of edits which were not saved
+            match IconStore::open().sweep(&Database::lock(&connection)) {
+                Ok(0) => {}
+                Ok(deleted) => info!("Deleted {} unused icons", deleted),
+                Err(e) => log::warn!("Could not delete unused icons: {:?}", e),
+            }
+
+            // a locked or unavailable keyring is reported by the main window, which offers to retry
             if let Err(e) = Paths::update_keyring_secrets(connection.clone()) {
                 log::error!("Failed to update keyring secrets: {:?}", e);
-                exit(1);
             } else {
                 info!("Added local accounts to keyring");
             }
@@ -111,7 +166,7 @@ fn main() {
         gdk::notify_startup_complete();
     });
 
-    application.run();
+    application.run_with_args(&cli.gtk_args);
 }
 
 /**
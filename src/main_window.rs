@@ -2,23 +2,42 @@ use async_channel::{Receiver, Sender};
 use std::cell::RefCell;
 use std::sync::{Arc, Mutex};
 
-use chrono::prelude::*;
 use gettextrs::*;
 use gio::prelude::SettingsExt;
 use glib::clone;
 use gtk::prelude::*;
 use gtk::{ApplicationWindow, Builder, Window};
 use gtk_macros::*;
-use log::{error, info};
+use log::{error, info, warn};
 use rusqlite::Connection;
 
-use crate::helpers::Keyring;
+use crate::helpers::{runner, Clock, Database, Keyring, LocalBackups, Paths, Profiles, RepositoryError, SecretStores, SecretType, SyncFolder, Vault, WebDav};
 use crate::ui::menu::*;
-use crate::ui::{AccountsWindow, AddGroupWindow, EditAccountWindow, ErrorsWindow, NoAccountsWindow};
+use crate::ui::{AccountsWindow, AddGroupWindow, EditAccountWindow, ErrorsWindow, NoAccountsWindow, SyncResult, SyncWindow, VaultWindow};
 use crate::{NAMESPACE, NAMESPACE_PREFIX};
 
+/**
+ * Seconds between two syncs with the sync folder.
+ */
+const SYNC_INTERVAL: u32 = 60;
+
+/**
+ * Seconds between two checks for a local or remote backup being due.
+ */
+const BACKUP_CHECK_INTERVAL: u32 = 15 * 60;
+
+/**
+ * Seconds between two checks of the clock against the time source.
+ */
+const CLOCK_CHECK_INTERVAL: u32 = 60 * 60;
+
+#[derive(Clone, Debug)]
 pub enum Action {
     RefreshAccounts { filter: Option<String> },
+    UnlockSecrets,
+    DeleteAccount { account_id: u32 },
+    DeleteGroup { group_id: u32 },
+    Sync,
 }
 
 #[derive(Clone, Debug)]
@@ -162,17 +181,7 @@ impl MainWindow {
 
         self.start_progress_bar();
 
-        match Keyring::ensure_unlocked() {
-            Ok(()) => {
-                info!("Keyring is available");
-                self.accounts_window.refresh_accounts(self);
-            }
-            Err(e) => {
-                error!("Keyring error: {:?}", e);
-                self.errors.error_display_message.set_text(&gettext("keyring_locked"));
-                self.switch_to(Display::Errors);
-            }
-        }
+        self.bind_retry_button();
 
         glib::spawn_future_local(clone!(
             #[strong]
@@ -186,12 +195,300 @@ impl MainWindow {
                             let results = AccountsWindow::load_account_groups(connection.clone(), filter).await;
                             gui.accounts_window.replace_accounts_and_widgets(results, gui.clone(), connection.clone()).await;
                         }
+                        Action::UnlockSecrets => gui.unlock_secrets(connection.clone()),
+                        Action::DeleteAccount { account_id } => {
+                            gui.accounts_window.delete_account_reload(&gui, account_id, connection.clone()).await;
+                        }
+                        Action::DeleteGroup { group_id } => {
+                            gui.accounts_window.delete_group_reload(&gui, group_id, connection.clone()).await;
+                        }
+                        Action::Sync => gui.sync(connection.clone(), false),
                     }
                 }
             }
         ));
 
+        glib::timeout_add_seconds_local(
+            SYNC_INTERVAL,
+            clone!(
+                #[strong(rename_to = gui)]
+                self,
+                #[strong]
+                connection,
+                move || {
+                    gui.sync(connection.clone(), true);
+                    glib::ControlFlow::Continue
+                }
+            ),
+        );
+
+        glib::timeout_add_seconds_local(
+            BACKUP_CHECK_INTERVAL,
+            clone!(
+                #[strong]
+                connection,
+                move || {
+                    Self::backup_if_due(connection.clone());
+                    glib::ControlFlow::Continue
+                }
+            ),
+        );
+
+        glib::timeout_add_seconds_local(
+            CLOCK_CHECK_INTERVAL,
+            clone!(
+                #[strong(rename_to = gui)]
+                self,
+                move || {
+                    gui.check_clock();
+                    glib::ControlFlow::Continue
+                }
+            ),
+        );
+
+        self.check_clock();
+
         self.window.show();
+
+        // after show(), the vault prompt needs a visible parent
+        self.unlock_secrets(connection);
+    }
+
+    /**
+     * Syncs with the chosen sync folder, if any, in the background.
+     * Failures of `quiet` (scheduled) syncs are only logged, as the sync folder may just be offline for a while.
+     */
+    pub fn sync(&self, connection: Arc<Mutex<Connection>>, quiet: bool) {
+        let (tx, rx) = async_channel::bounded::<SyncResult>(1);
+
+        glib::spawn_future(clone!(
+            #[strong]
+            connection,
+            async move {
+                let result = SyncFolder::sync(&Database::lock(&connection));
+
+                if let Err(e) = tx.send(result).await {
+                    warn!("Could not send sync result: {:?}", e);
+                }
+            }
+        ));
+
+        glib::spawn_future_local(clone!(
+            #[strong(rename_to = gui)]
+            self,
+            async move {
+                match rx.recv().await {
+                    Ok(Ok(None)) => {}
+                    Ok(Ok(Some(report))) => {
+                        if report.received > 0 {
+                            info!("Received {} changes from other devices", report.received);
+                            gui.accounts_window.refresh_accounts(&gui);
+                        }
+
+                        if !report.conflicts.is_empty() {
+                            SyncWindow::show(&gui, connection, Some(report));
+                        }
+                    }
+                    Ok(Err(e)) if quiet => warn!("Scheduled sync failed: {:?}", e),
+                    Ok(Err(e)) => {
+                        error!("Sync failed: {:?}", e);
+                        gui.display_error(e.message().as_str(), Action::Sync);
+                    }
+                    Err(e) => warn!("Channel is closed. Application terminated?: {:?}", e),
+                }
+            }
+        ));
+    }
+
+    /**
+     * Shows the error page with `message`. Clicking "Retry" replays `retry`,
+     * so that a transient failure (locked keyring, busy database) can be recovered from.
+     */
+    pub fn display_error(&self, message: &str, retry: Action) {
+        self.errors.error_display_message.set_text(message);
+        self.errors.set_retry_action(retry);
+        self.switch_to(Display::Errors);
+    }
+
+    /**
+     * Reopens the shared database connection on the profile's own database, then reloads its accounts.
+     * Nothing from the previous profile is kept, the vault passphrase included.
+     */
+    pub fn switch_profile(&self, profile: &str, connection: Arc<Mutex<Connection>>) {
+        let previous = Profiles::current();
+
+        let new_connection = Profiles::set_current(profile)
+            .and_then(|_| Paths::check_configuration_dir())
+            .and_then(|_| Database::create_connection());
+
+        let result = match new_connection {
+            Ok(new_connection) => {
+                *Database::lock(&connection) = new_connection;
+                runner::run(connection.clone())
+                    .map(|_| ())
+                    .map_err(|e| RepositoryError::ProfileError(format!("{:?}", e)))
+            }
+            Err(e) => {
+                // still on the previous database
+                if let Err(e) = Profiles::set_current(previous.as_str()) {
+                    warn!("Could not go back to profile {}: {:?}", previous, e);
+                }
+                Err(e)
+            }
+        };
+
+        Vault::lock();
+
+        match result {
+            Ok(()) => {
+                Profiles::remember(profile);
+                self.unlock_secrets(connection);
+            }
+            Err(e) => {
+                error!("Could not switch to profile {}: {:?}", profile, e);
+                self.display_error(e.message().as_str(), Action::UnlockSecrets);
+            }
+        }
+    }
+
+    /**
+     * Unlocks the secret backends holding accounts before loading them: the Secret Service collection,
+     * and the vault unless its passphrase was given through the environment.
+     */
+    fn unlock_secrets(&self, connection: Arc<Mutex<Connection>>) {
+        let in_use = match SecretStores::in_use(&Database::lock(&connection)) {
+            Ok(in_use) => in_use,
+            Err(e) => {
+                error!("Could not list secret backends: {:?}", e);
+                self.display_error(e.message().as_str(), Action::UnlockSecrets);
+                return;
+            }
+        };
+
+        if in_use.contains(&SecretType::KEYRING) {
+            if let Err(e) = Keyring::ensure_unlocked() {
+                error!("Keyring error: {:?}", e);
+                self.display_error(&gettext("keyring_locked"), Action::UnlockSecrets);
+                return;
+            }
+
+            info!("Keyring is available");
+        }
+
+        if in_use.contains(&SecretType::VAULT) && !Vault::is_unlocked() {
+            VaultWindow::prompt(
+                self,
+                clone!(
+                    #[strong(rename_to = gui)]
+                    self,
+                    #[strong]
+                    connection,
+                    move |unlocked| {
+                        if unlocked {
+                            gui.secrets_unlocked(&connection);
+                        } else {
+                            gui.display_error(&gettext("vault_locked"), Action::UnlockSecrets);
+                        }
+                    }
+                ),
+            );
+            return;
+        }
+
+        self.secrets_unlocked(&connection);
+    }
+
+    /**
+     * Secrets saved by earlier versions are still keyed by account id, and can only be moved once every backend is open.
+     */
+    fn secrets_unlocked(&self, connection: &Arc<Mutex<Connection>>) {
+        match SecretStores::rekey_legacy_secrets(&Database::lock(connection)) {
+            Ok(0) => {}
+            Ok(count) => info!("Moved {} secrets to account uuids", count),
+            Err(e) => warn!("Could not move secrets to account uuids: {:?}", e),
+        }
+
+        self.accounts_window.refresh_accounts(self);
+
+        self.sync(connection.clone(), false);
+
+        Self::backup_if_due(connection.clone());
+    }
+
+    /**
+     * Takes the daily local backup and uploads a remote one in the background when due. Failures are only logged, the next check retries.
+     */
+    fn backup_if_due(connection: Arc<Mutex<Connection>>) {
+        glib::spawn_future(async move {
+            match LocalBackups::open().daily_if_due(&Database::lock(&connection)) {
+                Ok(Some(backup)) => info!("Took daily backup {}", backup.path.display()),
+                Ok(None) => {}
+                Err(e) => warn!("Daily backup failed: {:?}", e),
+            }
+
+            match WebDav::backup_if_due(&Database::lock(&connection)) {
+                Ok(Some(version)) => info!("Uploaded remote backup {}", version.name),
+                Ok(None) => {}
+                Err(e) => warn!("Remote backup failed: {:?}", e),
+            }
+        });
+    }
+
+    /**
+     * Measures the drift of the clock against the time source set in the settings, if any, in the background.
+     */
+    pub fn check_clock(&self) {
+        let Some(source) = Clock::configured_source() else {
+            self.accounts_window.clock_banner.hide();
+            return;
+        };
+
+        let (tx, rx) = async_channel::bounded::<Result<i64, RepositoryError>>(1);
+
+        glib::spawn_future(async move {
+            let result = Clock::source(source.as_str()).and_then(|source| Clock::drift(source.as_ref()));
+
+            if let Err(e) = tx.send(result).await {
+                warn!("Could not send clock drift: {:?}", e);
+            }
+        });
+
+        glib::spawn_future_local(clone!(
+            #[strong(rename_to = gui)]
+            self,
+            async move {
+                match rx.recv().await {
+                    Ok(Ok(drift)) => {
+                        info!("Clock drift: {}s, offset: {}s", drift, Clock::offset());
+                        gui.accounts_window.display_clock_drift(drift);
+                    }
+                    // the source may just be unreachable for now
+                    Ok(Err(e)) => warn!("Could not check clock: {:?}", e),
+                    Err(e) => warn!("Channel is closed. Application terminated?: {:?}", e),
+                }
+            }
+        ));
+    }
+
+    fn bind_retry_button(&self) {
+        self.errors.retry_button.connect_clicked(clone!(
+            #[strong(rename_to = gui)]
+            self,
+            move |_| {
+                let action = gui.errors.take_retry_action().unwrap_or(Action::RefreshAccounts {
+                    filter: gui.accounts_window.get_filter_value(),
+                });
+
+                info!("Retrying {:?}", action);
+
+                let tx_events = gui.tx_events.clone();
+                glib::spawn_future_local(async move {
+                    if let Err(e) = tx_events.send(action).await {
+                        error!("Could not send retry action: {:?}", e);
+                    }
+                });
+            }
+        ));
     }
 
     pub fn bind_account_filter_events(&self) {
@@ -234,16 +531,12 @@ impl MainWindow {
             #[strong(rename_to = gui)]
             self,
             move || {
-                let seconds = Local::now().second() as u8;
-
-                AccountsWindow::progress_bar_fraction_for(&gui.accounts_window.progress_bar, seconds as u32);
+                AccountsWindow::progress_bar_fraction_now(&gui.accounts_window.progress_bar);
 
-                // Update TOTP widgets on 0 and 30 seconds (every 30s)
-                if seconds.is_multiple_of(30) {
-                    match gui.accounts_window.widgets.lock() {
-                        Ok(mut widgets) => widgets.iter_mut().for_each(|group| group.update()),
-                        Err(_) => error!("Failed to lock account widgets mutex (poisoned)"),
-                    }
+                // Every second: accounts have their own countdown, and their own period
+                match gui.accounts_window.widgets.lock() {
+                    Ok(mut widgets) => widgets.iter_mut().for_each(|group| group.update()),
+                    Err(_) => error!("Failed to lock account widgets mutex (poisoned)"),
                 }
 
                 glib::ControlFlow::Continue
@@ -1,26 +1,183 @@
+use std::cell::Cell;
+use std::f64::consts::PI;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::Duration;
+
 use base32::Alphabet;
 use gettextrs::*;
+use gio::prelude::SettingsExt;
 use glib::clone;
 use gtk::prelude::*;
 use gtk_macros::*;
 use log::warn;
 use serde::{Deserialize, Serialize};
+use strum_macros::{Display, EnumString};
+use totp_rs::{Algorithm, TOTP};
+use url::Url;
+use uuid::Uuid;
 
 use model::account_errors::TotpError;
 
-use crate::helpers::SecretType;
-use crate::{model, NAMESPACE_PREFIX};
+use crate::helpers::{BrandIcons, Clock, IconParser, OpenUri, Paths, SecretType};
+use crate::model::schemes;
+use crate::{model, NAMESPACE, NAMESPACE_PREFIX};
+
+/**
+ * How codes are generated from the secret. Steam Guard codes are 5 characters drawn from Steam's own alphabet,
+ * Yandex Key and Mobile-OTP codes also depend on the PIN of the account. HOTP codes depend on a counter rather than the time.
+ */
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default, EnumString, Display, Serialize, Deserialize)]
+#[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum AccountType {
+    #[default]
+    Totp,
+    Steam,
+    Yandex,
+    Motp,
+    Hotp,
+}
+
+impl AccountType {
+    pub fn has_pin(&self) -> bool {
+        matches!(self, AccountType::Yandex | AccountType::Motp)
+    }
+
+    pub fn has_counter(&self) -> bool {
+        *self == AccountType::Hotp
+    }
+
+    /**
+     * Seconds each code is valid for.
+     */
+    pub fn period(&self) -> u64 {
+        match self {
+            AccountType::Motp => 10,
+            _ => 30,
+        }
+    }
+}
+
+/**
+ * Hash function of TOTP accounts. Most services use SHA1.
+ */
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default, EnumString, Display, Serialize, Deserialize)]
+#[strum(serialize_all = "UPPERCASE")]
+#[serde(rename_all = "UPPERCASE")]
+pub enum TotpAlgorithm {
+    #[default]
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl TotpAlgorithm {
+    pub const ALL: [TotpAlgorithm; 3] = [TotpAlgorithm::Sha1, TotpAlgorithm::Sha256, TotpAlgorithm::Sha512];
+
+    fn is_default(&self) -> bool {
+        *self == TotpAlgorithm::default()
+    }
+
+    fn algorithm(&self) -> Algorithm {
+        match self {
+            TotpAlgorithm::Sha1 => Algorithm::SHA1,
+            TotpAlgorithm::Sha256 => Algorithm::SHA256,
+            TotpAlgorithm::Sha512 => Algorithm::SHA512,
+        }
+    }
+}
+
+/// Side of account icons, smaller than those of groups to fit in a row.
+const ICON_SIZE: i32 = 24;
+
+pub const DEFAULT_DIGITS: u32 = 6;
+pub const DEFAULT_PERIOD: u64 = 30;
+
+/**
+ * Shortest key RFC 4226 allows. Shorter ones still give codes, but are easier to guess.
+ */
+pub const MIN_KEY_BITS: usize = 128;
+
+/**
+ * What is known of a secret being entered: the code it gives now, and the length of its key.
+ */
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SecretCheck {
+    pub code: String,
+    pub bits: usize,
+    pub short: bool,
+}
+
+/**
+ * Parameters, and how many periods away from the given time, which give the code being verified.
+ * For HOTP accounts, how many codes ahead of the counter instead.
+ */
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct CodeMatch {
+    pub algorithm: TotpAlgorithm,
+    pub digits: u32,
+    pub steps: i64,
+}
+
+/**
+ * What copying does once the next code is shown, i.e. when the current one is about to expire.
+ */
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default, EnumString, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum CopyPolicy {
+    #[default]
+    Current,
+    Wait,
+    Next,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum CodeToCopy {
+    Now(String),
+    /// Seconds until the next code, which is the one to copy.
+    After(u64),
+}
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Account {
     #[serde(skip)]
     pub id: u32,
+    /// Stable identifier, kept across backups and restores. Secret backends key secrets by it.
+    #[serde(default)]
+    pub uuid: String,
     #[serde(skip)]
     pub group_id: u32,
     pub label: String,
     pub secret: String,
     #[serde(skip)]
     pub secret_type: SecretType,
+    #[serde(default)]
+    pub account_type: AccountType,
+    /// Only used by Yandex Key and Mobile-OTP accounts. Useless without the secret, hence kept in the accounts table.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub pin: String,
+    /// Only used by TOTP accounts, the other types have theirs set. Left out of exports when usual.
+    #[serde(default, skip_serializing_if = "TotpAlgorithm::is_default")]
+    pub algorithm: TotpAlgorithm,
+    #[serde(default = "Account::default_digits", skip_serializing_if = "Account::is_default_digits")]
+    pub digits: u32,
+    #[serde(default = "Account::default_period", skip_serializing_if = "Account::is_default_period")]
+    pub period: u64,
+    /// Only used by HOTP accounts: the counter the next code is generated from.
+    #[serde(default, skip_serializing_if = "Account::is_zero")]
+    pub counter: u64,
+    /// Filename in the icon store. Restores keep it when this device has the icon already.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    /// Content of `icon`, base64 encoded, in backups and sync logs: other devices do not have its file. Not kept in the database.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon_content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// What the account signs in with on its website, copied from its menu.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub username: String,
 }
 
 #[derive(Debug, Clone)]
@@ -28,6 +185,7 @@ pub struct AccountWidget {
     pub account_id: u32,
     pub event_grid: gtk::EventBox,
     pub edit_button: gtk::Button,
+    pub verify_button: gtk::Button,
     pub delete_button: gtk::Button,
     pub confirm_button: gtk::Button,
     pub confirm_button_label: gtk::Label,
@@ -36,35 +194,146 @@ pub struct AccountWidget {
     pub edit_copy_img: gtk::Image,
     pub dialog_ok_img: gtk::Image,
     totp_label: gtk::Label,
-    totp_secret: String,
+    next_code_label: gtk::Label,
+    countdown: gtk::DrawingArea,
+    /// Share of the current code's validity left, drawn by `countdown`.
+    remaining: Rc<Cell<f64>>,
+    next_code_seconds: u64,
+    account: Account,
 }
 
 impl AccountWidget {
+    /**
+     * Called every second: refreshes the code, the countdown ring and, close to expiry, the next code.
+     * HOTP codes do not expire, they have neither.
+     */
     pub fn update(&mut self) {
-        match Account::generate_time_based_password(self.totp_secret.as_str()) {
-            Ok(totp) => self.totp_label.set_label(totp.as_str()),
+        if self.account.account_type.has_counter() {
+            self.next_code_label.hide();
+            self.countdown.hide();
+        }
+
+        let codes = Account::now().and_then(|now| {
+            let remaining = self.account.remaining(now);
+            let code = self.account.code_at(now)?;
+            let next_code = if remaining < self.next_code_seconds {
+                Some(self.account.code_at(now + remaining)?)
+            } else {
+                None
+            };
+
+            Ok((code, next_code, remaining))
+        });
+
+        match codes {
+            Ok((code, next_code, remaining)) => {
+                self.totp_label.set_label(code.as_str());
+
+                match next_code.filter(|_| !self.account.account_type.has_counter()) {
+                    Some(next_code) => {
+                        self.next_code_label.set_label(next_code.as_str());
+                        self.next_code_label.show();
+                    }
+                    None => self.next_code_label.hide(),
+                }
+
+                self.remaining.set(remaining as f64 / self.account.period() as f64);
+                self.countdown.queue_draw();
+            }
             Err(error_key) => {
-                warn!("Account {} {}", self.account_id, error_key.error());
-                self.totp_label.set_label(&gettext(error_key.error()));
                 let context = self.totp_label.style_context();
-                context.add_class("error");
+
+                if !context.has_class("error") {
+                    warn!("Account {} {}", self.account_id, error_key.message());
+                    self.totp_label.set_label(error_key.message().as_str());
+                    self.next_code_label.hide();
+                    self.countdown.hide();
+                    context.add_class("error");
+                }
             }
         }
     }
+
+    pub fn account(&self) -> &Account {
+        &self.account
+    }
+
+    fn draw_countdown(area: &gtk::DrawingArea, cr: &gtk::cairo::Context, remaining: f64) {
+        let (width, height) = (area.allocated_width() as f64, area.allocated_height() as f64);
+        let radius = width.min(height) / 2.0 - 2.0;
+        let color = area.style_context().color(gtk::StateFlags::NORMAL);
+
+        cr.set_line_width(3.0);
+
+        cr.set_source_rgba(color.red(), color.green(), color.blue(), 0.2);
+        cr.arc(width / 2.0, height / 2.0, radius, 0.0, 2.0 * PI);
+        if let Err(e) = cr.stroke() {
+            warn!("Could not draw countdown: {:?}", e);
+        }
+
+        cr.set_source_rgba(color.red(), color.green(), color.blue(), color.alpha());
+        cr.arc(width / 2.0, height / 2.0, radius, -PI / 2.0, -PI / 2.0 + 2.0 * PI * remaining);
+        if let Err(e) = cr.stroke() {
+            warn!("Could not draw countdown: {:?}", e);
+        }
+    }
+
+    fn open_website(website: &Url) {
+        glib::spawn_future_local(clone!(
+            #[strong]
+            website,
+            async move {
+                if let Err(e) = OpenUri::open(&website).await {
+                    warn!("Could not open {}: {}", website, e.message());
+                }
+            }
+        ));
+    }
 }
 
 impl Account {
     pub fn new(id: u32, group_id: u32, label: &str, secret: &str, secret_type: SecretType) -> Self {
         Account {
             id,
+            uuid: Uuid::new_v4().to_string(),
             group_id,
             label: label.to_owned(),
             secret: secret.to_owned(),
             secret_type,
+            account_type: AccountType::default(),
+            pin: String::new(),
+            algorithm: TotpAlgorithm::default(),
+            digits: DEFAULT_DIGITS,
+            period: DEFAULT_PERIOD,
+            counter: 0,
+            icon: None,
+            icon_content: None,
+            url: None,
+            username: String::new(),
         }
     }
 
-    pub fn widget(&self, is_first: bool, is_last: bool) -> AccountWidget {
+    fn default_digits() -> u32 {
+        DEFAULT_DIGITS
+    }
+
+    fn default_period() -> u64 {
+        DEFAULT_PERIOD
+    }
+
+    fn is_default_digits(digits: &u32) -> bool {
+        *digits == DEFAULT_DIGITS
+    }
+
+    fn is_default_period(period: &u64) -> bool {
+        *period == DEFAULT_PERIOD
+    }
+
+    fn is_zero(counter: &u64) -> bool {
+        *counter == 0
+    }
+
+    pub fn widget(&self, is_first: bool, is_last: bool, group_url: Option<&str>, dark_mode: bool) -> AccountWidget {
         let builder = gtk::Builder::from_resource(format!("{}/{}", NAMESPACE_PREFIX, "account.ui").as_str());
 
         get_widget!(builder, gtk::EventBox, eventgrid);
@@ -74,10 +343,17 @@ impl Account {
         get_widget!(builder, gtk::Button, copy_button);
         get_widget!(builder, gtk::Button, confirm_button);
         get_widget!(builder, gtk::Label, confirm_button_label);
+        get_widget!(builder, gtk::Image, account_image);
         get_widget!(builder, gtk::Label, account_name);
         get_widget!(builder, gtk::Label, totp_label);
+        get_widget!(builder, gtk::Label, next_code_label);
+        get_widget!(builder, gtk::DrawingArea, countdown);
         get_widget!(builder, gtk::Button, edit_button);
+        get_widget!(builder, gtk::Button, verify_button);
         get_widget!(builder, gtk::Button, delete_button);
+        get_widget!(builder, gtk::Button, website_button);
+        get_widget!(builder, gtk::Button, login_button);
+        get_widget!(builder, gtk::Button, copy_username_button);
         get_widget!(builder, gtk::PopoverMenu, popover);
         get_widget!(builder, gtk::MenuButton, menu);
         get_widget!(builder, gtk::Frame, account_frame);
@@ -86,6 +362,30 @@ impl Account {
 
         account_name.set_label(self.label.as_str());
 
+        // accounts without an icon of their own get the bundled one of their website, if any
+        let icon = match &self.icon {
+            Some(icon) => IconParser::load_icon_at_size(&Paths::icons_path(icon), ICON_SIZE, dark_mode).map(Some),
+            None => self
+                .url
+                .as_deref()
+                .and_then(|url| BrandIcons::get().find(&[url]))
+                .map(|brand_icon| BrandIcons::load_icon(brand_icon, ICON_SIZE))
+                .transpose(),
+        };
+
+        match icon {
+            Ok(Some(pixbuf)) => {
+                account_image.set_from_pixbuf(Some(&pixbuf));
+                account_image.show();
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Could not load icon of account {}: {:?}", self.id, e),
+        }
+
+        if self.account_type == AccountType::Steam {
+            totp_label.set_tooltip_text(Some(&gettext("Steam Guard code")));
+        }
+
         menu.connect_clicked(clone!(
             #[strong]
             edit_button,
@@ -131,12 +431,87 @@ impl Account {
         add_hovering_class(&context, &copy_button);
         add_hovering_class(&context, &menu);
 
-        copy_button.connect_clicked(clone!(
+        let g_settings = gio::Settings::new(NAMESPACE);
+        let next_code_seconds = g_settings.int("next-code-seconds").max(0) as u64;
+        let copy_policy = CopyPolicy::from_str(g_settings.string("copy-near-expiry").as_str()).unwrap_or_default();
+
+        let remaining = Rc::new(Cell::new(1.0));
+
+        countdown.connect_draw(clone!(
             #[strong]
-            totp_label,
+            remaining,
+            move |area, cr| {
+                AccountWidget::draw_countdown(area, cr, remaining.get());
+                gtk::glib::Propagation::Stop
+            }
+        ));
+
+        let account = self.clone();
+
+        copy_button.connect_clicked(move |_| {
+            let clipboard = gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD);
+
+            match Account::now().and_then(|now| account.code_to_copy(now, copy_policy, next_code_seconds)) {
+                Ok(CodeToCopy::Now(code)) => clipboard.set_text(code.as_str()),
+                Ok(CodeToCopy::After(seconds)) => {
+                    // a little past the start of the next period, `now` being rounded down to the second
+                    let wait = Duration::from_secs(seconds) + Duration::from_millis(200);
+
+                    glib::timeout_add_local_once(
+                        wait,
+                        clone!(
+                            #[strong]
+                            account,
+                            move || match account.code() {
+                                Ok(code) => clipboard.set_text(code.as_str()),
+                                Err(e) => warn!("Could not copy code of account {}: {}", account.id, e.message()),
+                            }
+                        ),
+                    );
+                }
+                Err(e) => warn!("Could not copy code of account {}: {}", account.id, e.message()),
+            }
+        });
+
+        let website = self.website(group_url);
+        website_button.set_visible(website.is_some());
+        login_button.set_visible(website.is_some());
+
+        if let Some(website) = website {
+            website_button.connect_clicked(clone!(
+                #[strong]
+                popover,
+                #[strong]
+                website,
+                move |_| {
+                    popover.hide();
+                    AccountWidget::open_website(&website);
+                }
+            ));
+
+            // the code is copied as the copy button would, its policy close to expiry included
+            login_button.connect_clicked(clone!(
+                #[strong]
+                popover,
+                #[strong]
+                copy_button,
+                move |_| {
+                    popover.hide();
+                    copy_button.clicked();
+                    AccountWidget::open_website(&website);
+                }
+            ));
+        }
+
+        copy_username_button.set_visible(!self.username.is_empty());
+
+        let username = self.username.clone();
+        copy_username_button.connect_clicked(clone!(
+            #[strong]
+            popover,
             move |_| {
-                let clipboard = gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD);
-                clipboard.set_text(totp_label.label().as_str());
+                popover.hide();
+                gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD).set_text(username.as_str());
             }
         ));
 
@@ -144,6 +519,7 @@ impl Account {
             event_grid: eventgrid,
             account_id: self.id,
             edit_button,
+            verify_button,
             delete_button,
             copy_button,
             confirm_button,
@@ -152,7 +528,11 @@ impl Account {
             dialog_ok_img,
             popover,
             totp_label,
-            totp_secret: self.secret.clone(),
+            next_code_label,
+            countdown,
+            remaining,
+            next_code_seconds,
+            account: self.clone(),
         };
 
         widget.update();
@@ -160,7 +540,206 @@ impl Account {
         widget
     }
 
-    pub fn generate_time_based_password(key: &str) -> Result<String, TotpError> {
+    /**
+     * Page the account signs in on: its own URL, else that of its group. Bare hostnames are taken as `https://`.
+     * Only web pages are opened: URLs come from backups and other devices too, and other schemes reach file shares or custom handlers.
+     */
+    pub fn website(&self, group_url: Option<&str>) -> Option<Url> {
+        self.url
+            .as_deref()
+            .or(group_url)
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+            .and_then(|url| IconParser::site_url(url).ok())
+            .filter(|url| matches!(url.scheme(), "http" | "https"))
+    }
+
+    /**
+     * Current code of the account, following its type.
+     */
+    pub fn code(&self) -> Result<String, TotpError> {
+        self.code_at(Self::now()?)
+    }
+
+    pub fn code_at(&self, time: u64) -> Result<String, TotpError> {
+        if self.account_type.has_pin() && self.pin.is_empty() {
+            return Err(TotpError::PinRequired);
+        }
+
+        match self.account_type {
+            AccountType::Totp | AccountType::Steam => Ok(self.totp()?.generate(time)),
+            AccountType::Hotp => Ok(self.totp()?.generate(self.counter)),
+            AccountType::Yandex => Ok(schemes::yandex(&Self::decode(self.secret.as_str())?, self.pin.as_str(), time)),
+            AccountType::Motp => {
+                let normalized = Account::normalize(self.secret.as_str())?;
+
+                // Mobile-OTP secrets are hex strings, hashed as they are rather than decoded
+                if !normalized.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(TotpError::InvalidKey(self.secret.clone()));
+                }
+
+                Ok(schemes::mobile_otp(normalized.as_str(), self.pin.as_str(), time))
+            }
+        }
+    }
+
+    /**
+     * Seconds each code is valid for.
+     */
+    pub fn period(&self) -> u64 {
+        match self.account_type {
+            AccountType::Totp => self.period.max(1),
+            account_type => account_type.period(),
+        }
+    }
+
+    /**
+     * Seconds left before the code generated at `time` expires.
+     */
+    pub fn remaining(&self, time: u64) -> u64 {
+        let period = self.period();
+        period - time % period
+    }
+
+    /**
+     * Looks for the parameters giving `code` within `steps` periods of `time`, closest first, the account's own
+     * parameters first. Algorithms and digits are only searched for TOTP and HOTP accounts, the other types have theirs set.
+     * HOTP accounts are looked `steps` codes ahead of their counter instead, codes behind it having been used.
+     */
+    pub fn verify(&self, code: &str, time: u64, steps: u64) -> Result<Option<CodeMatch>, TotpError> {
+        let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+
+        let mut candidates = vec![self.clone()];
+
+        if matches!(self.account_type, AccountType::Totp | AccountType::Hotp) {
+            for algorithm in TotpAlgorithm::ALL {
+                for digits in 6..=8 {
+                    if (algorithm, digits) != (self.algorithm, self.digits) {
+                        candidates.push(Account {
+                            algorithm,
+                            digits,
+                            ..self.clone()
+                        });
+                    }
+                }
+            }
+        }
+
+        if self.account_type.has_counter() {
+            for step in 0..=steps {
+                for candidate in &candidates {
+                    let ahead = Account {
+                        counter: self.counter + step,
+                        ..candidate.clone()
+                    };
+
+                    if ahead.code_at(time)?.eq_ignore_ascii_case(code.as_str()) {
+                        return Ok(Some(CodeMatch {
+                            algorithm: candidate.algorithm,
+                            digits: candidate.digits,
+                            steps: step as i64,
+                        }));
+                    }
+                }
+            }
+
+            return Ok(None);
+        }
+
+        let period = self.period() as i64;
+
+        for distance in 0..=steps as i64 {
+            for step in if distance == 0 { vec![0] } else { vec![-distance, distance] } {
+                let Some(at) = (time as i64).checked_add(step * period).filter(|at| *at >= 0) else {
+                    continue;
+                };
+
+                for candidate in &candidates {
+                    if candidate.code_at(at as u64)?.eq_ignore_ascii_case(code.as_str()) {
+                        return Ok(Some(CodeMatch {
+                            algorithm: candidate.algorithm,
+                            digits: candidate.digits,
+                            steps: step,
+                        }));
+                    }
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /**
+     * The code to copy at `time`. Within the last `next_code_seconds` of a code, the policy decides
+     * between the current code, the next one, or waiting for the next one. HOTP codes never expire.
+     */
+    pub fn code_to_copy(&self, time: u64, policy: CopyPolicy, next_code_seconds: u64) -> Result<CodeToCopy, TotpError> {
+        let remaining = self.remaining(time);
+
+        if remaining >= next_code_seconds || self.account_type.has_counter() {
+            return Ok(CodeToCopy::Now(self.code_at(time)?));
+        }
+
+        match policy {
+            CopyPolicy::Current => Ok(CodeToCopy::Now(self.code_at(time)?)),
+            CopyPolicy::Next => Ok(CodeToCopy::Now(self.code_at(time + remaining)?)),
+            CopyPolicy::Wait => Ok(CodeToCopy::After(remaining)),
+        }
+    }
+
+    /**
+     * Seconds since the epoch, which codes are generated from. See `Clock` for the offset.
+     */
+    pub fn now() -> Result<u64, TotpError> {
+        Ok(Clock::now()?)
+    }
+
+    /**
+     * Code of an account with the default parameters, e.g. for one being entered.
+     */
+    pub fn generate_code(account_type: &AccountType, key: &str, pin: &str) -> Result<String, TotpError> {
+        let mut account = Account::new(0, 0, "", key, SecretType::LOCAL);
+        account.account_type = *account_type;
+        account.pin = pin.to_owned();
+
+        account.code()
+    }
+
+    /**
+     * Like `generate_code`, also measuring the key. Mobile-OTP secrets are hex, and hashed with the PIN rather than
+     * used as a key, so they are never too short.
+     */
+    pub fn check_secret(account_type: &AccountType, key: &str, pin: &str) -> Result<SecretCheck, TotpError> {
+        let normalized = Account::normalize(key)?;
+
+        let bits = match account_type {
+            AccountType::Motp => normalized.len() * 4,
+            _ => {
+                Self::decode(key)?;
+                normalized.len() * 5 / 8 * 8
+            }
+        };
+
+        Ok(SecretCheck {
+            code: Account::generate_code(account_type, key, pin)?,
+            bits,
+            short: *account_type != AccountType::Motp && bits < MIN_KEY_BITS,
+        })
+    }
+
+    fn totp(&self) -> Result<TOTP, TotpError> {
+        let secret = Self::decode(self.secret.as_str())?;
+
+        match self.account_type {
+            // TOTP::new only accepts 6 to 8 digits
+            AccountType::Steam => Ok(TOTP::new_unchecked(Algorithm::Steam, 5, 1, 30, secret)),
+            // the step is 1 so that HOTP codes can be generated from the counter as if it were the time
+            AccountType::Hotp => Ok(TOTP::new(self.algorithm.algorithm(), self.digits as usize, 1, 1, secret)?),
+            _ => Ok(TOTP::new(self.algorithm.algorithm(), self.digits as usize, 1, self.period(), secret)?),
+        }
+    }
+
+    fn decode(key: &str) -> Result<Vec<u8>, TotpError> {
         let normalized = Account::normalize(key)?;
 
         // helper that tries base32 decode with/without a padding flag
@@ -191,11 +770,7 @@ impl Account {
             secret = try_decode(&s32).or_else(|| try_decode(&normalized));
         }
 
-        let secret = secret.ok_or_else(|| TotpError::InvalidKey(key.to_string()))?;
-
-        let totp_sha1 = totp_rs::TOTP::new(totp_rs::Algorithm::SHA1, 6, 1, 30, secret)?;
-
-        totp_sha1.generate_current().map_err(TotpError::SystemTimeError)
+        secret.ok_or_else(|| TotpError::InvalidKey(key.to_string()))
     }
 
     /*
@@ -217,7 +792,9 @@ impl Account {
 #[cfg(test)]
 mod tests {
     use crate::helpers::QrCode;
-    use crate::model::Account;
+    use crate::helpers::SecretType;
+    use crate::model::account_errors::TotpError;
+    use crate::model::{Account, AccountType, CodeMatch, CodeToCopy, CopyPolicy, TotpAlgorithm};
 
     #[test]
     fn pad() {
@@ -235,7 +812,7 @@ mod tests {
     #[test]
     fn legacy_short_key() {
         // legacy short key that earlier code padded to 32 for compatibility
-        let totp = Account::generate_time_based_password("AXXETN6MTQO3TJN").unwrap();
+        let totp = Account::generate_code(&AccountType::Totp, "AXXETN6MTQO3TJN", "").unwrap();
         assert_eq!(totp.len(), 6);
     }
 
@@ -243,7 +820,7 @@ mod tests {
     fn modern_regular_key() {
         // full-length 32-character base32 secret (decodes to 20 bytes)
         let key = "JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP"; // repeated example to reach 32 chars
-        let totp = Account::generate_time_based_password(key).unwrap();
+        let totp = Account::generate_code(&AccountType::Totp, key, "").unwrap();
         assert_eq!(totp.len(), 6);
     }
 
@@ -251,7 +828,7 @@ mod tests {
     fn mixed_case_key() {
         // mixed case should be normalized to uppercase
         let key = "jBsWy3DPeHpK3pXpjBsWy3DPeHpK3pXp";
-        let totp = Account::generate_time_based_password(key).unwrap();
+        let totp = Account::generate_code(&AccountType::Totp, key, "").unwrap();
         assert_eq!(totp.len(), 6);
     }
 
@@ -259,17 +836,212 @@ mod tests {
     fn whitespace_in_key() {
         // whitespace should be ignored
         let key = " JBSWY3DPEH PK3PXP JBSWY3DPEH PK3PXP \n";
-        let totp = Account::generate_time_based_password(key).unwrap();
+        let totp = Account::generate_code(&AccountType::Totp, key, "").unwrap();
         assert_eq!(totp.len(), 6);
     }
 
     #[test]
     fn qr_percent_encoded_secret_integration() {
-        // QrCode::extract will percent-decode; ensure generate_time_based_password accepts the result
+        // QrCode::extract will percent-decode; ensure generate_code accepts the result
         let qr_code_payload = "otpauth://totp/Example:alice?secret=JBSWY3DPEHPK3PXP%3D&issuer=Example";
         let qr_code = QrCode::new(qr_code_payload.to_string());
         let extracted = qr_code.extract(); // should return with '=' at end
-        let totp = Account::generate_time_based_password(&extracted).unwrap();
+        let totp = Account::generate_code(&AccountType::Totp, &extracted, "").unwrap();
         assert_eq!(totp.len(), 6);
     }
+
+    #[test]
+    fn steam_guard_code() {
+        let key = "JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP";
+
+        let mut account = Account::new(0, 0, "label", key, SecretType::LOCAL);
+        account.account_type = AccountType::Steam;
+        assert_eq!("WD6N6", account.code_at(59).unwrap());
+        assert_eq!("JPR62", account.code_at(1_000_000_000).unwrap());
+
+        let code = Account::generate_code(&AccountType::Steam, key, "").unwrap();
+        assert_eq!(5, code.len());
+        assert!(code.chars().all(|c| "23456789BCDFGHJKMNPQRTVWXY".contains(c)));
+    }
+
+    #[test]
+    fn generate_code_by_account_type() {
+        let generate = |account_type, key: &str, pin: &str| {
+            let mut account = Account::new(0, 0, "label", key, SecretType::LOCAL);
+            account.account_type = account_type;
+            account.pin = pin.to_owned();
+            account.code_at(1_581_064_020)
+        };
+
+        assert_eq!("BQVND", generate(AccountType::Steam, "JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP", "").unwrap());
+        assert_eq!(
+            "oactmacq",
+            generate(AccountType::Yandex, "LA2V6KMCGYMWWVEW64RNP3JA3IAAAAAAHTSG4HRZPI", "7586").unwrap()
+        );
+        assert_eq!("b52fd1", generate(AccountType::Motp, "e3152afee62599c8", "1234").unwrap());
+
+        assert!(matches!(
+            generate(AccountType::Yandex, "LA2V6KMCGYMWWVEW64RNP3JA3I", ""),
+            Err(TotpError::PinRequired)
+        ));
+        assert!(matches!(generate(AccountType::Motp, "not hex", "1234"), Err(TotpError::InvalidKey(_))));
+    }
+
+    #[test]
+    fn check_secret() {
+        let check = Account::check_secret(&AccountType::Totp, "JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP", "").unwrap();
+        assert_eq!(160, check.bits);
+        assert!(!check.short);
+        assert_eq!(6, check.code.len());
+
+        let check = Account::check_secret(&AccountType::Totp, "AXXETN6MTQO3TJN", "").unwrap();
+        assert_eq!(72, check.bits);
+        assert!(check.short);
+
+        let check = Account::check_secret(&AccountType::Motp, "e3152afee62599c8", "1234").unwrap();
+        assert_eq!(64, check.bits);
+        assert!(!check.short);
+
+        assert!(matches!(Account::check_secret(&AccountType::Totp, " \n", ""), Err(TotpError::Empty)));
+        assert!(matches!(
+            Account::check_secret(&AccountType::Totp, "JBSWY3DP!", ""),
+            Err(TotpError::InvalidKey(_))
+        ));
+        assert!(matches!(
+            Account::check_secret(&AccountType::Yandex, "LA2V6KMCGYMWWVEW64RNP3JA3I", ""),
+            Err(TotpError::PinRequired)
+        ));
+    }
+
+    #[test]
+    fn remaining_follows_the_period_of_the_account() {
+        let mut account = Account::new(0, 0, "label", "JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP", SecretType::LOCAL);
+
+        assert_eq!(30, account.remaining(1_581_064_020));
+        assert_eq!(1, account.remaining(1_581_064_049));
+
+        account.account_type = AccountType::Motp;
+        assert_eq!(10, account.remaining(1_581_064_020));
+        assert_eq!(4, account.remaining(1_581_064_026));
+    }
+
+    #[test]
+    fn code_to_copy_near_expiry() {
+        let account = Account::new(0, 0, "label", "JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP", SecretType::LOCAL);
+
+        let current = account.code_at(1_581_064_047).unwrap();
+        let next = account.code_at(1_581_064_050).unwrap();
+        assert_ne!(current, next);
+
+        // 3 seconds left
+        for policy in [CopyPolicy::Current, CopyPolicy::Wait, CopyPolicy::Next] {
+            assert_eq!(CodeToCopy::Now(current.clone()), account.code_to_copy(1_581_064_047, policy, 3).unwrap());
+        }
+
+        assert_eq!(
+            CodeToCopy::Now(current.clone()),
+            account.code_to_copy(1_581_064_047, CopyPolicy::Current, 5).unwrap()
+        );
+        assert_eq!(CodeToCopy::Now(next), account.code_to_copy(1_581_064_047, CopyPolicy::Next, 5).unwrap());
+        assert_eq!(CodeToCopy::After(3), account.code_to_copy(1_581_064_047, CopyPolicy::Wait, 5).unwrap());
+
+        // disabled
+        assert_eq!(CodeToCopy::Now(current), account.code_to_copy(1_581_064_047, CopyPolicy::Next, 0).unwrap());
+    }
+
+    #[test]
+    fn totp_parameters() {
+        // RFC 6238 test vectors, 8 digits
+        let mut account = Account::new(0, 0, "label", "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ", SecretType::LOCAL);
+        account.digits = 8;
+        assert_eq!("94287082", account.code_at(59).unwrap());
+
+        account.algorithm = TotpAlgorithm::Sha256;
+        account.secret = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZA".to_owned();
+        assert_eq!("46119246", account.code_at(59).unwrap());
+
+        account.algorithm = TotpAlgorithm::Sha512;
+        account.secret = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNA".to_owned();
+        assert_eq!("90693936", account.code_at(59).unwrap());
+
+        account.period = 60;
+        assert_eq!(60, account.remaining(1_581_064_020));
+
+        // fixed for other types
+        account.account_type = AccountType::Steam;
+        assert_eq!(30, account.remaining(1_581_064_020));
+    }
+
+    #[test]
+    fn verify() {
+        let account = Account::new(0, 0, "label", "JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP", SecretType::LOCAL);
+        let time = 1_581_064_020;
+
+        let code = account.code_at(time).unwrap();
+        assert_eq!(
+            Some(CodeMatch {
+                algorithm: TotpAlgorithm::Sha1,
+                digits: 6,
+                steps: 0
+            }),
+            account.verify(code.as_str(), time, 10).unwrap()
+        );
+
+        // clock 2 minutes behind the server
+        let code = account.code_at(time + 120).unwrap();
+        assert_eq!(4, account.verify(code.as_str(), time, 10).unwrap().unwrap().steps);
+        assert_eq!(None, account.verify(code.as_str(), time, 3).unwrap());
+
+        // service using other parameters, one period ahead
+        let sha256 = Account {
+            algorithm: TotpAlgorithm::Sha256,
+            digits: 8,
+            ..account.clone()
+        };
+        let code = sha256.code_at(time - 30).unwrap();
+        assert_eq!(
+            Some(CodeMatch {
+                algorithm: TotpAlgorithm::Sha256,
+                digits: 8,
+                steps: -1
+            }),
+            account.verify(code.as_str(), time, 10).unwrap()
+        );
+
+        assert_eq!(None, account.verify("not a code", time, 10).unwrap());
+    }
+
+    #[test]
+    fn hotp() {
+        // test vectors of RFC 4226, the secret being "12345678901234567890"
+        let mut account = Account::new(0, 0, "label", "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ", SecretType::LOCAL);
+        account.account_type = AccountType::Hotp;
+
+        assert_eq!("755224", account.code_at(1_581_064_020).unwrap());
+
+        account.counter = 2;
+        assert_eq!("359152", account.code_at(0).unwrap());
+        assert_eq!(CodeToCopy::Now("359152".to_owned()), account.code_to_copy(29, CopyPolicy::Wait, 5).unwrap());
+
+        assert_eq!(2, account.verify("338314", 0, 10).unwrap().unwrap().steps);
+        assert_eq!(None, account.verify("287082", 0, 10).unwrap());
+    }
+
+    #[test]
+    fn website() {
+        let mut account = Account::new(0, 0, "label", "secret", SecretType::LOCAL);
+        let website = |account: &Account, group_url: Option<&str>| account.website(group_url).map(|url| url.to_string());
+
+        assert_eq!(None, website(&account, None));
+        assert_eq!(None, website(&account, Some(" ")));
+        assert_eq!(Some("https://example.com/".to_owned()), website(&account, Some("example.com")));
+
+        account.url = Some("http://github.com/login".to_owned());
+        assert_eq!(Some("http://github.com/login".to_owned()), website(&account, Some("example.com")));
+
+        for url in ["smb://nas/share", "sftp://example.com", "ftp://example.com", "x-handler://example.com/run"] {
+            account.url = Some(url.to_owned());
+            assert_eq!(None, website(&account, None));
+        }
+    }
 }
@@ -1,4 +1,6 @@
 use std::time::SystemTimeError;
+
+use gettextrs::gettext;
 use thiserror::Error;
 use totp_rs::SecretParseError;
 use totp_rs::TotpUrlError;
@@ -16,11 +18,26 @@ pub enum TotpError {
     SystemTimeError(SystemTimeError),
     #[error("Invalid Key: {0}")]
     InvalidKey(String),
+    #[error("PIN required")]
+    PinRequired,
 }
 
 impl TotpError {
-    pub fn error(&self) -> String {
-        format!("{:?}", self)
+    /**
+     * What went wrong, for users. Unlike the Debug format, never includes the secret.
+     */
+    pub fn message(&self) -> String {
+        match self {
+            TotpError::Empty => gettext("The secret is empty"),
+            TotpError::SecretParseError(_) | TotpError::InvalidKey(_) => gettext("The secret is not valid: check for mistyped characters"),
+            TotpError::TotpUrlError(TotpUrlError::SecretSize(bits)) => {
+                format!("{} ({} {})", gettext("The secret is too short"), bits, gettext("bits, at least 128 needed"))
+            }
+            TotpError::TotpUrlError(TotpUrlError::DigitsNumber(_)) => gettext("Codes must have 6 to 8 digits"),
+            TotpError::TotpUrlError(_) => gettext("Invalid account settings"),
+            TotpError::SystemTimeError(_) => gettext("The system clock is set before 1970"),
+            TotpError::PinRequired => gettext("PIN required"),
+        }
     }
 }
 
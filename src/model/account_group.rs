@@ -5,8 +5,9 @@ use gtk::prelude::*;
 use gtk_macros::*;
 use log::error;
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
-use crate::helpers::{IconParser, Paths};
+use crate::helpers::{BrandIcons, IconParser, Paths};
 use crate::main_window::State;
 use crate::model::{Account, AccountWidget};
 use crate::NAMESPACE_PREFIX;
@@ -15,6 +16,8 @@ use crate::NAMESPACE_PREFIX;
 pub struct AccountGroup {
     #[serde(skip)]
     pub id: u32,
+    #[serde(default)]
+    pub uuid: String,
     pub name: String,
 
     #[serde(skip)]
@@ -53,6 +56,7 @@ impl AccountGroup {
     pub fn new(id: u32, name: &str, icon: Option<&str>, url: Option<&str>, collapsed: bool, entries: Vec<Account>) -> Self {
         AccountGroup {
             id,
+            uuid: Uuid::new_v4().to_string(),
             name: name.to_owned(),
             icon: icon.map(str::to_owned),
             url: url.map(str::to_owned),
@@ -82,14 +86,24 @@ impl AccountGroup {
 
         group.set_widget_name(format!("group_id_{}", self.id).as_str());
 
-        match &self.icon {
-            Some(image) => {
+        // groups without an icon of their own get the bundled one of their brand, if any
+        let brand_icon = match self.icon {
+            Some(_) => None,
+            None => BrandIcons::get().find(&[self.name.as_str(), self.url.as_deref().unwrap_or_default()]),
+        };
+
+        match (&self.icon, brand_icon) {
+            (Some(image), _) => {
                 let dir = Paths::icons_path(image);
                 match IconParser::load_icon(&dir, state.dark_mode) {
                     Ok(pixbuf) => group_image.set_from_pixbuf(Some(&pixbuf)),
                     Err(_) => error!("Could not load image {}", dir.display()),
                 }
             }
+            (None, Some(brand_icon)) => match BrandIcons::load_icon(brand_icon, 48) {
+                Ok(pixbuf) => group_image.set_from_pixbuf(Some(&pixbuf)),
+                Err(e) => error!("Could not load brand icon {}: {:?}", brand_icon.slug, e),
+            },
             _ => {
                 group_image.clear();
                 group_image.set_visible(self.icon.is_some()); //apparently not enough to not draw some empty space
@@ -117,7 +131,7 @@ impl AccountGroup {
             .iter()
             .enumerate()
             .map(|(i, account)| {
-                let widget = account.widget(i == 0, i == self.entries.len() - 1);
+                let widget = account.widget(i == 0, i == self.entries.len() - 1, self.url.as_deref(), state.dark_mode);
                 accounts.add(&widget.event_grid);
                 widget
             })
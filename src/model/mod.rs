@@ -4,3 +4,4 @@ pub use self::account_group::*;
 mod account;
 mod account_errors;
 mod account_group;
+mod schemes;
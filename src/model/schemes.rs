@@ -0,0 +1,81 @@
+use hmac::{Hmac, Mac};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+/**
+ * Yandex Key codes: HMAC-SHA256 of the time step, keyed with the SHA256 of the PIN followed by the 16 byte secret.
+ * The 8 letters are the truncated HMAC written in base 26.
+ */
+pub(crate) fn yandex(secret: &[u8], pin: &str, time: u64) -> String {
+    const PERIOD: u64 = 30;
+    const DIGITS: u32 = 8;
+
+    let secret = &secret[..secret.len().min(16)];
+
+    let key = Sha256::new().chain_update(pin.as_bytes()).chain_update(secret).finalize();
+    // the reference implementation drops a leading zero byte
+    let key = if key[0] == 0 { &key[1..] } else { &key[..] };
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&(time / PERIOD).to_be_bytes());
+    let mut hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    hash[offset] &= 0x7f;
+
+    let mut code = u64::from_be_bytes(hash[offset..offset + 8].try_into().unwrap()) % 26u64.pow(DIGITS);
+
+    let mut letters = vec![b'a'; DIGITS as usize];
+    for letter in letters.iter_mut().rev() {
+        *letter += (code % 26) as u8;
+        code /= 26;
+    }
+
+    String::from_utf8(letters).unwrap()
+}
+
+/**
+ * Mobile-OTP codes: the first 6 hex digits of the MD5 of the 10 seconds time step, the secret and the PIN.
+ */
+pub(crate) fn mobile_otp(secret: &str, pin: &str, time: u64) -> String {
+    let digest = Md5::digest(format!("{}{}{}", time / 10, secret.to_ascii_lowercase(), pin).as_bytes());
+
+    digest.iter().take(3).map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use base32::Alphabet;
+
+    use super::{mobile_otp, yandex};
+
+    #[test]
+    fn yandex_vectors() {
+        let vectors = [
+            ("5239", "6SB2IKNM6OBZPAVBVTOHDKS4FAAAAAAADFUTQMBTRY", 1641559648, "umozdicq"),
+            ("7586", "LA2V6KMCGYMWWVEW64RNP3JA3IAAAAAAHTSG4HRZPI", 1581064020, "oactmacq"),
+            ("7586", "LA2V6KMCGYMWWVEW64RNP3JA3IAAAAAAHTSG4HRZPI", 1581090810, "wemdwrix"),
+            ("5210481216086702", "JBGSAU4G7IEZG6OY4UAXX62JU4AAAAAAHTSG4HXU3M", 1581091469, "dfrpywob"),
+            ("5210481216086702", "JBGSAU4G7IEZG6OY4UAXX62JU4AAAAAAHTSG4HXU3M", 1581093059, "vunyprpd"),
+        ];
+
+        for (pin, secret, time, code) in vectors {
+            let secret = base32::decode(Alphabet::Rfc4648 { padding: false }, secret).unwrap();
+            assert_eq!(code, yandex(&secret, pin, time));
+        }
+    }
+
+    #[test]
+    fn mobile_otp_vectors() {
+        let vectors = [
+            ("e3152afee62599c8", "1234", 1_000_000_000, "e3b372"),
+            ("e3152afee62599c8", "1234", 1_000_000_009, "e3b372"),
+            ("E3152AFEE62599C8", "1234", 1_234_567_890, "49c5b4"),
+            ("0123456789abcdef0123456789abcdef", "9876", 1_700_000_000, "da1d4a"),
+        ];
+
+        for (secret, pin, time, code) in vectors {
+            assert_eq!(code, mobile_otp(secret, pin, time));
+        }
+    }
+}
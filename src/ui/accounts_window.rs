@@ -1,11 +1,9 @@
-use crate::helpers::{Database, IconParser, Keyring, Paths, RepositoryError};
+use crate::helpers::{Clock, Database, IconParser, IconStore, Pasted, Paths, RepositoryError, SecretStores};
 use crate::main_window::{Action, Display, MainWindow};
 use crate::model::{Account, AccountGroup, AccountGroupWidget, AccountWidget};
-use crate::ui::{AddGroupWindow, EditAccountWindow};
+use crate::ui::{AddGroupWindow, EditAccountWindow, VerifyCodeWindow};
 use crate::NAMESPACE_PREFIX;
 use async_channel::Sender;
-use chrono::prelude::*;
-use chrono::Local;
 use gettextrs::*;
 use glib::clone;
 use gtk::prelude::*;
@@ -13,6 +11,8 @@ use gtk::Builder;
 use gtk_macros::*;
 use log::{debug, error, warn};
 use rusqlite::Connection;
+use std::cell::Cell;
+use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 use std::time;
 
@@ -24,6 +24,13 @@ pub struct AccountsWindow {
     pub accounts_container: gtk::Box,
     pub filter: gtk::Entry,
     pub progress_bar: gtk::ProgressBar,
+    pub clock_banner: gtk::InfoBar,
+    pub clock_banner_label: gtk::Label,
+    /// Progress of imports reading several images.
+    pub import_banner: gtk::InfoBar,
+    pub import_banner_label: gtk::Label,
+    /// Last drift measured against the time source, applied as offset when correcting.
+    pub clock_drift: Rc<Cell<i64>>,
     pub widgets: Arc<Mutex<Vec<AccountGroupWidget>>>,
 }
 
@@ -33,42 +40,129 @@ impl AccountsWindow {
         get_widget!(builder, gtk::Box, main_box);
         get_widget!(builder, gtk::Box, accounts_container);
         get_widget!(builder, gtk::Entry, account_filter);
+        get_widget!(builder, gtk::InfoBar, clock_banner);
+        get_widget!(builder, gtk::Label, clock_banner_label);
+        get_widget!(builder, gtk::Button, clock_banner_correct);
+        get_widget!(builder, gtk::InfoBar, import_banner);
+        get_widget!(builder, gtk::Label, import_banner_label);
 
         Self::progress_bar_fraction_now(&progress_bar);
 
+        let clock_drift = Rc::new(Cell::new(0));
+
+        clock_banner.connect_response(|banner, _| banner.hide());
+
+        clock_banner_correct.connect_clicked(clone!(
+            #[strong]
+            clock_banner,
+            #[strong]
+            clock_drift,
+            move |_| {
+                match Clock::set_offset(clock_drift.get()) {
+                    Ok(()) => clock_banner.hide(),
+                    Err(e) => error!("Could not set time offset: {:?}", e),
+                }
+            }
+        ));
+
         AccountsWindow {
             container: main_box,
             accounts_container,
             filter: account_filter,
             progress_bar,
+            clock_banner,
+            clock_banner_label,
+            import_banner,
+            import_banner_label,
+            clock_drift,
             #[allow(clippy::arc_with_non_send_sync)]
             widgets: Arc::new(Mutex::new(vec![])),
         }
     }
 
-    async fn delete_account_reload(&self, gui: &MainWindow, account_id: u32, connection: Arc<Mutex<Connection>>) {
-        {
-            let connection = connection.lock().unwrap();
-            Database::delete_account(&connection, account_id).unwrap();
-        }
+    /**
+     * Warns about the clock when, offset included, it is further than the threshold from the time source.
+     */
+    pub fn display_clock_drift(&self, drift: i64) {
+        self.clock_drift.set(drift);
+
+        match Clock::skew(drift, Clock::offset(), Clock::drift_threshold()) {
+            Some(skew) => {
+                let direction = if skew > 0 { gettext("seconds behind") } else { gettext("seconds ahead") };
+                let message = format!("{} {} {}. {}", gettext("The clock is"), skew.abs(), direction, gettext("Codes may be refused."));
 
-        Keyring::remove(account_id).unwrap();
+                self.clock_banner_label.set_label(message.as_str());
+                self.clock_banner.show();
+            }
+            None => self.clock_banner.hide(),
+        }
+    }
 
-        self.refresh_accounts(gui);
+    pub async fn delete_account_reload(&self, gui: &MainWindow, account_id: u32, connection: Arc<Mutex<Connection>>) {
+        match Self::delete_account(account_id, connection) {
+            Ok(()) => self.refresh_accounts(gui),
+            Err(e) => {
+                error!("Could not delete account {}: {:?}", account_id, e);
+                gui.display_error(e.message().as_str(), Action::DeleteAccount { account_id });
+            }
+        }
     }
 
-    async fn delete_group_reload(&self, gui: &MainWindow, group_id: u32, connection: Arc<Mutex<Connection>>) {
-        {
-            let connection = connection.lock().unwrap();
-            let group = Database::get_group(&connection, group_id).unwrap();
-            Database::delete_group(&connection, group_id).expect("Could not delete group");
+    /**
+     * The account row is only deleted once its secret is: should the secret backend be
+     * unavailable, the account is left untouched and retrying deletes both.
+     */
+    fn delete_account(account_id: u32, connection: Arc<Mutex<Connection>>) -> Result<(), RepositoryError> {
+        let connection = Database::lock(&connection);
+        let tx = connection.unchecked_transaction()?;
+
+        match Database::get_account(&tx, account_id)? {
+            Some(account) => {
+                Database::delete_account(&tx, account_id)?;
+                SecretStores::remove(&account)?;
+                tx.commit()?;
+
+                if let Some(icon) = &account.icon {
+                    if let Err(e) = IconStore::open().release(&connection, icon) {
+                        warn!("Could not delete icon {}: {:?}", icon, e);
+                    }
+                }
 
-            if let Some(path) = group.icon {
-                AddGroupWindow::delete_icon_file(&path);
+                Ok(())
+            }
+            None => {
+                warn!("Account {} already deleted", account_id);
+                Ok(())
             }
         }
+    }
+
+    pub async fn delete_group_reload(&self, gui: &MainWindow, group_id: u32, connection: Arc<Mutex<Connection>>) {
+        let result = {
+            let connection = Database::lock(&connection);
+
+            Database::get_group(&connection, group_id).and_then(|group| {
+                Database::delete_group(&connection, group_id)?;
+
+                if let Some(icon) = &group.icon {
+                    if let Err(e) = IconStore::open().release(&connection, icon) {
+                        warn!("Could not delete icon {}: {:?}", icon, e);
+                    }
+                }
 
-        self.refresh_accounts(gui);
+                Ok(group)
+            })
+        };
+
+        match result {
+            Ok(_) => {
+                self.refresh_accounts(gui);
+            }
+            Err(e) => {
+                error!("Could not delete group {}: {:?}", group_id, e);
+                gui.display_error(e.message().as_str(), Action::DeleteGroup { group_id });
+            }
+        }
     }
 
     pub fn refresh_accounts(&self, gui: &MainWindow) {
@@ -90,7 +184,7 @@ impl AccountsWindow {
             Ok((groups, has_groups)) => {
                 {
                     let accounts_container = gui.accounts_window.accounts_container.clone();
-                    let mut m_widgets = gui.accounts_window.widgets.lock().unwrap();
+                    let mut m_widgets = gui.accounts_window.widgets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
 
                     // empty list of accounts first
                     accounts_container.foreach(|e| accounts_container.remove(e));
@@ -116,8 +210,9 @@ impl AccountsWindow {
                 }
             }
             Err(e) => {
-                gui.errors.error_display_message.set_text(format!("{:?}", e).as_str());
-                gui.switch_to(Display::Errors);
+                error!("Could not load accounts: {:?}", e);
+                let filter = gui.accounts_window.get_filter_value();
+                gui.display_error(e.message().as_str(), Action::RefreshAccounts { filter });
             }
         };
     }
@@ -126,21 +221,21 @@ impl AccountsWindow {
      * Utility function to wrap around asynchronously ConfigManager::load_account_groups.
      */
     pub async fn load_account_groups(connection: Arc<Mutex<Connection>>, filter: Option<String>) -> AccountsRefreshResult {
-        let connection = connection.lock().unwrap();
+        let connection = Database::lock(&connection);
         let has_groups = Database::has_groups(&connection);
 
         let account_groups = Database::load_account_groups(&connection, filter.as_deref());
 
         let accounts = account_groups.and_then(|account_groups| {
             let mut account_groups = account_groups;
-            Keyring::set_secrets(&mut account_groups, &connection).map(|_| account_groups)
+            SecretStores::set_secrets(&mut account_groups, &connection).map(|_| account_groups)
         });
 
         has_groups.and_then(|has_groups| accounts.map(|account_groups| (account_groups, has_groups)))
     }
 
     fn group_edit_buttons_actions(&self, gui: &MainWindow, connection: Arc<Mutex<Connection>>) {
-        let widgets_list = self.widgets.lock().unwrap();
+        let widgets_list = self.widgets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
         let builder = Builder::from_resource(format!("{}/{}", NAMESPACE_PREFIX, "main.ui").as_str());
 
         for group_widgets in widgets_list.iter() {
@@ -191,13 +286,25 @@ impl AccountsWindow {
                     glib::spawn_future_local(clone!(
                         #[strong]
                         connection,
+                        #[strong]
+                        gui,
                         async move {
                             let group = {
-                                let connection = connection.lock().unwrap();
-                                Database::get_group(&connection, group_id).unwrap()
+                                let connection = Database::lock(&connection);
+                                Database::get_group(&connection, group_id)
                             };
-                            debug!("Loading group {:?}", group);
-                            let _ = tx.send(group).await;
+
+                            match group {
+                                Ok(group) => {
+                                    debug!("Loading group {:?}", group);
+                                    let _ = tx.send(group).await;
+                                }
+                                Err(e) => {
+                                    error!("Could not load group {}: {:?}", group_id, e);
+                                    let filter = gui.accounts_window.get_filter_value();
+                                    gui.display_error(e.message().as_str(), Action::RefreshAccounts { filter });
+                                }
+                            }
                         }
                     ));
 
@@ -243,7 +350,7 @@ impl AccountsWindow {
     }
 
     fn edit_buttons_actions(&self, gui: &MainWindow, connection: Arc<Mutex<Connection>>) {
-        let widgets_list = self.widgets.lock().unwrap();
+        let widgets_list = self.widgets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
         let builder = Builder::from_resource(format!("{}/{}", NAMESPACE_PREFIX, "main.ui").as_str());
 
         for group_widget in widgets_list.iter() {
@@ -253,10 +360,52 @@ impl AccountsWindow {
             for account_widget in account_widgets.iter() {
                 let connection = connection.clone();
                 copy_totp_token_handler(account_widget);
+                if account_widget.account().account_type.has_counter() {
+                    increment_counter_handler(account_widget, gui, connection.clone());
+                }
                 edit_account_widget_handler(account_widget, &builder, gui, connection.clone());
+                verify_code_handler(account_widget, gui, connection.clone());
             }
         }
 
+        /**
+         * Copying a HOTP code uses it up: the account moves on to the next one.
+         */
+        fn increment_counter_handler(account_widget: &AccountWidget, gui: &MainWindow, connection: Arc<Mutex<Connection>>) {
+            account_widget.copy_button.connect_clicked(clone!(
+                #[strong]
+                gui,
+                #[strong(rename_to = account_id)]
+                account_widget.account_id,
+                move |_| {
+                    let result = {
+                        let connection = Database::lock(&connection);
+                        Database::increment_counter(&connection, account_id)
+                    };
+
+                    match result {
+                        Ok(_) => gui.accounts_window.refresh_accounts(&gui),
+                        Err(e) => warn!("Could not increment counter of account {}: {:?}", account_id, e),
+                    }
+                }
+            ));
+        }
+
+        fn verify_code_handler(account_widget: &AccountWidget, gui: &MainWindow, connection: Arc<Mutex<Connection>>) {
+            account_widget.verify_button.connect_clicked(clone!(
+                #[strong]
+                gui,
+                #[strong(rename_to = account)]
+                account_widget.account(),
+                #[strong(rename_to = popover)]
+                account_widget.popover,
+                move |_| {
+                    popover.hide();
+                    VerifyCodeWindow::show(&gui, account.clone(), connection.clone());
+                }
+            ));
+        }
+
         fn edit_account_widget_handler(account_widget: &AccountWidget, builder: &Builder, gui: &MainWindow, connection: Arc<Mutex<Connection>>) {
             account_widget.edit_button.connect_clicked(clone!(
                 #[strong]
@@ -275,23 +424,23 @@ impl AccountsWindow {
                         gui,
                         async move {
                             let account = {
-                                let connection = connection.lock().unwrap();
+                                let connection = Database::lock(&connection);
                                 Database::get_account(&connection, account_widget.account_id)
                             };
 
+                            let filter = gui.accounts_window.get_filter_value();
+
                             match account {
                                 Ok(Some(account)) => {
                                     let _ = tx.send(account).await;
                                 }
                                 Ok(None) => {
-                                    gui.errors
-                                        .error_display_message
-                                        .set_text(format!("Group {} not found", account_widget.account_id).as_str());
-                                    gui.switch_to(Display::Errors);
+                                    warn!("Account {} not found", account_widget.account_id);
+                                    gui.display_error(&gettext("Account not found"), Action::RefreshAccounts { filter });
                                 }
                                 Err(e) => {
-                                    gui.errors.error_display_message.set_text(format!("{:?}", e).as_str());
-                                    gui.switch_to(Display::Errors);
+                                    error!("Could not load account {}: {:?}", account_widget.account_id, e);
+                                    gui.display_error(e.message().as_str(), Action::RefreshAccounts { filter });
                                 }
                             }
                         }
@@ -312,8 +461,23 @@ impl AccountsWindow {
                                 gui.edit_account.replace_with(&edit_account);
                                 edit_account.edit_account_buttons_actions(&gui, connection.clone());
 
-                                let connection = connection.lock().unwrap();
-                                let groups = Database::load_account_groups(&connection, None).unwrap();
+                                let groups = {
+                                    let connection = Database::lock(&connection);
+                                    Database::load_account_groups(&connection, None)
+                                };
+
+                                account_widget.popover.hide();
+
+                                let filter = gui.accounts_window.get_filter_value();
+
+                                let groups = match groups {
+                                    Ok(groups) => groups,
+                                    Err(e) => {
+                                        error!("Could not load groups: {:?}", e);
+                                        gui.display_error(e.message().as_str(), Action::RefreshAccounts { filter });
+                                        return;
+                                    }
+                                };
 
                                 edit_account.input_group.remove_all(); //re-added and refreshed just below
                                 edit_account.set_group_dropdown(Some(account.group_id), &groups);
@@ -321,18 +485,32 @@ impl AccountsWindow {
                                 let account_id = account.id.to_string();
                                 edit_account.input_account_id.set_text(account_id.as_str());
                                 edit_account.input_name.set_text(account.label.as_str());
+                                edit_account.input_pin.set_text(account.pin.as_str());
+                                edit_account.set_account_type(&account.account_type);
+                                edit_account.input_username.set_text(account.username.as_str());
+                                edit_account.input_url.set_text(account.url.as_deref().unwrap_or_default());
 
-                                account_widget.popover.hide();
+                                if let Some(image) = &account.icon {
+                                    edit_account.icon_filename.set_label(image.as_str());
 
-                                match Keyring::secret(account.id) {
+                                    let dir = Paths::icons_path(image);
+                                    let state = gui.state.borrow();
+                                    match IconParser::load_icon(&dir, state.dark_mode) {
+                                        Ok(pixbuf) => edit_account.image_input.set_from_pixbuf(Some(&pixbuf)),
+                                        Err(_) => error!("Could not load image {}", dir.display()),
+                                    };
+                                }
+
+                                match SecretStores::secret(&account) {
                                     Ok(secret) => {
-                                        let buffer = edit_account.input_secret.buffer().unwrap();
-                                        buffer.set_text(secret.unwrap_or_default().as_str());
+                                        if let Some(buffer) = edit_account.input_secret.buffer() {
+                                            buffer.set_text(secret.unwrap_or_default().as_str());
+                                        }
                                         gui.switch_to(Display::EditAccount);
                                     }
                                     Err(e) => {
-                                        gui.errors.error_display_message.set_text(format!("{:?}", e).as_str());
-                                        gui.switch_to(Display::Errors);
+                                        error!("Could not read secret of account {}: {:?}", account.id, e);
+                                        gui.display_error(e.message().as_str(), Action::RefreshAccounts { filter });
                                     }
                                 };
                             }
@@ -371,7 +549,7 @@ impl AccountsWindow {
     }
 
     fn delete_buttons_actions(&self, gui: &MainWindow, connection: Arc<Mutex<Connection>>) {
-        let widgets_list = self.widgets.lock().unwrap();
+        let widgets_list = self.widgets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
 
         for group_widget in widgets_list.iter() {
             let account_widgets = group_widget.account_widgets.clone();
@@ -458,8 +636,11 @@ impl AccountsWindow {
         }
     }
 
-    fn progress_bar_fraction_now(progress_bar: &gtk::ProgressBar) {
-        Self::progress_bar_fraction_for(progress_bar, Local::now().second())
+    pub fn progress_bar_fraction_now(progress_bar: &gtk::ProgressBar) {
+        match Clock::now() {
+            Ok(now) => Self::progress_bar_fraction_for(progress_bar, (now % 60) as u32),
+            Err(e) => warn!("Could not read clock: {:?}", e),
+        }
     }
 
     pub fn progress_bar_fraction_for(progress_bar: &gtk::ProgressBar, seconds: u32) {
@@ -487,13 +668,23 @@ impl AccountsWindow {
 
                 debug!("Collapsing/expanding group {:?}", group_id);
 
-                let connection = connection.lock().unwrap();
-                let mut group = Database::get_group(&connection, group_id).unwrap();
-
-                group.collapsed = !group.collapsed;
-                Database::update_group(&connection, &group).unwrap();
-
-                main_window.accounts_window.refresh_accounts(&main_window);
+                let result = {
+                    let connection = Database::lock(&connection);
+
+                    Database::get_group(&connection, group_id).and_then(|mut group| {
+                        group.collapsed = !group.collapsed;
+                        Database::update_group(&connection, &group)
+                    })
+                };
+
+                match result {
+                    Ok(()) => main_window.accounts_window.refresh_accounts(&main_window),
+                    Err(e) => {
+                        error!("Could not collapse/expand group {}: {:?}", group_id, e);
+                        let filter = main_window.accounts_window.get_filter_value();
+                        main_window.display_error(e.message().as_str(), Action::RefreshAccounts { filter });
+                    }
+                }
             }
         )
     }
@@ -511,47 +702,65 @@ impl AccountsWindow {
             #[strong]
             popover,
             move |_: &gtk::Button| {
-                let (tx, rx) = async_channel::bounded::<Vec<AccountGroup>>(1);
+                popover.hide();
+                main_window.accounts_window.add_account(connection.clone(), &main_window, group_id, None);
+            }
+        )
+    }
 
-                glib::spawn_future_local(clone!(
-                    #[strong]
-                    connection,
-                    async move {
-                        debug!("Loading for group_id {:?}", group_id);
+    /**
+     * Shows the form adding an account to `group_id`, filled in from the clipboard if anything was pasted.
+     */
+    pub fn add_account(&self, connection: Arc<Mutex<Connection>>, main_window: &MainWindow, group_id: Option<u32>, pasted: Option<Pasted>) {
+        let (tx, rx) = async_channel::bounded::<Vec<AccountGroup>>(1);
+
+        glib::spawn_future_local(clone!(
+            #[strong]
+            connection,
+            #[strong]
+            main_window,
+            async move {
+                debug!("Loading for group_id {:?}", group_id);
 
-                        let groups = {
-                            let connection = connection.lock().unwrap();
-                            Database::load_account_groups(&connection, None).unwrap()
-                        };
+                let groups = {
+                    let connection = Database::lock(&connection);
+                    Database::load_account_groups(&connection, None)
+                };
 
-                        tx.send(groups).await.expect("Could not sent groups");
+                match groups {
+                    Ok(groups) => {
+                        let _ = tx.send(groups).await;
                     }
-                ));
+                    Err(e) => {
+                        error!("Could not load groups: {:?}", e);
+                        let filter = main_window.accounts_window.get_filter_value();
+                        main_window.display_error(e.message().as_str(), Action::RefreshAccounts { filter });
+                    }
+                }
+            }
+        ));
 
-                glib::spawn_future_local(clone!(
-                    #[strong]
-                    main_window,
-                    #[strong]
-                    popover,
-                    #[strong]
-                    connection,
-                    async move {
-                        if let Ok(groups) = rx.recv().await {
-                            let builder = Builder::from_resource(format!("{}/{}", NAMESPACE_PREFIX, "main.ui").as_str());
+        glib::spawn_future_local(clone!(
+            #[strong]
+            main_window,
+            async move {
+                if let Ok(groups) = rx.recv().await {
+                    let builder = Builder::from_resource(format!("{}/{}", NAMESPACE_PREFIX, "main.ui").as_str());
 
-                            let edit_account = EditAccountWindow::new(&builder);
-                            edit_account.edit_account_buttons_actions(&main_window, connection.clone());
-                            edit_account.set_group_dropdown(group_id, &groups);
+                    let edit_account = EditAccountWindow::new(&builder);
+                    edit_account.edit_account_buttons_actions(&main_window, connection.clone());
+                    edit_account.set_group_dropdown(group_id, &groups);
 
-                            main_window.edit_account.replace_with(&edit_account);
+                    main_window.edit_account.replace_with(&edit_account);
 
-                            popover.hide();
-                            main_window.switch_to(Display::AddAccount);
-                        }
+                    main_window.switch_to(Display::AddAccount);
+
+                    if let Some(pasted) = pasted {
+                        edit_account.use_pasted(&main_window, pasted, connection);
                     }
-                ));
+                }
             }
-        )
+        ));
     }
 
     pub fn get_filter_value(&self) -> Option<String> {
@@ -592,7 +801,10 @@ async fn update_button(tx: Sender<u8>, popover: gtk::PopoverMenu, max_wait: u8)
  */
 async fn times_up(tx: Sender<bool>, wait_ms: u64) {
     glib::timeout_future_seconds(time::Duration::from_millis(wait_ms).as_secs() as u32).await;
-    tx.send(true).await.expect("Couldn't send data to channel");
+
+    if let Err(e) = tx.send(true).await {
+        warn!("Could not send data to channel: {:?}", e);
+    }
 }
 
 #[cfg(test)]
@@ -1,21 +1,19 @@
 use std::cell::RefCell;
 use std::fs;
-use std::fs::{remove_file, File};
-use std::io::prelude::*;
-use std::path::PathBuf;
+use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 
 use gettextrs::gettext;
 use glib::clone;
 use gtk::prelude::*;
 use gtk::{Builder, IconSize};
-use log::{debug, warn};
+use log::{debug, error, warn};
 use rusqlite::Connection;
 
-use crate::helpers::{AccountGroupIcon, Database, IconParser, Paths};
-use crate::main_window::{Display, MainWindow, State};
+use crate::helpers::{AccountGroupIcon, BrandIcon, BrandIcons, Database, IconParser, IconStore, RepositoryError};
+use crate::main_window::{MainWindow, State};
 use crate::model::AccountGroup;
-use crate::ui::{AccountsWindow, ValidationError};
+use crate::ui::{AccountsWindow, IconPickerWindow, ValidationError};
 
 #[derive(Clone, Debug)]
 pub struct AddGroupWindow {
@@ -27,6 +25,7 @@ pub struct AddGroupWindow {
     pub image_input: gtk::Image,
     pub icon_filename: gtk::Label,
     pub icon_reload: gtk::Button,
+    pub icon_library: gtk::Button,
     pub icon_delete: gtk::Button,
     pub icon_error: gtk::Label,
     pub group_id: gtk::Label,
@@ -45,6 +44,7 @@ impl AddGroupWindow {
             image_input: builder.object("add_group_image_input").unwrap(),
             icon_filename: builder.object("add_group_icon_filename").unwrap(),
             icon_reload: builder.object("group_icon_reload").unwrap(),
+            icon_library: builder.object("group_icon_library").unwrap(),
             icon_delete: builder.object("group_icon_delete").unwrap(),
             icon_error: builder.object("add_group_icon_error").unwrap(),
             group_id: builder.object("add_group_input_group_id").unwrap(),
@@ -68,7 +68,7 @@ impl AddGroupWindow {
             self.input_group.style_context().add_class("error");
             Err(ValidationError::FieldError("name".to_owned()))
         } else {
-            let connection = connection.lock().unwrap();
+            let connection = Database::lock(&connection);
             let existing_group = Database::group_exists(&connection, self.input_group.buffer().text().as_str());
             let existing_group = existing_group.unwrap_or(None);
 
@@ -86,7 +86,9 @@ impl AddGroupWindow {
     }
 
     pub fn reset(&self) {
-        Self::remove_tmp_file(Self::label_text(&self.icon_filename));
+        if let Some(icon_filename) = Self::label_text(&self.icon_filename) {
+            IconStore::open().remove_tmp(icon_filename.as_str());
+        }
 
         self.input_group.set_text("");
         self.url_input.set_text("");
@@ -115,11 +117,48 @@ impl AddGroupWindow {
         let icon_filename = self.icon_filename.clone();
         let image_input = self.image_input.clone();
 
+        // only the reload button goes online, pressing enter looks for a bundled icon
         url_input.connect_activate(clone!(
+            #[strong(rename_to = add_group)]
+            self,
             #[strong]
-            icon_reload,
+            state,
             move |_| {
-                icon_reload.clicked();
+                let url = add_group.url_input.buffer().text();
+                let name = add_group.input_group.buffer().text();
+
+                match BrandIcons::get().find(&[url.as_str(), name.as_str()]) {
+                    Some(icon) => add_group.use_brand_icon(&state, icon),
+                    None => {
+                        add_group
+                            .icon_error
+                            .set_label(&gettext("No bundled icon for this group: download it from the website instead"));
+                        add_group.icon_error.set_visible(true);
+                    }
+                }
+            }
+        ));
+
+        self.icon_library.connect_clicked(clone!(
+            #[strong(rename_to = add_group)]
+            self,
+            #[strong]
+            state,
+            move |button| {
+                let parent = button.toplevel().and_then(|toplevel| toplevel.downcast::<gtk::Window>().ok());
+                let name = add_group.input_group.buffer().text();
+
+                IconPickerWindow::show(
+                    parent.as_ref(),
+                    name.as_str(),
+                    Rc::new(clone!(
+                        #[strong]
+                        add_group,
+                        #[strong]
+                        state,
+                        move |icon| add_group.use_brand_icon(&state, icon)
+                    )),
+                );
             }
         ));
 
@@ -134,14 +173,18 @@ impl AddGroupWindow {
                 gtk::ResponseType::Accept => {
                     dialog.hide();
 
-                    let path = dialog.filename().unwrap();
+                    let Some(path) = dialog.filename() else {
+                        warn!("No filename in dialog");
+                        return;
+                    };
                     debug!("path: {}", path.display());
 
                     match fs::read(&path) {
                         Ok(bytes) => {
-                            let filename = path.file_name().unwrap();
-                            debug!("filename: {:?}", filename);
-                            Self::write_tmp_icon(&state, &icon_filename, &image_input, bytes.as_slice());
+                            debug!("filename: {:?}", path.file_name());
+                            if let Err(e) = Self::write_tmp_icon(&state, &icon_filename, &image_input, bytes.as_slice()) {
+                                warn!("Could not write temporary icon: {:?}", e);
+                            }
                         }
                         Err(_) => warn!("Could not read file {}", &path.display()),
                     }
@@ -181,7 +224,13 @@ impl AddGroupWindow {
                     async move {
                         match rx.recv().await {
                             Ok(Ok(account_group_icon)) => {
-                                Self::write_tmp_icon(&state, &add_group.icon_filename, &add_group.image_input, account_group_icon.content.as_slice())
+                                if let Err(e) =
+                                    Self::write_tmp_icon(&state, &add_group.icon_filename, &add_group.image_input, account_group_icon.content.as_slice())
+                                {
+                                    warn!("Could not write temporary icon: {:?}", e);
+                                    add_group.icon_error.set_label(e.message().as_str());
+                                    add_group.icon_error.set_visible(true);
+                                }
                             }
                             Ok(Err(e)) => {
                                 add_group.icon_error.set_label(format!("{}", e).as_str());
@@ -215,6 +264,24 @@ impl AddGroupWindow {
         }
     }
 
+    fn use_brand_icon(&self, state: &RefCell<State>, icon: &BrandIcon) {
+        self.icon_error.set_label("");
+        self.icon_error.set_visible(false);
+
+        let written = BrandIcons::content(icon)
+            .map_err(|e| {
+                warn!("Could not load brand icon {}: {:?}", icon.slug, e);
+                gettext("Could not load the brand icon")
+            })
+            .and_then(|icon| Self::write_tmp_icon(state, &self.icon_filename, &self.image_input, icon.content.as_slice()).map_err(|e| e.message()));
+
+        if let Err(e) = written {
+            warn!("Could not use brand icon {}: {}", icon.slug, e);
+            self.icon_error.set_label(e.as_str());
+            self.icon_error.set_visible(true);
+        }
+    }
+
     pub fn edit_group_buttons_actions(&self, gui: &MainWindow, connection: Arc<Mutex<Connection>>) {
         self.url_input_action(gui.state.clone());
 
@@ -249,49 +316,65 @@ impl AddGroupWindow {
                         connection,
                         #[strong]
                         gui,
+                        #[strong]
+                        add_group,
                         async move {
-                            Self::create_group(group_id, group_name, icon_filename, url_input, connection.clone()).await;
-                            let results = AccountsWindow::load_account_groups(connection.clone(), filter).await;
-                            gui.accounts_window.replace_accounts_and_widgets(results, gui.clone(), connection).await;
+                            match Self::create_group(group_id, group_name, icon_filename, url_input, connection.clone()).await {
+                                Ok(()) => {
+                                    let results = AccountsWindow::load_account_groups(connection.clone(), filter).await;
+                                    gui.accounts_window.replace_accounts_and_widgets(results, gui.clone(), connection).await;
+                                }
+                                Err(e) => {
+                                    // stay on the form so that nothing the user typed is lost
+                                    error!("Could not save group: {:?}", e);
+                                    add_group.icon_error.set_label(e.message().as_str());
+                                    add_group.icon_error.set_visible(true);
+                                }
+                            }
                         }
                     ));
-
-                    gui.switch_to(Display::Accounts);
                 }
             }
         ));
     }
 
-    async fn create_group(group_id: String, group_name: String, icon_filename: Option<String>, url_input: Option<String>, connection: Arc<Mutex<Connection>>) {
-        let connection = connection.lock().unwrap();
+    async fn create_group(
+        group_id: String,
+        group_name: String,
+        icon_filename: Option<String>,
+        url_input: Option<String>,
+        connection: Arc<Mutex<Connection>>,
+    ) -> Result<(), RepositoryError> {
+        let connection = Database::lock(&connection);
+
+        let icon_store = IconStore::open();
+        let icon = icon_filename.map(|icon_filename| icon_store.commit(icon_filename.as_str())).transpose()?;
 
         match group_id.parse() {
             Ok(group_id) => {
                 debug!("updating existing group id {:?}", group_id);
-                let mut group = Database::get_group(&connection, group_id).unwrap();
+                let mut group = Database::get_group(&connection, group_id)?;
 
                 group_name.clone_into(&mut group.name);
-                group.icon = icon_filename;
+                let previous_icon = std::mem::replace(&mut group.icon, icon);
                 group.url = url_input;
 
-                Self::write_icon(group.icon.clone());
+                Database::update_group(&connection, &group)?;
+
+                // replaced or removed
+                if let Some(previous_icon) = previous_icon.filter(|previous_icon| group.icon.as_ref() != Some(previous_icon)) {
+                    if let Err(e) = icon_store.release(&connection, previous_icon.as_str()) {
+                        warn!("Could not delete icon {}: {:?}", previous_icon, e);
+                    }
+                }
 
-                Database::update_group(&connection, &group).unwrap();
+                Ok(())
             }
             Err(_) => {
                 debug!("creating new group");
-                let mut group = AccountGroup::new(0, &group_name, icon_filename.as_deref(), url_input.as_deref(), false, vec![]);
+                let mut group = AccountGroup::new(0, &group_name, icon.as_deref(), url_input.as_deref(), false, vec![]);
 
-                Database::save_group(&connection, &mut group).unwrap();
-
-                //has no icon -> delete icon file if any
-                if group.icon.is_none() {
-                    if let Some(icon_filename) = icon_filename {
-                        Self::delete_icon_file(&icon_filename);
-                    }
-                } else {
-                    Self::write_icon(group.icon);
-                }
+                Database::save_group(&connection, &mut group)
             }
         }
     }
@@ -311,64 +394,19 @@ impl AddGroupWindow {
         }
     }
 
-    fn remove_tmp_file(icon_filename: Option<String>) {
-        if let Some(icon_filename_text) = icon_filename {
-            let mut temp_filepath = PathBuf::new();
-            temp_filepath.push(std::env::temp_dir());
-            temp_filepath.push(&icon_filename_text);
-
-            if temp_filepath.is_file() {
-                match remove_file(&temp_filepath) {
-                    Ok(_) => debug!("removed temp file: {}", temp_filepath.display()),
-                    Err(e) => warn!("could not delete temp file {}: {:?}", temp_filepath.display(), e),
-                };
-            }
-        }
-    }
-
-    fn write_icon(icon_filename: Option<String>) {
-        if let Some(icon_filename_text) = icon_filename {
-            debug!("icon_filename: {}", icon_filename_text);
-
-            let mut temp_filepath = PathBuf::new();
-            temp_filepath.push(std::env::temp_dir());
-            temp_filepath.push(&icon_filename_text);
-
-            match fs::read(&temp_filepath) {
-                Ok(bytes) => {
-                    let icon_filepath = Paths::icons_path(&icon_filename_text);
-                    debug!("icon_filepath: {}", icon_filepath.display());
-
-                    let mut file = File::create(&icon_filepath).unwrap_or_else(|_| panic!("could not create file {}", icon_filepath.display()));
-
-                    file.write_all(&bytes)
-                        .unwrap_or_else(|_| panic!("could not write image to file {}", icon_filepath.display()));
-
-                    Self::remove_tmp_file(Some(icon_filename_text));
-                }
-                Err(_) => warn!("temp file {} not found. Did you call write_tmp_icon() first ?", temp_filepath.display()),
-            }
-        }
-    }
-
-    fn write_tmp_icon(state: &RefCell<State>, icon_filename: &gtk::Label, image_input: &gtk::Image, buf: &[u8]) {
-        let mut temp_filepath = PathBuf::new();
-
-        temp_filepath.push(std::env::temp_dir());
-        temp_filepath.push(Self::reuse_filename(icon_filename));
-
-        let mut temp_file = tempfile_fast::Sponge::new_for(&temp_filepath).unwrap();
-        temp_file.write_all(buf).unwrap();
-        temp_file.commit().unwrap();
+    pub fn write_tmp_icon(state: &RefCell<State>, icon_filename: &gtk::Label, image_input: &gtk::Image, buf: &[u8]) -> Result<(), RepositoryError> {
+        let temp_filepath = IconStore::open().write_tmp(Self::reuse_filename(icon_filename).as_str(), buf)?;
 
         let state = state.borrow();
         match IconParser::load_icon(&temp_filepath, state.dark_mode) {
             Ok(pixbuf) => image_input.set_from_pixbuf(Some(&pixbuf)),
             Err(e) => warn!("Could not load image {}", e),
         };
+
+        Ok(())
     }
 
-    fn label_text(label: &gtk::Label) -> Option<String> {
+    pub fn label_text(label: &gtk::Label) -> Option<String> {
         let icon_filename = label.label();
         let icon_filename = icon_filename.as_str();
 
@@ -377,17 +415,4 @@ impl AddGroupWindow {
             v => Some(v.to_owned()),
         }
     }
-
-    pub fn delete_icon_file(icon_filename: &str) {
-        let icon_filepath = Paths::icons_path(icon_filename);
-
-        if icon_filepath.is_file() {
-            match remove_file(&icon_filepath) {
-                Ok(_) => debug!("deleted icon_filepath: {}", &icon_filepath.display()),
-                Err(e) => warn!("could not delete file {}: {:?}", icon_filepath.display(), e),
-            }
-        } else {
-            debug!("icon_filepath {} does exist. Skipping.", &icon_filepath.display())
-        }
-    }
 }
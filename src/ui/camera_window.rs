@@ -0,0 +1,93 @@
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use glib::clone;
+use gtk::prelude::*;
+use gtk::Builder;
+use log::{error, warn};
+
+use crate::helpers::{Camera, QrCode, RepositoryError};
+use crate::NAMESPACE_PREFIX;
+
+type ScanResult = Result<Option<QrCode>, RepositoryError>;
+
+#[derive(Clone, Debug)]
+pub struct CameraWindow {
+    pub popup: gtk::Window,
+    pub spinner: gtk::Spinner,
+    pub status: gtk::Label,
+    pub close: gtk::Button,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CameraWindow {
+    pub fn new(builder: &Builder) -> CameraWindow {
+        CameraWindow {
+            popup: builder.object("camera_popup").unwrap(),
+            spinner: builder.object("camera_spinner").unwrap(),
+            status: builder.object("camera_status").unwrap(),
+            close: builder.object("camera_close").unwrap(),
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /**
+     * Scans with the camera until the QR code of an account, or of a Google Authenticator export,
+     * is shown to it. Closing the window stops the camera.
+     */
+    pub fn show(parent: Option<&gtk::Window>, on_found: Rc<dyn Fn(QrCode)>) {
+        let builder = Builder::from_resource(format!("{}/{}", NAMESPACE_PREFIX, "camera.ui").as_str());
+        let window = CameraWindow::new(&builder);
+
+        window.popup.set_transient_for(parent);
+
+        window.close.connect_clicked(clone!(
+            #[strong(rename_to = popup)]
+            window.popup,
+            move |_| popup.close()
+        ));
+
+        window.popup.connect_destroy(clone!(
+            #[strong(rename_to = cancelled)]
+            window.cancelled,
+            move |_| cancelled.store(true, Ordering::Relaxed)
+        ));
+
+        let (tx, rx) = async_channel::bounded::<ScanResult>(1);
+        let cancelled = window.cancelled.clone();
+        let device = Camera::configured_device();
+
+        // frames keep coming until a QR code shows up, away from the main loop
+        thread::spawn(move || {
+            let result = Camera::open(device.as_deref()).and_then(|mut camera| Camera::scan(camera.as_mut(), &cancelled));
+
+            if let Err(e) = tx.send_blocking(result) {
+                warn!("Could not send scan result: {:?}", e);
+            }
+        });
+
+        glib::spawn_future_local(clone!(
+            #[strong]
+            window,
+            async move {
+                match rx.recv().await {
+                    Ok(Ok(Some(qr_code))) => {
+                        window.popup.close();
+                        on_found(qr_code);
+                    }
+                    Ok(Ok(None)) => {}
+                    Ok(Err(e)) => {
+                        error!("Could not scan with camera: {:?}", e);
+                        window.spinner.stop();
+                        window.status.set_label(e.message().as_str());
+                    }
+                    Err(e) => warn!("Channel is closed. Application terminated?: {:?}", e),
+                }
+            }
+        ));
+
+        window.popup.show();
+    }
+}
@@ -0,0 +1,144 @@
+use gettextrs::gettext;
+use glib::clone;
+use gtk::prelude::*;
+use gtk::Builder;
+use log::{error, warn};
+
+use crate::helpers::{Clock, RepositoryError};
+use crate::main_window::MainWindow;
+use crate::NAMESPACE_PREFIX;
+
+type DriftResult = Result<i64, RepositoryError>;
+
+#[derive(Clone, Debug)]
+pub struct ClockWindow {
+    pub popup: gtk::Window,
+    pub offset: gtk::SpinButton,
+    pub source: gtk::Entry,
+    pub status: gtk::Label,
+    pub check: gtk::Button,
+    pub close: gtk::Button,
+    pub save: gtk::Button,
+}
+
+impl ClockWindow {
+    pub fn new(builder: &Builder) -> ClockWindow {
+        ClockWindow {
+            popup: builder.object("clock_popup").unwrap(),
+            offset: builder.object("clock_offset").unwrap(),
+            source: builder.object("clock_source").unwrap(),
+            status: builder.object("clock_status").unwrap(),
+            check: builder.object("clock_check").unwrap(),
+            close: builder.object("clock_close").unwrap(),
+            save: builder.object("clock_save").unwrap(),
+        }
+    }
+
+    pub fn show(gui: &MainWindow) {
+        let builder = Builder::from_resource(format!("{}/{}", NAMESPACE_PREFIX, "clock.ui").as_str());
+        let window = ClockWindow::new(&builder);
+
+        window.popup.set_transient_for(Some(&gui.window));
+
+        window.offset.set_value(Clock::offset() as f64);
+        window.source.set_text(Clock::configured_source().unwrap_or_default().as_str());
+
+        window.close.connect_clicked(clone!(
+            #[strong(rename_to = popup)]
+            window.popup,
+            move |_| popup.close()
+        ));
+
+        window.check.connect_clicked(clone!(
+            #[strong]
+            window,
+            move |_| window.check_now()
+        ));
+
+        window.save.connect_clicked(clone!(
+            #[strong]
+            window,
+            #[strong]
+            gui,
+            move |_| window.save(&gui)
+        ));
+
+        window.popup.show();
+    }
+
+    /**
+     * Measures the drift against the source entered, and fills in the offset correcting it.
+     */
+    fn check_now(&self) {
+        let source = self.source.text().trim().to_owned();
+
+        if source.is_empty() {
+            self.status.set_label(&gettext("Enter a time source first"));
+            return;
+        }
+
+        self.check.set_sensitive(false);
+        self.status.set_label(&gettext("Checking…"));
+
+        let (tx, rx) = async_channel::bounded::<DriftResult>(1);
+
+        glib::spawn_future(async move {
+            let result = Clock::source(source.as_str()).and_then(|source| Clock::drift(source.as_ref()));
+
+            if let Err(e) = tx.send(result).await {
+                warn!("Could not send clock drift: {:?}", e);
+            }
+        });
+
+        glib::spawn_future_local(clone!(
+            #[strong(rename_to = window)]
+            self,
+            async move {
+                match rx.recv().await {
+                    Ok(Ok(drift)) => {
+                        window.offset.set_value(drift as f64);
+
+                        let message = match drift {
+                            0 => gettext("The clock is right"),
+                            drift if drift > 0 => format!("{} {} {}", gettext("The clock is"), drift, gettext("seconds behind")),
+                            drift => format!("{} {} {}", gettext("The clock is"), -drift, gettext("seconds ahead")),
+                        };
+
+                        window.status.set_label(message.as_str());
+                    }
+                    Ok(Err(e)) => {
+                        error!("Could not check clock: {:?}", e);
+                        window.status.set_label(e.message().as_str());
+                    }
+                    Err(e) => warn!("Channel is closed. Application terminated?: {:?}", e),
+                }
+
+                window.check.set_sensitive(true);
+            }
+        ));
+    }
+
+    fn save(&self, gui: &MainWindow) {
+        let source = self.source.text().trim().to_owned();
+
+        if !source.is_empty() {
+            if let Err(e) = Clock::source(source.as_str()) {
+                self.status.set_label(e.message().as_str());
+                return;
+            }
+        }
+
+        let saved = Clock::set_offset(self.offset.value_as_int() as i64).and_then(|_| Clock::set_configured_source(source.as_str()));
+
+        match saved {
+            Ok(()) => {
+                gui.check_clock();
+                self.popup.close();
+            }
+            Err(e) => {
+                error!("Could not save clock settings: {:?}", e);
+                self.status.set_label(e.message().as_str());
+            }
+        }
+    }
+}
@@ -1,34 +1,53 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 
 use gettextrs::gettext;
 use glib::clone;
 use gtk::prelude::*;
-use gtk::{Builder, StateFlags};
-use log::{debug, error};
+use gtk::{Builder, IconSize, StateFlags};
+use log::{debug, error, warn};
 use regex::Regex;
 use rusqlite::Connection;
 
-use crate::helpers::QrCode;
+use crate::exporting::AccountsImportExportResult;
 use crate::helpers::QrCodeResult::{Invalid, Valid};
 use crate::helpers::RepositoryError;
-use crate::helpers::{Database, Keyring, SecretType};
-use crate::main_window::{Action, Display, MainWindow};
-use crate::model::{Account, AccountGroup};
-use crate::ui::ValidationError;
+use crate::helpers::{AccountGroupIcon, BrandIcon, BrandIcons, IconParser, IconStore};
+use crate::helpers::{Backup, Database, SecretStores, SecretType};
+use crate::helpers::{Pasted, QrCode, QrCodeResult, Screenshot};
+use crate::main_window::{Action, Display, MainWindow, State};
+use crate::model::{Account, AccountGroup, AccountType};
+use crate::ui::{AddGroupWindow, CameraWindow, IconPickerWindow, QrCodesWindow, ValidationError};
 
 #[derive(Clone, Debug)]
 pub struct EditAccountWindow {
     pub container: gtk::Box,
     pub input_group: gtk::ComboBoxText,
+    pub input_type: gtk::ComboBoxText,
+    pub input_pin: gtk::Entry,
+    pub label_pin: gtk::Label,
     pub input_name: gtk::Entry,
     pub input_secret: gtk::TextView,
+    pub input_username: gtk::Entry,
+    pub input_url: gtk::Entry,
+    pub image_input: gtk::Image,
+    pub icon_filename: gtk::Label,
+    pub icon_reload: gtk::Button,
+    pub icon_library: gtk::Button,
+    pub icon_delete: gtk::Button,
     pub input_account_id: gtk::Entry,
     pub cancel_button: gtk::Button,
     pub qr_button: gtk::Button,
+    pub qr_screen_button: gtk::Button,
+    pub qr_camera_button: gtk::Button,
+    pub qr_paste_button: gtk::Button,
     pub save_button: gtk::Button,
     pub image_dialog: gtk::FileChooserDialog,
     pub input_secret_frame: gtk::Frame,
     pub icon_error: gtk::Label,
+    pub secret_info: gtk::Label,
 }
 
 impl EditAccountWindow {
@@ -36,15 +55,29 @@ impl EditAccountWindow {
         EditAccountWindow {
             container: builder.object("edit_account").unwrap(),
             input_group: builder.object("edit_account_input_group").unwrap(),
+            input_type: builder.object("edit_account_input_type").unwrap(),
+            input_pin: builder.object("edit_account_input_pin").unwrap(),
+            label_pin: builder.object("edit_account_label_pin").unwrap(),
             input_name: builder.object("edit_account_input_name").unwrap(),
             input_secret: builder.object("edit_account_input_secret").unwrap(),
+            input_username: builder.object("edit_account_input_username").unwrap(),
+            input_url: builder.object("edit_account_input_url").unwrap(),
+            image_input: builder.object("edit_account_image_input").unwrap(),
+            icon_filename: builder.object("edit_account_icon_filename").unwrap(),
+            icon_reload: builder.object("edit_account_icon_reload").unwrap(),
+            icon_library: builder.object("edit_account_icon_library").unwrap(),
+            icon_delete: builder.object("edit_account_icon_delete").unwrap(),
             input_account_id: builder.object("edit_account_input_account_id").unwrap(),
             cancel_button: builder.object("edit_account_cancel").unwrap(),
             save_button: builder.object("edit_account_save").unwrap(),
             qr_button: builder.object("qrcode_button").unwrap(),
+            qr_screen_button: builder.object("qrcode_screen_button").unwrap(),
+            qr_camera_button: builder.object("qrcode_camera_button").unwrap(),
+            qr_paste_button: builder.object("qrcode_paste_button").unwrap(),
             image_dialog: builder.object("file_chooser_dialog").unwrap(),
             input_secret_frame: builder.object("edit_account_input_secret_frame").unwrap(),
             icon_error: builder.object("edit_account_icon_error").unwrap(),
+            secret_info: builder.object("edit_account_secret_info").unwrap(),
         }
     }
 
@@ -82,7 +115,7 @@ impl EditAccountWindow {
             .parse::<u32>()
             .map_err(|c| c.into());
 
-        let connection = connection.lock().unwrap();
+        let connection = Database::lock(&connection);
         let existing_account = Database::account_exists(&connection, name.buffer().text().as_str(), group_id?);
         let existing_account = existing_account.unwrap_or(None);
 
@@ -96,7 +129,7 @@ impl EditAccountWindow {
             return Err(ValidationError::FieldError("name".to_owned()));
         }
 
-        let buffer = secret.buffer().unwrap();
+        let buffer = secret.buffer().ok_or(ValidationError::FieldError("secret".to_owned()))?;
         let (start, end) = buffer.bounds();
         let secret_value: String = match buffer.slice(&start, &end, true) {
             Some(secret_value) => secret_value.to_string(),
@@ -111,11 +144,11 @@ impl EditAccountWindow {
         let stripped = Self::strip_secret(&secret_value);
         let style_context = input_secret_frame.style_context();
 
-        match Account::generate_time_based_password(stripped.as_str()) {
+        match Account::generate_code(&self.account_type(), stripped.as_str(), self.input_pin.text().as_str()) {
             Ok(_) if style_context.has_class("error") => buffer.set_text(&secret_value),
             Ok(_) => buffer.set_text(&stripped),
             Err(error_key) => {
-                error!("{}", error_key.error());
+                error!("{}", error_key.message());
 
                 style_context.add_class("error");
                 return Err(ValidationError::FieldError("secret".to_owned()));
@@ -148,14 +181,27 @@ impl EditAccountWindow {
     }
 
     pub fn reset(&self) {
+        if let Some(icon_filename) = AddGroupWindow::label_text(&self.icon_filename) {
+            IconStore::open().remove_tmp(icon_filename.as_str());
+        }
+
         self.input_name.set_text("");
+        self.set_account_type(&AccountType::default());
+        self.input_pin.set_text("");
         self.input_account_id.set_text("");
+        self.input_username.set_text("");
+        self.input_url.set_text("");
+        self.icon_filename.set_label("");
+        self.icon_reload.set_sensitive(true);
+        self.save_button.set_sensitive(true);
+        self.image_input.set_from_icon_name(Some("content-loading-symbolic"), IconSize::Button);
 
         if let Some(buffer) = self.input_secret.buffer() {
             buffer.set_text("");
         }
 
         self.reset_errors();
+        self.show_secret_info("", None);
     }
 
     pub fn set_group_dropdown(&self, group_id: Option<u32>, groups: &[AccountGroup]) {
@@ -178,20 +224,78 @@ impl EditAccountWindow {
         }
     }
 
+    fn account_type(&self) -> AccountType {
+        self.input_type
+            .active_id()
+            .and_then(|id| AccountType::from_str(id.as_str()).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn set_account_type(&self, account_type: &AccountType) {
+        self.input_type.set_active_id(Some(account_type.to_string().as_str()));
+        self.show_pin();
+    }
+
+    /**
+     * The PIN is only asked for account types deriving codes from it.
+     */
+    fn show_pin(&self) {
+        let has_pin = self.account_type().has_pin();
+        self.label_pin.set_visible(has_pin);
+        self.input_pin.set_visible(has_pin);
+    }
+
+    /**
+     * Previews the code of the secret as it is typed, with the length of its key.
+     */
+    fn check_secret(&self) {
+        let secret = self
+            .input_secret
+            .buffer()
+            .and_then(|buffer| {
+                let (start, end) = buffer.bounds();
+                buffer.slice(&start, &end, true)
+            })
+            .map(|secret| Self::strip_secret(secret.as_str()))
+            .unwrap_or_default();
+
+        if secret.is_empty() {
+            self.show_secret_info("", None);
+            return;
+        }
+
+        match Account::check_secret(&self.account_type(), secret.as_str(), self.input_pin.text().as_str()) {
+            Ok(check) => {
+                let info = format!("{} {}, {} {}", gettext("Code"), check.code, check.bits, gettext("bit key"));
+
+                if check.short {
+                    let info = format!("{}: {}", info, gettext("short keys are easier to guess"));
+                    self.show_secret_info(info.as_str(), Some("warning"));
+                } else {
+                    self.show_secret_info(info.as_str(), None);
+                }
+            }
+            Err(e) => self.show_secret_info(e.message().as_str(), Some("error")),
+        }
+    }
+
+    fn show_secret_info(&self, info: &str, class: Option<&str>) {
+        self.secret_info.set_label(info);
+
+        let style_context = self.secret_info.style_context();
+        style_context.remove_class("warning");
+        style_context.remove_class("error");
+
+        if let Some(class) = class {
+            style_context.add_class(class);
+        }
+    }
+
     fn qrcode_action(&self) {
         let qr_button = self.qr_button.clone();
         let dialog = self.image_dialog.clone();
-        let input_secret = self.input_secret.clone();
-        let save_button = self.save_button.clone();
-        let input_secret_frame = self.input_secret_frame.clone();
 
         qr_button.connect_clicked(clone!(
-            #[strong]
-            save_button,
-            #[strong]
-            input_secret,
-            #[strong]
-            input_secret_frame,
             #[strong(rename_to = w)]
             self,
             move |_| {
@@ -200,62 +304,404 @@ impl EditAccountWindow {
                         if let Some(path) = dialog.filename() {
                             debug!("path: {}", path.display());
 
-                            let buffer = input_secret.buffer().unwrap();
-                            buffer.set_text(&gettext("Processing QR code"));
-
-                            save_button.set_sensitive(false);
+                            w.processing_qr_code();
                             dialog.hide();
 
                             glib::spawn_future_local(clone!(
-                                #[strong]
-                                save_button,
-                                #[strong]
-                                input_secret,
-                                #[strong]
-                                input_secret_frame,
                                 #[strong]
                                 w,
                                 async move {
-                                    let result = if let Some(path_str) = path.to_str() {
-                                        QrCode::process_qr_code(path_str.to_owned()).await
-                                    } else {
-                                        Invalid(gettext("Invalid file path").to_string())
+                                    let qr_codes = match path.to_str() {
+                                        Some(path_str) => QrCode::process_qr_codes(path_str.to_owned()).await,
+                                        None => Err(gettext("Invalid file path")),
                                     };
 
-                                    save_button.set_sensitive(true);
-                                    let style_context = input_secret_frame.style_context();
-
-                                    match result {
-                                        Valid(qr_code) => {
-                                            w.reset_errors();
-                                            let buffer = input_secret.buffer().unwrap();
-                                            style_context.remove_class("error");
-                                            buffer.set_text(qr_code.extract().as_str());
-                                        }
-                                        Invalid(qr_code) => {
-                                            let buffer = input_secret.buffer().unwrap();
-
-                                            w.icon_error.set_label(&gettext(qr_code));
-                                            w.icon_error.set_visible(true);
-
-                                            style_context.add_class("error");
-                                            buffer.set_text("");
-                                        }
-                                    };
+                                    w.use_qr_codes(qr_codes);
                                 }
                             ));
                         } else {
-                            panic!("No filename in dialog");
+                            warn!("No filename in dialog");
+                            dialog.hide();
                         }
                     }
                     _ => dialog.hide(),
                 }
             }
         ));
+
+        self.qr_screen_button.connect_clicked(clone!(
+            #[strong(rename_to = w)]
+            self,
+            move |_| w.scan_screen()
+        ));
+    }
+
+    /**
+     * Looks for QR codes in a screenshot, which is removed afterwards as it may show secrets.
+     */
+    fn scan_screen(&self) {
+        self.processing_qr_code();
+
+        glib::spawn_future_local(clone!(
+            #[strong(rename_to = w)]
+            self,
+            async move {
+                let qr_codes = match Screenshot::capture().await {
+                    Ok(path) => {
+                        let qr_codes = QrCode::process_qr_codes(path.display().to_string()).await;
+
+                        if let Err(e) = std::fs::remove_file(&path) {
+                            warn!("Could not remove screenshot {}: {:?}", path.display(), e);
+                        }
+
+                        qr_codes
+                    }
+                    Err(e) => {
+                        error!("Could not take screenshot: {:?}", e);
+                        Err(e.message())
+                    }
+                };
+
+                w.use_qr_codes(qr_codes);
+            }
+        ));
+    }
+
+    fn processing_qr_code(&self) {
+        if let Some(buffer) = self.input_secret.buffer() {
+            buffer.set_text(&gettext("Processing QR code"));
+        }
+        self.show_secret_info("", None);
+
+        self.save_button.set_sensitive(false);
+        self.qr_button.set_sensitive(false);
+        self.qr_screen_button.set_sensitive(false);
+    }
+
+    /**
+     * Fills in the secret from the QR codes found, asking which one to use when there are several.
+     */
+    fn use_qr_codes(&self, qr_codes: Result<Vec<QrCode>, String>) {
+        self.save_button.set_sensitive(true);
+        self.qr_button.set_sensitive(true);
+        self.qr_screen_button.set_sensitive(true);
+
+        match qr_codes {
+            Ok(qr_codes) if qr_codes.len() > 1 => {
+                if let Some(buffer) = self.input_secret.buffer() {
+                    buffer.set_text("");
+                }
+
+                QrCodesWindow::show(
+                    self.parent_window().as_ref(),
+                    qr_codes,
+                    Rc::new(clone!(
+                        #[strong(rename_to = w)]
+                        self,
+                        move |qr_code| w.use_qr_code(Valid(qr_code))
+                    )),
+                );
+            }
+            Ok(qr_codes) => match qr_codes.into_iter().next() {
+                Some(qr_code) => self.use_qr_code(Valid(qr_code)),
+                None => self.use_qr_code(Invalid(gettext("No QR codes found"))),
+            },
+            Err(e) => self.use_qr_code(Invalid(e)),
+        }
+    }
+
+    /**
+     * Fills in the secret of an account shown to the camera. Google Authenticator exports are imported as they are.
+     */
+    fn scan_camera(&self, gui: &MainWindow, connection: Arc<Mutex<Connection>>) {
+        CameraWindow::show(
+            self.parent_window().as_ref(),
+            Rc::new(clone!(
+                #[strong(rename_to = w)]
+                self,
+                #[strong]
+                gui,
+                move |qr_code| {
+                    if qr_code.is_gauth_export() {
+                        w.import_gauth_export(&gui, qr_code, connection.clone());
+                    } else {
+                        w.use_qr_code(Valid(qr_code));
+                    }
+                }
+            )),
+        );
+    }
+
+    /**
+     * Fills in the form from the clipboard. Google Authenticator exports are imported as they are.
+     */
+    fn paste(&self, gui: &MainWindow, connection: Arc<Mutex<Connection>>) {
+        glib::spawn_future_local(clone!(
+            #[strong(rename_to = w)]
+            self,
+            #[strong]
+            gui,
+            async move {
+                let pasted = Pasted::read().await;
+                w.use_pasted(&gui, pasted, connection);
+            }
+        ));
+    }
+
+    pub fn use_pasted(&self, gui: &MainWindow, pasted: Pasted, connection: Arc<Mutex<Connection>>) {
+        match pasted {
+            Pasted::QrCodes(qr_codes) => self.use_qr_codes(qr_codes),
+            Pasted::Uri(qr_code) if qr_code.is_gauth_export() => self.import_gauth_export(gui, qr_code, connection),
+            Pasted::Uri(qr_code) => self.use_qr_code(Valid(qr_code)),
+            Pasted::Secret(secret) => {
+                self.reset_errors();
+                self.input_secret_frame.style_context().remove_class("error");
+                if let Some(buffer) = self.input_secret.buffer() {
+                    buffer.set_text(secret.as_str());
+                }
+            }
+            Pasted::Nothing => {
+                self.icon_error.set_label(&gettext("The clipboard holds no QR code, otpauth URI or secret"));
+                self.icon_error.set_visible(true);
+            }
+        }
+    }
+
+    fn import_gauth_export(&self, gui: &MainWindow, qr_code: QrCode, connection: Arc<Mutex<Connection>>) {
+        let (tx, rx) = async_channel::bounded::<AccountsImportExportResult>(1);
+
+        glib::spawn_future(async move { Backup::restore_gauth_payload_and_signal_back(qr_code.qr_code_payload, connection, tx).await });
+
+        glib::spawn_future_local(clone!(
+            #[strong(rename_to = w)]
+            self,
+            #[strong]
+            gui,
+            async move {
+                match rx.recv().await {
+                    Ok(Ok(_)) => {
+                        w.reset();
+                        gui.accounts_window.refresh_accounts(&gui);
+                        gui.switch_to(Display::Accounts);
+                    }
+                    Ok(Err(e)) => {
+                        error!("Could not import accounts: {:?}", e);
+                        w.icon_error.set_label(e.message().as_str());
+                        w.icon_error.set_visible(true);
+                    }
+                    Err(e) => warn!("Channel is closed. Application terminated?: {:?}", e),
+                }
+            }
+        ));
+    }
+
+    fn parent_window(&self) -> Option<gtk::Window> {
+        self.container.toplevel().and_then(|w| w.downcast::<gtk::Window>().ok())
+    }
+
+    fn use_qr_code(&self, result: QrCodeResult) {
+        let style_context = self.input_secret_frame.style_context();
+
+        match result {
+            Valid(qr_code) => {
+                self.reset_errors();
+                style_context.remove_class("error");
+                self.set_account_type(&qr_code.account_type());
+                if self.input_username.text().is_empty() {
+                    self.input_username.set_text(qr_code.username().unwrap_or_default().as_str());
+                }
+                if let Some(buffer) = self.input_secret.buffer() {
+                    buffer.set_text(qr_code.extract().as_str());
+                }
+            }
+            Invalid(qr_code) => {
+                self.icon_error.set_label(&gettext(qr_code));
+                self.icon_error.set_visible(true);
+
+                style_context.add_class("error");
+                if let Some(buffer) = self.input_secret.buffer() {
+                    buffer.set_text("");
+                }
+            }
+        };
+    }
+
+    /**
+     * Same icon choices as groups: a bundled brand icon, picked from the library or matching the URL, or the one of the website.
+     */
+    fn icon_actions(&self, state: RefCell<State>) {
+        // only the reload button goes online, pressing enter looks for a bundled icon
+        self.input_url.connect_activate(clone!(
+            #[strong(rename_to = w)]
+            self,
+            #[strong]
+            state,
+            move |_| {
+                let url = w.input_url.buffer().text();
+                let name = w.input_name.buffer().text();
+
+                match BrandIcons::get().find(&[url.as_str(), name.as_str()]) {
+                    Some(icon) => w.use_brand_icon(&state, icon),
+                    None => {
+                        w.icon_error
+                            .set_label(&gettext("No bundled icon for this account: download it from the website instead"));
+                        w.icon_error.set_visible(true);
+                    }
+                }
+            }
+        ));
+
+        self.icon_library.connect_clicked(clone!(
+            #[strong(rename_to = w)]
+            self,
+            #[strong]
+            state,
+            move |_| {
+                let name = w.input_name.buffer().text();
+
+                IconPickerWindow::show(
+                    w.parent_window().as_ref(),
+                    name.as_str(),
+                    Rc::new(clone!(
+                        #[strong]
+                        w,
+                        #[strong]
+                        state,
+                        move |icon| w.use_brand_icon(&state, icon)
+                    )),
+                );
+            }
+        ));
+
+        self.icon_reload.connect_clicked(clone!(
+            #[strong(rename_to = w)]
+            self,
+            #[strong]
+            state,
+            move |_| {
+                let url = w.input_url.buffer().text();
+
+                w.icon_error.set_label("");
+                w.icon_error.set_visible(false);
+
+                if url.is_empty() {
+                    return;
+                }
+
+                let (tx, rx) = async_channel::bounded::<anyhow::Result<AccountGroupIcon>>(1);
+
+                w.save_button.set_sensitive(false);
+                w.icon_reload.set_sensitive(false);
+                w.image_input.set_from_icon_name(Some("content-loading-symbolic"), IconSize::Button);
+
+                glib::spawn_future(IconParser::html_notify(tx, url));
+
+                glib::spawn_future_local(clone!(
+                    #[strong]
+                    w,
+                    #[strong]
+                    state,
+                    async move {
+                        match rx.recv().await {
+                            Ok(Ok(icon)) => {
+                                if let Err(e) = AddGroupWindow::write_tmp_icon(&state, &w.icon_filename, &w.image_input, icon.content.as_slice()) {
+                                    warn!("Could not write temporary icon: {:?}", e);
+                                    w.icon_error.set_label(e.message().as_str());
+                                    w.icon_error.set_visible(true);
+                                }
+                            }
+                            Ok(Err(e)) => {
+                                w.icon_error.set_label(format!("{}", e).as_str());
+                                w.icon_error.set_visible(true);
+                            }
+                            Err(e) => warn!("Channel is closed. Application terminated?: {:?}", e),
+                        }
+
+                        w.icon_reload.set_sensitive(true);
+                        w.save_button.set_sensitive(true);
+                    }
+                ));
+            }
+        ));
+
+        self.icon_delete.connect_clicked(clone!(
+            #[strong(rename_to = w)]
+            self,
+            move |_| {
+                w.icon_filename.set_label("");
+
+                w.icon_error.set_label("");
+                w.icon_error.set_visible(false);
+
+                w.image_input.set_from_icon_name(Some("content-loading-symbolic"), IconSize::Button);
+            }
+        ));
+    }
+
+    fn use_brand_icon(&self, state: &RefCell<State>, icon: &BrandIcon) {
+        self.icon_error.set_label("");
+        self.icon_error.set_visible(false);
+
+        let written = BrandIcons::content(icon)
+            .map_err(|e| {
+                warn!("Could not load brand icon {}: {:?}", icon.slug, e);
+                gettext("Could not load the brand icon")
+            })
+            .and_then(|icon| AddGroupWindow::write_tmp_icon(state, &self.icon_filename, &self.image_input, icon.content.as_slice()).map_err(|e| e.message()));
+
+        if let Err(e) = written {
+            warn!("Could not use brand icon {}: {}", icon.slug, e);
+            self.icon_error.set_label(e.as_str());
+            self.icon_error.set_visible(true);
+        }
     }
 
     pub fn edit_account_buttons_actions(&self, gui: &MainWindow, connection: Arc<Mutex<Connection>>) {
         self.qrcode_action();
+        self.icon_actions(gui.state.clone());
+
+        self.qr_camera_button.connect_clicked(clone!(
+            #[strong(rename_to = w)]
+            self,
+            #[strong]
+            gui,
+            #[strong]
+            connection,
+            move |_| w.scan_camera(&gui, connection.clone())
+        ));
+
+        self.qr_paste_button.connect_clicked(clone!(
+            #[strong(rename_to = w)]
+            self,
+            #[strong]
+            gui,
+            #[strong]
+            connection,
+            move |_| w.paste(&gui, connection.clone())
+        ));
+
+        self.input_type.connect_changed(clone!(
+            #[strong(rename_to = w)]
+            self,
+            move |_| {
+                w.show_pin();
+                w.check_secret();
+            }
+        ));
+
+        self.input_pin.connect_changed(clone!(
+            #[strong(rename_to = w)]
+            self,
+            move |_| w.check_secret()
+        ));
+
+        if let Some(buffer) = self.input_secret.buffer() {
+            buffer.connect_changed(clone!(
+                #[strong(rename_to = w)]
+                self,
+                move |_| w.check_secret()
+            ));
+        }
 
         let edit_account = self.clone();
 
@@ -289,7 +735,7 @@ impl EditAccountWindow {
                     connection,
                     async move {
                         if let Ok(()) = edit_account.validate(connection).await {
-                            tx.send(()).await.expect("Could not send unit value.")
+                            let _ = tx.send(()).await;
                         }
                     }
                 ));
@@ -308,28 +754,51 @@ impl EditAccountWindow {
                             let account_id = edit_account.input_account_id.clone();
                             let group = edit_account.input_group.clone();
                             let name: String = name.buffer().text();
-                            let group_id: u32 = group.active_id().unwrap().as_str().parse().unwrap();
-                            let secret: String = {
-                                let buffer = secret.buffer().unwrap();
-                                let (start, end) = buffer.bounds();
-                                match buffer.slice(&start, &end, true) {
-                                    Some(secret_value) => secret_value.to_string(),
-                                    None => "".to_owned(),
-                                }
-                            };
+                            // validate() has already checked the selected group id parses
+                            let group_id: u32 = group.active_id().and_then(|id| id.as_str().parse().ok()).unwrap_or_default();
+                            let secret: String = secret
+                                .buffer()
+                                .and_then(|buffer| {
+                                    let (start, end) = buffer.bounds();
+                                    buffer.slice(&start, &end, true)
+                                })
+                                .map(|secret_value| secret_value.to_string())
+                                .unwrap_or_default();
 
                             let filter = gui.accounts_window.get_filter_value();
                             let account_id = account_id.buffer().text();
+                            let account_type = edit_account.account_type();
+                            // a PIN left over from another account type is dropped
+                            let pin = if account_type.has_pin() {
+                                edit_account.input_pin.text().to_string()
+                            } else {
+                                String::new()
+                            };
+
+                            let mut draft = Account::new(0, group_id, name.as_str(), secret.as_str(), SecretType::LOCAL);
+                            draft.account_type = account_type;
+                            draft.pin = pin;
+                            draft.username = edit_account.input_username.text().trim().to_owned();
+                            draft.icon = AddGroupWindow::label_text(&edit_account.icon_filename);
+                            draft.url = Some(edit_account.input_url.buffer().text()).filter(|url| !url.is_empty());
 
-                            Self::create_account(account_id, name, secret, group_id, connection.clone()).await;
-                            gui.tx_events
-                                .send(Action::RefreshAccounts { filter })
-                                .await
-                                .expect("Could not send RefreshAccounts");
+                            match Self::create_account(account_id, draft, connection.clone()).await {
+                                Ok(()) => {
+                                    if let Err(e) = gui.tx_events.send(Action::RefreshAccounts { filter }).await {
+                                        warn!("Could not send RefreshAccounts: {:?}", e);
+                                    }
 
-                            edit_account.reset();
+                                    edit_account.reset();
 
-                            gui.switch_to(Display::Accounts);
+                                    gui.switch_to(Display::Accounts);
+                                }
+                                Err(e) => {
+                                    // keep the form as it is so that the user can retry saving
+                                    error!("Could not save account: {:?}", e);
+                                    edit_account.icon_error.set_label(e.message().as_str());
+                                    edit_account.icon_error.set_visible(true);
+                                }
+                            }
                         };
                     }
                 ));
@@ -337,23 +806,59 @@ impl EditAccountWindow {
         ));
     }
 
-    async fn create_account(account_id: String, name: String, secret: String, group_id: u32, connection: Arc<Mutex<Connection>>) {
-        let connection = connection.lock().unwrap();
+    /**
+     * Saves the account and its secret as one unit: the database changes are
+     * rolled back if the secret cannot be stored.
+     *
+     * `draft` holds what the form sets. Existing accounts keep their secret backend and code parameters,
+     * new ones go to the selected backend.
+     */
+    async fn create_account(account_id: String, mut draft: Account, connection: Arc<Mutex<Connection>>) -> Result<(), RepositoryError> {
+        let connection = Database::lock(&connection);
+        let tx = connection.unchecked_transaction()?;
+
+        let existing = match account_id.parse() {
+            Ok(account_id) => Database::get_account(&tx, account_id)?,
+            Err(_) => None,
+        };
+
+        draft.secret_type = match existing {
+            Some(ref account) if account.secret_type != SecretType::LOCAL => account.secret_type.clone(),
+            _ => SecretStores::selected(),
+        };
 
-        let db_result: Result<u32, RepositoryError> = match account_id.parse() {
-            Ok(account_id) => {
-                let mut account = Account::new(account_id, group_id, name.as_str(), secret.as_str(), SecretType::KEYRING);
-                Database::update_account(&connection, &mut account)
+        let icon_store = IconStore::open();
+        draft.icon = draft.icon.map(|icon_filename| icon_store.commit(icon_filename.as_str())).transpose()?;
+
+        let previous_icon = match existing {
+            Some(existing) => {
+                draft.id = existing.id;
+                draft.uuid = existing.uuid;
+                draft.algorithm = existing.algorithm;
+                draft.digits = existing.digits;
+                draft.period = existing.period;
+                draft.counter = existing.counter;
+                Database::update_account(&tx, &mut draft)?;
+                // replaced or removed
+                existing.icon.filter(|previous_icon| draft.icon.as_ref() != Some(previous_icon))
             }
-            Err(_) => {
-                let mut account = Account::new(0, group_id, name.as_str(), secret.as_str(), SecretType::KEYRING);
-                Database::save_account(&connection, &mut account)
+            None => {
+                Database::save_account(&tx, &mut draft)?;
+                None
             }
         };
 
-        db_result
-            .and_then(|account_id| Keyring::upsert(name.as_str(), account_id, secret.as_str()))
-            .unwrap();
+        SecretStores::store(&draft.secret_type)?.upsert(draft.label.as_str(), draft.uuid.as_str(), draft.secret.as_str())?;
+
+        tx.commit()?;
+
+        if let Some(previous_icon) = previous_icon {
+            if let Err(e) = icon_store.release(&connection, previous_icon.as_str()) {
+                warn!("Could not delete icon {}: {:?}", previous_icon, e);
+            }
+        }
+
+        Ok(())
     }
 
     /**
@@ -1,10 +1,16 @@
+use std::sync::{Arc, Mutex};
+
 use gtk::prelude::*;
 use gtk::Builder;
 
+use crate::main_window::Action;
+
 #[derive(Clone, Debug)]
 pub struct ErrorsWindow {
     pub container: gtk::Box,
     pub error_display_message: gtk::TextBuffer,
+    pub retry_button: gtk::Button,
+    pub retry_action: Arc<Mutex<Option<Action>>>,
 }
 
 impl ErrorsWindow {
@@ -12,6 +18,22 @@ impl ErrorsWindow {
         ErrorsWindow {
             container: builder.object("errors").unwrap(),
             error_display_message: builder.object("error_display_message").unwrap(),
+            retry_button: builder.object("error_display_retry").unwrap(),
+            retry_action: Arc::new(Mutex::new(None)),
         }
     }
+
+    pub fn set_retry_action(&self, action: Action) {
+        let mut retry_action = self.retry_action.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        *retry_action = Some(action);
+    }
+
+    /**
+     * Returns the action to replay when the user clicks "Retry", leaving none behind
+     * so that a stale action is never replayed twice.
+     */
+    pub fn take_retry_action(&self) -> Option<Action> {
+        let mut retry_action = self.retry_action.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        retry_action.take()
+    }
 }
@@ -0,0 +1,103 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use gettextrs::gettext;
+use glib::clone;
+use gtk::prelude::*;
+use gtk::Builder;
+use log::{error, info};
+use rusqlite::Connection;
+
+use crate::helpers::{Database, IconStore};
+use crate::main_window::MainWindow;
+use crate::NAMESPACE_PREFIX;
+
+#[derive(Clone, Debug)]
+pub struct IconCacheWindow {
+    pub popup: gtk::Window,
+    pub size: gtk::Label,
+    pub status: gtk::Label,
+    pub clear: gtk::Button,
+    pub close: gtk::Button,
+}
+
+impl IconCacheWindow {
+    pub fn new(builder: &Builder) -> IconCacheWindow {
+        IconCacheWindow {
+            popup: builder.object("icon_cache_popup").unwrap(),
+            size: builder.object("icon_cache_size").unwrap(),
+            status: builder.object("icon_cache_status").unwrap(),
+            clear: builder.object("icon_cache_clear").unwrap(),
+            close: builder.object("icon_cache_close").unwrap(),
+        }
+    }
+
+    pub fn show(gui: &MainWindow, connection: Arc<Mutex<Connection>>) {
+        let builder = Builder::from_resource(format!("{}/{}", NAMESPACE_PREFIX, "icon_cache.ui").as_str());
+        let window = IconCacheWindow::new(&builder);
+
+        window.popup.set_transient_for(Some(&gui.window));
+
+        window.close.connect_clicked(clone!(
+            #[strong(rename_to = popup)]
+            window.popup,
+            move |_| popup.close()
+        ));
+
+        // groups and accounts lose their icons: the first click only asks for confirmation
+        let confirming = Rc::new(Cell::new(false));
+
+        window.clear.connect_clicked(clone!(
+            #[strong]
+            window,
+            #[strong]
+            gui,
+            move |_| {
+                if confirming.replace(true) {
+                    confirming.set(false);
+                    window.clear_icons(&gui, connection.clone());
+                } else {
+                    window.status.set_label(&gettext(
+                        "Every group and account will lose its icon, bar the brand icons bundled with the application. Click again to confirm.",
+                    ));
+                }
+            }
+        ));
+
+        window.refresh();
+        window.popup.show();
+    }
+
+    fn refresh(&self) {
+        match IconStore::open().size() {
+            Ok(size) => {
+                let label = format!("{} {}, {}", size.icons, gettext("icons"), glib::format_size(size.bytes));
+                self.size.set_label(label.as_str());
+                self.clear.set_sensitive(size.icons > 0 || size.bytes > 0);
+            }
+            Err(e) => {
+                error!("Could not measure icons: {:?}", e);
+                self.size.set_label(e.message().as_str());
+            }
+        }
+    }
+
+    fn clear_icons(&self, gui: &MainWindow, connection: Arc<Mutex<Connection>>) {
+        let cleared = IconStore::open().clear(&Database::lock(&connection));
+
+        match cleared {
+            Ok(deleted) => {
+                info!("Deleted {} icons", deleted);
+                self.status.set_label(&gettext("Icons cleared"));
+                gui.accounts_window.refresh_accounts(gui);
+            }
+            Err(e) => {
+                error!("Could not clear icons: {:?}", e);
+                self.status.set_label(e.message().as_str());
+            }
+        }
+
+        self.refresh();
+    }
+}
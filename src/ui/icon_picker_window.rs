@@ -0,0 +1,114 @@
+use std::rc::Rc;
+
+use gettextrs::gettext;
+use glib::clone;
+use gtk::prelude::*;
+use gtk::Builder;
+use log::warn;
+
+use crate::helpers::{BrandIcon, BrandIcons};
+use crate::NAMESPACE_PREFIX;
+
+/// Icons shown at once: rendering the whole library would take seconds.
+const MAX_ICONS: usize = 60;
+
+#[derive(Clone, Debug)]
+pub struct IconPickerWindow {
+    pub popup: gtk::Window,
+    pub search: gtk::SearchEntry,
+    pub empty: gtk::Label,
+    pub icons: gtk::FlowBox,
+    pub close: gtk::Button,
+}
+
+impl IconPickerWindow {
+    pub fn new(builder: &Builder) -> IconPickerWindow {
+        IconPickerWindow {
+            popup: builder.object("icon_picker_popup").unwrap(),
+            search: builder.object("icon_picker_search").unwrap(),
+            empty: builder.object("icon_picker_empty").unwrap(),
+            icons: builder.object("icon_picker_icons").unwrap(),
+            close: builder.object("icon_picker_close").unwrap(),
+        }
+    }
+
+    /**
+     * Lets the user pick one of the bundled brand icons, searching from `query`, e.g. the group name.
+     */
+    pub fn show(parent: Option<&gtk::Window>, query: &str, on_pick: Rc<dyn Fn(&'static BrandIcon)>) {
+        let builder = Builder::from_resource(format!("{}/{}", NAMESPACE_PREFIX, "icon_picker.ui").as_str());
+        let window = IconPickerWindow::new(&builder);
+
+        window.popup.set_transient_for(parent);
+
+        window.close.connect_clicked(clone!(
+            #[strong(rename_to = popup)]
+            window.popup,
+            move |_| popup.close()
+        ));
+
+        window.search.connect_search_changed(clone!(
+            #[strong]
+            window,
+            #[strong]
+            on_pick,
+            move |search| window.fill(search.text().as_str(), on_pick.clone())
+        ));
+
+        window.search.set_text(query);
+        window.fill(query, on_pick);
+
+        window.popup.show();
+    }
+
+    fn fill(&self, query: &str, on_pick: Rc<dyn Fn(&'static BrandIcon)>) {
+        self.icons.children().iter().for_each(|child| self.icons.remove(child));
+
+        let brand_icons = BrandIcons::get();
+        let found = brand_icons.search(query, MAX_ICONS);
+
+        let empty = if brand_icons.is_empty() {
+            Some(gettext("This build comes without brand icons"))
+        } else if found.is_empty() {
+            Some(gettext("No brand icon found"))
+        } else {
+            None
+        };
+
+        self.empty.set_label(empty.as_deref().unwrap_or_default());
+        self.empty.set_visible(empty.is_some());
+
+        for icon in found {
+            self.icons.add(&self.icon_button(icon, on_pick.clone()));
+        }
+
+        self.icons.show_all();
+    }
+
+    fn icon_button(&self, icon: &'static BrandIcon, on_pick: Rc<dyn Fn(&'static BrandIcon)>) -> gtk::Button {
+        let image = match BrandIcons::load_icon(icon, 32) {
+            Ok(pixbuf) => gtk::Image::from_pixbuf(Some(&pixbuf)),
+            Err(e) => {
+                warn!("Could not load brand icon {}: {:?}", icon.slug, e);
+                gtk::Image::from_icon_name(Some("image-missing"), gtk::IconSize::Dnd)
+            }
+        };
+
+        let button = gtk::Button::builder()
+            .image(&image)
+            .tooltip_text(icon.title.as_str())
+            .relief(gtk::ReliefStyle::None)
+            .build();
+
+        button.connect_clicked(clone!(
+            #[strong(rename_to = popup)]
+            self.popup,
+            move |_| {
+                on_pick(icon);
+                popup.close();
+            }
+        ));
+
+        button
+    }
+}
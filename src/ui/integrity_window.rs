@@ -0,0 +1,238 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use gettextrs::gettext;
+use glib::clone;
+use gtk::prelude::*;
+use gtk::Builder;
+use gtk_macros::get_widget;
+use log::{error, warn};
+use rusqlite::Connection;
+
+use crate::helpers::{Database, Integrity, IntegrityIssue, IntegrityReport, RepositoryError};
+use crate::main_window::MainWindow;
+use crate::NAMESPACE_PREFIX;
+
+pub type IntegrityCheckResult = Result<IntegrityReport, RepositoryError>;
+pub type IntegrityRepairResult = Result<(), RepositoryError>;
+
+#[derive(Clone, Debug)]
+pub struct IntegrityWindow {
+    pub popup: gtk::Window,
+    pub summary: gtk::Label,
+    pub issues: gtk::Box,
+    pub repair_all: gtk::Button,
+    pub close: gtk::Button,
+}
+
+impl IntegrityWindow {
+    pub fn new(builder: &Builder) -> IntegrityWindow {
+        IntegrityWindow {
+            popup: builder.object("integrity_popup").unwrap(),
+            summary: builder.object("integrity_summary").unwrap(),
+            issues: builder.object("integrity_issues").unwrap(),
+            repair_all: builder.object("integrity_repair_all").unwrap(),
+            close: builder.object("integrity_close").unwrap(),
+        }
+    }
+
+    /**
+     * Opens the integrity popup and runs a first check of the keyring against the accounts table.
+     */
+    pub fn show(gui: &MainWindow, connection: Arc<Mutex<Connection>>) {
+        let builder = Builder::from_resource(format!("{}/{}", NAMESPACE_PREFIX, "integrity.ui").as_str());
+        let integrity = IntegrityWindow::new(&builder);
+
+        integrity.popup.set_transient_for(Some(&gui.window));
+
+        integrity.close.connect_clicked(clone!(
+            #[strong(rename_to = popup)]
+            integrity.popup,
+            move |_| popup.close()
+        ));
+
+        integrity.repair_all.connect_clicked(clone!(
+            #[strong]
+            integrity,
+            #[strong]
+            gui,
+            #[strong]
+            connection,
+            move |_| {
+                integrity.repair_all.set_sensitive(false);
+                integrity.summary.set_label(&gettext("Repairing keyring…"));
+
+                let (tx, rx) = async_channel::bounded::<IntegrityRepairResult>(1);
+
+                glib::spawn_future(clone!(
+                    #[strong]
+                    connection,
+                    async move {
+                        let result = {
+                            let connection = Database::lock(&connection);
+                            Integrity::scan(&connection).and_then(|report| Integrity::repair_all(&connection, &report).map(|_| ()))
+                        };
+
+                        if let Err(e) = tx.send(result).await {
+                            warn!("Could not send repair result: {:?}", e);
+                        }
+                    }
+                ));
+
+                glib::spawn_future_local(clone!(
+                    #[strong]
+                    integrity,
+                    #[strong]
+                    gui,
+                    #[strong]
+                    connection,
+                    async move {
+                        match rx.recv().await {
+                            Ok(Ok(())) => {}
+                            Ok(Err(e)) => error!("Could not repair keyring: {:?}", e),
+                            Err(e) => warn!("Channel is closed. Application terminated?: {:?}", e),
+                        }
+
+                        gui.accounts_window.refresh_accounts(&gui);
+                        integrity.check(&gui, connection);
+                    }
+                ));
+            }
+        ));
+
+        integrity.popup.show();
+        integrity.check(gui, connection);
+    }
+
+    fn check(&self, gui: &MainWindow, connection: Arc<Mutex<Connection>>) {
+        let (tx, rx) = async_channel::bounded::<IntegrityCheckResult>(1);
+
+        self.summary.set_label(&gettext("Checking keyring…"));
+
+        glib::spawn_future(clone!(
+            #[strong]
+            connection,
+            async move {
+                let report = {
+                    let connection = Database::lock(&connection);
+                    Integrity::scan(&connection)
+                };
+
+                if let Err(e) = tx.send(report).await {
+                    warn!("Could not send integrity report: {:?}", e);
+                }
+            }
+        ));
+
+        glib::spawn_future_local(clone!(
+            #[strong(rename_to = integrity)]
+            self,
+            #[strong]
+            gui,
+            async move {
+                match rx.recv().await {
+                    Ok(Ok(report)) => integrity.display(&gui, connection, report),
+                    Ok(Err(e)) => integrity.summary.set_label(e.message().as_str()),
+                    Err(e) => warn!("Channel is closed. Application terminated?: {:?}", e),
+                }
+            }
+        ));
+    }
+
+    fn display(&self, gui: &MainWindow, connection: Arc<Mutex<Connection>>, report: IntegrityReport) {
+        self.issues.foreach(|w| self.issues.remove(w));
+
+        let counts = format!("{} {}, {} {}", report.accounts, gettext("accounts"), report.items, gettext("keyring items"));
+
+        if report.issues.is_empty() {
+            self.summary.set_label(format!("{} ({})", gettext("No issues found"), counts).as_str());
+        } else {
+            self.summary
+                .set_label(format!("{} {} ({})", report.issues.len(), gettext("issue(s) found"), counts).as_str());
+        }
+
+        self.repair_all.set_sensitive(report.issues.iter().any(|issue| issue.is_safe_to_repair()));
+
+        for issue in report.issues {
+            let builder = Builder::from_resource(format!("{}/{}", NAMESPACE_PREFIX, "integrity_issue.ui").as_str());
+            get_widget!(builder, gtk::Box, integrity_issue);
+            get_widget!(builder, gtk::Label, integrity_issue_description);
+            get_widget!(builder, gtk::Button, integrity_issue_repair);
+
+            integrity_issue_description.set_label(issue.to_string().as_str());
+            integrity_issue_repair.set_label(issue.repair_label().as_str());
+
+            // the account and its secret are gone for good: the first click only asks for confirmation
+            let confirming = Rc::new(Cell::new(false));
+
+            integrity_issue_repair.connect_clicked(clone!(
+                #[strong]
+                gui,
+                #[strong]
+                connection,
+                #[strong]
+                integrity_issue,
+                #[strong]
+                integrity_issue_description,
+                move |button| {
+                    if matches!(issue, IntegrityIssue::MissingSecret { .. }) && !confirming.replace(true) {
+                        let warning = gettext("Deleting the account cannot be undone. Click again to confirm.");
+                        integrity_issue_description.set_label(format!("{}\n{}", issue, warning).as_str());
+                        return;
+                    }
+
+                    confirming.set(false);
+                    button.set_sensitive(false);
+
+                    let (tx, rx) = async_channel::bounded::<IntegrityRepairResult>(1);
+
+                    glib::spawn_future(clone!(
+                        #[strong]
+                        connection,
+                        #[strong]
+                        issue,
+                        async move {
+                            let result = {
+                                let connection = Database::lock(&connection);
+                                Integrity::repair(&connection, &issue)
+                            };
+
+                            if let Err(e) = tx.send(result).await {
+                                warn!("Could not send repair result: {:?}", e);
+                            }
+                        }
+                    ));
+
+                    glib::spawn_future_local(clone!(
+                        #[strong]
+                        gui,
+                        #[strong]
+                        issue,
+                        #[strong]
+                        integrity_issue,
+                        #[strong]
+                        integrity_issue_description,
+                        #[strong]
+                        button,
+                        async move {
+                            match rx.recv().await {
+                                Ok(Ok(())) => {
+                                    integrity_issue.set_sensitive(false);
+                                    gui.accounts_window.refresh_accounts(&gui);
+                                }
+                                Ok(Err(e)) => {
+                                    integrity_issue_description.set_label(format!("{}: {}", issue, e.message()).as_str());
+                                    button.set_sensitive(true);
+                                }
+                                Err(e) => warn!("Channel is closed. Application terminated?: {:?}", e),
+                            }
+                        }
+                    ));
+                }
+            ));
+
+            self.issues.add(&integrity_issue);
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use std::sync::{Arc, Mutex};
+
+use gettextrs::gettext;
+use glib::clone;
+use gtk::prelude::*;
+use gtk::Builder;
+use log::{error, info, warn};
+use rusqlite::Connection;
+
+use crate::helpers::{Database, LocalBackup, LocalBackups, RepositoryError};
+use crate::main_window::MainWindow;
+use crate::NAMESPACE_PREFIX;
+
+type RestoreResult = Result<usize, RepositoryError>;
+
+#[derive(Clone, Debug)]
+pub struct LocalBackupsWindow {
+    pub popup: gtk::Window,
+    pub list: gtk::ListBox,
+    pub status: gtk::Label,
+    pub close: gtk::Button,
+}
+
+impl LocalBackupsWindow {
+    pub fn new(builder: &Builder) -> LocalBackupsWindow {
+        LocalBackupsWindow {
+            popup: builder.object("local_backups_popup").unwrap(),
+            list: builder.object("local_backups_list").unwrap(),
+            status: builder.object("local_backups_status").unwrap(),
+            close: builder.object("local_backups_close").unwrap(),
+        }
+    }
+
+    pub fn show(gui: &MainWindow, connection: Arc<Mutex<Connection>>) {
+        let builder = Builder::from_resource(format!("{}/{}", NAMESPACE_PREFIX, "local_backups.ui").as_str());
+        let window = LocalBackupsWindow::new(&builder);
+
+        window.popup.set_transient_for(Some(&gui.window));
+
+        window.close.connect_clicked(clone!(
+            #[strong(rename_to = popup)]
+            window.popup,
+            move |_| popup.close()
+        ));
+
+        window.display(gui, connection);
+        window.popup.show();
+    }
+
+    fn display(&self, gui: &MainWindow, connection: Arc<Mutex<Connection>>) {
+        self.list.foreach(|row| self.list.remove(row));
+
+        let backups = match LocalBackups::open().list() {
+            Ok(backups) => backups,
+            Err(e) => {
+                error!("Could not list backups: {:?}", e);
+                self.status.set_label(e.message().as_str());
+                return;
+            }
+        };
+
+        if backups.is_empty() {
+            self.status.set_label(&gettext("No automatic backups yet"));
+        }
+
+        for backup in backups {
+            self.list.add(&self.row(gui, connection.clone(), backup));
+        }
+
+        self.list.show_all();
+    }
+
+    fn row(&self, gui: &MainWindow, connection: Arc<Mutex<Connection>>, backup: LocalBackup) -> gtk::Box {
+        let mut counts = format!("{} {}, {} {}", backup.accounts, gettext("accounts"), backup.groups, gettext("groups"));
+
+        if !backup.secrets {
+            counts = format!("{} ({})", counts, gettext("secrets not included"));
+        }
+
+        let description = gtk::Label::builder()
+            .label(format!("{} - {}\n{}", backup.taken_label(), backup.reason.label(), counts))
+            .xalign(0.0)
+            .hexpand(true)
+            .build();
+
+        let restore = gtk::Button::with_label(&gettext("Restore"));
+
+        restore.connect_clicked(clone!(
+            #[strong(rename_to = window)]
+            self,
+            #[strong]
+            gui,
+            move |_| window.restore(&gui, connection.clone(), backup.clone())
+        ));
+
+        let row = gtk::Box::builder()
+            .orientation(gtk::Orientation::Horizontal)
+            .spacing(10)
+            .margin_start(5)
+            .margin_end(5)
+            .margin_top(5)
+            .margin_bottom(5)
+            .build();
+
+        row.add(&description);
+        row.add(&restore);
+        row
+    }
+
+    fn restore(&self, gui: &MainWindow, connection: Arc<Mutex<Connection>>, backup: LocalBackup) {
+        self.list.set_sensitive(false);
+        self.status.set_label(&gettext("Restoring…"));
+
+        let (tx, rx) = async_channel::bounded::<RestoreResult>(1);
+
+        glib::spawn_future(clone!(
+            #[strong]
+            connection,
+            async move {
+                let result = LocalBackups::open().restore(&mut Database::lock(&connection), &backup);
+
+                if let Err(e) = tx.send(result).await {
+                    warn!("Could not send restore result: {:?}", e);
+                }
+            }
+        ));
+
+        glib::spawn_future_local(clone!(
+            #[strong(rename_to = window)]
+            self,
+            #[strong]
+            gui,
+            async move {
+                match rx.recv().await {
+                    Ok(Ok(secrets)) => {
+                        info!("Backup restored, along with {} secrets", secrets);
+                        window.status.set_label(&gettext("Backup restored"));
+                        gui.accounts_window.refresh_accounts(&gui);
+                    }
+                    Ok(Err(e)) => {
+                        error!("Could not restore backup: {:?}", e);
+                        window.status.set_label(e.message().as_str());
+                    }
+                    Err(e) => warn!("Channel is closed. Application terminated?: {:?}", e),
+                }
+
+                window.list.set_sensitive(true);
+                window.display(&gui, connection);
+            }
+        ));
+    }
+}
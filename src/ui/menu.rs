@@ -1,16 +1,19 @@
 use gettextrs::gettext;
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 
-use gio::prelude::*;
+use gio::prelude::SettingsExt;
 use glib::clone;
 use gtk::prelude::*;
-use gtk::{Builder, Button, MenuButton, PopoverMenu};
+use gtk::{Builder, Button, ComboBoxText, MenuButton, PopoverMenu};
 use gtk_macros::get_widget;
+use log::{error, info, warn};
 use rusqlite::Connection;
 
 use crate::exporting::{Exporting, ImportType};
-use crate::main_window::{Display, MainWindow};
-use crate::ui::{AccountsWindow, AddGroupWindow};
+use crate::helpers::{Database, Pasted, Profiles, RepositoryError, SecretStores, SecretType, Vault};
+use crate::main_window::{Action, Display, MainWindow};
+use crate::ui::{AccountsWindow, AddGroupWindow, ClockWindow, IconCacheWindow, IntegrityWindow, LocalBackupsWindow, SyncWindow, VaultWindow, WebDavWindow};
 use crate::{NAMESPACE, NAMESPACE_PREFIX};
 
 pub trait Menus {
@@ -18,9 +21,13 @@ pub trait Menus {
 
     fn build_search_button(&self, connection: Arc<Mutex<Connection>>) -> Button;
 
+    fn build_profile_switcher(&self, connection: Arc<Mutex<Connection>>) -> ComboBoxText;
+
     fn build_system_menu(&self, connection: Arc<Mutex<Connection>>) -> MenuButton;
 
     fn build_action_menu(&self, connection: Arc<Mutex<Connection>>) -> MenuButton;
+
+    fn switch_secret_backend(&self, to: SecretType, combo: ComboBoxText, connection: Arc<Mutex<Connection>>);
 }
 
 impl Menus for MainWindow {
@@ -31,7 +38,9 @@ impl Menus for MainWindow {
 
         title_bar.pack_start(&self.build_search_button(connection.clone()));
 
-        title_bar.pack_end(&self.build_system_menu(connection));
+        title_bar.pack_end(&self.build_system_menu(connection.clone()));
+
+        title_bar.pack_end(&self.build_profile_switcher(connection));
         self.window.set_titlebar(Some(&title_bar));
 
         title_bar.show_all();
@@ -77,14 +86,82 @@ impl Menus for MainWindow {
         search_button
     }
 
+    fn build_profile_switcher(&self, connection: Arc<Mutex<Connection>>) -> ComboBoxText {
+        let builder = Builder::from_resource(format!("{}/{}", NAMESPACE_PREFIX, "system_menu.ui").as_str());
+        get_widget!(builder, ComboBoxText, profile_combo);
+        get_widget!(builder, gtk::Entry, profile_entry);
+
+        let fill = |combo: &ComboBoxText| {
+            combo.remove_all();
+
+            match Profiles::list() {
+                Ok(profiles) => profiles.iter().for_each(|profile| combo.append(Some(profile), profile)),
+                Err(e) => warn!("Could not list profiles: {:?}", e),
+            }
+
+            combo.set_active_id(Some(Profiles::current().as_str()));
+        };
+
+        fill(&profile_combo);
+
+        // picking an existing profile
+        profile_combo.connect_changed(clone!(
+            #[strong(rename_to = gui)]
+            self,
+            #[strong]
+            connection,
+            move |combo| {
+                match combo.active_id() {
+                    Some(profile) if profile != Profiles::current() => gui.switch_profile(profile.as_str(), connection.clone()),
+                    _ => {}
+                }
+            }
+        ));
+
+        // typing the name of a new one
+        profile_entry.connect_activate(clone!(
+            #[strong(rename_to = gui)]
+            self,
+            #[strong]
+            profile_combo,
+            move |entry| {
+                let name = entry.text().trim().to_owned();
+
+                match Profiles::create(name.as_str()) {
+                    Ok(()) => {
+                        entry.set_icon_from_icon_name(gtk::EntryIconPosition::Secondary, None);
+                        gui.switch_profile(name.as_str(), connection.clone());
+                        fill(&profile_combo);
+                    }
+                    Err(e) => {
+                        warn!("Could not create profile {}: {:?}", name, e);
+                        entry.set_icon_from_icon_name(gtk::EntryIconPosition::Secondary, Some("dialog-error-symbolic"));
+                        entry.set_icon_tooltip_text(gtk::EntryIconPosition::Secondary, Some(e.message().as_str()));
+                    }
+                }
+            }
+        ));
+
+        profile_combo
+    }
+
     fn build_system_menu(&self, connection: Arc<Mutex<Connection>>) -> MenuButton {
         let builder = Builder::from_resource(format!("{}/{}", NAMESPACE_PREFIX, "system_menu.ui").as_str());
 
         get_widget!(builder, PopoverMenu, popover);
         get_widget!(builder, Button, about_button);
         get_widget!(builder, Button, export_button);
+        get_widget!(builder, Button, integrity_button);
+        get_widget!(builder, Button, sync_button);
+        get_widget!(builder, Button, webdav_button);
+        get_widget!(builder, Button, local_backups_button);
+        get_widget!(builder, Button, clock_button);
+        get_widget!(builder, Button, icon_cache_button);
+        get_widget!(builder, ComboBoxText, secret_backend_combo);
+        get_widget!(builder, ComboBoxText, copy_policy_combo);
         get_widget!(builder, Button, import_button_yaml);
         get_widget!(builder, Button, import_button_ga);
+        get_widget!(builder, Button, import_button_steam);
         get_widget!(builder, MenuButton, system_menu);
 
         let dark_mode_slider: gtk::Switch = {
@@ -112,7 +189,148 @@ impl Menus for MainWindow {
         export_button.connect_clicked(self.export_accounts(popover.clone(), connection.clone()));
 
         import_button_yaml.connect_clicked(self.import_accounts(ImportType::Internal, popover.clone(), connection.clone()));
-        import_button_ga.connect_clicked(self.import_accounts(ImportType::GoogleAuthenticator, popover.clone(), connection));
+        import_button_ga.connect_clicked(self.import_accounts(ImportType::GoogleAuthenticator, popover.clone(), connection.clone()));
+        import_button_steam.connect_clicked(self.import_accounts(ImportType::Steam, popover.clone(), connection.clone()));
+
+        integrity_button.connect_clicked(clone!(
+            #[strong(rename_to = gui)]
+            self,
+            #[strong]
+            popover,
+            #[strong]
+            connection,
+            move |_| {
+                popover.set_visible(false);
+                IntegrityWindow::show(&gui, connection.clone());
+            }
+        ));
+
+        sync_button.connect_clicked(clone!(
+            #[strong(rename_to = gui)]
+            self,
+            #[strong]
+            popover,
+            #[strong]
+            connection,
+            move |_| {
+                popover.set_visible(false);
+                SyncWindow::show(&gui, connection.clone(), None);
+            }
+        ));
+
+        webdav_button.connect_clicked(clone!(
+            #[strong(rename_to = gui)]
+            self,
+            #[strong]
+            popover,
+            #[strong]
+            connection,
+            move |_| {
+                popover.set_visible(false);
+                WebDavWindow::show(&gui, connection.clone());
+            }
+        ));
+
+        local_backups_button.connect_clicked(clone!(
+            #[strong(rename_to = gui)]
+            self,
+            #[strong]
+            popover,
+            #[strong]
+            connection,
+            move |_| {
+                popover.set_visible(false);
+                LocalBackupsWindow::show(&gui, connection.clone());
+            }
+        ));
+
+        clock_button.connect_clicked(clone!(
+            #[strong(rename_to = gui)]
+            self,
+            #[strong]
+            popover,
+            move |_| {
+                popover.set_visible(false);
+                ClockWindow::show(&gui);
+            }
+        ));
+
+        icon_cache_button.connect_clicked(clone!(
+            #[strong(rename_to = gui)]
+            self,
+            #[strong]
+            popover,
+            #[strong]
+            connection,
+            move |_| {
+                popover.set_visible(false);
+                IconCacheWindow::show(&gui, connection.clone());
+            }
+        ));
+
+        secret_backend_combo.set_active_id(Some(SecretStores::selected().to_string().as_str()));
+
+        secret_backend_combo.connect_changed(clone!(
+            #[strong(rename_to = gui)]
+            self,
+            #[strong]
+            popover,
+            move |combo| {
+                let Some(to) = combo.active_id().and_then(|id| SecretType::from_str(id.as_str()).ok()) else {
+                    return;
+                };
+
+                // also reached when the selection is reverted below
+                if to == SecretStores::selected() {
+                    return;
+                }
+
+                popover.set_visible(false);
+
+                if to == SecretType::VAULT && !Vault::is_unlocked() {
+                    VaultWindow::prompt(
+                        &gui,
+                        clone!(
+                            #[strong]
+                            gui,
+                            #[strong]
+                            combo,
+                            #[strong]
+                            connection,
+                            move |unlocked| {
+                                if unlocked {
+                                    gui.switch_secret_backend(SecretType::VAULT, combo.clone(), connection.clone());
+                                } else {
+                                    combo.set_active_id(Some(SecretStores::selected().to_string().as_str()));
+                                }
+                            }
+                        ),
+                    );
+                } else {
+                    gui.switch_secret_backend(to, combo.clone(), connection.clone());
+                }
+            }
+        ));
+
+        copy_policy_combo.set_active_id(Some(gio::Settings::new(NAMESPACE).string("copy-near-expiry").as_str()));
+
+        copy_policy_combo.connect_changed(clone!(
+            #[strong(rename_to = gui)]
+            self,
+            move |combo| {
+                let Some(policy) = combo.active_id() else {
+                    return;
+                };
+
+                let g_settings = gio::Settings::new(NAMESPACE);
+                g_settings
+                    .set_string("copy-near-expiry", policy.as_str())
+                    .expect("Could not find setting copy-near-expiry");
+
+                // copy buttons read the policy when accounts are drawn
+                gui.accounts_window.refresh_accounts(&gui);
+            }
+        ));
 
         system_menu.connect_clicked(clone!(
             #[strong]
@@ -143,6 +361,7 @@ impl Menus for MainWindow {
         let builder = Builder::from_resource(format!("{}/{}", NAMESPACE_PREFIX, "action_menu.ui").as_str());
         get_widget!(builder, PopoverMenu, popover);
         get_widget!(builder, Button, add_account_button);
+        get_widget!(builder, Button, paste_account_button);
         get_widget!(builder, Button, add_group_button);
         get_widget!(builder, MenuButton, action_menu);
 
@@ -175,10 +394,12 @@ impl Menus for MainWindow {
             self.state,
             #[strong]
             add_account_button,
+            #[strong]
+            paste_account_button,
             #[strong(rename_to = widgets)]
             self.accounts_window.widgets,
             move |_| {
-                let widgets = widgets.lock().unwrap();
+                let widgets = widgets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
 
                 /*
                  * Both add group and account buttons are available only if on
@@ -192,6 +413,7 @@ impl Menus for MainWindow {
                 let display = state.display.clone();
                 // can't add account if no groups
                 add_account_button.set_sensitive(!widgets.is_empty() && display == Display::Accounts);
+                paste_account_button.set_sensitive(!widgets.is_empty() && display == Display::Accounts);
 
                 add_group_button.set_sensitive(display == Display::Accounts || display == Display::NoAccounts);
 
@@ -209,8 +431,82 @@ impl Menus for MainWindow {
             }
         ));
 
-        add_account_button.connect_clicked(self.accounts_window.display_add_account_form(connection, &popover, self, None));
+        add_account_button.connect_clicked(self.accounts_window.display_add_account_form(connection.clone(), &popover, self, None));
+
+        paste_account_button.connect_clicked(clone!(
+            #[strong]
+            popover,
+            #[strong(rename_to = gui)]
+            self,
+            move |_| {
+                popover.hide();
+
+                glib::spawn_future_local(clone!(
+                    #[strong]
+                    gui,
+                    #[strong]
+                    connection,
+                    async move {
+                        let pasted = Pasted::read().await;
+                        gui.accounts_window.add_account(connection, &gui, None, Some(pasted));
+                    }
+                ));
+            }
+        ));
 
         action_menu
     }
+
+    /**
+     * Moves every secret to the given backend, then makes it the one new secrets go to.
+     * On failure, accounts already moved stay where they are: each account records its own backend.
+     */
+    fn switch_secret_backend(&self, to: SecretType, combo: ComboBoxText, connection: Arc<Mutex<Connection>>) {
+        let (tx, rx) = async_channel::bounded::<Result<usize, RepositoryError>>(1);
+
+        combo.set_sensitive(false);
+
+        glib::spawn_future(clone!(
+            #[strong]
+            connection,
+            #[strong]
+            to,
+            async move {
+                let result = {
+                    let connection = Database::lock(&connection);
+                    SecretStores::migrate(&connection, &to).and_then(|migrated| SecretStores::select(&to).map(|_| migrated))
+                };
+
+                if let Err(e) = tx.send(result).await {
+                    warn!("Could not send migration result: {:?}", e);
+                }
+            }
+        ));
+
+        glib::spawn_future_local(clone!(
+            #[strong(rename_to = gui)]
+            self,
+            async move {
+                match rx.recv().await {
+                    Ok(Ok(migrated)) => {
+                        info!("Moved {} secrets to {}", migrated, to);
+                        gui.accounts_window.refresh_accounts(&gui);
+                    }
+                    Ok(Err(e)) => {
+                        error!("Could not move secrets to {}: {:?}", to, e);
+                        combo.set_active_id(Some(SecretStores::selected().to_string().as_str()));
+                        gui.display_error(
+                            e.message().as_str(),
+                            Action::RefreshAccounts {
+                                filter: gui.accounts_window.get_filter_value(),
+                            },
+                        );
+                    }
+                    Err(e) => warn!("Channel is closed. Application terminated?: {:?}", e),
+                }
+
+                combo.set_sensitive(true);
+            }
+        ));
+    }
 }
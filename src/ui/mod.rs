@@ -1,14 +1,36 @@
 pub use self::accounts_window::*;
 pub use self::add_group::*;
+pub use self::camera_window::*;
+pub use self::clock_window::*;
 pub use self::edit_account_window::*;
 pub use self::error_display::*;
 pub use self::form_errors::*;
+pub use self::icon_cache_window::*;
+pub use self::icon_picker_window::*;
+pub use self::integrity_window::*;
+pub use self::local_backups_window::*;
 pub use self::no_accounts::*;
+pub use self::qr_codes_window::*;
+pub use self::sync_window::*;
+pub use self::vault_window::*;
+pub use self::verify_code_window::*;
+pub use self::webdav_window::*;
 
 mod accounts_window;
 mod add_group;
+mod camera_window;
+mod clock_window;
 mod edit_account_window;
 mod error_display;
 mod form_errors;
+mod icon_cache_window;
+mod icon_picker_window;
+mod integrity_window;
+mod local_backups_window;
 pub(crate) mod menu;
 mod no_accounts;
+mod qr_codes_window;
+mod sync_window;
+mod vault_window;
+mod verify_code_window;
+mod webdav_window;
@@ -0,0 +1,78 @@
+use std::rc::Rc;
+
+use gettextrs::gettext;
+use glib::clone;
+use gtk::prelude::*;
+use gtk::Builder;
+
+use crate::helpers::QrCode;
+use crate::NAMESPACE_PREFIX;
+
+#[derive(Clone, Debug)]
+pub struct QrCodesWindow {
+    pub popup: gtk::Window,
+    pub list: gtk::ListBox,
+    pub close: gtk::Button,
+}
+
+impl QrCodesWindow {
+    pub fn new(builder: &Builder) -> QrCodesWindow {
+        QrCodesWindow {
+            popup: builder.object("qr_codes_popup").unwrap(),
+            list: builder.object("qr_codes_list").unwrap(),
+            close: builder.object("qr_codes_close").unwrap(),
+        }
+    }
+
+    /**
+     * Lets the user pick one of several QR codes found in the same image. Secrets are not shown.
+     */
+    pub fn show(parent: Option<&gtk::Window>, qr_codes: Vec<QrCode>, on_pick: Rc<dyn Fn(QrCode)>) {
+        let builder = Builder::from_resource(format!("{}/{}", NAMESPACE_PREFIX, "qr_codes.ui").as_str());
+        let window = QrCodesWindow::new(&builder);
+
+        window.popup.set_transient_for(parent);
+
+        window.close.connect_clicked(clone!(
+            #[strong(rename_to = popup)]
+            window.popup,
+            move |_| popup.close()
+        ));
+
+        for (i, qr_code) in qr_codes.into_iter().enumerate() {
+            let description = qr_code.label().unwrap_or_else(|| format!("{} {}", gettext("QR code"), i + 1));
+            window.list.add(&window.row(description.as_str(), qr_code, on_pick.clone()));
+        }
+
+        window.list.show_all();
+        window.popup.show();
+    }
+
+    fn row(&self, description: &str, qr_code: QrCode, on_pick: Rc<dyn Fn(QrCode)>) -> gtk::Box {
+        let description = gtk::Label::builder().label(description).xalign(0.0).hexpand(true).build();
+
+        let pick = gtk::Button::with_label(&gettext("Use"));
+
+        pick.connect_clicked(clone!(
+            #[strong(rename_to = popup)]
+            self.popup,
+            move |_| {
+                on_pick(qr_code.clone());
+                popup.close();
+            }
+        ));
+
+        let row = gtk::Box::builder()
+            .orientation(gtk::Orientation::Horizontal)
+            .spacing(10)
+            .margin_start(5)
+            .margin_end(5)
+            .margin_top(5)
+            .margin_bottom(5)
+            .build();
+
+        row.add(&description);
+        row.add(&pick);
+        row
+    }
+}
@@ -0,0 +1,224 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use gettextrs::gettext;
+use glib::clone;
+use gtk::prelude::*;
+use gtk::Builder;
+use log::{error, info, warn};
+use rusqlite::Connection;
+
+use crate::helpers::{Database, RepositoryError, SyncFolder, SyncReport};
+use crate::main_window::MainWindow;
+use crate::NAMESPACE_PREFIX;
+
+pub type SyncResult = Result<Option<SyncReport>, RepositoryError>;
+
+#[derive(Clone, Debug)]
+pub struct SyncWindow {
+    pub popup: gtk::Window,
+    pub folder: gtk::FileChooserButton,
+    pub passphrase: gtk::Entry,
+    pub status: gtk::Label,
+    pub conflicts: gtk::Box,
+    pub disable: gtk::Button,
+    pub close: gtk::Button,
+    pub now: gtk::Button,
+}
+
+impl SyncWindow {
+    pub fn new(builder: &Builder) -> SyncWindow {
+        SyncWindow {
+            popup: builder.object("sync_popup").unwrap(),
+            folder: builder.object("sync_folder").unwrap(),
+            passphrase: builder.object("sync_passphrase").unwrap(),
+            status: builder.object("sync_status").unwrap(),
+            conflicts: builder.object("sync_conflicts").unwrap(),
+            disable: builder.object("sync_disable").unwrap(),
+            close: builder.object("sync_close").unwrap(),
+            now: builder.object("sync_now").unwrap(),
+        }
+    }
+
+    /**
+     * Opens the sync settings, along with the outcome of the last sync when it ran into conflicts.
+     */
+    pub fn show(gui: &MainWindow, connection: Arc<Mutex<Connection>>, report: Option<SyncReport>) {
+        let builder = Builder::from_resource(format!("{}/{}", NAMESPACE_PREFIX, "sync.ui").as_str());
+        let sync = SyncWindow::new(&builder);
+
+        sync.popup.set_transient_for(Some(&gui.window));
+
+        let directory = SyncFolder::directory(&Database::lock(&connection));
+
+        match directory {
+            Ok(Some(directory)) => {
+                sync.folder.set_filename(&directory);
+                sync.set_configured(true);
+            }
+            Ok(None) => sync.set_configured(false),
+            Err(e) => {
+                warn!("Could not read sync settings: {:?}", e);
+                sync.status.set_label(e.message().as_str());
+            }
+        }
+
+        if let Some(report) = report {
+            sync.display(&report);
+        }
+
+        sync.close.connect_clicked(clone!(
+            #[strong(rename_to = popup)]
+            sync.popup,
+            move |_| popup.close()
+        ));
+
+        sync.disable.connect_clicked(clone!(
+            #[strong]
+            sync,
+            #[strong]
+            connection,
+            move |_| {
+                let result = SyncFolder::disable(&Database::lock(&connection));
+
+                match result {
+                    Ok(()) => {
+                        info!("Sync disabled");
+                        sync.folder.unselect_all();
+                        sync.conflicts.foreach(|w| sync.conflicts.remove(w));
+                        sync.set_configured(false);
+                    }
+                    Err(e) => {
+                        error!("Could not disable sync: {:?}", e);
+                        sync.status.set_label(e.message().as_str());
+                    }
+                }
+            }
+        ));
+
+        sync.now.connect_clicked(clone!(
+            #[strong]
+            sync,
+            #[strong]
+            gui,
+            move |_| sync.sync(&gui, connection.clone())
+        ));
+
+        sync.popup.show();
+    }
+
+    /**
+     * Saves the folder and passphrase when either changed, then syncs.
+     */
+    fn sync(&self, gui: &MainWindow, connection: Arc<Mutex<Connection>>) {
+        let Some(folder) = self.folder.filename() else {
+            self.status.set_label(&gettext("Choose a folder first"));
+            return;
+        };
+
+        let passphrase = self.passphrase.text().to_string();
+
+        self.now.set_sensitive(false);
+        self.status.set_label(&gettext("Syncing…"));
+
+        let (tx, rx) = async_channel::bounded::<SyncResult>(1);
+
+        glib::spawn_future(clone!(
+            #[strong]
+            connection,
+            async move {
+                let result = {
+                    let connection = Database::lock(&connection);
+                    Self::configure(&connection, folder.as_path(), passphrase.as_str()).and_then(|_| SyncFolder::sync(&connection))
+                };
+
+                if let Err(e) = tx.send(result).await {
+                    warn!("Could not send sync result: {:?}", e);
+                }
+            }
+        ));
+
+        glib::spawn_future_local(clone!(
+            #[strong(rename_to = sync)]
+            self,
+            #[strong]
+            gui,
+            async move {
+                match rx.recv().await {
+                    Ok(Ok(report)) => {
+                        sync.passphrase.set_text("");
+                        sync.set_configured(true);
+
+                        if let Some(report) = report {
+                            sync.display(&report);
+
+                            if report.received > 0 {
+                                gui.accounts_window.refresh_accounts(&gui);
+                            }
+                        }
+                    }
+                    Ok(Err(e)) => {
+                        error!("Sync failed: {:?}", e);
+                        sync.status.set_label(e.message().as_str());
+                    }
+                    Err(e) => warn!("Channel is closed. Application terminated?: {:?}", e),
+                }
+
+                sync.now.set_sensitive(true);
+            }
+        ));
+    }
+
+    /**
+     * An empty passphrase keeps the one saved for the current folder.
+     */
+    fn configure(connection: &Connection, folder: &Path, passphrase: &str) -> Result<(), RepositoryError> {
+        let current: Option<PathBuf> = SyncFolder::directory(connection)?;
+
+        if passphrase.is_empty() && current.as_deref() == Some(folder) {
+            Ok(())
+        } else {
+            SyncFolder::configure(connection, folder, passphrase)
+        }
+    }
+
+    fn set_configured(&self, configured: bool) {
+        self.disable.set_sensitive(configured);
+
+        if configured {
+            self.passphrase.set_placeholder_text(Some(&gettext("Passphrase (unchanged)")));
+            self.status.set_label(&gettext("Syncing through this folder"));
+        } else {
+            self.passphrase.set_placeholder_text(Some(&gettext("Passphrase")));
+            self.status.set_label(&gettext("Not syncing"));
+        }
+    }
+
+    fn display(&self, report: &SyncReport) {
+        self.conflicts.foreach(|w| self.conflicts.remove(w));
+
+        let mut summary = format!(
+            "{} {}, {} {}",
+            report.sent,
+            gettext("change(s) sent"),
+            report.received,
+            gettext("change(s) received")
+        );
+
+        if !report.conflicts.is_empty() {
+            summary = format!("{}, {} {}", summary, report.conflicts.len(), gettext("conflict(s)"));
+        }
+
+        self.status.set_label(summary.as_str());
+
+        let unreadable = report
+            .unreadable
+            .iter()
+            .map(|device| format!("{}: {}", device, gettext("could not read its log, is its passphrase the same?")));
+
+        for line in report.conflicts.iter().map(|conflict| conflict.to_string()).chain(unreadable) {
+            let label = gtk::Label::builder().label(line).wrap(true).xalign(0.0).visible(true).build();
+            self.conflicts.add(&label);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use gettextrs::gettext;
+use glib::clone;
+use gtk::prelude::*;
+use gtk::Builder;
+use log::{info, warn};
+
+use crate::helpers::{RepositoryError, Vault};
+use crate::main_window::MainWindow;
+use crate::NAMESPACE_PREFIX;
+
+#[derive(Clone, Debug)]
+pub struct VaultWindow {
+    pub popup: gtk::Window,
+    pub message: gtk::Label,
+    pub passphrase: gtk::Entry,
+    pub confirm: gtk::Entry,
+    pub error: gtk::Label,
+    pub unlock: gtk::Button,
+    pub cancel: gtk::Button,
+}
+
+impl VaultWindow {
+    pub fn new(builder: &Builder) -> VaultWindow {
+        VaultWindow {
+            popup: builder.object("vault_popup").unwrap(),
+            message: builder.object("vault_message").unwrap(),
+            passphrase: builder.object("vault_passphrase").unwrap(),
+            confirm: builder.object("vault_confirm").unwrap(),
+            error: builder.object("vault_error").unwrap(),
+            unlock: builder.object("vault_unlock").unwrap(),
+            cancel: builder.object("vault_cancel").unwrap(),
+        }
+    }
+
+    /**
+     * Asks for the vault passphrase, twice when the vault is yet to be created.
+     * `on_close` is called with whether the vault got unlocked.
+     */
+    pub fn prompt<F: Fn(bool) + 'static>(gui: &MainWindow, on_close: F) {
+        let builder = Builder::from_resource(format!("{}/{}", NAMESPACE_PREFIX, "vault_unlock.ui").as_str());
+        let vault = VaultWindow::new(&builder);
+
+        let creating = !Vault::exists();
+        let unlocked = Rc::new(Cell::new(false));
+
+        vault.popup.set_transient_for(Some(&gui.window));
+
+        if creating {
+            vault.message.set_label(&gettext("Choose a passphrase for the new vault"));
+            vault.confirm.show();
+        }
+
+        vault.popup.connect_delete_event(clone!(
+            #[strong]
+            unlocked,
+            move |_, _| {
+                on_close(unlocked.get());
+                gtk::glib::Propagation::Proceed
+            }
+        ));
+
+        vault.cancel.connect_clicked(clone!(
+            #[strong(rename_to = popup)]
+            vault.popup,
+            move |_| popup.close()
+        ));
+
+        vault.unlock.connect_clicked(clone!(
+            #[strong]
+            vault,
+            move |_| {
+                let passphrase = vault.passphrase.text().to_string();
+
+                if passphrase.is_empty() {
+                    vault.show_error(&gettext("Passphrase is empty"));
+                    return;
+                }
+
+                if creating && passphrase != vault.confirm.text().as_str() {
+                    vault.show_error(&gettext("Passphrases do not match"));
+                    return;
+                }
+
+                vault.unlock.set_sensitive(false);
+
+                let (tx, rx) = async_channel::bounded::<Result<(), RepositoryError>>(1);
+
+                // key derivation takes a while, keep it off the main loop
+                glib::spawn_future(async move {
+                    if let Err(e) = tx.send(Vault::unlock(passphrase.as_str())).await {
+                        warn!("Could not send vault unlock result: {:?}", e);
+                    }
+                });
+
+                glib::spawn_future_local(clone!(
+                    #[strong]
+                    vault,
+                    #[strong]
+                    unlocked,
+                    async move {
+                        match rx.recv().await {
+                            Ok(Ok(())) => {
+                                info!("Vault unlocked");
+                                unlocked.set(true);
+                                vault.popup.close();
+                            }
+                            Ok(Err(RepositoryError::SecretStoreError(message))) => vault.show_error(&gettext(message)),
+                            Ok(Err(e)) => {
+                                warn!("Could not unlock vault: {:?}", e);
+                                vault.show_error(e.message().as_str());
+                            }
+                            Err(e) => warn!("Channel is closed. Application terminated?: {:?}", e),
+                        }
+
+                        vault.unlock.set_sensitive(true);
+                    }
+                ));
+            }
+        ));
+
+        vault.popup.show();
+    }
+
+    fn show_error(&self, message: &str) {
+        self.error.set_label(message);
+        self.error.show();
+    }
+}
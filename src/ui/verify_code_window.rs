@@ -0,0 +1,203 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use gettextrs::gettext;
+use glib::clone;
+use gtk::prelude::*;
+use gtk::Builder;
+use log::{error, info};
+use rusqlite::Connection;
+
+use crate::helpers::{Clock, Database, RepositoryError};
+use crate::main_window::MainWindow;
+use crate::model::{Account, CodeMatch};
+use crate::NAMESPACE_PREFIX;
+
+#[derive(Clone, Debug)]
+pub struct VerifyCodeWindow {
+    pub popup: gtk::Window,
+    pub code: gtk::Entry,
+    pub steps: gtk::SpinButton,
+    pub status: gtk::Label,
+    pub apply: gtk::Button,
+    pub correct_clock: gtk::Button,
+    pub close: gtk::Button,
+    pub verify: gtk::Button,
+    account: Rc<RefCell<Account>>,
+    found: Rc<Cell<Option<CodeMatch>>>,
+}
+
+impl VerifyCodeWindow {
+    pub fn new(builder: &Builder, account: Account) -> VerifyCodeWindow {
+        VerifyCodeWindow {
+            popup: builder.object("verify_code_popup").unwrap(),
+            code: builder.object("verify_code_code").unwrap(),
+            steps: builder.object("verify_code_steps").unwrap(),
+            status: builder.object("verify_code_status").unwrap(),
+            apply: builder.object("verify_code_apply").unwrap(),
+            correct_clock: builder.object("verify_code_correct_clock").unwrap(),
+            close: builder.object("verify_code_close").unwrap(),
+            verify: builder.object("verify_code_verify").unwrap(),
+            account: Rc::new(RefCell::new(account)),
+            found: Rc::new(Cell::new(None)),
+        }
+    }
+
+    /**
+     * `account` comes with its secret, as shown in the accounts list.
+     */
+    pub fn show(gui: &MainWindow, account: Account, connection: Arc<Mutex<Connection>>) {
+        let builder = Builder::from_resource(format!("{}/{}", NAMESPACE_PREFIX, "verify_code.ui").as_str());
+        let window = VerifyCodeWindow::new(&builder, account);
+
+        window.popup.set_transient_for(Some(&gui.window));
+        window
+            .popup
+            .set_title(format!("{} - {}", gettext("Verify code"), window.account.borrow().label).as_str());
+
+        window.close.connect_clicked(clone!(
+            #[strong(rename_to = popup)]
+            window.popup,
+            move |_| popup.close()
+        ));
+
+        window.verify.connect_clicked(clone!(
+            #[strong]
+            window,
+            move |_| window.verify()
+        ));
+
+        window.apply.connect_clicked(clone!(
+            #[strong]
+            window,
+            #[strong]
+            gui,
+            move |_| window.apply(&gui, connection.clone())
+        ));
+
+        window.correct_clock.connect_clicked(clone!(
+            #[strong]
+            window,
+            move |_| window.correct_clock()
+        ));
+
+        window.popup.show();
+    }
+
+    fn verify(&self) {
+        let code = self.code.text().to_string();
+
+        if code.trim().is_empty() {
+            self.status.set_label(&gettext("Enter a code first"));
+            return;
+        }
+
+        let account = self.account.borrow();
+        let found = Account::now().and_then(|now| account.verify(code.as_str(), now, self.steps.value_as_int() as u64));
+
+        match found {
+            Ok(Some(found)) => {
+                self.found.set(Some(found));
+                self.status.set_label(Self::describe(&account, &found).as_str());
+                self.apply.set_sensitive((found.algorithm, found.digits) != (account.algorithm, account.digits));
+                self.correct_clock.set_sensitive(found.steps != 0 && !account.account_type.has_counter());
+            }
+            Ok(None) => {
+                self.found.set(None);
+                self.status
+                    .set_label(&gettext("No match: the secret, or the type of the account, is likely wrong"));
+                self.apply.set_sensitive(false);
+                self.correct_clock.set_sensitive(false);
+            }
+            Err(e) => {
+                self.found.set(None);
+                self.status.set_label(e.message().as_str());
+            }
+        }
+    }
+
+    fn describe(account: &Account, found: &CodeMatch) -> String {
+        let parameters = format!("{} {}, {} {}", gettext("Matches with"), found.algorithm, found.digits, gettext("digits"));
+
+        if account.account_type.has_counter() {
+            return match found.steps {
+                0 => format!("{}, {}", parameters, gettext("at the counter")),
+                steps => format!("{}, {} {}", parameters, steps, gettext("codes ahead of the counter")),
+            };
+        }
+
+        let seconds = found.steps.abs() * account.period() as i64;
+
+        match found.steps {
+            0 => format!("{}, {}", parameters, gettext("on time")),
+            steps if steps > 0 => format!("{}, {} {} ({}s)", parameters, steps, gettext("periods later: the clock is behind"), seconds),
+            steps => format!("{}, {} {} ({}s)", parameters, -steps, gettext("periods earlier: the clock is ahead"), seconds),
+        }
+    }
+
+    fn apply(&self, gui: &MainWindow, connection: Arc<Mutex<Connection>>) {
+        let Some(found) = self.found.get() else {
+            return;
+        };
+
+        let account_id = self.account.borrow().id;
+
+        let result = {
+            let connection = Database::lock(&connection);
+            Self::save_parameters(&connection, account_id, &found)
+        };
+
+        match result {
+            Ok(()) => {
+                info!("Account {} now uses {} with {} digits", account_id, found.algorithm, found.digits);
+
+                let mut account = self.account.borrow_mut();
+                account.algorithm = found.algorithm;
+                account.digits = found.digits;
+
+                self.apply.set_sensitive(false);
+                self.status.set_label(&gettext("Account updated"));
+                gui.accounts_window.refresh_accounts(gui);
+            }
+            Err(e) => {
+                error!("Could not update account {}: {:?}", account_id, e);
+                self.status.set_label(e.message().as_str());
+            }
+        }
+    }
+
+    fn save_parameters(connection: &Connection, account_id: u32, found: &CodeMatch) -> Result<(), RepositoryError> {
+        let Some(mut account) = Database::get_account(connection, account_id)? else {
+            return Ok(());
+        };
+
+        account.algorithm = found.algorithm;
+        account.digits = found.digits;
+
+        Database::update_account(connection, &mut account).map(|_| ())
+    }
+
+    /**
+     * Moves the clock offset by the periods the code was found at.
+     */
+    fn correct_clock(&self) {
+        let Some(found) = self.found.get() else {
+            return;
+        };
+
+        let correction = found.steps * self.account.borrow().period() as i64;
+
+        match Clock::set_offset(Clock::offset() + correction) {
+            Ok(()) => {
+                self.found.set(Some(CodeMatch { steps: 0, ..found }));
+                self.correct_clock.set_sensitive(false);
+                self.status.set_label(&gettext("Clock corrected"));
+            }
+            Err(e) => {
+                error!("Could not set time offset: {:?}", e);
+                self.status.set_label(e.message().as_str());
+            }
+        }
+    }
+}
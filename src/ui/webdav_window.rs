@@ -0,0 +1,317 @@
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use gettextrs::gettext;
+use glib::clone;
+use gtk::prelude::*;
+use gtk::Builder;
+use log::{error, info, warn};
+use rusqlite::Connection;
+
+use crate::exporting::{AccountsImportExportResult, ImportType};
+use crate::helpers::{Backup, Database, Paths, RemoteVersion, RepositoryError, WebDav, WebDavSettings};
+use crate::main_window::MainWindow;
+use crate::NAMESPACE_PREFIX;
+
+type VersionsResult = Result<Vec<RemoteVersion>, RepositoryError>;
+type BackupResult = Result<(RemoteVersion, Vec<RemoteVersion>), RepositoryError>;
+
+#[derive(Clone, Debug)]
+pub struct WebDavWindow {
+    pub popup: gtk::Window,
+    pub url: gtk::Entry,
+    pub username: gtk::Entry,
+    pub password: gtk::Entry,
+    pub passphrase: gtk::Entry,
+    pub versions: gtk::SpinButton,
+    pub interval: gtk::SpinButton,
+    pub status: gtk::Label,
+    pub remote_versions: gtk::ComboBoxText,
+    pub restore: gtk::Button,
+    pub disable: gtk::Button,
+    pub close: gtk::Button,
+    pub backup: gtk::Button,
+}
+
+impl WebDavWindow {
+    pub fn new(builder: &Builder) -> WebDavWindow {
+        WebDavWindow {
+            popup: builder.object("webdav_popup").unwrap(),
+            url: builder.object("webdav_url").unwrap(),
+            username: builder.object("webdav_username").unwrap(),
+            password: builder.object("webdav_password").unwrap(),
+            passphrase: builder.object("webdav_passphrase").unwrap(),
+            versions: builder.object("webdav_versions").unwrap(),
+            interval: builder.object("webdav_interval").unwrap(),
+            status: builder.object("webdav_status").unwrap(),
+            remote_versions: builder.object("webdav_remote_versions").unwrap(),
+            restore: builder.object("webdav_restore").unwrap(),
+            disable: builder.object("webdav_disable").unwrap(),
+            close: builder.object("webdav_close").unwrap(),
+            backup: builder.object("webdav_backup").unwrap(),
+        }
+    }
+
+    pub fn show(gui: &MainWindow, connection: Arc<Mutex<Connection>>) {
+        let builder = Builder::from_resource(format!("{}/{}", NAMESPACE_PREFIX, "webdav.ui").as_str());
+        let webdav = WebDavWindow::new(&builder);
+
+        webdav.popup.set_transient_for(Some(&gui.window));
+
+        let settings = WebDav::settings(&Database::lock(&connection));
+
+        match settings {
+            Ok(Some(settings)) => {
+                webdav.url.set_text(settings.url.as_str());
+                webdav.username.set_text(settings.username.as_str());
+                webdav.versions.set_value(settings.versions as f64);
+                webdav.interval.set_value(settings.interval_hours as f64);
+                webdav.set_configured(true);
+                webdav.list_versions(connection.clone());
+            }
+            Ok(None) => webdav.set_configured(false),
+            Err(e) => webdav.status.set_label(e.message().as_str()),
+        }
+
+        webdav.close.connect_clicked(clone!(
+            #[strong(rename_to = popup)]
+            webdav.popup,
+            move |_| popup.close()
+        ));
+
+        webdav.remote_versions.connect_changed(clone!(
+            #[strong(rename_to = restore)]
+            webdav.restore,
+            move |combo| restore.set_sensitive(combo.active_id().is_some())
+        ));
+
+        webdav.disable.connect_clicked(clone!(
+            #[strong]
+            webdav,
+            #[strong]
+            connection,
+            move |_| {
+                let result = WebDav::disable(&Database::lock(&connection));
+
+                match result {
+                    Ok(()) => {
+                        webdav.remote_versions.remove_all();
+                        webdav.set_configured(false);
+                    }
+                    Err(e) => {
+                        error!("Could not disable remote backups: {:?}", e);
+                        webdav.status.set_label(e.message().as_str());
+                    }
+                }
+            }
+        ));
+
+        webdav.backup.connect_clicked(clone!(
+            #[strong]
+            webdav,
+            #[strong]
+            connection,
+            move |_| webdav.backup_now(connection.clone())
+        ));
+
+        webdav.restore.connect_clicked(clone!(
+            #[strong]
+            webdav,
+            #[strong]
+            gui,
+            move |_| webdav.restore_version(&gui, connection.clone())
+        ));
+
+        webdav.popup.show();
+    }
+
+    /**
+     * Saves the settings, then uploads a backup straight away.
+     */
+    fn backup_now(&self, connection: Arc<Mutex<Connection>>) {
+        let settings = WebDavSettings {
+            url: self.url.text().trim().to_owned(),
+            username: self.username.text().trim().to_owned(),
+            versions: self.versions.value_as_int() as u32,
+            interval_hours: self.interval.value_as_int() as u32,
+        };
+
+        if settings.url.is_empty() {
+            self.status.set_label(&gettext("Enter the URL of a WebDAV folder first"));
+            return;
+        }
+
+        let password = self.password.text().to_string();
+        let passphrase = self.passphrase.text().to_string();
+
+        self.backup.set_sensitive(false);
+        self.status.set_label(&gettext("Uploading…"));
+
+        let (tx, rx) = async_channel::bounded::<BackupResult>(1);
+
+        glib::spawn_future(clone!(
+            #[strong]
+            connection,
+            async move {
+                let result = Self::configure_and_backup(&Database::lock(&connection), &settings, password.as_str(), passphrase.as_str());
+
+                if let Err(e) = tx.send(result).await {
+                    warn!("Could not send backup result: {:?}", e);
+                }
+            }
+        ));
+
+        glib::spawn_future_local(clone!(
+            #[strong(rename_to = webdav)]
+            self,
+            async move {
+                match rx.recv().await {
+                    Ok(Ok((version, versions))) => {
+                        webdav.password.set_text("");
+                        webdav.passphrase.set_text("");
+                        webdav.set_configured(true);
+                        webdav.display_versions(&versions);
+                        webdav.status.set_label(format!("{} {}", gettext("Backed up on"), version).as_str());
+                    }
+                    Ok(Err(e)) => {
+                        error!("Remote backup failed: {:?}", e);
+                        webdav.status.set_label(e.message().as_str());
+                    }
+                    Err(e) => warn!("Channel is closed. Application terminated?: {:?}", e),
+                }
+
+                webdav.backup.set_sensitive(true);
+            }
+        ));
+    }
+
+    fn configure_and_backup(connection: &Connection, settings: &WebDavSettings, password: &str, passphrase: &str) -> BackupResult {
+        WebDav::configure(connection, settings, password, passphrase)?;
+
+        let webdav = Self::open(connection)?;
+        let version = webdav.backup(connection)?;
+
+        Ok((version, webdav.versions()?))
+    }
+
+    fn list_versions(&self, connection: Arc<Mutex<Connection>>) {
+        let (tx, rx) = async_channel::bounded::<VersionsResult>(1);
+
+        glib::spawn_future(async move {
+            let result = Self::open(&Database::lock(&connection)).and_then(|webdav| webdav.versions());
+
+            if let Err(e) = tx.send(result).await {
+                warn!("Could not send remote backups: {:?}", e);
+            }
+        });
+
+        glib::spawn_future_local(clone!(
+            #[strong(rename_to = webdav)]
+            self,
+            async move {
+                match rx.recv().await {
+                    Ok(Ok(versions)) => webdav.display_versions(&versions),
+                    Ok(Err(e)) => {
+                        error!("Could not list remote backups: {:?}", e);
+                        webdav.status.set_label(e.message().as_str());
+                    }
+                    Err(e) => warn!("Channel is closed. Application terminated?: {:?}", e),
+                }
+            }
+        ));
+    }
+
+    /**
+     * Imports the chosen backup like an exported file: accounts already there are updated, none are removed.
+     */
+    fn restore_version(&self, gui: &MainWindow, connection: Arc<Mutex<Connection>>) {
+        let Some(version) = self.remote_versions.active_id().and_then(|name| RemoteVersion::parse(name.as_str())) else {
+            return;
+        };
+
+        self.restore.set_sensitive(false);
+        self.status.set_label(&gettext("Restoring…"));
+
+        let (tx, rx) = async_channel::bounded::<AccountsImportExportResult>(1);
+
+        glib::spawn_future(async move {
+            let path = Paths::path().join("webdav-restore.yaml");
+
+            let downloaded = Self::open(&Database::lock(&connection)).and_then(|webdav| webdav.download_to(&version, path.as_path()));
+
+            match downloaded {
+                Ok(()) => {
+                    Backup::restore_account_and_signal_back(ImportType::Internal, path.clone(), connection, tx).await;
+                    Self::remove(path.as_path());
+                }
+                Err(e) => {
+                    Self::remove(path.as_path());
+
+                    if let Err(e) = tx.send(Err(e)).await {
+                        warn!("Could not send restore result: {:?}", e);
+                    }
+                }
+            }
+        });
+
+        glib::spawn_future_local(clone!(
+            #[strong(rename_to = webdav)]
+            self,
+            #[strong]
+            gui,
+            async move {
+                match rx.recv().await {
+                    Ok(Ok(_)) => {
+                        info!("Restored remote backup");
+                        webdav.status.set_label(&gettext("Backup restored"));
+                        gui.accounts_window.refresh_accounts(&gui);
+                    }
+                    Ok(Err(e)) => {
+                        error!("Could not restore remote backup: {:?}", e);
+                        webdav.status.set_label(e.message().as_str());
+                    }
+                    Err(e) => warn!("Channel is closed. Application terminated?: {:?}", e),
+                }
+
+                webdav.restore.set_sensitive(true);
+            }
+        ));
+    }
+
+    fn open(connection: &Connection) -> Result<WebDav, RepositoryError> {
+        WebDav::open(connection)?.ok_or_else(|| RepositoryError::BackupError("webdav_not_configured".to_owned()))
+    }
+
+    fn remove(path: &Path) {
+        if path.exists() {
+            if let Err(e) = fs::remove_file(path) {
+                warn!("Could not remove {}: {:?}", path.display(), e);
+            }
+        }
+    }
+
+    fn display_versions(&self, versions: &[RemoteVersion]) {
+        self.remote_versions.remove_all();
+
+        for version in versions {
+            self.remote_versions.append(Some(version.name.as_str()), version.to_string().as_str());
+        }
+
+        self.remote_versions.set_active(if versions.is_empty() { None } else { Some(0) });
+    }
+
+    fn set_configured(&self, configured: bool) {
+        self.disable.set_sensitive(configured);
+
+        if configured {
+            self.password.set_placeholder_text(Some(&gettext("(unchanged)")));
+            self.passphrase.set_placeholder_text(Some(&gettext("(unchanged)")));
+            self.status.set_label(&gettext("Backups are uploaded on schedule"));
+        } else {
+            self.password.set_placeholder_text(None);
+            self.passphrase.set_placeholder_text(None);
+            self.status.set_label(&gettext("No remote backups"));
+        }
+    }
+}